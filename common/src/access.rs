@@ -0,0 +1,51 @@
+use std::{fmt::Display, str::FromStr};
+
+use serde::{Deserialize, Serialize};
+
+use crate::Operation;
+
+/// What a user may do with a repository. Every level includes the ones below
+/// it, so accesses compare with `>=`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Access {
+    /// Clone, fetch, browse and download LFS objects.
+    Read,
+    /// Push, upload LFS objects and take locks.
+    Write,
+    /// Manage the repository, like breaking the locks of others.
+    Admin,
+}
+
+impl Display for Access {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Access::Read => write!(f, "read"),
+            Access::Write => write!(f, "write"),
+            Access::Admin => write!(f, "admin"),
+        }
+    }
+}
+
+impl FromStr for Access {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "read" => Ok(Self::Read),
+            "write" => Ok(Self::Write),
+            "admin" => Ok(Self::Admin),
+            _ => Err(format!("unknown access: {}", s)),
+        }
+    }
+}
+
+/// The access an LFS operation needs.
+impl From<Operation> for Access {
+    fn from(operation: Operation) -> Self {
+        match operation {
+            Operation::Download => Access::Read,
+            Operation::Upload => Access::Write,
+        }
+    }
+}
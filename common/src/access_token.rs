@@ -0,0 +1,25 @@
+use crate::secret;
+
+/// Personal access tokens start with this, so they can be told apart from
+/// the JWTs minted by git-lfs-authenticate and spotted by secret scanners.
+pub const ACCESS_TOKEN_PREFIX: &str = "rst_";
+
+const ACCESS_TOKEN_LENGTH: usize = 40;
+
+/// A fresh personal access token, like `rst_` followed by 40 alphanumeric
+/// characters. It is shown to its owner once; only its [`hash`] is kept.
+pub fn generate() -> String {
+    format!(
+        "{}{}",
+        ACCESS_TOKEN_PREFIX,
+        secret::random(ACCESS_TOKEN_LENGTH)
+    )
+}
+
+pub fn hash(token: &str) -> String {
+    secret::hash(token)
+}
+
+pub fn is_access_token(token: &str) -> bool {
+    token.starts_with(ACCESS_TOKEN_PREFIX)
+}
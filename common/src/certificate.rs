@@ -0,0 +1,492 @@
+use std::fmt::Display;
+
+use crate::fingerprint::sha256_fingerprint_of_blob;
+
+/// A certificate offered by sshd could not be parsed, or is not one this
+/// server accepts.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CertificateError {
+    Malformed(String),
+    /// A host certificate, or some other type that does not sign users in.
+    NotUserCertificate(u32),
+    NotYetValid,
+    Expired,
+    /// Critical options must be understood, or the certificate refused.
+    UnsupportedCriticalOption(String),
+    /// No configured certificate authority signed it.
+    UntrustedAuthority(String),
+}
+
+impl Display for CertificateError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            CertificateError::Malformed(reason) => write!(f, "malformed certificate: {}", reason),
+            CertificateError::NotUserCertificate(kind) => {
+                write!(f, "certificate of type {} is no user certificate", kind)
+            }
+            CertificateError::NotYetValid => write!(f, "certificate is not valid yet"),
+            CertificateError::Expired => write!(f, "certificate has expired"),
+            CertificateError::UnsupportedCriticalOption(name) => {
+                write!(
+                    f,
+                    "certificate has the unsupported critical option {}",
+                    name
+                )
+            }
+            CertificateError::UntrustedAuthority(fingerprint) => {
+                write!(
+                    f,
+                    "certificate is signed by the untrusted key {}",
+                    fingerprint
+                )
+            }
+        }
+    }
+}
+
+impl std::error::Error for CertificateError {}
+
+const SSH_CERT_TYPE_USER: u32 = 1;
+
+/// Critical options this server honors. `source-address` is enforced by sshd
+/// for keys trusted with `cert-authority`, and `force-command` would replace
+/// rustile-shell, so it is refused like unknown options.
+const SUPPORTED_CRITICAL_OPTIONS: &[&str] = &["source-address"];
+
+/// Whether sshd's `%t` names an OpenSSH certificate, like
+/// `ssh-ed25519-cert-v01@openssh.com`.
+pub fn is_certificate_type(key_type: &str) -> bool {
+    key_type.ends_with("-cert-v01@openssh.com")
+}
+
+/// The public fields of an OpenSSH certificate, see `PROTOCOL.certkeys`. The
+/// signature is not checked here, sshd does that for `cert-authority` keys.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Certificate {
+    pub key_type: String,
+    pub serial: u64,
+    pub cert_type: u32,
+    pub key_id: String,
+    /// Empty means any principal, which this server never accepts.
+    pub principals: Vec<String>,
+    /// Seconds since the epoch.
+    pub valid_after: u64,
+    /// Seconds since the epoch, `u64::MAX` for certificates that never expire.
+    pub valid_before: u64,
+    pub critical_options: Vec<(String, String)>,
+    pub extensions: Vec<String>,
+    /// The public key blob of the certificate authority.
+    pub signature_key: Vec<u8>,
+}
+
+struct Reader<'a>(&'a [u8]);
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, len: usize) -> Result<&'a [u8], CertificateError> {
+        if self.0.len() < len {
+            return Err(CertificateError::Malformed(String::from("truncated")));
+        }
+        let (taken, rest) = self.0.split_at(len);
+        self.0 = rest;
+        Ok(taken)
+    }
+
+    fn u32(&mut self) -> Result<u32, CertificateError> {
+        let bytes = self.take(4)?;
+        Ok(u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+    }
+
+    fn u64(&mut self) -> Result<u64, CertificateError> {
+        Ok((u64::from(self.u32()?) << 32) | u64::from(self.u32()?))
+    }
+
+    fn bytes(&mut self) -> Result<&'a [u8], CertificateError> {
+        let len = self.u32()? as usize;
+        self.take(len)
+    }
+
+    fn string(&mut self) -> Result<String, CertificateError> {
+        String::from_utf8(self.bytes()?.to_vec())
+            .map_err(|_| CertificateError::Malformed(String::from("invalid utf-8")))
+    }
+
+    fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+}
+
+/// The public key fields between the nonce and the serial, which differ by
+/// key type.
+fn skip_public_key(reader: &mut Reader, key_type: &str) -> Result<(), CertificateError> {
+    let fields = match key_type {
+        "ssh-rsa-cert-v01@openssh.com" => 2,
+        "ssh-dss-cert-v01@openssh.com" => 4,
+        "ssh-ed25519-cert-v01@openssh.com" => 1,
+        "sk-ssh-ed25519-cert-v01@openssh.com" => 2,
+        "sk-ecdsa-sha2-nistp256-cert-v01@openssh.com" => 3,
+        _ if key_type.starts_with("ecdsa-sha2-") => 2,
+        _ => {
+            return Err(CertificateError::Malformed(format!(
+                "unknown certificate type {}",
+                key_type
+            )))
+        }
+    };
+    for _ in 0..fields {
+        reader.bytes()?;
+    }
+    Ok(())
+}
+
+impl Certificate {
+    /// Parses the base64 encoded certificate sshd hands over as `%k`.
+    pub fn parse(key: &str) -> Result<Self, CertificateError> {
+        let blob = base64::decode(key.trim())
+            .map_err(|err| CertificateError::Malformed(err.to_string()))?;
+        let mut reader = Reader(&blob);
+
+        let key_type = reader.string()?;
+        if !is_certificate_type(&key_type) {
+            return Err(CertificateError::Malformed(format!(
+                "{} is no certificate type",
+                key_type
+            )));
+        }
+        reader.bytes()?; // nonce
+        skip_public_key(&mut reader, &key_type)?;
+        let serial = reader.u64()?;
+        let cert_type = reader.u32()?;
+        let key_id = reader.string()?;
+
+        let mut principals = Vec::new();
+        let mut packed = Reader(reader.bytes()?);
+        while !packed.is_empty() {
+            principals.push(packed.string()?);
+        }
+        let valid_after = reader.u64()?;
+        let valid_before = reader.u64()?;
+
+        let mut critical_options = Vec::new();
+        let mut packed = Reader(reader.bytes()?);
+        while !packed.is_empty() {
+            let name = packed.string()?;
+            // the data is itself a string, empty for flags
+            let mut data = Reader(packed.bytes()?);
+            let value = if data.is_empty() {
+                String::new()
+            } else {
+                data.string()?
+            };
+            critical_options.push((name, value));
+        }
+        let mut extensions = Vec::new();
+        let mut packed = Reader(reader.bytes()?);
+        while !packed.is_empty() {
+            extensions.push(packed.string()?);
+            packed.bytes()?;
+        }
+        reader.bytes()?; // reserved
+        let signature_key = reader.bytes()?.to_vec();
+        reader.bytes()?; // signature
+        if !reader.is_empty() {
+            return Err(CertificateError::Malformed(String::from("trailing data")));
+        }
+
+        Ok(Self {
+            key_type,
+            serial,
+            cert_type,
+            key_id,
+            principals,
+            valid_after,
+            valid_before,
+            critical_options,
+            extensions,
+            signature_key,
+        })
+    }
+
+    /// Checks the type, validity window and critical options at `unix_time`.
+    pub fn check(&self, unix_time: u64) -> Result<(), CertificateError> {
+        if self.cert_type != SSH_CERT_TYPE_USER {
+            return Err(CertificateError::NotUserCertificate(self.cert_type));
+        }
+        if unix_time < self.valid_after {
+            return Err(CertificateError::NotYetValid);
+        }
+        if unix_time >= self.valid_before {
+            return Err(CertificateError::Expired);
+        }
+        if let Some((name, _)) = self
+            .critical_options
+            .iter()
+            .find(|(name, _)| !SUPPORTED_CRITICAL_OPTIONS.contains(&name.as_str()))
+        {
+            return Err(CertificateError::UnsupportedCriticalOption(name.clone()));
+        }
+        Ok(())
+    }
+
+    /// The SHA-256 fingerprint of the certificate authority's key.
+    pub fn signature_key_fingerprint(&self) -> String {
+        sha256_fingerprint_of_blob(&self.signature_key)
+    }
+}
+
+/// A certificate authority trusted to sign user certificates.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CertificateAuthority {
+    pub key_type: String,
+    /// Base64 encoded, as in `authorized_keys`.
+    pub key: String,
+    pub fingerprint: String,
+}
+
+/// The certificate authorities listed in the file at `SSH_USER_CA_KEYS`, one
+/// public key per line in `authorized_keys` form.
+#[derive(Debug, Clone, Default)]
+pub struct CertificateAuthorities(Vec<CertificateAuthority>);
+
+impl CertificateAuthorities {
+    pub fn parse(contents: &str) -> Result<Self, CertificateError> {
+        let mut authorities = Vec::new();
+        for line in contents.lines().map(str::trim) {
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let mut fields = line.split_whitespace();
+            let (key_type, key) = match (fields.next(), fields.next()) {
+                (Some(key_type), Some(key)) => (key_type, key),
+                _ => {
+                    return Err(CertificateError::Malformed(format!(
+                        "expected '<type> <base64> [comment]', got '{}'",
+                        line
+                    )))
+                }
+            };
+            let fingerprint = crate::sha256_fingerprint(key)
+                .map_err(|err| CertificateError::Malformed(err.to_string()))?;
+            authorities.push(CertificateAuthority {
+                key_type: String::from(key_type),
+                key: String::from(key),
+                fingerprint,
+            });
+        }
+        Ok(Self(authorities))
+    }
+
+    /// `None` if `SSH_USER_CA_KEYS` is not set, so certificates are refused.
+    pub fn from_env() -> Option<Result<Self, String>> {
+        let path = std::env::var("SSH_USER_CA_KEYS").ok()?;
+        Some(
+            std::fs::read_to_string(&path)
+                .map_err(|err| format!("failed to read {}: {}", path, err))
+                .and_then(|contents| Self::parse(&contents).map_err(|err| err.to_string())),
+        )
+    }
+
+    /// The authority that signed `certificate`, if it is trusted.
+    pub fn issuer(
+        &self,
+        certificate: &Certificate,
+    ) -> Result<&CertificateAuthority, CertificateError> {
+        let fingerprint = certificate.signature_key_fingerprint();
+        self.0
+            .iter()
+            .find(|authority| authority.fingerprint == fingerprint)
+            .ok_or(CertificateError::UntrustedAuthority(fingerprint))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // made with `ssh-keygen -s ca -I alice@example -n alice
+    // -V 20200101000000Z:20400101000000Z` unless noted otherwise
+    const CA: &str =
+        "ssh-ed25519 AAAAC3NzaC1lZDI1NTE5AAAAIGKisLqmmVEDcCe2FAAm5ixb7Za/F7f9uDWbVjPqWBi9 ca";
+    const OTHER_CA: &str =
+        "ssh-ed25519 AAAAC3NzaC1lZDI1NTE5AAAAIJOGWGd13qSWd67H4goNGRVWtO8BUsxgUoJoQ/5hk1Lv other_ca";
+
+    /// Serial 7 for alice@example, principals alice and git, valid through 2039.
+    const VALID: &str = "AAAAIHNzaC1lZDI1NTE5LWNlcnQtdjAxQG9wZW5zc2guY29tAAAAIP295POZBVvteonw77IuotShngzPJx1d6XLGdC2qC9olAAAAIEQPxFN8lHxN38ABRPuu530d3HQsznnT5RrfLtnlaqvlAAAAAAAAAAcAAAABAAAADWFsaWNlQGV4YW1wbGUAAAAQAAAABWFsaWNlAAAAA2dpdAAAAABeC+EAAAAAAIOqfoAAAAAAAAAAggAAABVwZXJtaXQtWDExLWZvcndhcmRpbmcAAAAAAAAAF3Blcm1pdC1hZ2VudC1mb3J3YXJkaW5nAAAAAAAAABZwZXJtaXQtcG9ydC1mb3J3YXJkaW5nAAAAAAAAAApwZXJtaXQtcHR5AAAAAAAAAA5wZXJtaXQtdXNlci1yYwAAAAAAAAAAAAAAMwAAAAtzc2gtZWQyNTUxOQAAACBiorC6pplRA3AnthQAJuYsW+2Wvxe3/bg1m1Yz6lgYvQAAAFMAAAALc3NoLWVkMjU1MTkAAABAdGUxCYf8Zpxah7zjL0qCi+Fn4jXOdf7q3y+tX1KclHY7+Nq9U0pfjwMrMyNAPs9/2R8Cuto8kCJgj1wHXqNnAg==";
+
+    /// Valid during the year 2000 only.
+    const EXPIRED: &str = "AAAAIHNzaC1lZDI1NTE5LWNlcnQtdjAxQG9wZW5zc2guY29tAAAAIH+LP8xL0jbk6HG/JsnspRQGMjV/5rKgS6txYRgCrb76AAAAIEQPxFN8lHxN38ABRPuu530d3HQsznnT5RrfLtnlaqvlAAAAAAAAAAAAAAABAAAADWFsaWNlQGV4YW1wbGUAAAAJAAAABWFsaWNlAAAAADhtQ4AAAAAAOk/IgAAAAAAAAACCAAAAFXBlcm1pdC1YMTEtZm9yd2FyZGluZwAAAAAAAAAXcGVybWl0LWFnZW50LWZvcndhcmRpbmcAAAAAAAAAFnBlcm1pdC1wb3J0LWZvcndhcmRpbmcAAAAAAAAACnBlcm1pdC1wdHkAAAAAAAAADnBlcm1pdC11c2VyLXJjAAAAAAAAAAAAAAAzAAAAC3NzaC1lZDI1NTE5AAAAIGKisLqmmVEDcCe2FAAm5ixb7Za/F7f9uDWbVjPqWBi9AAAAUwAAAAtzc2gtZWQyNTUxOQAAAECLKLol42X4cGVu/eooM7T4NIdrfexIDLVFLhVZ+S6bcluP3L729LC73hlr5jsIvI3jHTuQKzC2x58pwQSHabgK";
+
+    /// With `-O critical:verify-everything=yes`.
+    const UNKNOWN_CRITICAL_OPTION: &str = "AAAAIHNzaC1lZDI1NTE5LWNlcnQtdjAxQG9wZW5zc2guY29tAAAAINHnXwjdCOEP0ayb5jvNsST3cOVwAMTGDlwmZpSfqbEuAAAAIEQPxFN8lHxN38ABRPuu530d3HQsznnT5RrfLtnlaqvlAAAAAAAAAAAAAAABAAAADWFsaWNlQGV4YW1wbGUAAAAJAAAABWFsaWNlAAAAAF4L4QAAAAAAg6p+gAAAACAAAAARdmVyaWZ5LWV2ZXJ5dGhpbmcAAAAHAAAAA3llcwAAAAAAAAAAAAAAMwAAAAtzc2gtZWQyNTUxOQAAACBiorC6pplRA3AnthQAJuYsW+2Wvxe3/bg1m1Yz6lgYvQAAAFMAAAALc3NoLWVkMjU1MTkAAABAJemTCkkJiSFHIk/S0BDow2HGisl2wqnkOPHFkImc7LJacTX6FaNy/T7sSotc3xIuCFfcOhommcKcv90B1HrYBA==";
+
+    /// With `-O force-command=/bin/true -O source-address=10.0.0.0/8`.
+    const FORCE_COMMAND: &str = "AAAAIHNzaC1lZDI1NTE5LWNlcnQtdjAxQG9wZW5zc2guY29tAAAAIF50gPwVrT+vbDsFHXtja1TuW9NpPOprQwNjerUKoqDJAAAAIEQPxFN8lHxN38ABRPuu530d3HQsznnT5RrfLtnlaqvlAAAAAAAAAAAAAAABAAAADWFsaWNlQGV4YW1wbGUAAAAJAAAABWFsaWNlAAAAAF4L4QAAAAAAg6p+gAAAAEYAAAANZm9yY2UtY29tbWFuZAAAAA0AAAAJL2Jpbi90cnVlAAAADnNvdXJjZS1hZGRyZXNzAAAADgAAAAoxMC4wLjAuMC84AAAAggAAABVwZXJtaXQtWDExLWZvcndhcmRpbmcAAAAAAAAAF3Blcm1pdC1hZ2VudC1mb3J3YXJkaW5nAAAAAAAAABZwZXJtaXQtcG9ydC1mb3J3YXJkaW5nAAAAAAAAAApwZXJtaXQtcHR5AAAAAAAAAA5wZXJtaXQtdXNlci1yYwAAAAAAAAAAAAAAMwAAAAtzc2gtZWQyNTUxOQAAACBiorC6pplRA3AnthQAJuYsW+2Wvxe3/bg1m1Yz6lgYvQAAAFMAAAALc3NoLWVkMjU1MTkAAABAdpffNQxw4KwZKZEqJDw9v8I/0nEDZH+ot6Al7cKbJc0HnlfCRmrEhoaEfNDh0ckcbNF7AUYEydQNIif3XMy3BA==";
+
+    /// With `-O source-address=10.0.0.0/8`.
+    const SOURCE_ADDRESS: &str = "AAAAIHNzaC1lZDI1NTE5LWNlcnQtdjAxQG9wZW5zc2guY29tAAAAIOSveorfNnIpzBXrHkZW9hKobPl2YvSSU4u0n8fRlPczAAAAIEQPxFN8lHxN38ABRPuu530d3HQsznnT5RrfLtnlaqvlAAAAAAAAAAAAAAABAAAADWFsaWNlQGV4YW1wbGUAAAAJAAAABWFsaWNlAAAAAF4L4QAAAAAAg6p+gAAAACQAAAAOc291cmNlLWFkZHJlc3MAAAAOAAAACjEwLjAuMC4wLzgAAACCAAAAFXBlcm1pdC1YMTEtZm9yd2FyZGluZwAAAAAAAAAXcGVybWl0LWFnZW50LWZvcndhcmRpbmcAAAAAAAAAFnBlcm1pdC1wb3J0LWZvcndhcmRpbmcAAAAAAAAACnBlcm1pdC1wdHkAAAAAAAAADnBlcm1pdC11c2VyLXJjAAAAAAAAAAAAAAAzAAAAC3NzaC1lZDI1NTE5AAAAIGKisLqmmVEDcCe2FAAm5ixb7Za/F7f9uDWbVjPqWBi9AAAAUwAAAAtzc2gtZWQyNTUxOQAAAEB00aYwnoi0v29zrhLXPYf9Gt5mKWp58YjDD6WTRU3u88s9NNdvEfimBW8b3VwXMDhrVTPVVjadrRI0Qi2+CjYE";
+
+    /// Signed by `OTHER_CA` instead.
+    const OTHER_CA_SIGNED: &str = "AAAAIHNzaC1lZDI1NTE5LWNlcnQtdjAxQG9wZW5zc2guY29tAAAAIFRn7yU6lcdorAhdicP+FIS5QePL6TCbU0OlxDtyZ64/AAAAIEQPxFN8lHxN38ABRPuu530d3HQsznnT5RrfLtnlaqvlAAAAAAAAAAAAAAABAAAADWFsaWNlQGV4YW1wbGUAAAAJAAAABWFsaWNlAAAAAF4L4QAAAAAAg6p+gAAAAAAAAACCAAAAFXBlcm1pdC1YMTEtZm9yd2FyZGluZwAAAAAAAAAXcGVybWl0LWFnZW50LWZvcndhcmRpbmcAAAAAAAAAFnBlcm1pdC1wb3J0LWZvcndhcmRpbmcAAAAAAAAACnBlcm1pdC1wdHkAAAAAAAAADnBlcm1pdC11c2VyLXJjAAAAAAAAAAAAAAAzAAAAC3NzaC1lZDI1NTE5AAAAIJOGWGd13qSWd67H4goNGRVWtO8BUsxgUoJoQ/5hk1LvAAAAUwAAAAtzc2gtZWQyNTUxOQAAAECDBYSxrsJZMFldJ03eiBufkFCJiyuaRF9hdV6sEPGKs3NKRakqGcNZ3jV7kBlxtnQEacjnwmoRI+0Scnu1g9AF";
+
+    /// Of an ECDSA key.
+    const ECDSA: &str = "AAAAKGVjZHNhLXNoYTItbmlzdHAyNTYtY2VydC12MDFAb3BlbnNzaC5jb20AAAAg5/E6296o1NCbhwXH6i2at23TOh7mOY6vEwOun43eJk4AAAAIbmlzdHAyNTYAAABBBDC07w2Pae1g4NLesEutJu+qLeydNGUBdlVVAeUUXNAOa8uRkjBHtbFZ8ictftAJIr/9YJYgt9JNyOG52s+t/A0AAAAAAAAAAAAAAAEAAAADYm9iAAAABwAAAANib2IAAAAAXgvhAAAAAACDqn6AAAAAAAAAAIIAAAAVcGVybWl0LVgxMS1mb3J3YXJkaW5nAAAAAAAAABdwZXJtaXQtYWdlbnQtZm9yd2FyZGluZwAAAAAAAAAWcGVybWl0LXBvcnQtZm9yd2FyZGluZwAAAAAAAAAKcGVybWl0LXB0eQAAAAAAAAAOcGVybWl0LXVzZXItcmMAAAAAAAAAAAAAADMAAAALc3NoLWVkMjU1MTkAAAAgYqKwuqaZUQNwJ7YUACbmLFvtlr8Xt/24NZtWM+pYGL0AAABTAAAAC3NzaC1lZDI1NTE5AAAAQOflXy5QQ1ywPMhbUXdrkWOQs6uXfN3wfmbxtih3MFSQv0WSrbuHInJ9LyJ3V6vKy5IP02/rp7s5UWxpJcWNiQY=";
+
+    /// A host certificate, `-h`.
+    const HOST: &str = "AAAAIHNzaC1lZDI1NTE5LWNlcnQtdjAxQG9wZW5zc2guY29tAAAAIH+QNUO3a1m4jzEcp7RMqcp7SPe8V+0RdTfAE/vLQ5ZcAAAAIEQPxFN8lHxN38ABRPuu530d3HQsznnT5RrfLtnlaqvlAAAAAAAAAAAAAAACAAAABGhvc3QAAAAPAAAAC2dpdC5leGFtcGxlAAAAAF4L4QAAAAAAg6p+gAAAAAAAAAAAAAAAAAAAADMAAAALc3NoLWVkMjU1MTkAAAAgYqKwuqaZUQNwJ7YUACbmLFvtlr8Xt/24NZtWM+pYGL0AAABTAAAAC3NzaC1lZDI1NTE5AAAAQF/+2V/yhFg9/ePbNKZAjePztO8b3jTh9URJpj1A6kS+MWJFq86OanrvTE2o+JI1sQzzeaPqHt8iu1TRPRel8A0=";
+
+    /// 2030-01-01, within the validity of all but [`EXPIRED`].
+    const NOW: u64 = 1893456000;
+
+    #[test]
+    fn parses_certificates_made_by_ssh_keygen() {
+        let certificate = Certificate::parse(VALID).unwrap();
+        assert_eq!(certificate.key_type, "ssh-ed25519-cert-v01@openssh.com");
+        assert_eq!(certificate.serial, 7);
+        assert_eq!(certificate.cert_type, SSH_CERT_TYPE_USER);
+        assert_eq!(certificate.key_id, "alice@example");
+        assert_eq!(certificate.principals, ["alice", "git"]);
+        assert_eq!(certificate.valid_after, 1577836800);
+        assert_eq!(certificate.valid_before, 2208988800);
+        assert!(certificate.critical_options.is_empty());
+        assert!(certificate.extensions.contains(&String::from("permit-pty")));
+        assert_eq!(
+            certificate.signature_key_fingerprint(),
+            "SHA256:L3HHoLJEaBTeb4qJvNtX1yc6H++Ucn1DWmkuNymU6sw"
+        );
+        assert_eq!(certificate.check(NOW), Ok(()));
+
+        let certificate = Certificate::parse(ECDSA).unwrap();
+        assert_eq!(certificate.principals, ["bob"]);
+        assert_eq!(certificate.check(NOW), Ok(()));
+    }
+
+    #[test]
+    fn checks_the_validity_window() {
+        let certificate = Certificate::parse(VALID).unwrap();
+        assert_eq!(
+            certificate.check(1577836799),
+            Err(CertificateError::NotYetValid)
+        );
+        assert_eq!(certificate.check(1577836800), Ok(()));
+        assert_eq!(certificate.check(2208988799), Ok(()));
+        assert_eq!(
+            certificate.check(2208988800),
+            Err(CertificateError::Expired)
+        );
+
+        let expired = Certificate::parse(EXPIRED).unwrap();
+        assert_eq!(expired.check(NOW), Err(CertificateError::Expired));
+    }
+
+    #[test]
+    fn refuses_critical_options_it_does_not_know() {
+        let certificate = Certificate::parse(UNKNOWN_CRITICAL_OPTION).unwrap();
+        assert_eq!(
+            certificate.critical_options,
+            [(String::from("verify-everything"), String::from("yes"))]
+        );
+        assert_eq!(
+            certificate.check(NOW),
+            Err(CertificateError::UnsupportedCriticalOption(String::from(
+                "verify-everything"
+            )))
+        );
+
+        let certificate = Certificate::parse(FORCE_COMMAND).unwrap();
+        assert_eq!(
+            certificate.check(NOW),
+            Err(CertificateError::UnsupportedCriticalOption(String::from(
+                "force-command"
+            )))
+        );
+
+        let certificate = Certificate::parse(SOURCE_ADDRESS).unwrap();
+        assert_eq!(
+            certificate.critical_options,
+            [(String::from("source-address"), String::from("10.0.0.0/8"))]
+        );
+        assert_eq!(certificate.check(NOW), Ok(()));
+    }
+
+    #[test]
+    fn refuses_host_certificates() {
+        let certificate = Certificate::parse(HOST).unwrap();
+        assert_eq!(
+            certificate.check(NOW),
+            Err(CertificateError::NotUserCertificate(2))
+        );
+    }
+
+    #[test]
+    fn finds_the_issuing_authority() {
+        let authorities =
+            CertificateAuthorities::parse(&format!("# the user CA\n{}\n", CA)).unwrap();
+        let issuer = authorities
+            .issuer(&Certificate::parse(VALID).unwrap())
+            .unwrap();
+        assert_eq!(issuer.key_type, "ssh-ed25519");
+        assert_eq!(
+            issuer.fingerprint,
+            "SHA256:L3HHoLJEaBTeb4qJvNtX1yc6H++Ucn1DWmkuNymU6sw"
+        );
+
+        let other_signed = Certificate::parse(OTHER_CA_SIGNED).unwrap();
+        assert_eq!(other_signed.check(NOW), Ok(()));
+        assert_eq!(
+            authorities.issuer(&other_signed),
+            Err(CertificateError::UntrustedAuthority(String::from(
+                "SHA256:lyoyr+GO1lzgre1H5aWZ8RI33fnesAcZ62vzUEib85g"
+            )))
+        );
+        let both = CertificateAuthorities::parse(&format!("{}\n{}", CA, OTHER_CA)).unwrap();
+        assert!(both.issuer(&other_signed).is_ok());
+        assert!(CertificateAuthorities::default()
+            .issuer(&other_signed)
+            .is_err());
+    }
+
+    #[test]
+    fn refuses_truncated_certificates() {
+        let blob = base64::decode(VALID).unwrap();
+        for len in 0..blob.len() {
+            let truncated = base64::encode(&blob[..len]);
+            assert!(
+                matches!(
+                    Certificate::parse(&truncated),
+                    Err(CertificateError::Malformed(_))
+                ),
+                "parsed {} of {} bytes",
+                len,
+                blob.len()
+            );
+        }
+
+        let mut trailing = blob;
+        trailing.push(0);
+        assert_eq!(
+            Certificate::parse(&base64::encode(&trailing)),
+            Err(CertificateError::Malformed(String::from("trailing data")))
+        );
+    }
+
+    #[test]
+    fn survives_malformed_blobs() {
+        for garbage in ["", "!!!", "AAAA", "/////////w==", "AAAAC3NzaC1lZDI1NTE5"] {
+            assert!(Certificate::parse(garbage).is_err(), "parsed {}", garbage);
+        }
+        // a plain public key is no certificate
+        assert!(Certificate::parse(
+            "AAAAC3NzaC1lZDI1NTE5AAAAIGKisLqmmVEDcCe2FAAm5ixb7Za/F7f9uDWbVjPqWBi9"
+        )
+        .is_err());
+
+        // every single corrupted byte must be refused or parsed, not panic
+        let blob = base64::decode(VALID).unwrap();
+        for position in 0..blob.len() {
+            for byte in [0x00, 0x7f, 0xff] {
+                let mut corrupted = blob.clone();
+                corrupted[position] = byte;
+                let _ = Certificate::parse(&base64::encode(&corrupted));
+            }
+        }
+    }
+}
@@ -0,0 +1,51 @@
+use std::{fmt::Display, str::FromStr};
+
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Operation {
+    Download,
+    Upload,
+}
+
+impl Display for Operation {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Operation::Download => write!(f, "download"),
+            Operation::Upload => write!(f, "upload"),
+        }
+    }
+}
+
+impl FromStr for Operation {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "download" => Ok(Self::Download),
+            "upload" => Ok(Self::Upload),
+            _ => Err(format!("unknown operation: {}", s)),
+        }
+    }
+}
+
+/// What a token minted by git-lfs-authenticate allows: `operation` on the
+/// repository `repo`, on behalf of the user with the uuid `sub`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Claims {
+    pub sub: String,
+    pub iat: i64,
+    pub exp: i64,
+    /// The uuid the token is recorded under, so it can be revoked.
+    pub jti: String,
+
+    /// The normalized [`RepoPath`](crate::RepoPath) of the repository.
+    pub repo: String,
+    pub operation: Operation,
+
+    /// Issued to a deploy key rather than a user, `sub` is then the SHA-256
+    /// fingerprint of the key.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub deploy_key: bool,
+}
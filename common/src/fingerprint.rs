@@ -0,0 +1,49 @@
+use sha2::{Digest, Sha256};
+use std::fmt::Display;
+
+/// The key blob handed over by sshd (the base64 part of an `authorized_keys`
+/// line) could not be decoded.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FingerprintError(String);
+
+impl Display for FingerprintError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "invalid public key blob: {}", self.0)
+    }
+}
+
+impl std::error::Error for FingerprintError {}
+
+fn decode(key: &str) -> Result<Vec<u8>, FingerprintError> {
+    let blob = base64::decode(key.trim()).map_err(|err| FingerprintError(err.to_string()))?;
+    if blob.is_empty() {
+        return Err(FingerprintError(String::from("empty key")));
+    }
+    Ok(blob)
+}
+
+/// SHA-256 fingerprint of a base64 encoded public key, like
+/// `SHA256:uNiVztksCsDhcc0u9e8BujQXVUpKZIDTMczCvj3tD2s`. This is what
+/// `ssh-keygen -l` and sshd print by default.
+pub fn sha256_fingerprint(key: &str) -> Result<String, FingerprintError> {
+    Ok(sha256_fingerprint_of_blob(&decode(key)?))
+}
+
+/// Like [`sha256_fingerprint`], for a key blob that is already decoded.
+pub fn sha256_fingerprint_of_blob(blob: &[u8]) -> String {
+    format!(
+        "SHA256:{}",
+        base64::encode_config(Sha256::digest(blob), base64::STANDARD_NO_PAD)
+    )
+}
+
+/// MD5 fingerprint of a base64 encoded public key, in the colon separated
+/// form printed by `ssh-keygen -E md5 -l`.
+pub fn md5_fingerprint(key: &str) -> Result<String, FingerprintError> {
+    let digest = md5::compute(&decode(key)?);
+    Ok(digest
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect::<Vec<_>>()
+        .join(":"))
+}
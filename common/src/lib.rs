@@ -0,0 +1,23 @@
+pub mod access;
+pub mod access_token;
+pub mod certificate;
+pub mod claims;
+pub mod fingerprint;
+pub mod keys;
+pub mod oid;
+pub mod password;
+pub mod public_key;
+pub mod repo_path;
+pub mod secret;
+pub mod totp;
+pub mod username;
+
+pub use access::Access;
+pub use certificate::{Certificate, CertificateAuthorities, CertificateError};
+pub use claims::{Claims, Operation};
+pub use fingerprint::{md5_fingerprint, sha256_fingerprint, FingerprintError};
+pub use keys::{KeyError, SigningKey, VerifyingKeys};
+pub use oid::{Oid, OidError};
+pub use public_key::{PublicKey, PublicKeyError};
+pub use repo_path::{RepoPath, RepoPathError};
+pub use username::{is_valid_username, sanitize_username};
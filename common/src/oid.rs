@@ -0,0 +1,106 @@
+use std::{convert::TryFrom, fmt::Display, str::FromStr};
+
+use serde::{Deserialize, Serialize};
+
+/// The sha256 of an LFS object, exactly 64 lowercase hex characters. Oids end
+/// up in storage keys, so nothing else may pass.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct Oid(String);
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct OidError(String);
+
+impl Display for OidError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "invalid oid '{}': expected 64 lowercase hex characters",
+            self.0
+        )
+    }
+}
+
+impl std::error::Error for OidError {}
+
+impl Oid {
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+}
+
+impl FromStr for Oid {
+    type Err = OidError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if s.len() == 64 && s.bytes().all(|b| matches!(b, b'0'..=b'9' | b'a'..=b'f')) {
+            Ok(Self(String::from(s)))
+        } else {
+            Err(OidError(String::from(s)))
+        }
+    }
+}
+
+impl TryFrom<String> for Oid {
+    type Error = OidError;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        value.parse()
+    }
+}
+
+impl From<Oid> for String {
+    fn from(oid: Oid) -> Self {
+        oid.0
+    }
+}
+
+impl AsRef<str> for Oid {
+    fn as_ref(&self) -> &str {
+        &self.0
+    }
+}
+
+impl Display for Oid {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Oid;
+
+    const OID: &str = "4d7a214614ab2935c943f9e0ff69d22eadbb8f32b1258daaa5e2ca24d17e2393";
+
+    #[test]
+    fn accepts_lowercase_sha256() {
+        let oid: Oid = OID.parse().unwrap();
+        assert_eq!(oid.as_str(), OID);
+    }
+
+    #[test]
+    fn rejects_uppercase_hex() {
+        assert!(OID.to_uppercase().parse::<Oid>().is_err());
+    }
+
+    #[test]
+    fn rejects_wrong_length() {
+        assert!(OID[..63].parse::<Oid>().is_err());
+        assert!(format!("{}0", OID).parse::<Oid>().is_err());
+        assert!("".parse::<Oid>().is_err());
+    }
+
+    #[test]
+    fn rejects_other_characters() {
+        assert!(OID.replacen('4', "g", 1).parse::<Oid>().is_err());
+        assert!(format!("../{}", &OID[3..]).parse::<Oid>().is_err());
+        assert!(format!("{}\n", &OID[1..]).parse::<Oid>().is_err());
+    }
+
+    #[test]
+    fn deserializing_validates() {
+        assert!(serde_json::from_str::<Oid>(&format!("\"{}\"", OID)).is_ok());
+        assert!(serde_json::from_str::<Oid>("\"../../etc/passwd\"").is_err());
+    }
+}
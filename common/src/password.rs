@@ -0,0 +1,63 @@
+use argon2::{
+    password_hash::{rand_core::OsRng, PasswordHash, PasswordHasher, PasswordVerifier, SaltString},
+    Argon2,
+};
+
+pub const MIN_PASSWORD_LENGTH: usize = 8;
+
+/// Hashes a password for storage, in the PHC string format of argon2id.
+pub fn hash_password(password: &str) -> Result<String, argon2::password_hash::Error> {
+    let salt = SaltString::generate(&mut OsRng);
+    Ok(Argon2::default()
+        .hash_password(password.as_bytes(), &salt)?
+        .to_string())
+}
+
+/// Whether `password` matches a hash from [`hash_password`]. Malformed hashes
+/// match nothing.
+pub fn verify_password(password: &str, hash: &str) -> bool {
+    match PasswordHash::new(hash) {
+        Ok(hash) => Argon2::default()
+            .verify_password(password.as_bytes(), &hash)
+            .is_ok(),
+        Err(_) => false,
+    }
+}
+
+/// A hash of a password nobody knows, made with the same parameters as
+/// [`hash_password`].
+const DUMMY_HASH: &str =
+    "$argon2id$v=19$m=4096,t=3,p=1$RV8TGZvGZ6aRv0eJuC6gRA$Zqu8We24Z917igoxK3GRPfHv35j1Ch2VYOwJx5FOpq0";
+
+/// Takes as long as [`verify_password`] against a real hash and fails, so
+/// signing in as an unknown user is no quicker than with a wrong password.
+pub fn verify_dummy_password(password: &str) -> bool {
+    verify_password(password, DUMMY_HASH);
+    false
+}
+
+#[cfg(test)]
+mod tests {
+    use argon2::PasswordHash;
+
+    use super::{hash_password, verify_dummy_password, verify_password, DUMMY_HASH};
+
+    #[test]
+    fn verifies_hashed_passwords() {
+        let hash = hash_password("correct horse").unwrap();
+        assert!(verify_password("correct horse", &hash));
+        assert!(!verify_password("battery staple", &hash));
+        assert!(!verify_password("correct horse", "not a hash"));
+    }
+
+    #[test]
+    fn dummy_hash_costs_as_much_as_real_ones() {
+        let hash = hash_password("correct horse").unwrap();
+        let real = PasswordHash::new(&hash).unwrap();
+        let dummy = PasswordHash::new(DUMMY_HASH).unwrap();
+        assert_eq!(dummy.algorithm, real.algorithm);
+        assert_eq!(dummy.version, real.version);
+        assert_eq!(dummy.params, real.params);
+        assert!(!verify_dummy_password("rustile dummy password"));
+    }
+}
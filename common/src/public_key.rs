@@ -0,0 +1,149 @@
+use std::fmt::Display;
+
+use crate::certificate::is_certificate_type;
+use crate::fingerprint::{md5_fingerprint, sha256_fingerprint, FingerprintError};
+
+/// A public key as written in a `.pub` file or an `authorized_keys` line,
+/// `<type> <base64> [comment]`. Options in front of the type are not
+/// supported.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PublicKey {
+    pub key_type: String,
+    /// Base64 encoded, as in the file.
+    pub key_data: String,
+    pub comment: Option<String>,
+    /// Like `SHA256:...`, as printed by `ssh-keygen -l`.
+    pub sha256_fingerprint: String,
+    /// The colon separated form of `ssh-keygen -E md5 -l`.
+    pub md5_fingerprint: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PublicKeyError {
+    /// Not of the form `<type> <base64> [comment]`.
+    Malformed(String),
+    /// Certificates are trusted through `SSH_USER_CA_KEYS`, not registered.
+    Certificate,
+    Blob(FingerprintError),
+    /// The type in front of the key is not the one encoded in the blob.
+    TypeMismatch { declared: String, blob: String },
+}
+
+impl Display for PublicKeyError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            PublicKeyError::Malformed(line) => write!(
+                f,
+                "expected a public key like 'ssh-ed25519 AAAA... comment', got '{}'",
+                line
+            ),
+            PublicKeyError::Certificate => {
+                write!(f, "certificates cannot be registered as public keys")
+            }
+            PublicKeyError::Blob(err) => write!(f, "{}", err),
+            PublicKeyError::TypeMismatch { declared, blob } => {
+                write!(f, "the key is declared as {} but holds a {} key", declared, blob)
+            }
+        }
+    }
+}
+
+impl std::error::Error for PublicKeyError {}
+
+impl From<FingerprintError> for PublicKeyError {
+    fn from(err: FingerprintError) -> Self {
+        PublicKeyError::Blob(err)
+    }
+}
+
+/// The key type a public key blob starts with, as an SSH string.
+fn blob_key_type(key_data: &str) -> Option<String> {
+    let blob = base64::decode(key_data).ok()?;
+    let len = u32::from_be_bytes(blob.get(..4)?.try_into().ok()?) as usize;
+    let key_type = blob.get(4..4usize.checked_add(len)?)?;
+    String::from_utf8(key_type.to_vec()).ok()
+}
+
+impl PublicKey {
+    pub fn parse(line: &str) -> Result<Self, PublicKeyError> {
+        let mut fields = line.split_whitespace();
+        let (key_type, key_data) = match (fields.next(), fields.next()) {
+            (Some(key_type), Some(key_data)) => (key_type, key_data),
+            _ => return Err(PublicKeyError::Malformed(String::from(line.trim()))),
+        };
+        if is_certificate_type(key_type) {
+            return Err(PublicKeyError::Certificate);
+        }
+        let sha256_fingerprint = sha256_fingerprint(key_data)?;
+        match blob_key_type(key_data) {
+            Some(blob) if blob == key_type => {}
+            blob => {
+                return Err(PublicKeyError::TypeMismatch {
+                    declared: String::from(key_type),
+                    blob: blob.unwrap_or_else(|| String::from("malformed")),
+                })
+            }
+        }
+        let comment = fields.collect::<Vec<_>>().join(" ");
+        Ok(Self {
+            key_type: String::from(key_type),
+            key_data: String::from(key_data),
+            comment: Some(comment).filter(|comment| !comment.is_empty()),
+            sha256_fingerprint,
+            md5_fingerprint: md5_fingerprint(key_data)?,
+        })
+    }
+
+    /// Parses every key of a `.pub` or `authorized_keys` file, skipping
+    /// empty lines and comments.
+    pub fn parse_file(contents: &str) -> Result<Vec<Self>, PublicKeyError> {
+        contents
+            .lines()
+            .map(str::trim)
+            .filter(|line| !line.is_empty() && !line.starts_with('#'))
+            .map(Self::parse)
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ED25519: &str = "AAAAC3NzaC1lZDI1NTE5AAAAIE/hcVWfNPmcwACIWWLf8C0PEZaSYYeVI7dWj8xIJIwX";
+
+    #[test]
+    fn parses_authorized_keys_lines() {
+        let key = PublicKey::parse(&format!("ssh-ed25519 {} alice@laptop", ED25519)).unwrap();
+        assert_eq!(key.key_type, "ssh-ed25519");
+        assert_eq!(key.comment.as_deref(), Some("alice@laptop"));
+        assert_eq!(
+            key.sha256_fingerprint,
+            "SHA256:FMAC7eebBGD/Gw3gL1+joP0s2+TbMR7onM0CmHVADwA"
+        );
+        assert_eq!(
+            key.md5_fingerprint,
+            "ee:16:f5:c2:f6:30:7f:cb:19:55:11:a4:ce:b3:0e:fb"
+        );
+    }
+
+    #[test]
+    fn refuses_keys_of_another_type_than_declared() {
+        assert_eq!(
+            PublicKey::parse(&format!("ssh-rsa {}", ED25519)),
+            Err(PublicKeyError::TypeMismatch {
+                declared: String::from("ssh-rsa"),
+                blob: String::from("ssh-ed25519"),
+            })
+        );
+        // a blob too short to hold its own type
+        assert!(matches!(
+            PublicKey::parse("ssh-ed25519 AAAAC3NzaC1l"),
+            Err(PublicKeyError::TypeMismatch { .. })
+        ));
+        assert!(matches!(
+            PublicKey::parse("ssh-ed25519 !!!"),
+            Err(PublicKeyError::Blob(_))
+        ));
+    }
+}
@@ -0,0 +1,231 @@
+use std::{
+    fmt::Display,
+    path::{Path, PathBuf},
+};
+
+/// The path of a bare repository relative to the served root, like
+/// `group/project.git`.
+///
+/// A `RepoPath` is normalized (no leading, trailing or repeated slashes, no
+/// `.` segments), has no `..` or hidden segments, ends with `.git` and names
+/// a bare repository that really is below the root, even after resolving
+/// symlinks.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct RepoPath(String);
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RepoPathError {
+    /// The path is malformed or tries to leave the root.
+    Invalid(String, &'static str),
+    /// The path is well formed, but there is no bare repository there.
+    NotFound(String),
+}
+
+impl Display for RepoPathError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            RepoPathError::Invalid(path, reason) => {
+                write!(f, "invalid repository path '{}': {}", path, reason)
+            }
+            RepoPathError::NotFound(path) => write!(f, "no such repository: {}", path),
+        }
+    }
+}
+
+impl std::error::Error for RepoPathError {}
+
+impl RepoPath {
+    /// Normalizes `raw` without looking at the filesystem.
+    pub fn normalize(raw: &str) -> Result<String, RepoPathError> {
+        let invalid = |reason| RepoPathError::Invalid(String::from(raw), reason);
+
+        if raw.chars().any(|c| c.is_control() || c == '\\') {
+            return Err(invalid("contains control characters or backslashes"));
+        }
+
+        let mut segments = Vec::new();
+        for segment in raw.split('/') {
+            match segment {
+                "" | "." => continue,
+                ".." => return Err(invalid("contains '..'")),
+                s if s.starts_with('.') => return Err(invalid("contains hidden segments")),
+                s => segments.push(s),
+            }
+        }
+
+        let normalized = segments.join("/");
+        if normalized.is_empty() {
+            return Err(invalid("is empty"));
+        }
+        if !normalized.ends_with(".git") {
+            return Err(invalid("does not end with .git"));
+        }
+        Ok(normalized)
+    }
+
+    /// Validates `raw` and checks that it names a bare repository below
+    /// `root`.
+    pub fn parse(raw: &str, root: &Path) -> Result<Self, RepoPathError> {
+        let normalized = Self::normalize(raw)?;
+        let not_found = || RepoPathError::NotFound(normalized.clone());
+
+        let full_path = root.join(&normalized);
+        let is_bare_repo = full_path.join("HEAD").is_file()
+            && full_path.join("objects").is_dir()
+            && full_path.join("refs").is_dir();
+        if !is_bare_repo {
+            return Err(not_found());
+        }
+
+        let canonical_root = root.canonicalize().map_err(|_| not_found())?;
+        let canonical_path = full_path.canonicalize().map_err(|_| not_found())?;
+        if !canonical_path.starts_with(&canonical_root) {
+            return Err(RepoPathError::Invalid(
+                String::from(raw),
+                "resolves to outside of the served root",
+            ));
+        }
+
+        Ok(Self(normalized))
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+
+    /// Where the repository lives on disk.
+    pub fn to_path(&self, root: &Path) -> PathBuf {
+        root.join(&self.0)
+    }
+}
+
+impl AsRef<str> for RepoPath {
+    fn as_ref(&self) -> &str {
+        &self.0
+    }
+}
+
+impl Display for RepoPath {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use super::{RepoPath, RepoPathError};
+
+    fn init_bare(path: &Path) {
+        std::fs::create_dir_all(path.join("objects")).unwrap();
+        std::fs::create_dir_all(path.join("refs")).unwrap();
+        std::fs::write(path.join("HEAD"), "ref: refs/heads/master\n").unwrap();
+    }
+
+    fn is_invalid(raw: &str) -> bool {
+        matches!(RepoPath::normalize(raw), Err(RepoPathError::Invalid(..)))
+    }
+
+    #[test]
+    fn normalizes_slashes_and_dots() {
+        assert_eq!(
+            RepoPath::normalize("/group//./project.git/").unwrap(),
+            "group/project.git"
+        );
+    }
+
+    #[test]
+    fn rejects_parent_segments() {
+        assert!(is_invalid("../project.git"));
+        assert!(is_invalid("group/../../project.git"));
+    }
+
+    #[test]
+    fn rejects_hidden_segments() {
+        assert!(is_invalid(".ssh/project.git"));
+        assert!(is_invalid("group/.project.git"));
+    }
+
+    #[test]
+    fn rejects_backslashes_and_control_characters() {
+        assert!(is_invalid("group\\..\\project.git"));
+        assert!(is_invalid("group/project.git\n"));
+        assert!(is_invalid("group/\0project.git"));
+    }
+
+    #[test]
+    fn rejects_missing_git_suffix() {
+        assert!(is_invalid("group/project"));
+        assert!(is_invalid("/"));
+        assert!(is_invalid(""));
+    }
+
+    #[test]
+    fn parses_bare_repositories() {
+        let root = tempfile::tempdir().unwrap();
+        init_bare(&root.path().join("group/project.git"));
+
+        let repo_path = RepoPath::parse("group/project.git", root.path()).unwrap();
+        assert_eq!(repo_path.as_str(), "group/project.git");
+        assert_eq!(
+            repo_path.to_path(root.path()),
+            root.path().join("group/project.git")
+        );
+    }
+
+    #[test]
+    fn rejects_non_bare_directories() {
+        let root = tempfile::tempdir().unwrap();
+        std::fs::create_dir_all(root.path().join("project.git/.git")).unwrap();
+        std::fs::create_dir_all(root.path().join("missing.git")).unwrap();
+
+        assert_eq!(
+            RepoPath::parse("project.git", root.path()),
+            Err(RepoPathError::NotFound(String::from("project.git")))
+        );
+        assert_eq!(
+            RepoPath::parse("missing.git", root.path()),
+            Err(RepoPathError::NotFound(String::from("missing.git")))
+        );
+        assert_eq!(
+            RepoPath::parse("absent.git", root.path()),
+            Err(RepoPathError::NotFound(String::from("absent.git")))
+        );
+    }
+
+    #[test]
+    fn rejects_symlinks_escaping_the_root() {
+        let outside = tempfile::tempdir().unwrap();
+        init_bare(&outside.path().join("secret.git"));
+        let root = tempfile::tempdir().unwrap();
+        std::os::unix::fs::symlink(
+            outside.path().join("secret.git"),
+            root.path().join("secret.git"),
+        )
+        .unwrap();
+        std::os::unix::fs::symlink(outside.path(), root.path().join("group")).unwrap();
+
+        assert!(matches!(
+            RepoPath::parse("secret.git", root.path()),
+            Err(RepoPathError::Invalid(..))
+        ));
+        assert!(matches!(
+            RepoPath::parse("group/secret.git", root.path()),
+            Err(RepoPathError::Invalid(..))
+        ));
+    }
+
+    #[test]
+    fn accepts_symlinks_within_the_root() {
+        let root = tempfile::tempdir().unwrap();
+        init_bare(&root.path().join("project.git"));
+        std::os::unix::fs::symlink(
+            root.path().join("project.git"),
+            root.path().join("alias.git"),
+        )
+        .unwrap();
+
+        assert!(RepoPath::parse("alias.git", root.path()).is_ok());
+    }
+}
@@ -0,0 +1,42 @@
+use rand::{distributions::Alphanumeric, Rng};
+use sha2::{Digest, Sha256};
+use subtle::ConstantTimeEq;
+
+/// A random alphanumeric string from the operating system's generator, for
+/// tokens, session ids and the like.
+pub fn random(length: usize) -> String {
+    rand::rngs::OsRng
+        .sample_iter(&Alphanumeric)
+        .take(length)
+        .map(char::from)
+        .collect()
+}
+
+/// The sha256 of a secret, in hex. Secrets from [`random`] are long enough
+/// that a plain hash suffices to store them, and lookups stay a single
+/// indexed query.
+pub fn hash(secret: &str) -> String {
+    Sha256::digest(secret.as_bytes())
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect()
+}
+
+/// Compares secrets like CSRF tokens in time that depends on their lengths
+/// only, so timing tells nothing about how much of a guess was right.
+pub fn constant_time_eq(a: &str, b: &str) -> bool {
+    a.as_bytes().ct_eq(b.as_bytes()).into()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::constant_time_eq;
+
+    #[test]
+    fn compares_whole_secrets() {
+        assert!(constant_time_eq("csrf-token", "csrf-token"));
+        assert!(!constant_time_eq("csrf-token", "csrf-tokem"));
+        assert!(!constant_time_eq("csrf-token", "csrf"));
+        assert!(!constant_time_eq("csrf-token", ""));
+    }
+}
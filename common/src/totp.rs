@@ -0,0 +1,250 @@
+use hmac::{Hmac, Mac, NewMac};
+use rand::{rngs::OsRng, RngCore};
+use sha1::Sha1;
+
+/// RFC 6238 defaults, the only parameters authenticator apps reliably support.
+pub const TOTP_STEP_SECONDS: u64 = 30;
+pub const TOTP_DIGITS: u32 = 6;
+/// Codes of the previous and the next step are accepted too, for clocks that
+/// are a little off.
+const TOTP_WINDOW: u64 = 1;
+
+pub const RECOVERY_CODE_COUNT: usize = 10;
+
+const BASE32_ALPHABET: &[u8; 32] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZ234567";
+
+/// Unpadded RFC 4648 base32, the encoding of secrets in `otpauth://` urls.
+pub fn base32_encode(data: &[u8]) -> String {
+    let mut encoded = String::with_capacity((data.len() * 8).div_ceil(5));
+    let mut buffer = 0u16;
+    let mut bits = 0;
+    for byte in data {
+        buffer = (buffer << 8) | u16::from(*byte);
+        bits += 8;
+        while bits >= 5 {
+            bits -= 5;
+            encoded.push(BASE32_ALPHABET[usize::from((buffer >> bits) & 31)] as char);
+        }
+    }
+    if bits > 0 {
+        encoded.push(BASE32_ALPHABET[usize::from((buffer << (5 - bits)) & 31)] as char);
+    }
+    encoded
+}
+
+pub fn base32_decode(encoded: &str) -> Option<Vec<u8>> {
+    let mut decoded = Vec::with_capacity(encoded.len() * 5 / 8);
+    let mut buffer = 0u16;
+    let mut bits = 0;
+    for c in encoded.trim_end_matches('=').bytes() {
+        let value = BASE32_ALPHABET
+            .iter()
+            .position(|a| *a == c.to_ascii_uppercase())?;
+        buffer = (buffer << 5) | value as u16;
+        bits += 5;
+        if bits >= 8 {
+            bits -= 8;
+            decoded.push((buffer >> bits) as u8);
+        }
+    }
+    Some(decoded)
+}
+
+/// A new 160 bit secret, base32 encoded.
+pub fn generate_secret() -> String {
+    let mut secret = [0u8; 20];
+    OsRng.fill_bytes(&mut secret);
+    base32_encode(&secret)
+}
+
+fn percent_encode(s: &str) -> String {
+    s.bytes()
+        .map(|b| match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' => {
+                String::from(b as char)
+            }
+            _ => format!("%{:02X}", b),
+        })
+        .collect()
+}
+
+/// The url authenticator apps scan from the QR code.
+pub fn otpauth_url(issuer: &str, account: &str, secret: &str) -> String {
+    format!(
+        "otpauth://totp/{}:{}?secret={}&issuer={}&algorithm=SHA1&digits={}&period={}",
+        percent_encode(issuer),
+        percent_encode(account),
+        secret,
+        percent_encode(issuer),
+        TOTP_DIGITS,
+        TOTP_STEP_SECONDS
+    )
+}
+
+/// The time step `unix_time` falls into.
+pub fn step(unix_time: u64) -> u64 {
+    unix_time / TOTP_STEP_SECONDS
+}
+
+fn hotp(key: &[u8], counter: u64) -> u32 {
+    let mut mac = Hmac::<Sha1>::new_from_slice(key).expect("hmac takes keys of any length");
+    mac.update(&counter.to_be_bytes());
+    let hash = mac.finalize().into_bytes();
+    let offset = usize::from(hash[hash.len() - 1] & 0xf);
+    let code = u32::from_be_bytes([
+        hash[offset] & 0x7f,
+        hash[offset + 1],
+        hash[offset + 2],
+        hash[offset + 3],
+    ]);
+    code % 10u32.pow(TOTP_DIGITS)
+}
+
+/// The code an authenticator app with `secret` shows at `unix_time`, `None`
+/// if the secret is no valid base32.
+pub fn generate_code(secret: &str, unix_time: u64) -> Option<String> {
+    let key = base32_decode(secret)?;
+    Some(format!(
+        "{:0width$}",
+        hotp(&key, step(unix_time)),
+        width = TOTP_DIGITS as usize
+    ))
+}
+
+/// Checks `code` against the steps around `unix_time`, returning the step it
+/// belongs to. Callers should refuse steps that were used before, so a code
+/// cannot be replayed.
+pub fn verify(secret: &str, code: &str, unix_time: u64) -> Option<u64> {
+    if !is_totp_code(code) {
+        return None;
+    }
+    let code: u32 = code.trim().parse().ok()?;
+    let key = base32_decode(secret)?;
+
+    let now = step(unix_time);
+    (now.saturating_sub(TOTP_WINDOW)..=now + TOTP_WINDOW).find(|step| hotp(&key, *step) == code)
+}
+
+/// Whether `code` looks like a TOTP code rather than a recovery code.
+pub fn is_totp_code(code: &str) -> bool {
+    let code = code.trim();
+    code.len() == TOTP_DIGITS as usize && code.bytes().all(|b| b.is_ascii_digit())
+}
+
+/// A single use code for when the authenticator is lost, like `k3x9q-wm2hd`.
+pub fn generate_recovery_code() -> String {
+    let code = crate::secret::random(10).to_ascii_lowercase();
+    format!("{}-{}", &code[..5], &code[5..])
+}
+
+/// Recovery codes are compared without the dash and case insensitively,
+/// and stored by the sha256 of that form.
+pub fn hash_recovery_code(code: &str) -> String {
+    let normalized: String = code
+        .trim()
+        .chars()
+        .filter(|c| *c != '-')
+        .map(|c| c.to_ascii_lowercase())
+        .collect();
+    crate::secret::hash(&normalized)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The SHA-1 secret of the test vectors in RFC 6238, appendix B.
+    const RFC_SECRET: &[u8] = b"12345678901234567890";
+
+    #[test]
+    fn matches_the_rfc_6238_test_vectors() {
+        // the 8 digit codes of the RFC, of which we show the last 6
+        let vectors = [
+            (59, "94287082"),
+            (1111111109, "07081804"),
+            (1111111111, "14050471"),
+            (1234567890, "89005924"),
+            (2000000000, "69279037"),
+            (20000000000, "65353130"),
+        ];
+        let secret = base32_encode(RFC_SECRET);
+        for (unix_time, expected) in vectors {
+            let code = generate_code(&secret, unix_time).unwrap();
+            assert_eq!(code, expected[2..], "at {}", unix_time);
+            assert_eq!(verify(&secret, &code, unix_time), Some(step(unix_time)));
+        }
+    }
+
+    #[test]
+    fn base32_round_trips() {
+        assert_eq!(base32_encode(b""), "");
+        assert_eq!(base32_encode(b"f"), "MY");
+        assert_eq!(base32_encode(b"foobar"), "MZXW6YTBOI");
+        assert_eq!(
+            base32_encode(RFC_SECRET),
+            "GEZDGNBVGY3TQOJQGEZDGNBVGY3TQOJQ"
+        );
+        assert_eq!(base32_decode("MZXW6YTBOI======").unwrap(), b"foobar");
+        assert_eq!(base32_decode("mzxw6ytboi").unwrap(), b"foobar");
+        assert_eq!(base32_decode("MZXW1"), None);
+
+        for len in 0..=20 {
+            let data: Vec<u8> = (0..len).map(|i| (i * 37 + 11) as u8).collect();
+            assert_eq!(base32_decode(&base32_encode(&data)).unwrap(), data);
+        }
+        let secret = generate_secret();
+        assert_eq!(secret.len(), 32);
+        assert_eq!(base32_decode(&secret).unwrap().len(), 20);
+    }
+
+    #[test]
+    fn accepts_one_step_of_clock_skew() {
+        let secret = base32_encode(RFC_SECRET);
+        let at = 1111111111;
+        let code = generate_code(&secret, at).unwrap();
+        let first = step(at) * TOTP_STEP_SECONDS;
+        let last = first + TOTP_STEP_SECONDS - 1;
+
+        // from the first second of the previous step to the last of the next
+        for unix_time in [
+            first - TOTP_STEP_SECONDS,
+            first,
+            last,
+            last + TOTP_STEP_SECONDS,
+        ] {
+            assert_eq!(
+                verify(&secret, &code, unix_time),
+                Some(step(at)),
+                "at {}",
+                unix_time
+            );
+        }
+        for unix_time in [first - TOTP_STEP_SECONDS - 1, last + TOTP_STEP_SECONDS + 1] {
+            assert_eq!(verify(&secret, &code, unix_time), None, "at {}", unix_time);
+        }
+    }
+
+    #[test]
+    fn refuses_malformed_codes() {
+        let secret = base32_encode(RFC_SECRET);
+        assert_eq!(verify(&secret, "28708", 59), None);
+        assert_eq!(verify(&secret, "2870822", 59), None);
+        assert_eq!(verify(&secret, "28708a", 59), None);
+        assert_eq!(verify("not base32!", "287082", 59), None);
+        assert_eq!(verify(&secret, " 287082 ", 59), Some(1));
+    }
+
+    #[test]
+    fn recovery_codes_compare_loosely() {
+        let code = generate_recovery_code();
+        assert!(!is_totp_code(&code));
+        assert_eq!(
+            hash_recovery_code("K3X9Q-WM2HD"),
+            hash_recovery_code(" k3x9qwm2hd ")
+        );
+        assert_ne!(
+            hash_recovery_code("k3x9q-wm2hd"),
+            hash_recovery_code("k3x9q-wm2he")
+        );
+    }
+}
@@ -0,0 +1,57 @@
+/// Usernames end up in `authorized_keys` principals and in urls, so they
+/// contain no whitespace, control characters, quotes, commas or slashes.
+fn allowed(c: char) -> bool {
+    !(c.is_whitespace() || c.is_control() || matches!(c, '"' | ',' | '/'))
+}
+
+pub fn is_valid_username(username: &str) -> bool {
+    !username.is_empty() && username.chars().all(allowed)
+}
+
+/// A valid username made from a name picked elsewhere, like by an identity
+/// provider: what a username may not contain becomes `-`.
+pub fn sanitize_username(name: &str) -> String {
+    let username: String = name
+        .trim()
+        .chars()
+        .map(|c| if allowed(c) { c } else { '-' })
+        .collect();
+    match username.is_empty() {
+        true => String::from("user"),
+        false => username,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{is_valid_username, sanitize_username};
+
+    #[test]
+    fn checks_usernames() {
+        for username in ["alice", "alice.smith", "alice@example.com", "ÄLICE_1"] {
+            assert!(is_valid_username(username), "{}", username);
+        }
+        for username in [
+            "",
+            "alice smith",
+            "alice\n",
+            "a\"b",
+            "a,b",
+            "grp/alice",
+            "\u{0}",
+        ] {
+            assert!(!is_valid_username(username), "{:?}", username);
+        }
+    }
+
+    #[test]
+    fn sanitizes_foreign_names() {
+        assert_eq!(sanitize_username("alice"), "alice");
+        assert_eq!(sanitize_username(" Alice Smith "), "Alice-Smith");
+        assert_eq!(sanitize_username("grp/alice,\"x\""), "grp-alice--x-");
+        assert_eq!(sanitize_username("  "), "user");
+        for name in ["Alice Smith", "\t", "a\u{7f}b"] {
+            assert!(is_valid_username(&sanitize_username(name)));
+        }
+    }
+}
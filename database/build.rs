@@ -0,0 +1,41 @@
+//! Lists the migrations of the enabled backend for `src/migrations.rs`, so
+//! they are embedded in the binaries.
+
+use std::{env, fs, path::Path};
+
+fn main() {
+    let backend = if env::var_os("CARGO_FEATURE_POSTGRES").is_some() {
+        "postgres"
+    } else if env::var_os("CARGO_FEATURE_SQLITE").is_some() {
+        "sqlite"
+    } else {
+        "mysql"
+    };
+    let dir = Path::new(&env::var("CARGO_MANIFEST_DIR").unwrap())
+        .join("migrations")
+        .join(backend);
+    println!("cargo:rerun-if-changed={}", dir.display());
+
+    let mut names = fs::read_dir(&dir)
+        .unwrap_or_else(|err| panic!("failed to read {}: {}", dir.display(), err))
+        .map(|entry| entry.unwrap().file_name().into_string().unwrap())
+        .filter(|name| !name.starts_with('.'))
+        .collect::<Vec<_>>();
+    names.sort();
+
+    // diesel records a migration by the digits of its timestamp
+    let mut list = String::from("&[\n");
+    for name in names {
+        let version = name.split('_').next().unwrap().replace('-', "");
+        list.push_str(&format!(
+            "    EmbeddedMigration {{ version: {:?}, name: {:?}, up_sql: include_str!({:?}) }},\n",
+            version,
+            name,
+            dir.join(&name).join("up.sql"),
+        ));
+    }
+    list.push(']');
+
+    let out = Path::new(&env::var("OUT_DIR").unwrap()).join("migrations.rs");
+    fs::write(&out, list).unwrap();
+}
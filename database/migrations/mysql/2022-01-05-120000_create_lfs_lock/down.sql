@@ -0,0 +1 @@
+DROP TABLE lfs_lock;
\ No newline at end of file
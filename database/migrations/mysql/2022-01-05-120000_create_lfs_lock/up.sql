@@ -0,0 +1,13 @@
+CREATE TABLE lfs_lock (
+    `id`        CHAR(36),
+    `repo`      VARCHAR(255) NOT NULL,
+    `path`      VARCHAR(512) NOT NULL,
+    `ref_name`  VARCHAR(255),
+    `owner`     CHAR(36) NOT NULL,
+
+    locked_at TIMESTAMP DEFAULT CURRENT_TIMESTAMP,
+
+    PRIMARY KEY(id),
+    UNIQUE(repo, path),
+    FOREIGN KEY(owner) REFERENCES user(uuid)
+);
@@ -0,0 +1,5 @@
+ALTER TABLE public_key
+    DROP INDEX sha256_fingerprint,
+    DROP COLUMN sha256_fingerprint,
+    DROP COLUMN key_data,
+    DROP COLUMN key_type;
@@ -0,0 +1,5 @@
+DROP TABLE repository_permission;
+DROP TABLE repository;
+DROP TABLE group_member;
+DROP TABLE `group`;
+ALTER TABLE user DROP COLUMN `is_admin`;
@@ -0,0 +1,2 @@
+DROP TABLE session;
+ALTER TABLE user DROP COLUMN `password_hash`;
@@ -0,0 +1,6 @@
+ALTER TABLE session
+    DROP COLUMN `second_factor_pending`,
+    DROP COLUMN `second_factor_failures`,
+    DROP COLUMN `second_factor_at`;
+DROP TABLE recovery_code;
+DROP TABLE user_totp;
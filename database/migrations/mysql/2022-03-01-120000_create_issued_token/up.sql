@@ -0,0 +1,19 @@
+-- tokens minted by git-lfs-authenticate, by their jti, and the ssh key that
+-- asked for them. git-server refuses revoked tokens until they expire.
+CREATE TABLE issued_token (
+    `jti`             CHAR(36),
+    `user`            CHAR(36),
+    `key_fingerprint` VARCHAR(64) NOT NULL,
+    `is_deploy_key`   BOOLEAN NOT NULL DEFAULT FALSE,
+    `repository`      VARCHAR(255) NOT NULL,
+    `operation`       VARCHAR(16) NOT NULL,
+
+    issued_at  TIMESTAMP DEFAULT CURRENT_TIMESTAMP,
+    expires_at DATETIME NOT NULL,
+    revoked_at DATETIME,
+
+    PRIMARY KEY(jti),
+    INDEX(key_fingerprint),
+    INDEX(expires_at),
+    FOREIGN KEY(`user`) REFERENCES user(uuid) ON DELETE SET NULL
+);
@@ -0,0 +1,28 @@
+-- what happened, who did it, from where and how it went. rows are only ever
+-- inserted, the triggers refuse changing or removing them. actors are kept by
+-- name rather than by reference, so the trail outlives users and keys.
+CREATE TABLE audit_log (
+    `id`         BIGINT AUTO_INCREMENT,
+    `action`     VARCHAR(64) NOT NULL,
+    `actor`      VARCHAR(255) NOT NULL,
+    `actor_user` CHAR(36),
+    `repository` VARCHAR(255),
+    `ip`         VARCHAR(64),
+    `outcome`    VARCHAR(16) NOT NULL,
+    `detail`     TEXT,
+
+    created_at TIMESTAMP DEFAULT CURRENT_TIMESTAMP,
+
+    PRIMARY KEY(id),
+    INDEX(action),
+    INDEX(actor),
+    INDEX(repository),
+    INDEX(created_at),
+    CHECK (outcome IN ('success', 'failure', 'denied'))
+);
+
+CREATE TRIGGER audit_log_no_update BEFORE UPDATE ON audit_log FOR EACH ROW
+    SIGNAL SQLSTATE '45000' SET MESSAGE_TEXT = 'audit_log is append-only';
+
+CREATE TRIGGER audit_log_no_delete BEFORE DELETE ON audit_log FOR EACH ROW
+    SIGNAL SQLSTATE '45000' SET MESSAGE_TEXT = 'audit_log is append-only';
@@ -0,0 +1,18 @@
+-- OpenSSH user certificates that git-authorized-keys accepted, so the owner
+-- of the certificate can be found by its fingerprint until it expires.
+CREATE TABLE ssh_certificate (
+    `fingerprint`    VARCHAR(64),
+    `user`           CHAR(36) NOT NULL,
+    `principal`      VARCHAR(255) NOT NULL,
+    `ca_fingerprint` VARCHAR(64) NOT NULL,
+    `key_id`         VARCHAR(255) NOT NULL,
+    `serial`         VARCHAR(20) NOT NULL,
+    `valid_after`    DATETIME NOT NULL,
+    `valid_before`   DATETIME,
+
+    created_at TIMESTAMP DEFAULT CURRENT_TIMESTAMP,
+
+    PRIMARY KEY(fingerprint),
+    FOREIGN KEY(user) REFERENCES user(uuid) ON DELETE CASCADE,
+    INDEX(valid_before)
+);
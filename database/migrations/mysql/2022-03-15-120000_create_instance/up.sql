@@ -0,0 +1,14 @@
+-- Set up by `git-server bootstrap`, which creates the first admin. There is
+-- at most one row, with the id 1.
+CREATE TABLE instance (
+    `id`             INTEGER NOT NULL,
+    `initialized_by` CHAR(36),
+
+    initialized_at TIMESTAMP DEFAULT CURRENT_TIMESTAMP,
+
+    PRIMARY KEY(id),
+    FOREIGN KEY(initialized_by) REFERENCES user(uuid) ON DELETE SET NULL
+);
+
+-- installations from before bootstrap already have their users
+INSERT INTO instance (id) SELECT 1 FROM user LIMIT 1;
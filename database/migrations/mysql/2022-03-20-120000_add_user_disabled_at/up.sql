@@ -0,0 +1,4 @@
+-- disabled users keep their data, but cannot sign in or use their keys and
+-- tokens until they are enabled again
+ALTER TABLE user
+    ADD COLUMN `disabled_at` TIMESTAMP NULL;
@@ -0,0 +1,3 @@
+ALTER TABLE user
+    DROP INDEX deleted_at,
+    MODIFY COLUMN `updated_at` TIMESTAMP DEFAULT CURRENT_TIMESTAMP;
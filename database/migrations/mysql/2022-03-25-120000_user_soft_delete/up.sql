@@ -0,0 +1,5 @@
+-- updated_at follows every change to the row, and deleted users waiting to be
+-- purged are found by when they were deleted
+ALTER TABLE user
+    MODIFY COLUMN `updated_at` TIMESTAMP DEFAULT CURRENT_TIMESTAMP ON UPDATE CURRENT_TIMESTAMP,
+    ADD INDEX(deleted_at);
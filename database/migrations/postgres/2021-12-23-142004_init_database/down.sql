@@ -0,0 +1,2 @@
+DROP TABLE public_key;
+DROP TABLE "user";
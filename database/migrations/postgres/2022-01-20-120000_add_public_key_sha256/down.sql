@@ -0,0 +1,5 @@
+ALTER TABLE public_key
+    DROP CONSTRAINT public_key_sha256_fingerprint_key,
+    DROP COLUMN sha256_fingerprint,
+    DROP COLUMN key_data,
+    DROP COLUMN key_type;
@@ -0,0 +1,19 @@
+-- personal access tokens. only the sha256 of a token is stored, the token
+-- itself is shown to its owner once when it is created.
+CREATE TABLE access_token (
+    id         CHAR(36),
+    "user"     CHAR(36) NOT NULL,
+    name       VARCHAR(255) NOT NULL,
+    token_hash CHAR(64) NOT NULL,
+    scope      VARCHAR(16) NOT NULL,
+    repository VARCHAR(255),
+
+    created_at TIMESTAMP DEFAULT CURRENT_TIMESTAMP,
+    expires_at TIMESTAMP NOT NULL,
+
+    PRIMARY KEY(id),
+    UNIQUE(token_hash),
+    UNIQUE("user", name),
+    FOREIGN KEY("user") REFERENCES "user"(uuid) ON DELETE CASCADE,
+    CHECK (scope IN ('read', 'write', 'admin'))
+);
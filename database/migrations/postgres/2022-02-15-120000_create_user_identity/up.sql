@@ -0,0 +1,12 @@
+-- accounts at the OpenID Connect provider, by the issuer and subject of their
+-- ID tokens, linked to the users they sign in as.
+CREATE TABLE user_identity (
+    issuer  VARCHAR(255),
+    subject VARCHAR(255),
+    "user"  CHAR(36) NOT NULL,
+
+    created_at TIMESTAMP DEFAULT CURRENT_TIMESTAMP,
+
+    PRIMARY KEY(issuer, subject),
+    FOREIGN KEY("user") REFERENCES "user"(uuid) ON DELETE CASCADE
+);
@@ -0,0 +1,32 @@
+-- TOTP second factor. confirmed_at stays NULL until the user entered a first
+-- code, last_step is the time step of the last code used, so it cannot be
+-- used again.
+CREATE TABLE user_totp (
+    "user"    CHAR(36),
+    secret    VARCHAR(64) NOT NULL,
+    last_step BIGINT,
+
+    created_at   TIMESTAMP DEFAULT CURRENT_TIMESTAMP,
+    confirmed_at TIMESTAMP,
+
+    PRIMARY KEY("user"),
+    FOREIGN KEY("user") REFERENCES "user"(uuid) ON DELETE CASCADE
+);
+
+-- single use codes for when the authenticator is lost, stored as sha256.
+CREATE TABLE recovery_code (
+    "user"    CHAR(36),
+    code_hash CHAR(64),
+
+    created_at TIMESTAMP DEFAULT CURRENT_TIMESTAMP,
+
+    PRIMARY KEY("user", code_hash),
+    FOREIGN KEY("user") REFERENCES "user"(uuid) ON DELETE CASCADE
+);
+
+-- sessions of users with a second factor start out pending, and only count
+-- as signed in once a code was entered.
+ALTER TABLE session
+    ADD COLUMN second_factor_pending  BOOLEAN NOT NULL DEFAULT FALSE,
+    ADD COLUMN second_factor_failures INTEGER NOT NULL DEFAULT 0,
+    ADD COLUMN second_factor_at       TIMESTAMP;
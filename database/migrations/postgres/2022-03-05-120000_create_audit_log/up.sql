@@ -0,0 +1,35 @@
+-- what happened, who did it, from where and how it went. rows are only ever
+-- inserted, the triggers refuse changing or removing them. actors are kept by
+-- name rather than by reference, so the trail outlives users and keys.
+CREATE TABLE audit_log (
+    id         BIGSERIAL,
+    action     VARCHAR(64) NOT NULL,
+    actor      VARCHAR(255) NOT NULL,
+    actor_user CHAR(36),
+    repository VARCHAR(255),
+    ip         VARCHAR(64),
+    outcome    VARCHAR(16) NOT NULL,
+    detail     TEXT,
+
+    created_at TIMESTAMP DEFAULT CURRENT_TIMESTAMP,
+
+    PRIMARY KEY(id),
+    CHECK (outcome IN ('success', 'failure', 'denied'))
+);
+
+CREATE INDEX audit_log_action ON audit_log(action);
+CREATE INDEX audit_log_actor ON audit_log(actor);
+CREATE INDEX audit_log_repository ON audit_log(repository);
+CREATE INDEX audit_log_created_at ON audit_log(created_at);
+
+CREATE FUNCTION audit_log_append_only() RETURNS trigger AS $$
+BEGIN
+    RAISE EXCEPTION 'audit_log is append-only';
+END;
+$$ LANGUAGE plpgsql;
+
+CREATE TRIGGER audit_log_no_update BEFORE UPDATE ON audit_log FOR EACH ROW
+    EXECUTE PROCEDURE audit_log_append_only();
+
+CREATE TRIGGER audit_log_no_delete BEFORE DELETE ON audit_log FOR EACH ROW
+    EXECUTE PROCEDURE audit_log_append_only();
@@ -0,0 +1,3 @@
+DROP INDEX user_deleted_at;
+DROP TRIGGER user_updated_at ON "user";
+DROP FUNCTION user_touch_updated_at();
@@ -0,0 +1,13 @@
+-- updated_at follows every change to the row, and deleted users waiting to be
+-- purged are found by when they were deleted
+CREATE FUNCTION user_touch_updated_at() RETURNS trigger AS $$
+BEGIN
+    NEW.updated_at = CURRENT_TIMESTAMP;
+    RETURN NEW;
+END;
+$$ LANGUAGE plpgsql;
+
+CREATE TRIGGER user_updated_at BEFORE UPDATE ON "user" FOR EACH ROW
+    EXECUTE PROCEDURE user_touch_updated_at();
+
+CREATE INDEX user_deleted_at ON "user"(deleted_at);
@@ -0,0 +1,18 @@
+CREATE TABLE "user" (
+    uuid CHAR(36),
+    username TEXT NOT NULL,
+
+    created_at TIMESTAMP DEFAULT CURRENT_TIMESTAMP,
+    updated_at TIMESTAMP DEFAULT CURRENT_TIMESTAMP,
+    deleted_at TIMESTAMP,
+
+    PRIMARY KEY(uuid)
+);
+
+CREATE TABLE public_key (
+    fingerprint CHAR(47),
+    "user"      CHAR(36) NOT NULL,
+
+    PRIMARY KEY(fingerprint),
+    FOREIGN KEY("user") REFERENCES "user"(uuid)
+);
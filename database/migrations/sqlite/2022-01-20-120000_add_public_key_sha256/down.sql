@@ -0,0 +1,4 @@
+DROP INDEX public_key_sha256_fingerprint;
+ALTER TABLE public_key DROP COLUMN sha256_fingerprint;
+ALTER TABLE public_key DROP COLUMN key_data;
+ALTER TABLE public_key DROP COLUMN key_type;
@@ -0,0 +1,7 @@
+-- keys known by their md5 fingerprint only get their blob and sha256
+-- fingerprint filled in by git-authorized-keys the next time they are used,
+-- or by rustile-admin key backfill.
+ALTER TABLE public_key ADD COLUMN key_type VARCHAR(64);
+ALTER TABLE public_key ADD COLUMN key_data TEXT;
+ALTER TABLE public_key ADD COLUMN sha256_fingerprint CHAR(50);
+CREATE UNIQUE INDEX public_key_sha256_fingerprint ON public_key(sha256_fingerprint);
@@ -0,0 +1,48 @@
+ALTER TABLE "user" ADD COLUMN is_admin BOOLEAN NOT NULL DEFAULT FALSE;
+
+CREATE TABLE "group" (
+    uuid CHAR(36),
+    name VARCHAR(255) NOT NULL,
+
+    created_at TIMESTAMP DEFAULT CURRENT_TIMESTAMP,
+
+    PRIMARY KEY(uuid),
+    UNIQUE(name)
+);
+
+CREATE TABLE group_member (
+    "group" CHAR(36),
+    "user"  CHAR(36),
+
+    PRIMARY KEY("group", "user"),
+    FOREIGN KEY("group") REFERENCES "group"(uuid) ON DELETE CASCADE,
+    FOREIGN KEY("user") REFERENCES "user"(uuid) ON DELETE CASCADE
+);
+
+-- repositories are known by their normalized path below the served root.
+-- bare repositories on disk without a row here are only visible to admins.
+CREATE TABLE repository (
+    path      VARCHAR(255),
+    is_public BOOLEAN NOT NULL DEFAULT FALSE,
+
+    created_at TIMESTAMP DEFAULT CURRENT_TIMESTAMP,
+
+    PRIMARY KEY(path)
+);
+
+-- each row grants read, write or admin to either a user or a group
+CREATE TABLE repository_permission (
+    id         INTEGER PRIMARY KEY AUTOINCREMENT,
+    repository VARCHAR(255) NOT NULL,
+    "user"     CHAR(36),
+    "group"    CHAR(36),
+    access     VARCHAR(16) NOT NULL,
+
+    UNIQUE(repository, "user"),
+    UNIQUE(repository, "group"),
+    FOREIGN KEY(repository) REFERENCES repository(path) ON DELETE CASCADE ON UPDATE CASCADE,
+    FOREIGN KEY("user") REFERENCES "user"(uuid) ON DELETE CASCADE,
+    FOREIGN KEY("group") REFERENCES "group"(uuid) ON DELETE CASCADE,
+    CHECK (("user" IS NULL) <> ("group" IS NULL)),
+    CHECK (access IN ('read', 'write', 'admin'))
+);
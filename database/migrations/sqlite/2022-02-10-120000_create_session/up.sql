@@ -0,0 +1,14 @@
+ALTER TABLE "user" ADD COLUMN password_hash VARCHAR(255);
+
+-- web sessions. the cookie holds the session id, only its sha256 is stored.
+CREATE TABLE session (
+    id_hash    CHAR(64),
+    "user"     CHAR(36) NOT NULL,
+    csrf_token CHAR(32) NOT NULL,
+
+    created_at TIMESTAMP DEFAULT CURRENT_TIMESTAMP,
+    expires_at TIMESTAMP NOT NULL,
+
+    PRIMARY KEY(id_hash),
+    FOREIGN KEY("user") REFERENCES "user"(uuid) ON DELETE CASCADE
+);
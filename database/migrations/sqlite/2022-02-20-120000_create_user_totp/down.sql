@@ -0,0 +1,5 @@
+ALTER TABLE session DROP COLUMN second_factor_pending;
+ALTER TABLE session DROP COLUMN second_factor_failures;
+ALTER TABLE session DROP COLUMN second_factor_at;
+DROP TABLE recovery_code;
+DROP TABLE user_totp;
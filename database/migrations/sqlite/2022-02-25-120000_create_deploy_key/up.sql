@@ -0,0 +1,19 @@
+-- ssh keys of machines rather than users, each bound to one repository with
+-- read or write access.
+CREATE TABLE deploy_key (
+    sha256_fingerprint VARCHAR(64),
+    repository         VARCHAR(255) NOT NULL,
+    name               VARCHAR(255) NOT NULL,
+    key_type           VARCHAR(64) NOT NULL,
+    key_data           TEXT NOT NULL,
+    access             VARCHAR(16) NOT NULL,
+    created_by         CHAR(36),
+
+    created_at TIMESTAMP DEFAULT CURRENT_TIMESTAMP,
+
+    PRIMARY KEY(sha256_fingerprint),
+    UNIQUE(repository, name),
+    FOREIGN KEY(repository) REFERENCES repository(path) ON DELETE CASCADE ON UPDATE CASCADE,
+    FOREIGN KEY(created_by) REFERENCES "user"(uuid) ON DELETE SET NULL,
+    CHECK (access IN ('read', 'write'))
+);
@@ -0,0 +1,20 @@
+-- tokens minted by git-lfs-authenticate, by their jti, and the ssh key that
+-- asked for them. git-server refuses revoked tokens until they expire.
+CREATE TABLE issued_token (
+    jti             CHAR(36),
+    "user"          CHAR(36),
+    key_fingerprint VARCHAR(64) NOT NULL,
+    is_deploy_key   BOOLEAN NOT NULL DEFAULT FALSE,
+    repository      VARCHAR(255) NOT NULL,
+    operation       VARCHAR(16) NOT NULL,
+
+    issued_at  TIMESTAMP DEFAULT CURRENT_TIMESTAMP,
+    expires_at TIMESTAMP NOT NULL,
+    revoked_at TIMESTAMP,
+
+    PRIMARY KEY(jti),
+    FOREIGN KEY("user") REFERENCES "user"(uuid) ON DELETE SET NULL
+);
+
+CREATE INDEX issued_token_key_fingerprint ON issued_token(key_fingerprint);
+CREATE INDEX issued_token_expires_at ON issued_token(expires_at);
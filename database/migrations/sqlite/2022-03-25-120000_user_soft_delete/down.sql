@@ -0,0 +1,2 @@
+DROP INDEX user_deleted_at;
+DROP TRIGGER user_updated_at;
@@ -0,0 +1,9 @@
+-- updated_at follows every change to the row, and deleted users waiting to be
+-- purged are found by when they were deleted
+CREATE TRIGGER user_updated_at AFTER UPDATE ON "user"
+    WHEN NEW.updated_at IS OLD.updated_at
+BEGIN
+    UPDATE "user" SET updated_at = CURRENT_TIMESTAMP WHERE uuid = NEW.uuid;
+END;
+
+CREATE INDEX user_deleted_at ON "user"(deleted_at);
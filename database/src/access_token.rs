@@ -0,0 +1,129 @@
+use crate::connection::DbBackend;
+use crate::models::{AccessToken, NewAccessToken};
+use crate::schema::{access_token, user};
+use common::Access;
+use diesel::prelude::*;
+
+pub fn create_access_token<C: Connection<Backend = DbBackend>>(
+    conn: &C,
+    user: String,
+    name: String,
+    token_hash: String,
+    scope: Access,
+    repository: Option<String>,
+    expires_at: chrono::NaiveDateTime,
+) -> Result<String, diesel::result::Error> {
+    let id = uuid::Uuid::new_v4().to_string();
+    diesel::insert_into(access_token::table)
+        .values(&NewAccessToken {
+            id: id.clone(),
+            user,
+            name,
+            token_hash,
+            scope: scope.to_string(),
+            repository,
+            expires_at,
+        })
+        .execute(conn)?;
+    Ok(id)
+}
+
+/// The unexpired token whose secret hashes to `token_hash`, unless its user
+/// is disabled or deleted.
+pub fn query_access_token_by_hash<C: Connection<Backend = DbBackend>>(
+    conn: &C,
+    token_hash: String,
+) -> Result<Option<AccessToken>, diesel::result::Error> {
+    access_token::table
+        .inner_join(user::table)
+        .select(access_token::all_columns)
+        .filter(access_token::token_hash.eq(token_hash))
+        .filter(access_token::expires_at.gt(chrono::Utc::now().naive_utc()))
+        .filter(user::disabled_at.is_null())
+        .filter(user::deleted_at.is_null())
+        .first::<AccessToken>(conn)
+        .optional()
+}
+
+pub fn query_access_tokens_by_user<C: Connection<Backend = DbBackend>>(
+    conn: &C,
+    user: String,
+) -> Result<Vec<AccessToken>, diesel::result::Error> {
+    access_token::table
+        .filter(access_token::user.eq(user))
+        .order(access_token::name.asc())
+        .load::<AccessToken>(conn)
+}
+
+pub fn delete_access_token<C: Connection<Backend = DbBackend>>(
+    conn: &C,
+    user: String,
+    name: String,
+) -> Result<usize, diesel::result::Error> {
+    diesel::delete(
+        access_token::table
+            .filter(access_token::user.eq(user))
+            .filter(access_token::name.eq(name)),
+    )
+    .execute(conn)
+}
+
+#[cfg(all(test, feature = "sqlite"))]
+mod tests {
+    use super::*;
+    use crate::connection::in_memory;
+    use chrono::{Duration, Utc};
+
+    #[test]
+    fn finds_unexpired_tokens_of_active_users() {
+        let conn = in_memory();
+        let alice = crate::user::create_user(&conn, String::from("alice")).unwrap();
+        let in_a_day = Utc::now().naive_utc() + Duration::days(1);
+        let id = create_access_token(
+            &conn,
+            alice.clone(),
+            String::from("ci"),
+            String::from("hash"),
+            Access::Write,
+            Some(String::from("grp/repo.git")),
+            in_a_day,
+        )
+        .unwrap();
+        create_access_token(
+            &conn,
+            alice.clone(),
+            String::from("old"),
+            String::from("expired"),
+            Access::Read,
+            None,
+            Utc::now().naive_utc() - Duration::days(1),
+        )
+        .unwrap();
+
+        let token = query_access_token_by_hash(&conn, String::from("hash"))
+            .unwrap()
+            .unwrap();
+        assert_eq!(token.id, id);
+        assert_eq!(token.scope, "write");
+        assert!(query_access_token_by_hash(&conn, String::from("expired"))
+            .unwrap()
+            .is_none());
+
+        crate::user::set_disabled(&conn, alice.clone(), true).unwrap();
+        assert!(query_access_token_by_hash(&conn, String::from("hash"))
+            .unwrap()
+            .is_none());
+
+        let names: Vec<_> = query_access_tokens_by_user(&conn, alice.clone())
+            .unwrap()
+            .into_iter()
+            .map(|token| token.name)
+            .collect();
+        assert_eq!(names, ["ci", "old"]);
+        assert_eq!(
+            delete_access_token(&conn, alice.clone(), String::from("ci")).unwrap(),
+            1
+        );
+        assert_eq!(query_access_tokens_by_user(&conn, alice).unwrap().len(), 1);
+    }
+}
@@ -0,0 +1,311 @@
+use crate::authorization::KeyOwner;
+use crate::connection::DbBackend;
+use crate::models::{AuditEntry, NewAuditEntry};
+use crate::schema::{audit_log, user};
+use diesel::prelude::*;
+use std::fmt::Display;
+
+pub const LOGIN: &str = "login";
+pub const LOGOUT: &str = "logout";
+pub const SECOND_FACTOR: &str = "login.second_factor";
+/// A token minted by git-lfs-authenticate.
+pub const TOKEN_ISSUE: &str = "token.issue";
+/// A personal access token created.
+pub const TOKEN_CREATE: &str = "token.create";
+pub const TOKEN_REVOKE: &str = "token.revoke";
+pub const SESSION_REVOKE: &str = "session.revoke";
+pub const LFS_DOWNLOAD: &str = "lfs.download";
+pub const LFS_UPLOAD: &str = "lfs.upload";
+pub const LOCK_CREATE: &str = "lock.create";
+pub const LOCK_DELETE: &str = "lock.delete";
+pub const GIT_FETCH: &str = "git.fetch";
+pub const GIT_PUSH: &str = "git.push";
+pub const PASSWORD_SET: &str = "password.set";
+pub const TOTP_ENABLE: &str = "totp.enable";
+pub const TOTP_DISABLE: &str = "totp.disable";
+pub const TOTP_RESET: &str = "totp.reset";
+pub const DEPLOY_KEY_ADD: &str = "deploy_key.add";
+pub const DEPLOY_KEY_REMOVE: &str = "deploy_key.remove";
+pub const DEPLOY_KEY_LIST: &str = "deploy_key.list";
+/// The first admin created by `git-server bootstrap`.
+pub const BOOTSTRAP: &str = "instance.bootstrap";
+/// Group memberships changed by single sign-on.
+pub const GROUP_SYNC: &str = "group.sync";
+pub const USER_CREATE: &str = "user.create";
+pub const USER_DISABLE: &str = "user.disable";
+pub const USER_ENABLE: &str = "user.enable";
+pub const USER_DELETE: &str = "user.delete";
+pub const USER_RESTORE: &str = "user.restore";
+pub const USER_PURGE: &str = "user.purge";
+/// An ssh key registered to a user.
+pub const KEY_ADD: &str = "key.add";
+pub const KEY_REMOVE: &str = "key.remove";
+/// The blob of a key registered by its MD5 fingerprint only.
+pub const KEY_BACKFILL: &str = "key.backfill";
+pub const REPOSITORY_CREATE: &str = "repository.create";
+pub const PERMISSION_GRANT: &str = "permission.grant";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Outcome {
+    Success,
+    /// Wrong credentials, or something broke.
+    Failure,
+    /// Authenticated, but not permitted.
+    Denied,
+}
+
+impl Display for Outcome {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Outcome::Success => write!(f, "success"),
+            Outcome::Failure => write!(f, "failure"),
+            Outcome::Denied => write!(f, "denied"),
+        }
+    }
+}
+
+/// Who did something.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Actor {
+    /// A known user, by uuid.
+    User(String),
+    /// A username as it was entered, like on a failed login.
+    Username(String),
+    /// A deploy key, by SHA-256 fingerprint.
+    DeployKey(String),
+    /// Someone running rustile-admin on the server, by their login.
+    Operator(String),
+    Anonymous,
+}
+
+impl From<&KeyOwner> for Actor {
+    fn from(owner: &KeyOwner) -> Self {
+        match owner {
+            KeyOwner::User(user) => Actor::User(user.clone()),
+            KeyOwner::DeployKey(fingerprint) => Actor::DeployKey(fingerprint.clone()),
+        }
+    }
+}
+
+/// Something worth recording, see [`record`].
+#[derive(Debug, Clone)]
+pub struct Event {
+    /// One of the constants of this module.
+    pub action: &'static str,
+    pub actor: Actor,
+    pub repository: Option<String>,
+    pub ip: Option<String>,
+    pub outcome: Outcome,
+    pub detail: Option<String>,
+}
+
+impl Event {
+    pub fn new(action: &'static str, actor: Actor, outcome: Outcome) -> Self {
+        Self {
+            action,
+            actor,
+            repository: None,
+            ip: None,
+            outcome,
+            detail: None,
+        }
+    }
+
+    pub fn repository(self, repository: impl ToString) -> Self {
+        Self {
+            repository: Some(repository.to_string()),
+            ..self
+        }
+    }
+
+    pub fn ip(self, ip: Option<String>) -> Self {
+        Self { ip, ..self }
+    }
+
+    pub fn detail(self, detail: impl Into<String>) -> Self {
+        Self {
+            detail: Some(detail.into()),
+            ..self
+        }
+    }
+}
+
+/// The address of the ssh client, for events recorded over ssh.
+pub fn ssh_client_ip() -> Option<String> {
+    let connection = std::env::var("SSH_CONNECTION").ok()?;
+    connection.split_whitespace().next().map(String::from)
+}
+
+/// Appends `event` to the audit log. Users are recorded by their current
+/// username, so the entry still reads right after they are gone.
+pub fn record<C: Connection<Backend = DbBackend>>(
+    conn: &C,
+    event: Event,
+) -> Result<usize, diesel::result::Error> {
+    let (actor, actor_user) = match event.actor {
+        Actor::User(uuid) => {
+            let username = user::table
+                .select(user::username)
+                .filter(user::uuid.eq(&uuid))
+                .first::<String>(conn)
+                .optional()?;
+            (username.unwrap_or_else(|| uuid.clone()), Some(uuid))
+        }
+        Actor::Username(username) => {
+            let uuid = user::table
+                .select(user::uuid)
+                .filter(user::username.eq(&username))
+                .filter(user::deleted_at.is_null())
+                .first::<String>(conn)
+                .optional()?;
+            (username, uuid)
+        }
+        Actor::DeployKey(fingerprint) => (format!("deploy-key:{}", fingerprint), None),
+        Actor::Operator(login) => (format!("operator:{}", login), None),
+        Actor::Anonymous => (String::from("anonymous"), None),
+    };
+
+    diesel::insert_into(audit_log::table)
+        .values(&NewAuditEntry {
+            action: String::from(event.action),
+            actor,
+            actor_user,
+            repository: event.repository,
+            ip: event.ip,
+            outcome: event.outcome.to_string(),
+            detail: event.detail,
+        })
+        .execute(conn)
+}
+
+/// Narrows down [`query_audit_log`], every field that is set has to match.
+#[derive(Debug, Clone, Default)]
+pub struct AuditFilter {
+    pub action: Option<String>,
+    pub actor: Option<String>,
+    pub repository: Option<String>,
+    pub outcome: Option<String>,
+    pub since: Option<chrono::NaiveDateTime>,
+    pub until: Option<chrono::NaiveDateTime>,
+    /// Only entries older than this id, for paging backwards.
+    pub before: Option<i64>,
+}
+
+/// Up to `limit` entries matching `filter`, newest first.
+pub fn query_audit_log<C: Connection<Backend = DbBackend>>(
+    conn: &C,
+    filter: &AuditFilter,
+    limit: i64,
+) -> Result<Vec<AuditEntry>, diesel::result::Error> {
+    let mut query = audit_log::table.into_boxed();
+    if let Some(action) = &filter.action {
+        query = query.filter(audit_log::action.eq(action));
+    }
+    if let Some(actor) = &filter.actor {
+        query = query.filter(audit_log::actor.eq(actor));
+    }
+    if let Some(repository) = &filter.repository {
+        query = query.filter(audit_log::repository.eq(repository));
+    }
+    if let Some(outcome) = &filter.outcome {
+        query = query.filter(audit_log::outcome.eq(outcome));
+    }
+    if let Some(since) = filter.since {
+        query = query.filter(audit_log::created_at.ge(since));
+    }
+    if let Some(until) = filter.until {
+        query = query.filter(audit_log::created_at.lt(until));
+    }
+    if let Some(before) = filter.before {
+        query = query.filter(audit_log::id.lt(before));
+    }
+    query
+        .order(audit_log::id.desc())
+        .limit(limit)
+        .load::<AuditEntry>(conn)
+}
+
+#[cfg(all(test, feature = "sqlite"))]
+mod tests {
+    use super::*;
+    use crate::connection::in_memory;
+
+    #[test]
+    fn records_actors_by_name() {
+        let conn = in_memory();
+        let alice = crate::user::create_user(&conn, String::from("alice")).unwrap();
+        record(
+            &conn,
+            Event::new(LOGIN, Actor::User(alice.clone()), Outcome::Success),
+        )
+        .unwrap();
+        record(
+            &conn,
+            Event::new(
+                LOGIN,
+                Actor::Username(String::from("alice")),
+                Outcome::Failure,
+            )
+            .ip(Some(String::from("192.0.2.1"))),
+        )
+        .unwrap();
+        record(
+            &conn,
+            Event::new(
+                LFS_DOWNLOAD,
+                Actor::DeployKey(String::from("SHA256:a")),
+                Outcome::Denied,
+            )
+            .repository("grp/repo.git")
+            .detail("no access"),
+        )
+        .unwrap();
+
+        let entries = query_audit_log(&conn, &AuditFilter::default(), 10).unwrap();
+        assert_eq!(entries.len(), 3);
+        assert_eq!(entries[0].actor, "deploy-key:SHA256:a");
+        assert_eq!(entries[0].repository.as_deref(), Some("grp/repo.git"));
+        assert_eq!(entries[0].outcome, "denied");
+        assert_eq!(entries[1].actor, "alice");
+        assert_eq!(entries[1].actor_user.as_deref(), Some(alice.as_str()));
+        assert_eq!(entries[1].ip.as_deref(), Some("192.0.2.1"));
+        assert_eq!(entries[2].actor_user, Some(alice));
+    }
+
+    #[test]
+    fn filters_and_pages_backwards() {
+        let conn = in_memory();
+        for outcome in [Outcome::Success, Outcome::Failure, Outcome::Success] {
+            record(&conn, Event::new(LOGIN, Actor::Anonymous, outcome)).unwrap();
+        }
+        record(
+            &conn,
+            Event::new(
+                LOGOUT,
+                Actor::Operator(String::from("root")),
+                Outcome::Success,
+            ),
+        )
+        .unwrap();
+
+        let filter = AuditFilter {
+            action: Some(String::from(LOGIN)),
+            outcome: Some(String::from("success")),
+            ..AuditFilter::default()
+        };
+        let entries = query_audit_log(&conn, &filter, 10).unwrap();
+        assert_eq!(entries.len(), 2);
+        assert!(entries[0].id > entries[1].id);
+
+        let older = AuditFilter {
+            before: Some(entries[0].id),
+            ..filter
+        };
+        let entries = query_audit_log(&conn, &older, 10).unwrap();
+        assert_eq!(entries.len(), 1);
+        assert_eq!(
+            query_audit_log(&conn, &AuditFilter::default(), 1).unwrap()[0].actor,
+            "operator:root"
+        );
+    }
+}
@@ -0,0 +1,444 @@
+//! The one place deciding who may do what with a repository. git-server,
+//! rustile-shell and git-lfs-authenticate all ask here.
+
+use crate::connection::DbBackend;
+use crate::models::Repository;
+use crate::schema::{deploy_key, group_member, repository, repository_permission, user};
+use common::Access;
+use diesel::prelude::*;
+
+/// `user` unless they are disabled or deleted, with whether they are an
+/// admin.
+fn active_user_of<'a, C: Connection<Backend = DbBackend>>(
+    conn: &C,
+    user: Option<&'a str>,
+) -> Result<Option<(&'a str, bool)>, diesel::result::Error> {
+    let user = match user {
+        Some(user) => user,
+        None => return Ok(None),
+    };
+    let is_admin = user::table
+        .select(user::is_admin)
+        .filter(user::uuid.eq(user))
+        .filter(user::disabled_at.is_null())
+        .filter(user::deleted_at.is_null())
+        .first::<bool>(conn)
+        .optional()?;
+    Ok(is_admin.map(|is_admin| (user, is_admin)))
+}
+
+/// The highest access `user` has on the repository at `path`, `None` for an
+/// anonymous user.
+///
+/// - disabled and deleted users count as anonymous;
+/// - admins have admin access to every repository, registered or not;
+/// - otherwise only repositories in the `repository` table are accessible;
+/// - access is the highest one granted to the user or any of their groups;
+/// - everyone, anonymous users included, can read public repositories.
+pub fn repository_access<C: Connection<Backend = DbBackend>>(
+    conn: &C,
+    user: Option<&str>,
+    path: &str,
+) -> Result<Option<Access>, diesel::result::Error> {
+    let user = match active_user_of(conn, user)? {
+        Some((_, true)) => return Ok(Some(Access::Admin)),
+        Some((user, false)) => Some(user),
+        None => None,
+    };
+
+    let is_public = match repository::table
+        .select(repository::is_public)
+        .filter(repository::path.eq(path))
+        .first::<bool>(conn)
+        .optional()?
+    {
+        Some(is_public) => is_public,
+        None => return Ok(None),
+    };
+    let mut access = if is_public { Some(Access::Read) } else { None };
+
+    if let Some(user) = user {
+        let groups = group_member::table
+            .select(group_member::group.nullable())
+            .filter(group_member::user.eq(user));
+        let granted = repository_permission::table
+            .select(repository_permission::access)
+            .filter(repository_permission::repository.eq(path))
+            .filter(
+                repository_permission::user
+                    .eq(user)
+                    .or(repository_permission::group.eq_any(groups)),
+            )
+            .load::<String>(conn)?;
+        access = granted
+            .iter()
+            .filter_map(|access| access.parse::<Access>().ok())
+            .chain(access)
+            .max();
+    }
+
+    Ok(access)
+}
+
+/// Whether `user`, or an anonymous user for `None`, has at least `required`
+/// access on the repository at `path`.
+pub fn authorize<C: Connection<Backend = DbBackend>>(
+    conn: &C,
+    user: Option<&str>,
+    path: &str,
+    required: Access,
+) -> Result<bool, diesel::result::Error> {
+    Ok(repository_access(conn, user, path)?.is_some_and(|access| access >= required))
+}
+
+/// The registered repositories `user`, or an anonymous user for `None`, can
+/// read, by path. Follows the rules of [`repository_access`], but with a
+/// fixed number of queries however many repositories there are.
+pub fn query_readable_repositories<C: Connection<Backend = DbBackend>>(
+    conn: &C,
+    user: Option<&str>,
+) -> Result<Vec<Repository>, diesel::result::Error> {
+    let user = match active_user_of(conn, user)? {
+        Some((_, true)) => return crate::repository::query_repositories(conn),
+        Some((user, false)) => Some(user),
+        None => None,
+    };
+
+    let readable = repository::table
+        .left_join(repository_permission::table)
+        .select(repository::all_columns)
+        .distinct()
+        .order(repository::path.asc());
+    match user {
+        Some(user) => {
+            let groups = group_member::table
+                .select(group_member::group.nullable())
+                .filter(group_member::user.eq(user));
+            readable
+                .filter(
+                    repository::is_public
+                        .eq(true)
+                        .or(repository_permission::user.eq(user))
+                        .or(repository_permission::group.eq_any(groups)),
+                )
+                .load::<Repository>(conn)
+        }
+        None => readable
+            .filter(repository::is_public.eq(true))
+            .load::<Repository>(conn),
+    }
+}
+
+/// The access the deploy key with `sha256_fingerprint` has on the repository
+/// at `path`: its own access there, and none anywhere else.
+pub fn deploy_key_access<C: Connection<Backend = DbBackend>>(
+    conn: &C,
+    sha256_fingerprint: &str,
+    path: &str,
+) -> Result<Option<Access>, diesel::result::Error> {
+    let access = deploy_key::table
+        .select(deploy_key::access)
+        .filter(deploy_key::sha256_fingerprint.eq(sha256_fingerprint))
+        .filter(deploy_key::repository.eq(path))
+        .first::<String>(conn)
+        .optional()?;
+    Ok(access.and_then(|access| access.parse().ok()))
+}
+
+/// Who connects over ssh, by the key they offered.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum KeyOwner {
+    /// The uuid of the user the key belongs to.
+    User(String),
+    /// The SHA-256 fingerprint of a deploy key.
+    DeployKey(String),
+}
+
+impl KeyOwner {
+    /// The highest access the owner of the key has on the repository at
+    /// `path`.
+    pub fn repository_access<C: Connection<Backend = DbBackend>>(
+        &self,
+        conn: &C,
+        path: &str,
+    ) -> Result<Option<Access>, diesel::result::Error> {
+        match self {
+            KeyOwner::User(user) => repository_access(conn, Some(user), path),
+            KeyOwner::DeployKey(fingerprint) => deploy_key_access(conn, fingerprint, path),
+        }
+    }
+
+    pub fn authorize<C: Connection<Backend = DbBackend>>(
+        &self,
+        conn: &C,
+        path: &str,
+        required: Access,
+    ) -> Result<bool, diesel::result::Error> {
+        Ok(self
+            .repository_access(conn, path)?
+            .is_some_and(|access| access >= required))
+    }
+}
+
+fn active_user<C: Connection<Backend = DbBackend>>(
+    conn: &C,
+    user: String,
+) -> Result<Option<KeyOwner>, diesel::result::Error> {
+    Ok(crate::user::query_is_active(conn, user.clone())?.then_some(KeyOwner::User(user)))
+}
+
+/// The owner of the key with `fingerprint`, matched against user keys first,
+/// then against user certificates that are still valid and then against
+/// deploy keys. Keys of disabled or deleted users have no owner.
+pub fn query_key_owner<C: Connection<Backend = DbBackend>>(
+    conn: &C,
+    fingerprint: String,
+) -> Result<Option<KeyOwner>, diesel::result::Error> {
+    if let Some(key) = crate::user::query_public_keys_by_fingerprint(conn, fingerprint.clone())?
+        .into_iter()
+        .next()
+    {
+        return active_user(conn, key.user);
+    }
+    if let Some(certificate) =
+        crate::certificate::query_valid_certificate_by_fingerprint(conn, fingerprint.clone())?
+    {
+        return active_user(conn, certificate.user);
+    }
+    Ok(
+        crate::deploy_key::query_deploy_key_by_fingerprint(conn, fingerprint)?
+            .map(|key| KeyOwner::DeployKey(key.sha256_fingerprint)),
+    )
+}
+
+/// Whether `key` is registered already, to a user by either of its
+/// fingerprints or as a deploy key.
+pub fn query_key_registered<C: Connection<Backend = DbBackend>>(
+    conn: &C,
+    key: &common::PublicKey,
+) -> Result<bool, diesel::result::Error> {
+    for fingerprint in [&key.sha256_fingerprint, &key.md5_fingerprint] {
+        if !crate::user::query_public_keys_by_fingerprint(conn, fingerprint.clone())?.is_empty() {
+            return Ok(true);
+        }
+    }
+    Ok(
+        crate::deploy_key::query_deploy_key_by_fingerprint(conn, key.sha256_fingerprint.clone())?
+            .is_some(),
+    )
+}
+
+#[cfg(all(test, feature = "sqlite"))]
+mod tests {
+    use super::*;
+    use crate::connection::in_memory;
+    use crate::models::{NewDeployKey, NewPublicKey};
+    use crate::repository::{create_repository, grant_permission, Grantee};
+
+    const REPO: &str = "grp/repo.git";
+
+    #[test]
+    fn admins_reach_every_repository() {
+        let conn = in_memory();
+        let admin = crate::user::create_user(&conn, String::from("admin")).unwrap();
+        crate::user::set_admin(&conn, admin.clone(), true).unwrap();
+        assert_eq!(
+            repository_access(&conn, Some(&admin), "not/registered.git").unwrap(),
+            Some(Access::Admin)
+        );
+
+        crate::user::set_disabled(&conn, admin.clone(), true).unwrap();
+        assert_eq!(
+            repository_access(&conn, Some(&admin), "not/registered.git").unwrap(),
+            None
+        );
+    }
+
+    #[test]
+    fn grants_the_highest_access_of_user_and_groups() {
+        let conn = in_memory();
+        let alice = crate::user::create_user(&conn, String::from("alice")).unwrap();
+        let team = crate::group::create_group(&conn, String::from("team")).unwrap();
+        create_repository(&conn, String::from(REPO), false).unwrap();
+        assert_eq!(repository_access(&conn, Some(&alice), REPO).unwrap(), None);
+        assert_eq!(repository_access(&conn, None, REPO).unwrap(), None);
+
+        grant_permission(
+            &conn,
+            String::from(REPO),
+            Grantee::User(alice.clone()),
+            Access::Read,
+        )
+        .unwrap();
+        assert_eq!(
+            repository_access(&conn, Some(&alice), REPO).unwrap(),
+            Some(Access::Read)
+        );
+
+        grant_permission(
+            &conn,
+            String::from(REPO),
+            Grantee::Group(team.clone()),
+            Access::Write,
+        )
+        .unwrap();
+        crate::group::add_group_member(&conn, team, alice.clone()).unwrap();
+        assert!(authorize(&conn, Some(&alice), REPO, Access::Write).unwrap());
+        assert!(!authorize(&conn, Some(&alice), REPO, Access::Admin).unwrap());
+    }
+
+    #[test]
+    fn everyone_reads_public_repositories() {
+        let conn = in_memory();
+        create_repository(&conn, String::from(REPO), true).unwrap();
+        assert_eq!(
+            repository_access(&conn, None, REPO).unwrap(),
+            Some(Access::Read)
+        );
+        assert!(!authorize(&conn, None, REPO, Access::Write).unwrap());
+    }
+
+    #[test]
+    fn lists_the_repositories_a_user_can_read() {
+        let conn = in_memory();
+        let alice = crate::user::create_user(&conn, String::from("alice")).unwrap();
+        let admin = crate::user::create_user(&conn, String::from("admin")).unwrap();
+        crate::user::set_admin(&conn, admin.clone(), true).unwrap();
+        let team = crate::group::create_group(&conn, String::from("team")).unwrap();
+        crate::group::add_group_member(&conn, team.clone(), alice.clone()).unwrap();
+        for (path, is_public) in [
+            ("a/public.git", true),
+            ("b/granted.git", false),
+            ("c/team.git", false),
+            ("d/private.git", false),
+        ] {
+            create_repository(&conn, String::from(path), is_public).unwrap();
+        }
+        grant_permission(
+            &conn,
+            String::from("b/granted.git"),
+            Grantee::User(alice.clone()),
+            Access::Read,
+        )
+        .unwrap();
+        // granted twice, listed once
+        for grantee in [Grantee::Group(team), Grantee::User(alice.clone())] {
+            grant_permission(&conn, String::from("c/team.git"), grantee, Access::Write).unwrap();
+        }
+
+        let readable = |user: Option<&str>| -> Vec<String> {
+            query_readable_repositories(&conn, user)
+                .unwrap()
+                .into_iter()
+                .map(|repository| repository.path)
+                .collect()
+        };
+        assert_eq!(readable(None), ["a/public.git"]);
+        assert_eq!(
+            readable(Some(&alice)),
+            ["a/public.git", "b/granted.git", "c/team.git"]
+        );
+        assert_eq!(
+            readable(Some(&admin)),
+            [
+                "a/public.git",
+                "b/granted.git",
+                "c/team.git",
+                "d/private.git"
+            ]
+        );
+        for path in readable(Some(&admin)) {
+            assert_eq!(
+                readable(Some(&alice)).contains(&path),
+                repository_access(&conn, Some(&alice), &path)
+                    .unwrap()
+                    .is_some()
+            );
+        }
+
+        crate::user::set_disabled(&conn, alice.clone(), true).unwrap();
+        assert_eq!(readable(Some(&alice)), ["a/public.git"]);
+    }
+
+    #[test]
+    fn resolves_key_owners() {
+        let conn = in_memory();
+        let alice = crate::user::create_user(&conn, String::from("alice")).unwrap();
+        crate::user::create_public_key(
+            &conn,
+            NewPublicKey {
+                fingerprint: String::from("aa:bb"),
+                user: alice.clone(),
+                key_type: None,
+                key_data: None,
+                sha256_fingerprint: None,
+            },
+        )
+        .unwrap();
+        create_repository(&conn, String::from(REPO), false).unwrap();
+        crate::deploy_key::create_deploy_key(
+            &conn,
+            NewDeployKey {
+                sha256_fingerprint: String::from("SHA256:deploy"),
+                repository: String::from(REPO),
+                name: String::from("ci"),
+                key_type: String::from("ssh-ed25519"),
+                key_data: String::from("AAAA"),
+                access: String::from("read"),
+                created_by: None,
+            },
+        )
+        .unwrap();
+
+        assert_eq!(
+            query_key_owner(&conn, String::from("aa:bb")).unwrap(),
+            Some(KeyOwner::User(alice.clone()))
+        );
+        let deploy_key = query_key_owner(&conn, String::from("SHA256:deploy"))
+            .unwrap()
+            .unwrap();
+        assert_eq!(
+            deploy_key,
+            KeyOwner::DeployKey(String::from("SHA256:deploy"))
+        );
+        assert!(deploy_key.authorize(&conn, REPO, Access::Read).unwrap());
+        assert!(!deploy_key.authorize(&conn, REPO, Access::Write).unwrap());
+        assert_eq!(
+            deploy_key
+                .repository_access(&conn, "other/repo.git")
+                .unwrap(),
+            None
+        );
+        assert_eq!(query_key_owner(&conn, String::from("cc:dd")).unwrap(), None);
+
+        crate::user::set_disabled(&conn, alice, true).unwrap();
+        assert_eq!(query_key_owner(&conn, String::from("aa:bb")).unwrap(), None);
+    }
+
+    #[test]
+    fn knows_registered_keys() {
+        let conn = in_memory();
+        let alice = crate::user::create_user(&conn, String::from("alice")).unwrap();
+        let key = common::PublicKey {
+            key_type: String::from("ssh-ed25519"),
+            key_data: String::from("AAAA"),
+            comment: None,
+            sha256_fingerprint: String::from("SHA256:alice"),
+            md5_fingerprint: String::from("aa:bb"),
+        };
+        assert!(!query_key_registered(&conn, &key).unwrap());
+
+        crate::user::create_public_key(
+            &conn,
+            NewPublicKey {
+                fingerprint: String::from("aa:bb"),
+                user: alice,
+                key_type: None,
+                key_data: None,
+                sha256_fingerprint: None,
+            },
+        )
+        .unwrap();
+        assert!(query_key_registered(&conn, &key).unwrap());
+    }
+}
@@ -0,0 +1,122 @@
+use crate::connection::DbBackend;
+use crate::models::{NewSshCertificate, SshCertificate};
+use crate::schema::ssh_certificate;
+use diesel::prelude::*;
+
+/// Remembers a certificate git-authorized-keys accepted, replacing the row
+/// if the same certificate was offered before. Expired certificates are
+/// dropped on the way.
+pub fn record_certificate<C: Connection<Backend = DbBackend>>(
+    conn: &C,
+    certificate: NewSshCertificate,
+) -> Result<usize, diesel::result::Error> {
+    let now = chrono::Utc::now().naive_utc();
+    conn.transaction(|| {
+        diesel::delete(
+            ssh_certificate::table
+                .filter(ssh_certificate::valid_before.lt(now))
+                .or_filter(ssh_certificate::fingerprint.eq(&certificate.fingerprint)),
+        )
+        .execute(conn)?;
+        diesel::insert_into(ssh_certificate::table)
+            .values(&certificate)
+            .execute(conn)
+    })
+}
+
+/// The certificate with `fingerprint`, if it is valid now.
+pub fn query_valid_certificate_by_fingerprint<C: Connection<Backend = DbBackend>>(
+    conn: &C,
+    fingerprint: String,
+) -> Result<Option<SshCertificate>, diesel::result::Error> {
+    let now = chrono::Utc::now().naive_utc();
+    ssh_certificate::table
+        .filter(ssh_certificate::fingerprint.eq(fingerprint))
+        .filter(ssh_certificate::valid_after.le(now))
+        .filter(
+            ssh_certificate::valid_before
+                .is_null()
+                .or(ssh_certificate::valid_before.gt(now)),
+        )
+        .first::<SshCertificate>(conn)
+        .optional()
+}
+
+#[cfg(all(test, feature = "sqlite"))]
+mod tests {
+    use super::*;
+    use crate::connection::in_memory;
+    use chrono::{Duration, Utc};
+
+    fn new_certificate(
+        user: &str,
+        fingerprint: &str,
+        valid_before: Option<chrono::NaiveDateTime>,
+    ) -> NewSshCertificate {
+        NewSshCertificate {
+            fingerprint: String::from(fingerprint),
+            user: String::from(user),
+            principal: String::from("alice"),
+            ca_fingerprint: String::from("SHA256:ca"),
+            key_id: String::from("alice@example.com"),
+            serial: String::from("1"),
+            valid_after: Utc::now().naive_utc() - Duration::hours(1),
+            valid_before,
+        }
+    }
+
+    #[test]
+    fn finds_valid_certificates_only() {
+        let conn = in_memory();
+        let alice = crate::user::create_user(&conn, String::from("alice")).unwrap();
+        let now = Utc::now().naive_utc();
+        record_certificate(&conn, new_certificate(&alice, "SHA256:forever", None)).unwrap();
+        record_certificate(
+            &conn,
+            new_certificate(&alice, "SHA256:expired", Some(now - Duration::minutes(1))),
+        )
+        .unwrap();
+        record_certificate(
+            &conn,
+            NewSshCertificate {
+                valid_after: now + Duration::hours(1),
+                ..new_certificate(&alice, "SHA256:future", None)
+            },
+        )
+        .unwrap();
+
+        assert!(
+            query_valid_certificate_by_fingerprint(&conn, String::from("SHA256:forever"))
+                .unwrap()
+                .is_some()
+        );
+        for fingerprint in ["SHA256:expired", "SHA256:future"] {
+            assert!(
+                query_valid_certificate_by_fingerprint(&conn, String::from(fingerprint))
+                    .unwrap()
+                    .is_none()
+            );
+        }
+    }
+
+    #[test]
+    fn replaces_certificates_offered_again() {
+        let conn = in_memory();
+        let alice = crate::user::create_user(&conn, String::from("alice")).unwrap();
+        record_certificate(&conn, new_certificate(&alice, "SHA256:cert", None)).unwrap();
+        record_certificate(
+            &conn,
+            NewSshCertificate {
+                serial: String::from("2"),
+                ..new_certificate(&alice, "SHA256:cert", None)
+            },
+        )
+        .unwrap();
+
+        let certificate =
+            query_valid_certificate_by_fingerprint(&conn, String::from("SHA256:cert"))
+                .unwrap()
+                .unwrap();
+        assert_eq!(certificate.serial, "2");
+    }
+}
@@ -0,0 +1,137 @@
+use crate::connection::DbBackend;
+use crate::models::{DeployKey, NewDeployKey};
+use crate::schema::deploy_key;
+use diesel::prelude::*;
+
+pub fn create_deploy_key<C: Connection<Backend = DbBackend>>(
+    conn: &C,
+    new_key: NewDeployKey,
+) -> Result<usize, diesel::result::Error> {
+    diesel::insert_into(deploy_key::table)
+        .values(&new_key)
+        .execute(conn)
+}
+
+pub fn query_deploy_key_by_fingerprint<C: Connection<Backend = DbBackend>>(
+    conn: &C,
+    sha256_fingerprint: String,
+) -> Result<Option<DeployKey>, diesel::result::Error> {
+    deploy_key::table
+        .filter(deploy_key::sha256_fingerprint.eq(sha256_fingerprint))
+        .first::<DeployKey>(conn)
+        .optional()
+}
+
+pub fn query_deploy_keys_by_repository<C: Connection<Backend = DbBackend>>(
+    conn: &C,
+    repository: String,
+) -> Result<Vec<DeployKey>, diesel::result::Error> {
+    deploy_key::table
+        .filter(deploy_key::repository.eq(repository))
+        .order(deploy_key::name)
+        .load::<DeployKey>(conn)
+}
+
+/// Deletes a deploy key and revokes the tokens it was issued.
+pub fn delete_deploy_key<C: Connection<Backend = DbBackend>>(
+    conn: &C,
+    repository: String,
+    name: String,
+) -> Result<usize, diesel::result::Error> {
+    conn.transaction(|| {
+        let fingerprint = deploy_key::table
+            .select(deploy_key::sha256_fingerprint)
+            .filter(deploy_key::repository.eq(&repository))
+            .filter(deploy_key::name.eq(&name))
+            .first::<String>(conn)
+            .optional()?;
+        let fingerprint = match fingerprint {
+            Some(fingerprint) => fingerprint,
+            None => return Ok(0),
+        };
+        crate::issued_token::revoke_tokens_by_key(conn, fingerprint.clone())?;
+        diesel::delete(deploy_key::table.filter(deploy_key::sha256_fingerprint.eq(fingerprint)))
+            .execute(conn)
+    })
+}
+
+#[cfg(all(test, feature = "sqlite"))]
+mod tests {
+    use super::*;
+    use crate::connection::in_memory;
+    use crate::models::NewIssuedToken;
+
+    const REPO: &str = "grp/repo.git";
+
+    fn new_key(fingerprint: &str, name: &str) -> NewDeployKey {
+        NewDeployKey {
+            sha256_fingerprint: String::from(fingerprint),
+            repository: String::from(REPO),
+            name: String::from(name),
+            key_type: String::from("ssh-ed25519"),
+            key_data: String::from("AAAA"),
+            access: String::from("read"),
+            created_by: None,
+        }
+    }
+
+    #[test]
+    fn lists_keys_of_a_repository() {
+        let conn = in_memory();
+        crate::repository::create_repository(&conn, String::from(REPO), false).unwrap();
+        create_deploy_key(&conn, new_key("SHA256:b", "deploy")).unwrap();
+        create_deploy_key(&conn, new_key("SHA256:a", "ci")).unwrap();
+
+        let names: Vec<_> = query_deploy_keys_by_repository(&conn, String::from(REPO))
+            .unwrap()
+            .into_iter()
+            .map(|key| key.name)
+            .collect();
+        assert_eq!(names, ["ci", "deploy"]);
+        assert_eq!(
+            query_deploy_key_by_fingerprint(&conn, String::from("SHA256:a"))
+                .unwrap()
+                .unwrap()
+                .name,
+            "ci"
+        );
+    }
+
+    #[test]
+    fn deleting_a_key_revokes_its_tokens() {
+        let conn = in_memory();
+        crate::repository::create_repository(&conn, String::from(REPO), false).unwrap();
+        create_deploy_key(&conn, new_key("SHA256:a", "ci")).unwrap();
+        crate::issued_token::record_issued_token(
+            &conn,
+            NewIssuedToken {
+                jti: String::from("jti"),
+                user: None,
+                key_fingerprint: String::from("SHA256:a"),
+                is_deploy_key: true,
+                repository: String::from(REPO),
+                operation: String::from("download"),
+                expires_at: chrono::Utc::now().naive_utc() + chrono::Duration::hours(1),
+            },
+        )
+        .unwrap();
+
+        assert_eq!(
+            delete_deploy_key(&conn, String::from(REPO), String::from("missing")).unwrap(),
+            0
+        );
+        assert_eq!(
+            delete_deploy_key(&conn, String::from(REPO), String::from("ci")).unwrap(),
+            1
+        );
+        assert!(
+            query_deploy_key_by_fingerprint(&conn, String::from("SHA256:a"))
+                .unwrap()
+                .is_none()
+        );
+        assert_eq!(
+            crate::issued_token::query_revoked_jtis(&conn).unwrap(),
+            ["jti"]
+        );
+    }
+}
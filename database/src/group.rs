@@ -0,0 +1,163 @@
+use crate::connection::DbBackend;
+use crate::models::{Group, GroupMember, NewGroup};
+use crate::schema::{group, group_member};
+use diesel::prelude::*;
+
+pub fn create_group<C: Connection<Backend = DbBackend>>(
+    conn: &C,
+    name: String,
+) -> Result<String, diesel::result::Error> {
+    let uuid = uuid::Uuid::new_v4().to_string();
+    diesel::insert_into(group::table)
+        .values(&NewGroup {
+            uuid: uuid.clone(),
+            name,
+        })
+        .execute(conn)?;
+    Ok(uuid)
+}
+
+pub fn query_group_by_name<C: Connection<Backend = DbBackend>>(
+    conn: &C,
+    name: String,
+) -> Result<Option<Group>, diesel::result::Error> {
+    group::table
+        .filter(group::name.eq(name))
+        .first::<Group>(conn)
+        .optional()
+}
+
+pub fn query_group_by_id<C: Connection<Backend = DbBackend>>(
+    conn: &C,
+    uuid: String,
+) -> Result<Option<Group>, diesel::result::Error> {
+    group::table
+        .filter(group::uuid.eq(uuid))
+        .first::<Group>(conn)
+        .optional()
+}
+
+/// Adds `user` to `group`, returning 0 if they were a member already.
+pub fn add_group_member<C: Connection<Backend = DbBackend>>(
+    conn: &C,
+    group: String,
+    user: String,
+) -> Result<usize, diesel::result::Error> {
+    conn.transaction(|| {
+        let is_member = group_member::table
+            .filter(group_member::group.eq(&group))
+            .filter(group_member::user.eq(&user))
+            .count()
+            .get_result::<i64>(conn)?
+            > 0;
+        if is_member {
+            return Ok(0);
+        }
+        diesel::insert_into(group_member::table)
+            .values(&GroupMember { group, user })
+            .execute(conn)
+    })
+}
+
+pub fn remove_group_member<C: Connection<Backend = DbBackend>>(
+    conn: &C,
+    group: String,
+    user: String,
+) -> Result<usize, diesel::result::Error> {
+    diesel::delete(
+        group_member::table
+            .filter(group_member::group.eq(group))
+            .filter(group_member::user.eq(user)),
+    )
+    .execute(conn)
+}
+
+/// The groups [`sync_group_members`] added a user to and removed them from.
+#[derive(Debug, Default)]
+pub struct GroupChanges {
+    pub joined: Vec<String>,
+    pub left: Vec<String>,
+}
+
+impl GroupChanges {
+    pub fn is_empty(&self) -> bool {
+        self.joined.is_empty() && self.left.is_empty()
+    }
+}
+
+/// Makes `user` a member of the groups named in `member_of`, and no member of
+/// the other groups named in `managed`. Groups that do not exist are skipped.
+pub fn sync_group_members<C: Connection<Backend = DbBackend>>(
+    conn: &C,
+    user: &str,
+    managed: &[String],
+    member_of: &[String],
+) -> Result<GroupChanges, diesel::result::Error> {
+    conn.transaction(|| {
+        let mut changes = GroupChanges::default();
+        for name in managed {
+            let group = match query_group_by_name(conn, name.clone())? {
+                Some(group) => group,
+                None => continue,
+            };
+            if member_of.contains(name) {
+                if add_group_member(conn, group.uuid, String::from(user))? > 0 {
+                    changes.joined.push(name.clone());
+                }
+            } else if remove_group_member(conn, group.uuid, String::from(user))? > 0 {
+                changes.left.push(name.clone());
+            }
+        }
+        Ok(changes)
+    })
+}
+
+#[cfg(all(test, feature = "sqlite"))]
+mod tests {
+    use super::*;
+    use crate::connection::in_memory;
+
+    #[test]
+    fn finds_groups_by_name_and_id() {
+        let conn = in_memory();
+        let uuid = create_group(&conn, String::from("team")).unwrap();
+        let group = query_group_by_name(&conn, String::from("team"))
+            .unwrap()
+            .unwrap();
+        assert_eq!(group.uuid, uuid);
+        assert_eq!(
+            query_group_by_id(&conn, uuid).unwrap().unwrap().name,
+            "team"
+        );
+        assert!(query_group_by_name(&conn, String::from("nobody"))
+            .unwrap()
+            .is_none());
+    }
+
+    #[test]
+    fn syncs_managed_groups_only() {
+        let conn = in_memory();
+        let alice = crate::user::create_user(&conn, String::from("alice")).unwrap();
+        let devs = create_group(&conn, String::from("devs")).unwrap();
+        let ops = create_group(&conn, String::from("ops")).unwrap();
+        let local = create_group(&conn, String::from("local")).unwrap();
+        add_group_member(&conn, ops, alice.clone()).unwrap();
+        add_group_member(&conn, local.clone(), alice.clone()).unwrap();
+
+        let managed = [
+            String::from("devs"),
+            String::from("ops"),
+            String::from("missing"),
+        ];
+        let member_of = [String::from("devs"), String::from("missing")];
+        let changes = sync_group_members(&conn, &alice, &managed, &member_of).unwrap();
+        assert_eq!(changes.joined, ["devs"]);
+        assert_eq!(changes.left, ["ops"]);
+
+        assert!(sync_group_members(&conn, &alice, &managed, &member_of)
+            .unwrap()
+            .is_empty());
+        assert_eq!(remove_group_member(&conn, devs, alice.clone()).unwrap(), 1);
+        assert_eq!(remove_group_member(&conn, local, alice).unwrap(), 1);
+    }
+}
@@ -0,0 +1,159 @@
+use crate::connection::DbBackend;
+use crate::models::NewUserIdentity;
+use crate::schema::user_identity;
+use crate::user::{create_user, query_user_id_by_username};
+use diesel::prelude::*;
+
+/// The uuid of the user the account `subject` at `issuer` is linked to.
+pub fn query_user_by_identity<C: Connection<Backend = DbBackend>>(
+    conn: &C,
+    issuer: String,
+    subject: String,
+) -> Result<Option<String>, diesel::result::Error> {
+    user_identity::table
+        .select(user_identity::user)
+        .filter(user_identity::issuer.eq(issuer))
+        .filter(user_identity::subject.eq(subject))
+        .first::<String>(conn)
+        .optional()
+}
+
+pub fn create_identity<C: Connection<Backend = DbBackend>>(
+    conn: &C,
+    new_identity: NewUserIdentity,
+) -> Result<usize, diesel::result::Error> {
+    diesel::insert_into(user_identity::table)
+        .values(&new_identity)
+        .execute(conn)
+}
+
+/// A username nobody uses yet, `username` made valid and numbered if taken.
+fn free_username<C: Connection<Backend = DbBackend>>(
+    conn: &C,
+    username: &str,
+) -> Result<String, diesel::result::Error> {
+    let username = common::sanitize_username(username);
+    let mut candidate = username.clone();
+    let mut number = 1;
+    while query_user_id_by_username(conn, candidate.clone())?.is_some() {
+        number += 1;
+        candidate = format!("{}-{}", username, number);
+    }
+    Ok(candidate)
+}
+
+/// The uuid of the user signing in as `subject` at `issuer`. Accounts are
+/// only ever linked by `(issuer, subject)`: on the first sign in a new user
+/// is created, named after `username` if that name is free. Providers let
+/// people pick their username, so matching it to an existing user would
+/// hand that user over to whoever picks their name.
+pub fn resolve_identity<C: Connection<Backend = DbBackend>>(
+    conn: &C,
+    issuer: String,
+    subject: String,
+    username: String,
+) -> Result<String, diesel::result::Error> {
+    conn.transaction(|| {
+        if let Some(user) = query_user_by_identity(conn, issuer.clone(), subject.clone())? {
+            return Ok(user);
+        }
+
+        let user = create_user(conn, free_username(conn, &username)?)?;
+        create_identity(
+            conn,
+            NewUserIdentity {
+                issuer,
+                subject,
+                user: user.clone(),
+            },
+        )?;
+        Ok(user)
+    })
+}
+
+#[cfg(all(test, feature = "sqlite"))]
+mod tests {
+    use super::*;
+    use crate::connection::in_memory;
+
+    const ISSUER: &str = "https://idp.example.com";
+
+    fn resolve(conn: &crate::connection::DbConnection, subject: &str, username: &str) -> String {
+        resolve_identity(
+            conn,
+            String::from(ISSUER),
+            String::from(subject),
+            String::from(username),
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn later_sign_ins_follow_the_link() {
+        let conn = in_memory();
+        let user = resolve(&conn, "sub-1", "alice");
+        assert_eq!(
+            query_user_by_identity(&conn, String::from(ISSUER), String::from("sub-1")).unwrap(),
+            Some(user.clone())
+        );
+        assert_eq!(
+            query_user_id_by_username(&conn, String::from("alice")).unwrap(),
+            Some(user.clone())
+        );
+
+        // whatever name they carry
+        assert_eq!(resolve(&conn, "sub-1", "renamed"), user);
+        assert!(query_user_id_by_username(&conn, String::from("renamed"))
+            .unwrap()
+            .is_none());
+    }
+
+    #[test]
+    fn never_links_existing_users_by_name() {
+        let conn = in_memory();
+        let admin = create_user(&conn, String::from("admin")).unwrap();
+        crate::user::set_admin(&conn, admin.clone(), true).unwrap();
+
+        // someone who set their name at the provider to that of the admin
+        let attacker = resolve(&conn, "attacker", "admin");
+        assert_ne!(attacker, admin);
+        assert_eq!(
+            crate::user::query_username_by_id(&conn, attacker.clone())
+                .unwrap()
+                .as_deref(),
+            Some("admin-2")
+        );
+        assert!(!crate::user::query_is_admin(&conn, attacker).unwrap());
+        assert_eq!(
+            query_user_id_by_username(&conn, String::from("admin")).unwrap(),
+            Some(admin)
+        );
+
+        let other = resolve(&conn, "other", "admin");
+        assert_eq!(
+            crate::user::query_username_by_id(&conn, other)
+                .unwrap()
+                .as_deref(),
+            Some("admin-3")
+        );
+    }
+
+    #[test]
+    fn names_new_users_validly() {
+        let conn = in_memory();
+        let user = resolve(&conn, "sub-2", "Bob Smith/ops");
+        assert_eq!(
+            crate::user::query_username_by_id(&conn, user)
+                .unwrap()
+                .as_deref(),
+            Some("Bob-Smith-ops")
+        );
+        assert!(query_user_by_identity(
+            &conn,
+            String::from("https://other.example.com"),
+            String::from("sub-2")
+        )
+        .unwrap()
+        .is_none());
+    }
+}
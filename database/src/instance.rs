@@ -0,0 +1,183 @@
+use crate::connection::DbBackend;
+use crate::models::NewPublicKey;
+use crate::schema::{instance, public_key};
+use crate::user;
+use diesel::prelude::*;
+
+/// Whether `git-server bootstrap` has set up the first admin.
+pub fn query_initialized<C: Connection<Backend = DbBackend>>(
+    conn: &C,
+) -> Result<bool, diesel::result::Error> {
+    Ok(instance::table
+        .select(instance::id)
+        .first::<i32>(conn)
+        .optional()?
+        .is_some())
+}
+
+/// The user `guochao` an early version of the first migration seeded into
+/// every installation.
+pub const LEGACY_SEED_USER: &str = "ceef801c-ee91-491d-93ce-e7682d12fa78";
+/// The MD5 fingerprints of the two keys seeded with [`LEGACY_SEED_USER`].
+pub const LEGACY_SEED_KEYS: [&str; 2] = [
+    "b0:9e:ac:48:63:fa:8a:71:b3:c3:8d:96:08:9b:fe:85",
+    "d1:01:7e:d1:5e:16:9c:af:d2:20:eb:33:26:8f:98:f8",
+];
+
+/// Whether the seeded user or either of their keys is still around.
+pub fn query_legacy_seed<C: Connection<Backend = DbBackend>>(
+    conn: &C,
+) -> Result<bool, diesel::result::Error> {
+    let keys = public_key::table
+        .filter(public_key::fingerprint.eq_any(LEGACY_SEED_KEYS))
+        .count()
+        .get_result::<i64>(conn)?;
+    Ok(keys > 0 || !user::query_users_by_id(conn, String::from(LEGACY_SEED_USER))?.is_empty())
+}
+
+/// Deletes the seeded user with their keys and locks, and the seeded keys
+/// wherever they were moved. If nobody else is left, the initialized mark
+/// the seed caused goes too, so `git-server bootstrap` works again. Returns
+/// whether there was anything to remove.
+pub fn remove_legacy_seed<C: Connection<Backend = DbBackend>>(
+    conn: &C,
+) -> Result<bool, diesel::result::Error> {
+    conn.transaction(|| {
+        let keys = diesel::delete(
+            public_key::table.filter(public_key::fingerprint.eq_any(LEGACY_SEED_KEYS)),
+        )
+        .execute(conn)?;
+        let users = user::purge_user(conn, String::from(LEGACY_SEED_USER))?;
+        let others = crate::schema::user::table.count().get_result::<i64>(conn)?;
+        if others == 0 {
+            diesel::delete(instance::table.filter(instance::initialized_by.is_null()))
+                .execute(conn)?;
+        }
+        Ok(keys + users > 0)
+    })
+}
+
+/// Creates the first admin with `key` and marks the instance initialized, all
+/// or nothing. Returns the uuid of the admin, and fails with a unique
+/// violation if the instance was initialized already.
+pub fn bootstrap<C: Connection<Backend = DbBackend>>(
+    conn: &C,
+    username: String,
+    key: &common::PublicKey,
+) -> Result<String, diesel::result::Error> {
+    conn.transaction(|| {
+        let uuid = user::create_user(conn, username)?;
+        user::set_admin(conn, uuid.clone(), true)?;
+        user::create_public_key(
+            conn,
+            NewPublicKey {
+                fingerprint: key.md5_fingerprint.clone(),
+                user: uuid.clone(),
+                key_type: Some(key.key_type.clone()),
+                key_data: Some(key.key_data.clone()),
+                sha256_fingerprint: Some(key.sha256_fingerprint.clone()),
+            },
+        )?;
+        diesel::insert_into(instance::table)
+            .values((instance::id.eq(1), instance::initialized_by.eq(&uuid)))
+            .execute(conn)?;
+        Ok(uuid)
+    })
+}
+
+#[cfg(all(test, feature = "sqlite"))]
+mod tests {
+    use super::*;
+    use crate::connection::in_memory;
+
+    fn key(fingerprint: &str) -> common::PublicKey {
+        common::PublicKey {
+            key_type: String::from("ssh-ed25519"),
+            key_data: String::from("AAAA"),
+            comment: None,
+            sha256_fingerprint: format!("SHA256:{}", fingerprint),
+            md5_fingerprint: String::from(fingerprint),
+        }
+    }
+
+    /// What an early version of the first migration inserted, which also
+    /// marked the instance initialized.
+    fn seed(conn: &crate::connection::DbConnection) {
+        diesel::insert_into(crate::schema::user::table)
+            .values(&crate::models::NewUser {
+                uuid: String::from(LEGACY_SEED_USER),
+                username: String::from("guochao"),
+            })
+            .execute(conn)
+            .unwrap();
+        for fingerprint in LEGACY_SEED_KEYS {
+            user::create_public_key(
+                conn,
+                NewPublicKey {
+                    fingerprint: String::from(fingerprint),
+                    user: String::from(LEGACY_SEED_USER),
+                    key_type: None,
+                    key_data: None,
+                    sha256_fingerprint: None,
+                },
+            )
+            .unwrap();
+        }
+        diesel::insert_into(instance::table)
+            .values(instance::id.eq(1))
+            .execute(conn)
+            .unwrap();
+    }
+
+    #[test]
+    fn removes_the_legacy_seed_on_request() {
+        let conn = in_memory();
+        assert!(!query_legacy_seed(&conn).unwrap());
+        assert!(!remove_legacy_seed(&conn).unwrap());
+
+        seed(&conn);
+        assert!(query_legacy_seed(&conn).unwrap());
+        assert!(query_initialized(&conn).unwrap());
+
+        assert!(remove_legacy_seed(&conn).unwrap());
+        assert!(!query_legacy_seed(&conn).unwrap());
+        assert!(user::query_users(&conn).unwrap().is_empty());
+        // nobody is left, so the first admin can be bootstrapped
+        assert!(!query_initialized(&conn).unwrap());
+        bootstrap(&conn, String::from("admin"), &key("aa:bb")).unwrap();
+    }
+
+    #[test]
+    fn keeps_other_users_when_removing_the_legacy_seed() {
+        let conn = in_memory();
+        seed(&conn);
+        let alice = user::create_user(&conn, String::from("alice")).unwrap();
+
+        assert!(remove_legacy_seed(&conn).unwrap());
+        let users = user::query_users(&conn).unwrap();
+        assert_eq!(users.len(), 1);
+        assert_eq!(users[0].uuid, alice);
+        assert!(query_initialized(&conn).unwrap());
+    }
+
+    #[test]
+    fn bootstraps_once() {
+        let conn = in_memory();
+        assert!(!query_initialized(&conn).unwrap());
+
+        let admin = bootstrap(&conn, String::from("admin"), &key("aa:bb")).unwrap();
+        assert!(query_initialized(&conn).unwrap());
+        assert!(user::query_is_admin(&conn, admin.clone()).unwrap());
+        assert_eq!(
+            user::query_public_keys_by_user(&conn, admin).unwrap()[0]
+                .sha256_fingerprint
+                .as_deref(),
+            Some("SHA256:aa:bb")
+        );
+
+        assert!(bootstrap(&conn, String::from("second"), &key("cc:dd")).is_err());
+        assert!(user::query_user_by_username(&conn, String::from("second"))
+            .unwrap()
+            .is_none());
+    }
+}
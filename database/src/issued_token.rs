@@ -0,0 +1,172 @@
+use crate::connection::DbBackend;
+use crate::models::{IssuedToken, NewIssuedToken};
+use crate::schema::issued_token;
+use diesel::prelude::*;
+
+/// Records a token about to be handed out, and forgets the expired ones.
+pub fn record_issued_token<C: Connection<Backend = DbBackend>>(
+    conn: &C,
+    new_token: NewIssuedToken,
+) -> Result<usize, diesel::result::Error> {
+    diesel::delete(
+        issued_token::table.filter(issued_token::expires_at.le(chrono::Utc::now().naive_utc())),
+    )
+    .execute(conn)?;
+    diesel::insert_into(issued_token::table)
+        .values(&new_token)
+        .execute(conn)
+}
+
+/// The unexpired and unrevoked tokens of `user`, newest first.
+pub fn query_active_tokens_by_user<C: Connection<Backend = DbBackend>>(
+    conn: &C,
+    user: String,
+) -> Result<Vec<IssuedToken>, diesel::result::Error> {
+    issued_token::table
+        .filter(issued_token::user.eq(user))
+        .filter(issued_token::expires_at.gt(chrono::Utc::now().naive_utc()))
+        .filter(issued_token::revoked_at.is_null())
+        .order(issued_token::issued_at.desc())
+        .load::<IssuedToken>(conn)
+}
+
+/// The jtis of revoked tokens that would otherwise still be valid.
+pub fn query_revoked_jtis<C: Connection<Backend = DbBackend>>(
+    conn: &C,
+) -> Result<Vec<String>, diesel::result::Error> {
+    issued_token::table
+        .select(issued_token::jti)
+        .filter(issued_token::expires_at.gt(chrono::Utc::now().naive_utc()))
+        .filter(issued_token::revoked_at.is_not_null())
+        .load::<String>(conn)
+}
+
+/// Revokes the token `jti` of `user`.
+pub fn revoke_issued_token<C: Connection<Backend = DbBackend>>(
+    conn: &C,
+    user: String,
+    jti: String,
+) -> Result<usize, diesel::result::Error> {
+    diesel::update(
+        issued_token::table
+            .filter(issued_token::jti.eq(jti))
+            .filter(issued_token::user.eq(user))
+            .filter(issued_token::revoked_at.is_null()),
+    )
+    .set(issued_token::revoked_at.eq(chrono::Utc::now().naive_utc()))
+    .execute(conn)
+}
+
+/// Revokes every active token of `user`, returning how many there were.
+pub fn revoke_tokens_by_user<C: Connection<Backend = DbBackend>>(
+    conn: &C,
+    user: String,
+) -> Result<usize, diesel::result::Error> {
+    diesel::update(
+        issued_token::table
+            .filter(issued_token::user.eq(user))
+            .filter(issued_token::expires_at.gt(chrono::Utc::now().naive_utc()))
+            .filter(issued_token::revoked_at.is_null()),
+    )
+    .set(issued_token::revoked_at.eq(chrono::Utc::now().naive_utc()))
+    .execute(conn)
+}
+
+/// Revokes every token issued to the ssh key with `key_fingerprint`, for when
+/// the key is removed.
+pub fn revoke_tokens_by_key<C: Connection<Backend = DbBackend>>(
+    conn: &C,
+    key_fingerprint: String,
+) -> Result<usize, diesel::result::Error> {
+    diesel::update(
+        issued_token::table
+            .filter(issued_token::key_fingerprint.eq(key_fingerprint))
+            .filter(issued_token::expires_at.gt(chrono::Utc::now().naive_utc()))
+            .filter(issued_token::revoked_at.is_null()),
+    )
+    .set(issued_token::revoked_at.eq(chrono::Utc::now().naive_utc()))
+    .execute(conn)
+}
+
+#[cfg(all(test, feature = "sqlite"))]
+mod tests {
+    use super::*;
+    use crate::connection::in_memory;
+    use chrono::{Duration, Utc};
+
+    fn new_token(jti: &str, user: &str, key: &str, expires_in: Duration) -> NewIssuedToken {
+        NewIssuedToken {
+            jti: String::from(jti),
+            user: Some(String::from(user)),
+            key_fingerprint: String::from(key),
+            is_deploy_key: false,
+            repository: String::from("grp/repo.git"),
+            operation: String::from("upload"),
+            expires_at: Utc::now().naive_utc() + expires_in,
+        }
+    }
+
+    #[test]
+    fn forgets_expired_tokens() {
+        let conn = in_memory();
+        let alice = crate::user::create_user(&conn, String::from("alice")).unwrap();
+        record_issued_token(
+            &conn,
+            new_token("old", &alice, "SHA256:a", -Duration::hours(1)),
+        )
+        .unwrap();
+        record_issued_token(
+            &conn,
+            new_token("new", &alice, "SHA256:a", Duration::hours(1)),
+        )
+        .unwrap();
+
+        assert_eq!(
+            issued_token::table
+                .count()
+                .get_result::<i64>(&conn)
+                .unwrap(),
+            1
+        );
+        let active = query_active_tokens_by_user(&conn, alice).unwrap();
+        assert_eq!(active.len(), 1);
+        assert_eq!(active[0].jti, "new");
+    }
+
+    #[test]
+    fn revokes_tokens_by_id_user_and_key() {
+        let conn = in_memory();
+        let alice = crate::user::create_user(&conn, String::from("alice")).unwrap();
+        for (jti, key) in [
+            ("one", "SHA256:a"),
+            ("two", "SHA256:a"),
+            ("three", "SHA256:b"),
+        ] {
+            record_issued_token(&conn, new_token(jti, &alice, key, Duration::hours(1))).unwrap();
+        }
+
+        assert_eq!(
+            revoke_issued_token(&conn, String::from("someone else"), String::from("one")).unwrap(),
+            0
+        );
+        assert_eq!(
+            revoke_issued_token(&conn, alice.clone(), String::from("one")).unwrap(),
+            1
+        );
+        assert_eq!(
+            revoke_issued_token(&conn, alice.clone(), String::from("one")).unwrap(),
+            0
+        );
+        assert_eq!(query_revoked_jtis(&conn).unwrap(), ["one"]);
+
+        assert_eq!(
+            revoke_tokens_by_key(&conn, String::from("SHA256:a")).unwrap(),
+            1
+        );
+        assert_eq!(revoke_tokens_by_user(&conn, alice.clone()).unwrap(), 1);
+        assert!(query_active_tokens_by_user(&conn, alice)
+            .unwrap()
+            .is_empty());
+        assert_eq!(query_revoked_jtis(&conn).unwrap().len(), 3);
+    }
+}
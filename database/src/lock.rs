@@ -0,0 +1,208 @@
+use crate::connection::DbBackend;
+use crate::models::{Lock, NewLock};
+use crate::schema::{lfs_lock, user};
+use diesel::prelude::*;
+
+/// A lock together with the username of its owner.
+pub type LockWithOwner = (Lock, String);
+
+/// Filters accepted by [`query_locks`]. Locks are ordered by id, and `cursor`
+/// is the id of the first lock to return.
+#[derive(Debug, Default, Clone)]
+pub struct LockFilter {
+    pub id: Option<String>,
+    pub path: Option<String>,
+    pub owner: Option<String>,
+    pub cursor: Option<String>,
+    pub limit: Option<i64>,
+}
+
+pub fn create_lock<C: Connection<Backend = DbBackend>>(
+    conn: &C,
+    repo: String,
+    path: String,
+    ref_name: Option<String>,
+    owner: String,
+) -> Result<String, diesel::result::Error> {
+    let id = uuid::Uuid::new_v4().to_string();
+    diesel::insert_into(lfs_lock::table)
+        .values(&NewLock {
+            id: id.clone(),
+            repo,
+            path,
+            ref_name,
+            owner,
+        })
+        .execute(conn)?;
+    Ok(id)
+}
+
+pub fn query_lock_by_id<C: Connection<Backend = DbBackend>>(
+    conn: &C,
+    repo: String,
+    id: String,
+) -> Result<Option<LockWithOwner>, diesel::result::Error> {
+    lfs_lock::table
+        .inner_join(user::table)
+        .select((lfs_lock::all_columns, user::username))
+        .filter(lfs_lock::repo.eq(repo))
+        .filter(lfs_lock::id.eq(id))
+        .first::<LockWithOwner>(conn)
+        .optional()
+}
+
+pub fn query_lock_by_path<C: Connection<Backend = DbBackend>>(
+    conn: &C,
+    repo: String,
+    path: String,
+) -> Result<Option<LockWithOwner>, diesel::result::Error> {
+    lfs_lock::table
+        .inner_join(user::table)
+        .select((lfs_lock::all_columns, user::username))
+        .filter(lfs_lock::repo.eq(repo))
+        .filter(lfs_lock::path.eq(path))
+        .first::<LockWithOwner>(conn)
+        .optional()
+}
+
+pub fn query_locks<C: Connection<Backend = DbBackend>>(
+    conn: &C,
+    repo: String,
+    filter: LockFilter,
+) -> Result<Vec<LockWithOwner>, diesel::result::Error> {
+    let mut query = lfs_lock::table
+        .inner_join(user::table)
+        .select((lfs_lock::all_columns, user::username))
+        .filter(lfs_lock::repo.eq(repo))
+        .order(lfs_lock::id.asc())
+        .into_boxed();
+    if let Some(id) = filter.id {
+        query = query.filter(lfs_lock::id.eq(id));
+    }
+    if let Some(path) = filter.path {
+        query = query.filter(lfs_lock::path.eq(path));
+    }
+    if let Some(owner) = filter.owner {
+        query = query.filter(lfs_lock::owner.eq(owner));
+    }
+    if let Some(cursor) = filter.cursor {
+        query = query.filter(lfs_lock::id.ge(cursor));
+    }
+    if let Some(limit) = filter.limit {
+        query = query.limit(limit);
+    }
+    query.load::<LockWithOwner>(conn)
+}
+
+pub fn delete_lock<C: Connection<Backend = DbBackend>>(
+    conn: &C,
+    id: String,
+) -> Result<usize, diesel::result::Error> {
+    diesel::delete(lfs_lock::table.filter(lfs_lock::id.eq(id))).execute(conn)
+}
+
+#[cfg(all(test, feature = "sqlite"))]
+mod tests {
+    use super::*;
+    use crate::connection::in_memory;
+
+    const REPO: &str = "grp/repo.git";
+
+    #[test]
+    fn finds_locks_with_their_owner() {
+        let conn = in_memory();
+        let alice = crate::user::create_user(&conn, String::from("alice")).unwrap();
+        let id = create_lock(
+            &conn,
+            String::from(REPO),
+            String::from("a.psd"),
+            Some(String::from("refs/heads/main")),
+            alice,
+        )
+        .unwrap();
+
+        let (lock, owner) = query_lock_by_id(&conn, String::from(REPO), id.clone())
+            .unwrap()
+            .unwrap();
+        assert_eq!(lock.path, "a.psd");
+        assert_eq!(owner, "alice");
+        assert_eq!(
+            query_lock_by_path(&conn, String::from(REPO), String::from("a.psd"))
+                .unwrap()
+                .unwrap()
+                .0
+                .id,
+            id
+        );
+        assert!(
+            query_lock_by_id(&conn, String::from("other/repo.git"), id.clone())
+                .unwrap()
+                .is_none()
+        );
+
+        assert_eq!(delete_lock(&conn, id.clone()).unwrap(), 1);
+        assert!(query_lock_by_id(&conn, String::from(REPO), id)
+            .unwrap()
+            .is_none());
+    }
+
+    #[test]
+    fn pages_through_locks_by_id() {
+        let conn = in_memory();
+        let alice = crate::user::create_user(&conn, String::from("alice")).unwrap();
+        let bob = crate::user::create_user(&conn, String::from("bob")).unwrap();
+        let mut ids: Vec<_> = ["a", "b", "c"]
+            .iter()
+            .map(|path| {
+                create_lock(
+                    &conn,
+                    String::from(REPO),
+                    String::from(*path),
+                    None,
+                    alice.clone(),
+                )
+                .unwrap()
+            })
+            .collect();
+        ids.sort();
+        create_lock(
+            &conn,
+            String::from(REPO),
+            String::from("d"),
+            None,
+            bob.clone(),
+        )
+        .unwrap();
+
+        let page = |cursor: Option<String>| {
+            query_locks(
+                &conn,
+                String::from(REPO),
+                LockFilter {
+                    owner: Some(alice.clone()),
+                    cursor,
+                    limit: Some(2),
+                    ..LockFilter::default()
+                },
+            )
+            .unwrap()
+            .into_iter()
+            .map(|(lock, _)| lock.id)
+            .collect::<Vec<_>>()
+        };
+        assert_eq!(page(None), ids[..2]);
+        assert_eq!(page(Some(ids[2].clone())), ids[2..]);
+
+        let by_path = query_locks(
+            &conn,
+            String::from(REPO),
+            LockFilter {
+                path: Some(String::from("d")),
+                ..LockFilter::default()
+            },
+        )
+        .unwrap();
+        assert_eq!(by_path.len(), 1);
+        assert_eq!(by_path[0].1, "bob");
+    }
+}
@@ -0,0 +1,133 @@
+//! The migrations below `migrations/<backend>`, embedded at build time so
+//! deployments need neither the diesel CLI nor the migration folders.
+//! Versions are recorded in `__diesel_schema_migrations` like the diesel CLI
+//! does, so databases migrated with it are picked up where they are.
+
+use diesel::connection::SimpleConnection;
+use diesel_migrations::{Migration, MigrationConnection, RunMigrationsError};
+
+struct EmbeddedMigration {
+    version: &'static str,
+    name: &'static str,
+    up_sql: &'static str,
+}
+
+impl Migration for EmbeddedMigration {
+    fn version(&self) -> &str {
+        self.version
+    }
+
+    fn run(&self, conn: &dyn SimpleConnection) -> Result<(), RunMigrationsError> {
+        conn.batch_execute(self.up_sql).map_err(Into::into)
+    }
+
+    fn revert(&self, _conn: &dyn SimpleConnection) -> Result<(), RunMigrationsError> {
+        unreachable!("embedded migrations are never reverted")
+    }
+}
+
+const MIGRATIONS: &[EmbeddedMigration] = include!(concat!(env!("OUT_DIR"), "/migrations.rs"));
+
+/// The names of the migrations not applied yet, oldest first.
+pub fn pending_migrations<C: MigrationConnection>(
+    conn: &C,
+) -> Result<Vec<&'static str>, RunMigrationsError> {
+    let applied = match conn.previously_run_migration_versions() {
+        Ok(applied) => applied,
+        // a database that was never migrated lacks the table
+        Err(diesel::result::Error::DatabaseError(_, _)) => Default::default(),
+        Err(err) => return Err(err.into()),
+    };
+    Ok(MIGRATIONS
+        .iter()
+        .filter(|migration| !applied.contains(migration.version))
+        .map(|migration| migration.name)
+        .collect())
+}
+
+/// Applies the pending migrations, returning their names.
+pub fn run_pending_migrations<C: MigrationConnection>(
+    conn: &C,
+) -> Result<Vec<&'static str>, RunMigrationsError> {
+    diesel_migrations::setup_database(conn)?;
+    let pending = pending_migrations(conn)?;
+    diesel_migrations::run_migrations(
+        conn,
+        MIGRATIONS
+            .iter()
+            .map(|migration| migration as &dyn Migration),
+        &mut std::io::sink(),
+    )?;
+    Ok(pending)
+}
+
+/// Fails unless every migration was applied, for whatever must not change
+/// the schema itself.
+pub fn check_migrations<C: MigrationConnection>(conn: &C) -> Result<(), String> {
+    let pending = pending_migrations(conn)
+        .map_err(|err| format!("failed to read the applied migrations: {}", err))?;
+    if pending.is_empty() {
+        Ok(())
+    } else {
+        Err(format!(
+            "the database lacks {} migration(s), start git-server without --check-migrations to apply them: {}",
+            pending.len(),
+            pending.join(", ")
+        ))
+    }
+}
+
+#[cfg(all(test, feature = "sqlite"))]
+mod tests {
+    use super::*;
+    use diesel::Connection;
+
+    #[test]
+    fn applies_every_migration_once() {
+        let conn = diesel::SqliteConnection::establish(":memory:").unwrap();
+        assert_eq!(pending_migrations(&conn).unwrap().len(), MIGRATIONS.len());
+        assert!(check_migrations(&conn).is_err());
+
+        let applied = run_pending_migrations(&conn).unwrap();
+        assert_eq!(applied.len(), MIGRATIONS.len());
+        assert!(pending_migrations(&conn).unwrap().is_empty());
+        assert!(check_migrations(&conn).is_ok());
+        assert!(run_pending_migrations(&conn).unwrap().is_empty());
+    }
+
+    /// What the first migration used to insert.
+    const SEED: &str = r#"
+        INSERT INTO "user" (uuid, username) VALUES('ceef801c-ee91-491d-93ce-e7682d12fa78', 'guochao');
+        INSERT INTO public_key(fingerprint, "user") VALUES('b0:9e:ac:48:63:fa:8a:71:b3:c3:8d:96:08:9b:fe:85', 'ceef801c-ee91-491d-93ce-e7682d12fa78');
+        INSERT INTO public_key(fingerprint, "user") VALUES('d1:01:7e:d1:5e:16:9c:af:d2:20:eb:33:26:8f:98:f8', 'ceef801c-ee91-491d-93ce-e7682d12fa78');
+    "#;
+
+    /// Migrates a database that got the seeded user from the first migration.
+    fn migrate_seeded() -> diesel::SqliteConnection {
+        let conn = diesel::SqliteConnection::establish(":memory:").unwrap();
+        conn.batch_execute("PRAGMA foreign_keys = ON;").unwrap();
+        for (i, migration) in MIGRATIONS.iter().enumerate() {
+            conn.batch_execute(migration.up_sql).unwrap();
+            if i == 0 {
+                conn.batch_execute(SEED).unwrap();
+            }
+        }
+        conn
+    }
+
+    #[test]
+    fn leaves_the_seeded_user_alone() {
+        let conn = migrate_seeded();
+        let users = crate::user::query_users(&conn).unwrap();
+        assert_eq!(users.len(), 1);
+        assert_eq!(users[0].uuid, crate::instance::LEGACY_SEED_USER);
+        assert_eq!(
+            crate::user::query_public_keys_by_user(&conn, users[0].uuid.clone())
+                .unwrap()
+                .len(),
+            2
+        );
+        assert!(crate::instance::query_legacy_seed(&conn).unwrap());
+        assert!(crate::instance::query_initialized(&conn).unwrap());
+    }
+}
@@ -1,4 +1,6 @@
-use super::schema::{user, public_key};
+use serde::Serialize;
+
+use super::schema::{access_token, audit_log, deploy_key, group, group_member, issued_token, lfs_lock, public_key, recovery_code, repository, repository_permission, session, ssh_certificate, user, user_identity, user_totp};
 
 #[derive(Queryable)]
 pub struct User {
@@ -7,7 +9,13 @@ pub struct User {
 
     pub created_at: chrono::NaiveDateTime,
     pub updated_at: chrono::NaiveDateTime,
-    pub deleted_at: chrono::NaiveDateTime,
+    pub deleted_at: Option<chrono::NaiveDateTime>,
+    /// Admins have admin access to every repository.
+    pub is_admin: bool,
+    /// argon2 hash for signing in on the web, `None` if the user cannot.
+    pub password_hash: Option<String>,
+    /// Disabled users cannot sign in, nor use their keys and tokens.
+    pub disabled_at: Option<chrono::NaiveDateTime>,
 }
 
 #[derive(Insertable)]
@@ -19,8 +27,15 @@ pub struct NewUser {
 
 #[derive(Queryable)]
 pub struct PublicKey {
+    /// Legacy MD5 fingerprint, like `b0:9e:ac:...`.
     pub fingerprint: String,
     pub user: String,
+    /// Key type and base64 blob, as in an `authorized_keys` line. Keys
+    /// registered by their fingerprint alone have neither.
+    pub key_type: Option<String>,
+    pub key_data: Option<String>,
+    /// Like `SHA256:...`, as printed by `ssh-keygen -l`.
+    pub sha256_fingerprint: Option<String>,
 }
 
 #[derive(Insertable)]
@@ -28,4 +43,304 @@ pub struct PublicKey {
 pub struct NewPublicKey {
     pub fingerprint: String,
     pub user: String,
-}
\ No newline at end of file
+    pub key_type: Option<String>,
+    pub key_data: Option<String>,
+    pub sha256_fingerprint: Option<String>,
+}
+
+#[derive(Queryable)]
+pub struct Lock {
+    pub id: String,
+    pub repo: String,
+    pub path: String,
+    pub ref_name: Option<String>,
+    pub owner: String,
+
+    pub locked_at: chrono::NaiveDateTime,
+}
+
+#[derive(Insertable)]
+#[table_name="lfs_lock"]
+pub struct NewLock {
+    pub id: String,
+    pub repo: String,
+    pub path: String,
+    pub ref_name: Option<String>,
+    pub owner: String,
+}
+
+#[derive(Queryable)]
+pub struct Group {
+    pub uuid: String,
+    pub name: String,
+
+    pub created_at: chrono::NaiveDateTime,
+}
+
+#[derive(Insertable)]
+#[table_name="group"]
+pub struct NewGroup {
+    pub uuid: String,
+    pub name: String,
+}
+
+#[derive(Queryable, Insertable)]
+#[table_name="group_member"]
+pub struct GroupMember {
+    pub group: String,
+    pub user: String,
+}
+
+#[derive(Queryable)]
+pub struct Repository {
+    /// The normalized path below the served root, like `group/project.git`.
+    pub path: String,
+    /// Public repositories can be read by anyone, even anonymously.
+    pub is_public: bool,
+
+    pub created_at: chrono::NaiveDateTime,
+}
+
+#[derive(Insertable)]
+#[table_name="repository"]
+pub struct NewRepository {
+    pub path: String,
+    pub is_public: bool,
+}
+
+/// Grants `access` on `repository` to either a user or a group.
+#[derive(Queryable)]
+pub struct RepositoryPermission {
+    pub id: i32,
+    pub repository: String,
+    pub user: Option<String>,
+    pub group: Option<String>,
+    /// One of `read`, `write` or `admin`, see [`common::Access`].
+    pub access: String,
+}
+
+#[derive(Insertable)]
+#[table_name="repository_permission"]
+pub struct NewRepositoryPermission {
+    pub repository: String,
+    pub user: Option<String>,
+    pub group: Option<String>,
+    pub access: String,
+}
+
+/// A personal access token, known by the sha256 of its secret only.
+#[derive(Debug, Queryable)]
+pub struct AccessToken {
+    pub id: String,
+    pub user: String,
+    pub name: String,
+    pub token_hash: String,
+    /// The highest access the token grants, see [`common::Access`].
+    pub scope: String,
+    /// The only repository the token works for, any if `None`.
+    pub repository: Option<String>,
+
+    pub created_at: chrono::NaiveDateTime,
+    pub expires_at: chrono::NaiveDateTime,
+}
+
+#[derive(Insertable)]
+#[table_name="access_token"]
+pub struct NewAccessToken {
+    pub id: String,
+    pub user: String,
+    pub name: String,
+    pub token_hash: String,
+    pub scope: String,
+    pub repository: Option<String>,
+    pub expires_at: chrono::NaiveDateTime,
+}
+
+/// A web session, known by the sha256 of the id in its cookie.
+#[derive(Queryable)]
+pub struct Session {
+    pub id_hash: String,
+    pub user: String,
+    /// Forms posted within the session have to carry this.
+    pub csrf_token: String,
+
+    pub created_at: chrono::NaiveDateTime,
+    pub expires_at: chrono::NaiveDateTime,
+    /// Signed in with a password or at the identity provider, but the
+    /// second factor is still missing.
+    pub second_factor_pending: bool,
+    pub second_factor_failures: i32,
+    pub second_factor_at: Option<chrono::NaiveDateTime>,
+}
+
+#[derive(Insertable)]
+#[table_name="session"]
+pub struct NewSession {
+    pub id_hash: String,
+    pub user: String,
+    pub csrf_token: String,
+    pub expires_at: chrono::NaiveDateTime,
+    pub second_factor_pending: bool,
+}
+
+#[derive(Queryable)]
+pub struct UserIdentity {
+    /// The `iss` and `sub` claims of the ID tokens.
+    pub issuer: String,
+    pub subject: String,
+    pub user: String,
+
+    pub created_at: chrono::NaiveDateTime,
+}
+
+#[derive(Insertable)]
+#[table_name="user_identity"]
+pub struct NewUserIdentity {
+    pub issuer: String,
+    pub subject: String,
+    pub user: String,
+}
+
+#[derive(Queryable)]
+pub struct UserTotp {
+    pub user: String,
+    /// Base32, as shown to authenticator apps.
+    pub secret: String,
+    pub last_step: Option<i64>,
+
+    pub created_at: chrono::NaiveDateTime,
+    /// `None` while enrolling, until the first code was entered.
+    pub confirmed_at: Option<chrono::NaiveDateTime>,
+}
+
+#[derive(Insertable)]
+#[table_name="user_totp"]
+pub struct NewUserTotp {
+    pub user: String,
+    pub secret: String,
+}
+
+#[derive(Insertable)]
+#[table_name="recovery_code"]
+pub struct NewRecoveryCode {
+    pub user: String,
+    pub code_hash: String,
+}
+
+#[derive(Debug, Queryable)]
+pub struct DeployKey {
+    /// Like `SHA256:...`, as printed by `ssh-keygen -l`.
+    pub sha256_fingerprint: String,
+    pub repository: String,
+    pub name: String,
+    pub key_type: String,
+    pub key_data: String,
+    /// `read` or `write`, see [`common::Access`].
+    pub access: String,
+    pub created_by: Option<String>,
+
+    pub created_at: chrono::NaiveDateTime,
+}
+
+#[derive(Insertable)]
+#[table_name="deploy_key"]
+pub struct NewDeployKey {
+    pub sha256_fingerprint: String,
+    pub repository: String,
+    pub name: String,
+    pub key_type: String,
+    pub key_data: String,
+    pub access: String,
+    pub created_by: Option<String>,
+}
+
+/// A token minted by git-lfs-authenticate, see [`common::Claims`].
+#[derive(Debug, Queryable)]
+pub struct IssuedToken {
+    pub jti: String,
+    /// `None` for tokens of deploy keys.
+    pub user: Option<String>,
+    /// The SHA-256 fingerprint of the ssh key the token was issued to.
+    pub key_fingerprint: String,
+    pub is_deploy_key: bool,
+    pub repository: String,
+    pub operation: String,
+
+    pub issued_at: chrono::NaiveDateTime,
+    pub expires_at: chrono::NaiveDateTime,
+    pub revoked_at: Option<chrono::NaiveDateTime>,
+}
+
+#[derive(Insertable)]
+#[table_name="issued_token"]
+pub struct NewIssuedToken {
+    pub jti: String,
+    pub user: Option<String>,
+    pub key_fingerprint: String,
+    pub is_deploy_key: bool,
+    pub repository: String,
+    pub operation: String,
+    pub expires_at: chrono::NaiveDateTime,
+}
+
+/// An entry of the audit trail, see [`crate::audit`].
+#[derive(Debug, Queryable, Serialize)]
+pub struct AuditEntry {
+    pub id: i64,
+    /// Like `login` or `lfs.upload`, see [`crate::audit`] for the list.
+    pub action: String,
+    /// The username, `deploy-key:<fingerprint>`, or `anonymous`.
+    pub actor: String,
+    /// The uuid of the user acting, if it was a known user.
+    pub actor_user: Option<String>,
+    pub repository: Option<String>,
+    pub ip: Option<String>,
+    /// `success`, `failure` or `denied`.
+    pub outcome: String,
+    pub detail: Option<String>,
+
+    pub created_at: chrono::NaiveDateTime,
+}
+
+#[derive(Insertable)]
+#[table_name="audit_log"]
+pub struct NewAuditEntry {
+    pub action: String,
+    pub actor: String,
+    pub actor_user: Option<String>,
+    pub repository: Option<String>,
+    pub ip: Option<String>,
+    pub outcome: String,
+    pub detail: Option<String>,
+}
+
+/// An OpenSSH user certificate accepted by git-authorized-keys.
+#[derive(Debug, Queryable)]
+pub struct SshCertificate {
+    /// The SHA-256 fingerprint of the whole certificate blob.
+    pub fingerprint: String,
+    pub user: String,
+    /// The principal of the certificate that named the user.
+    pub principal: String,
+    pub ca_fingerprint: String,
+    pub key_id: String,
+    pub serial: String,
+    pub valid_after: chrono::NaiveDateTime,
+    /// `None` for certificates that never expire.
+    pub valid_before: Option<chrono::NaiveDateTime>,
+
+    pub created_at: chrono::NaiveDateTime,
+}
+
+#[derive(Insertable)]
+#[table_name="ssh_certificate"]
+pub struct NewSshCertificate {
+    pub fingerprint: String,
+    pub user: String,
+    pub principal: String,
+    pub ca_fingerprint: String,
+    pub key_id: String,
+    pub serial: String,
+    pub valid_after: chrono::NaiveDateTime,
+    pub valid_before: Option<chrono::NaiveDateTime>,
+}
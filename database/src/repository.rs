@@ -0,0 +1,178 @@
+use crate::connection::DbBackend;
+use crate::models::{NewRepository, NewRepositoryPermission, Repository, RepositoryPermission};
+use crate::schema::{repository, repository_permission};
+use common::Access;
+use diesel::prelude::*;
+
+/// Who a permission is granted to.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Grantee {
+    User(String),
+    Group(String),
+}
+
+pub fn create_repository<C: Connection<Backend = DbBackend>>(
+    conn: &C,
+    path: String,
+    is_public: bool,
+) -> Result<usize, diesel::result::Error> {
+    diesel::insert_into(repository::table)
+        .values(&NewRepository { path, is_public })
+        .execute(conn)
+}
+
+pub fn query_repository<C: Connection<Backend = DbBackend>>(
+    conn: &C,
+    path: String,
+) -> Result<Option<Repository>, diesel::result::Error> {
+    repository::table
+        .filter(repository::path.eq(path))
+        .first::<Repository>(conn)
+        .optional()
+}
+
+pub fn query_repositories<C: Connection<Backend = DbBackend>>(
+    conn: &C,
+) -> Result<Vec<Repository>, diesel::result::Error> {
+    repository::table
+        .order(repository::path.asc())
+        .load::<Repository>(conn)
+}
+
+pub fn set_repository_public<C: Connection<Backend = DbBackend>>(
+    conn: &C,
+    path: String,
+    is_public: bool,
+) -> Result<usize, diesel::result::Error> {
+    diesel::update(repository::table.filter(repository::path.eq(path)))
+        .set(repository::is_public.eq(is_public))
+        .execute(conn)
+}
+
+pub fn query_permissions<C: Connection<Backend = DbBackend>>(
+    conn: &C,
+    path: String,
+) -> Result<Vec<RepositoryPermission>, diesel::result::Error> {
+    repository_permission::table
+        .filter(repository_permission::repository.eq(path))
+        .order(repository_permission::id.asc())
+        .load::<RepositoryPermission>(conn)
+}
+
+/// Grants `access` on the repository at `path`, replacing whatever the
+/// grantee had before.
+pub fn grant_permission<C: Connection<Backend = DbBackend>>(
+    conn: &C,
+    path: String,
+    grantee: Grantee,
+    access: Access,
+) -> Result<usize, diesel::result::Error> {
+    let (user, group) = match grantee {
+        Grantee::User(user) => (Some(user), None),
+        Grantee::Group(group) => (None, Some(group)),
+    };
+    conn.transaction(|| {
+        let on_repository =
+            repository_permission::table.filter(repository_permission::repository.eq(&path));
+        match (&user, &group) {
+            (Some(user), _) => {
+                diesel::delete(on_repository.filter(repository_permission::user.eq(user)))
+                    .execute(conn)?
+            }
+            (_, Some(group)) => {
+                diesel::delete(on_repository.filter(repository_permission::group.eq(group)))
+                    .execute(conn)?
+            }
+            (None, None) => 0,
+        };
+        diesel::insert_into(repository_permission::table)
+            .values(&NewRepositoryPermission {
+                repository: path,
+                user,
+                group,
+                access: access.to_string(),
+            })
+            .execute(conn)
+    })
+}
+
+pub fn revoke_permission<C: Connection<Backend = DbBackend>>(
+    conn: &C,
+    path: String,
+    grantee: Grantee,
+) -> Result<usize, diesel::result::Error> {
+    let query = repository_permission::table.filter(repository_permission::repository.eq(path));
+    match grantee {
+        Grantee::User(user) => {
+            diesel::delete(query.filter(repository_permission::user.eq(user))).execute(conn)
+        }
+        Grantee::Group(group) => {
+            diesel::delete(query.filter(repository_permission::group.eq(group))).execute(conn)
+        }
+    }
+}
+
+#[cfg(all(test, feature = "sqlite"))]
+mod tests {
+    use super::*;
+    use crate::connection::in_memory;
+
+    #[test]
+    fn registers_repositories() {
+        let conn = in_memory();
+        create_repository(&conn, String::from("b/repo.git"), false).unwrap();
+        create_repository(&conn, String::from("a/repo.git"), true).unwrap();
+        assert!(create_repository(&conn, String::from("a/repo.git"), false).is_err());
+
+        let paths: Vec<_> = query_repositories(&conn)
+            .unwrap()
+            .into_iter()
+            .map(|repository| repository.path)
+            .collect();
+        assert_eq!(paths, ["a/repo.git", "b/repo.git"]);
+
+        set_repository_public(&conn, String::from("b/repo.git"), true).unwrap();
+        assert!(
+            query_repository(&conn, String::from("b/repo.git"))
+                .unwrap()
+                .unwrap()
+                .is_public
+        );
+        assert!(query_repository(&conn, String::from("c/repo.git"))
+            .unwrap()
+            .is_none());
+    }
+
+    #[test]
+    fn replaces_and_revokes_permissions() {
+        let conn = in_memory();
+        let alice = crate::user::create_user(&conn, String::from("alice")).unwrap();
+        let path = String::from("grp/repo.git");
+        create_repository(&conn, path.clone(), false).unwrap();
+
+        grant_permission(
+            &conn,
+            path.clone(),
+            Grantee::User(alice.clone()),
+            Access::Read,
+        )
+        .unwrap();
+        grant_permission(
+            &conn,
+            path.clone(),
+            Grantee::User(alice.clone()),
+            Access::Admin,
+        )
+        .unwrap();
+        let permissions = query_permissions(&conn, path.clone()).unwrap();
+        assert_eq!(permissions.len(), 1);
+        assert_eq!(permissions[0].user.as_deref(), Some(alice.as_str()));
+        assert_eq!(permissions[0].access, "admin");
+
+        assert_eq!(
+            revoke_permission(&conn, path.clone(), Grantee::User(alice)).unwrap(),
+            1
+        );
+        assert!(query_permissions(&conn, path).unwrap().is_empty());
+    }
+}
@@ -1,7 +1,151 @@
+table! {
+    access_token (id) {
+        id -> Char,
+        user -> Char,
+        name -> Varchar,
+        token_hash -> Char,
+        scope -> Varchar,
+        repository -> Nullable<Varchar>,
+        created_at -> Timestamp,
+        expires_at -> Timestamp,
+    }
+}
+
+table! {
+    audit_log (id) {
+        id -> Bigint,
+        action -> Varchar,
+        actor -> Varchar,
+        actor_user -> Nullable<Char>,
+        repository -> Nullable<Varchar>,
+        ip -> Nullable<Varchar>,
+        outcome -> Varchar,
+        detail -> Nullable<Text>,
+        created_at -> Timestamp,
+    }
+}
+
+table! {
+    deploy_key (sha256_fingerprint) {
+        sha256_fingerprint -> Varchar,
+        repository -> Varchar,
+        name -> Varchar,
+        key_type -> Varchar,
+        key_data -> Text,
+        access -> Varchar,
+        created_by -> Nullable<Char>,
+        created_at -> Timestamp,
+    }
+}
+
+table! {
+    group (uuid) {
+        uuid -> Char,
+        name -> Varchar,
+        created_at -> Timestamp,
+    }
+}
+
+table! {
+    group_member (group, user) {
+        group -> Char,
+        user -> Char,
+    }
+}
+
+table! {
+    instance (id) {
+        id -> Integer,
+        initialized_by -> Nullable<Char>,
+        initialized_at -> Timestamp,
+    }
+}
+
+table! {
+    issued_token (jti) {
+        jti -> Char,
+        user -> Nullable<Char>,
+        key_fingerprint -> Varchar,
+        is_deploy_key -> Bool,
+        repository -> Varchar,
+        operation -> Varchar,
+        issued_at -> Timestamp,
+        expires_at -> Timestamp,
+        revoked_at -> Nullable<Timestamp>,
+    }
+}
+
+table! {
+    lfs_lock (id) {
+        id -> Char,
+        repo -> Varchar,
+        path -> Varchar,
+        ref_name -> Nullable<Varchar>,
+        owner -> Char,
+        locked_at -> Timestamp,
+    }
+}
+
 table! {
     public_key (fingerprint) {
         fingerprint -> Char,
         user -> Char,
+        key_type -> Nullable<Varchar>,
+        key_data -> Nullable<Text>,
+        sha256_fingerprint -> Nullable<Char>,
+    }
+}
+
+table! {
+    recovery_code (user, code_hash) {
+        user -> Char,
+        code_hash -> Char,
+        created_at -> Timestamp,
+    }
+}
+
+table! {
+    repository (path) {
+        path -> Varchar,
+        is_public -> Bool,
+        created_at -> Timestamp,
+    }
+}
+
+table! {
+    repository_permission (id) {
+        id -> Integer,
+        repository -> Varchar,
+        user -> Nullable<Char>,
+        group -> Nullable<Char>,
+        access -> Varchar,
+    }
+}
+
+table! {
+    session (id_hash) {
+        id_hash -> Char,
+        user -> Char,
+        csrf_token -> Char,
+        created_at -> Timestamp,
+        expires_at -> Timestamp,
+        second_factor_pending -> Bool,
+        second_factor_failures -> Integer,
+        second_factor_at -> Nullable<Timestamp>,
+    }
+}
+
+table! {
+    ssh_certificate (fingerprint) {
+        fingerprint -> Varchar,
+        user -> Char,
+        principal -> Varchar,
+        ca_fingerprint -> Varchar,
+        key_id -> Varchar,
+        serial -> Varchar,
+        valid_after -> Timestamp,
+        valid_before -> Nullable<Timestamp>,
+        created_at -> Timestamp,
     }
 }
 
@@ -11,13 +155,64 @@ table! {
         username -> Text,
         created_at -> Timestamp,
         updated_at -> Timestamp,
-        deleted_at -> Timestamp,
+        deleted_at -> Nullable<Timestamp>,
+        is_admin -> Bool,
+        password_hash -> Nullable<Varchar>,
+        disabled_at -> Nullable<Timestamp>,
+    }
+}
+
+table! {
+    user_identity (issuer, subject) {
+        issuer -> Varchar,
+        subject -> Varchar,
+        user -> Char,
+        created_at -> Timestamp,
+    }
+}
+
+table! {
+    user_totp (user) {
+        user -> Char,
+        secret -> Varchar,
+        last_step -> Nullable<Bigint>,
+        created_at -> Timestamp,
+        confirmed_at -> Nullable<Timestamp>,
     }
 }
 
+joinable!(access_token -> user (user));
+joinable!(deploy_key -> repository (repository));
+joinable!(deploy_key -> user (created_by));
+joinable!(group_member -> group (group));
+joinable!(group_member -> user (user));
+joinable!(instance -> user (initialized_by));
+joinable!(issued_token -> user (user));
+joinable!(lfs_lock -> user (owner));
 joinable!(public_key -> user (user));
+joinable!(recovery_code -> user (user));
+joinable!(repository_permission -> repository (repository));
+joinable!(session -> user (user));
+joinable!(ssh_certificate -> user (user));
+joinable!(user_identity -> user (user));
+joinable!(user_totp -> user (user));
 
 allow_tables_to_appear_in_same_query!(
+    access_token,
+    audit_log,
+    deploy_key,
+    group,
+    group_member,
+    instance,
+    issued_token,
+    lfs_lock,
     public_key,
+    recovery_code,
+    repository,
+    repository_permission,
+    session,
+    ssh_certificate,
     user,
+    user_identity,
+    user_totp,
 );
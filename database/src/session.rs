@@ -0,0 +1,198 @@
+use crate::connection::DbBackend;
+use crate::models::{NewSession, Session};
+use crate::schema::{session, user};
+use diesel::prelude::*;
+
+/// A session together with the username of its user.
+pub type SessionWithUsername = (Session, String);
+
+pub fn create_session<C: Connection<Backend = DbBackend>>(
+    conn: &C,
+    new_session: NewSession,
+) -> Result<usize, diesel::result::Error> {
+    diesel::insert_into(session::table)
+        .values(&new_session)
+        .execute(conn)
+}
+
+/// The unexpired session whose id hashes to `id_hash`, unless its user is
+/// disabled or deleted.
+pub fn query_session<C: Connection<Backend = DbBackend>>(
+    conn: &C,
+    id_hash: String,
+) -> Result<Option<SessionWithUsername>, diesel::result::Error> {
+    session::table
+        .inner_join(user::table)
+        .select((session::all_columns, user::username))
+        .filter(session::id_hash.eq(id_hash))
+        .filter(session::expires_at.gt(chrono::Utc::now().naive_utc()))
+        .filter(user::disabled_at.is_null())
+        .filter(user::deleted_at.is_null())
+        .first::<SessionWithUsername>(conn)
+        .optional()
+}
+
+pub fn delete_session<C: Connection<Backend = DbBackend>>(
+    conn: &C,
+    id_hash: String,
+) -> Result<usize, diesel::result::Error> {
+    diesel::delete(session::table.filter(session::id_hash.eq(id_hash))).execute(conn)
+}
+
+/// The unexpired, fully signed in sessions of `user`, newest first.
+pub fn query_sessions_by_user<C: Connection<Backend = DbBackend>>(
+    conn: &C,
+    user: String,
+) -> Result<Vec<Session>, diesel::result::Error> {
+    session::table
+        .filter(session::user.eq(user))
+        .filter(session::second_factor_pending.eq(false))
+        .filter(session::expires_at.gt(chrono::Utc::now().naive_utc()))
+        .order(session::created_at.desc())
+        .load::<Session>(conn)
+}
+
+/// Signs `user` out of the session `id_hash`, which has to be theirs.
+pub fn delete_session_of_user<C: Connection<Backend = DbBackend>>(
+    conn: &C,
+    user: String,
+    id_hash: String,
+) -> Result<usize, diesel::result::Error> {
+    diesel::delete(
+        session::table
+            .filter(session::user.eq(user))
+            .filter(session::id_hash.eq(id_hash)),
+    )
+    .execute(conn)
+}
+
+pub fn delete_expired_sessions<C: Connection<Backend = DbBackend>>(
+    conn: &C,
+) -> Result<usize, diesel::result::Error> {
+    diesel::delete(session::table.filter(session::expires_at.le(chrono::Utc::now().naive_utc())))
+        .execute(conn)
+}
+
+/// Completes a pending session once the second factor was checked, and
+/// gives it its full lifetime.
+pub fn complete_second_factor<C: Connection<Backend = DbBackend>>(
+    conn: &C,
+    id_hash: String,
+    expires_at: chrono::NaiveDateTime,
+) -> Result<usize, diesel::result::Error> {
+    diesel::update(session::table.filter(session::id_hash.eq(id_hash)))
+        .set((
+            session::second_factor_pending.eq(false),
+            session::second_factor_failures.eq(0),
+            session::second_factor_at.eq(chrono::Utc::now().naive_utc()),
+            session::expires_at.eq(expires_at),
+        ))
+        .execute(conn)
+}
+
+/// Counts a wrong code entered for a pending session, returning how many
+/// there were so far.
+pub fn record_second_factor_failure<C: Connection<Backend = DbBackend>>(
+    conn: &C,
+    id_hash: String,
+) -> Result<i32, diesel::result::Error> {
+    conn.transaction(|| {
+        diesel::update(session::table.filter(session::id_hash.eq(&id_hash)))
+            .set(session::second_factor_failures.eq(session::second_factor_failures + 1))
+            .execute(conn)?;
+        session::table
+            .select(session::second_factor_failures)
+            .filter(session::id_hash.eq(id_hash))
+            .first::<i32>(conn)
+    })
+}
+
+#[cfg(all(test, feature = "sqlite"))]
+mod tests {
+    use super::*;
+    use crate::connection::in_memory;
+    use chrono::{Duration, Utc};
+
+    fn new_session(user: &str, id_hash: &str, expires_at: chrono::NaiveDateTime) -> NewSession {
+        NewSession {
+            id_hash: String::from(id_hash),
+            user: String::from(user),
+            csrf_token: String::from("csrf"),
+            expires_at,
+            second_factor_pending: false,
+        }
+    }
+
+    #[test]
+    fn finds_unexpired_sessions_with_their_username() {
+        let conn = in_memory();
+        let alice = crate::user::create_user(&conn, String::from("alice")).unwrap();
+        let now = Utc::now().naive_utc();
+        create_session(&conn, new_session(&alice, "live", now + Duration::hours(1))).unwrap();
+        create_session(
+            &conn,
+            new_session(&alice, "stale", now - Duration::hours(1)),
+        )
+        .unwrap();
+
+        let (session, username) = query_session(&conn, String::from("live")).unwrap().unwrap();
+        assert_eq!(session.user, alice);
+        assert_eq!(username, "alice");
+        assert!(query_session(&conn, String::from("stale"))
+            .unwrap()
+            .is_none());
+        assert_eq!(
+            query_sessions_by_user(&conn, alice.clone()).unwrap().len(),
+            1
+        );
+
+        assert_eq!(delete_expired_sessions(&conn).unwrap(), 1);
+        assert_eq!(
+            delete_session_of_user(&conn, String::from("someone else"), String::from("live"))
+                .unwrap(),
+            0
+        );
+        assert_eq!(
+            delete_session_of_user(&conn, alice, String::from("live")).unwrap(),
+            1
+        );
+    }
+
+    #[test]
+    fn completes_the_second_factor() {
+        let conn = in_memory();
+        let alice = crate::user::create_user(&conn, String::from("alice")).unwrap();
+        let now = Utc::now().naive_utc();
+        create_session(
+            &conn,
+            NewSession {
+                second_factor_pending: true,
+                ..new_session(&alice, "pending", now + Duration::minutes(5))
+            },
+        )
+        .unwrap();
+        assert!(query_sessions_by_user(&conn, alice.clone())
+            .unwrap()
+            .is_empty());
+
+        assert_eq!(
+            record_second_factor_failure(&conn, String::from("pending")).unwrap(),
+            1
+        );
+        assert_eq!(
+            record_second_factor_failure(&conn, String::from("pending")).unwrap(),
+            2
+        );
+
+        complete_second_factor(&conn, String::from("pending"), now + Duration::hours(1)).unwrap();
+        let (session, _) = query_session(&conn, String::from("pending"))
+            .unwrap()
+            .unwrap();
+        assert!(!session.second_factor_pending);
+        assert_eq!(session.second_factor_failures, 0);
+        assert!(session.second_factor_at.is_some());
+        assert_eq!(query_sessions_by_user(&conn, alice).unwrap().len(), 1);
+
+        assert_eq!(delete_session(&conn, String::from("pending")).unwrap(), 1);
+    }
+}
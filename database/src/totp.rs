@@ -0,0 +1,229 @@
+use crate::connection::DbBackend;
+use crate::models::{NewRecoveryCode, NewUserTotp, UserTotp};
+use crate::schema::{recovery_code, user_totp};
+use diesel::prelude::*;
+
+pub fn query_totp<C: Connection<Backend = DbBackend>>(
+    conn: &C,
+    user: String,
+) -> Result<Option<UserTotp>, diesel::result::Error> {
+    user_totp::table
+        .filter(user_totp::user.eq(user))
+        .first::<UserTotp>(conn)
+        .optional()
+}
+
+/// Whether `user` has a confirmed second factor.
+pub fn has_second_factor<C: Connection<Backend = DbBackend>>(
+    conn: &C,
+    user: String,
+) -> Result<bool, diesel::result::Error> {
+    Ok(query_totp(conn, user)?.is_some_and(|totp| totp.confirmed_at.is_some()))
+}
+
+/// Starts over enrolling `user` with a new secret. Confirmed secrets are
+/// left alone, they have to be deleted first.
+pub fn start_enrollment<C: Connection<Backend = DbBackend>>(
+    conn: &C,
+    user: String,
+    secret: String,
+) -> Result<(), diesel::result::Error> {
+    conn.transaction(|| {
+        diesel::delete(
+            user_totp::table
+                .filter(user_totp::user.eq(&user))
+                .filter(user_totp::confirmed_at.is_null()),
+        )
+        .execute(conn)?;
+        diesel::insert_into(user_totp::table)
+            .values(&NewUserTotp { user, secret })
+            .execute(conn)?;
+        Ok(())
+    })
+}
+
+/// Finishes enrolling with the step of the first code entered, replacing
+/// the recovery codes of `user`.
+pub fn confirm_enrollment<C: Connection<Backend = DbBackend>>(
+    conn: &C,
+    user: String,
+    step: i64,
+    recovery_code_hashes: Vec<String>,
+) -> Result<(), diesel::result::Error> {
+    conn.transaction(|| {
+        diesel::update(user_totp::table.filter(user_totp::user.eq(&user)))
+            .set((
+                user_totp::confirmed_at.eq(chrono::Utc::now().naive_utc()),
+                user_totp::last_step.eq(step),
+            ))
+            .execute(conn)?;
+        replace_recovery_codes(conn, user, recovery_code_hashes)
+    })
+}
+
+pub fn replace_recovery_codes<C: Connection<Backend = DbBackend>>(
+    conn: &C,
+    user: String,
+    code_hashes: Vec<String>,
+) -> Result<(), diesel::result::Error> {
+    conn.transaction(|| {
+        diesel::delete(recovery_code::table.filter(recovery_code::user.eq(&user)))
+            .execute(conn)?;
+        for code_hash in code_hashes {
+            diesel::insert_into(recovery_code::table)
+                .values(&NewRecoveryCode {
+                    user: user.clone(),
+                    code_hash,
+                })
+                .execute(conn)?;
+        }
+        Ok(())
+    })
+}
+
+pub fn count_recovery_codes<C: Connection<Backend = DbBackend>>(
+    conn: &C,
+    user: String,
+) -> Result<i64, diesel::result::Error> {
+    recovery_code::table
+        .filter(recovery_code::user.eq(user))
+        .count()
+        .get_result(conn)
+}
+
+/// Removes the second factor and the recovery codes of `user`.
+pub fn delete_totp<C: Connection<Backend = DbBackend>>(
+    conn: &C,
+    user: String,
+) -> Result<usize, diesel::result::Error> {
+    conn.transaction(|| {
+        diesel::delete(recovery_code::table.filter(recovery_code::user.eq(&user)))
+            .execute(conn)?;
+        diesel::delete(user_totp::table.filter(user_totp::user.eq(&user))).execute(conn)
+    })
+}
+
+/// Records that the code of `step` was used, unless it or a later one was
+/// used before.
+fn use_step<C: Connection<Backend = DbBackend>>(
+    conn: &C,
+    user: &str,
+    step: i64,
+) -> Result<bool, diesel::result::Error> {
+    let updated = diesel::update(
+        user_totp::table
+            .filter(user_totp::user.eq(user))
+            .filter(user_totp::last_step.is_null().or(user_totp::last_step.lt(step))),
+    )
+    .set(user_totp::last_step.eq(step))
+    .execute(conn)?;
+    Ok(updated == 1)
+}
+
+/// Checks a TOTP code or a recovery code of `user`. Each code works once,
+/// recovery codes are deleted when used.
+pub fn check_second_factor<C: Connection<Backend = DbBackend>>(
+    conn: &C,
+    user: String,
+    code: &str,
+) -> Result<bool, diesel::result::Error> {
+    let totp = match query_totp(conn, user.clone())? {
+        Some(totp) if totp.confirmed_at.is_some() => totp,
+        _ => return Ok(false),
+    };
+
+    if common::totp::is_totp_code(code) {
+        let now = chrono::Utc::now().timestamp() as u64;
+        return match common::totp::verify(&totp.secret, code, now) {
+            Some(step) => use_step(conn, &user, step as i64),
+            None => Ok(false),
+        };
+    }
+
+    let deleted = diesel::delete(
+        recovery_code::table
+            .filter(recovery_code::user.eq(user))
+            .filter(recovery_code::code_hash.eq(common::totp::hash_recovery_code(code))),
+    )
+    .execute(conn)?;
+    Ok(deleted == 1)
+}
+
+#[cfg(all(test, feature = "sqlite"))]
+mod tests {
+    use super::*;
+    use crate::connection::in_memory;
+
+    #[test]
+    fn enrolls_once_confirmed() {
+        let conn = in_memory();
+        let alice = crate::user::create_user(&conn, String::from("alice")).unwrap();
+        start_enrollment(&conn, alice.clone(), String::from("FIRST")).unwrap();
+        start_enrollment(&conn, alice.clone(), String::from("SECOND")).unwrap();
+        assert_eq!(
+            query_totp(&conn, alice.clone()).unwrap().unwrap().secret,
+            "SECOND"
+        );
+        assert!(!has_second_factor(&conn, alice.clone()).unwrap());
+        assert!(!check_second_factor(&conn, alice.clone(), "123456").unwrap());
+
+        confirm_enrollment(
+            &conn,
+            alice.clone(),
+            7,
+            vec![String::from("a"), String::from("b")],
+        )
+        .unwrap();
+        assert!(has_second_factor(&conn, alice.clone()).unwrap());
+        assert_eq!(
+            query_totp(&conn, alice.clone()).unwrap().unwrap().last_step,
+            Some(7)
+        );
+        assert_eq!(count_recovery_codes(&conn, alice.clone()).unwrap(), 2);
+
+        assert_eq!(delete_totp(&conn, alice.clone()).unwrap(), 1);
+        assert!(!has_second_factor(&conn, alice.clone()).unwrap());
+        assert_eq!(count_recovery_codes(&conn, alice).unwrap(), 0);
+    }
+
+    #[test]
+    fn recovery_codes_work_once() {
+        let conn = in_memory();
+        let alice = crate::user::create_user(&conn, String::from("alice")).unwrap();
+        start_enrollment(&conn, alice.clone(), common::totp::generate_secret()).unwrap();
+        let code = common::totp::generate_recovery_code();
+        confirm_enrollment(
+            &conn,
+            alice.clone(),
+            0,
+            vec![common::totp::hash_recovery_code(&code)],
+        )
+        .unwrap();
+
+        assert!(!check_second_factor(&conn, alice.clone(), "wrong-code").unwrap());
+        assert!(check_second_factor(&conn, alice.clone(), &code).unwrap());
+        assert!(!check_second_factor(&conn, alice.clone(), &code).unwrap());
+
+        replace_recovery_codes(&conn, alice.clone(), vec![String::from("x")]).unwrap();
+        assert_eq!(count_recovery_codes(&conn, alice).unwrap(), 1);
+    }
+
+    #[test]
+    fn codes_work_once() {
+        let conn = in_memory();
+        let alice = crate::user::create_user(&conn, String::from("alice")).unwrap();
+        let secret = common::totp::generate_secret();
+        start_enrollment(&conn, alice.clone(), secret.clone()).unwrap();
+        confirm_enrollment(&conn, alice.clone(), 0, Vec::new()).unwrap();
+
+        let now = chrono::Utc::now().timestamp() as u64;
+        let code = common::totp::generate_code(&secret, now).unwrap();
+        assert!(check_second_factor(&conn, alice.clone(), &code).unwrap());
+        assert!(!check_second_factor(&conn, alice.clone(), &code).unwrap());
+
+        // nor do codes of earlier steps once a later one was used
+        let earlier =
+            common::totp::generate_code(&secret, now - common::totp::TOTP_STEP_SECONDS).unwrap();
+        assert!(!check_second_factor(&conn, alice, &earlier).unwrap());
+    }
+}
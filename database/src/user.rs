@@ -1,34 +1,528 @@
-use crate::models::{NewUser, PublicKey, User};
-use crate::schema::{public_key, user};
-use diesel::mysql::{MysqlConnection};
+use crate::connection::DbBackend;
+use crate::models::{NewPublicKey, NewUser, PublicKey, User};
+use crate::schema::{lfs_lock, public_key, user};
 use diesel::prelude::*;
 // use diesel::RunQueryDsl;
 // use diesel::*;
 
-pub fn create_user(
-    conn: &MysqlConnection,
+/// Creates a user, returning their uuid.
+pub fn create_user<C: Connection<Backend = DbBackend>>(
+    conn: &C,
     username: String,
-) -> Result<usize, diesel::result::Error> {
+) -> Result<String, diesel::result::Error> {
+    let uuid = uuid::Uuid::new_v4().to_string();
     let new_user = &NewUser {
-        uuid: uuid::Uuid::new_v4().to_string(),
-        username: username,
+        uuid: uuid.clone(),
+        username,
     };
     diesel::insert_into(user::table)
         .values(new_user)
-        .execute(conn)
+        .execute(conn)?;
+    Ok(uuid)
+}
+
+/// Every user that is not deleted, by username.
+pub fn query_users<C: Connection<Backend = DbBackend>>(
+    conn: &C,
+) -> Result<Vec<User>, diesel::result::Error> {
+    user::dsl::user
+        .filter(user::dsl::deleted_at.is_null())
+        .order(user::dsl::username.asc())
+        .load::<User>(conn)
 }
 
-pub fn query_users_by_id(
-    conn: &MysqlConnection,
+/// The deleted users waiting to be purged, most recently deleted first.
+pub fn query_deleted_users<C: Connection<Backend = DbBackend>>(
+    conn: &C,
+) -> Result<Vec<User>, diesel::result::Error> {
+    user::dsl::user
+        .filter(user::dsl::deleted_at.is_not_null())
+        .order(user::dsl::deleted_at.desc())
+        .load::<User>(conn)
+}
+
+pub fn query_user_by_username<C: Connection<Backend = DbBackend>>(
+    conn: &C,
+    username: String,
+) -> Result<Option<User>, diesel::result::Error> {
+    user::dsl::user
+        .filter(user::dsl::username.eq(username))
+        .filter(user::dsl::deleted_at.is_null())
+        .first::<User>(conn)
+        .optional()
+}
+
+/// The most recently deleted user named `username`, as the name may have
+/// been taken again since.
+pub fn query_deleted_user_by_username<C: Connection<Backend = DbBackend>>(
+    conn: &C,
+    username: String,
+) -> Result<Option<User>, diesel::result::Error> {
+    user::dsl::user
+        .filter(user::dsl::username.eq(username))
+        .filter(user::dsl::deleted_at.is_not_null())
+        .order(user::dsl::deleted_at.desc())
+        .first::<User>(conn)
+        .optional()
+}
+
+/// Whether the user exists and is neither disabled nor deleted, so they may
+/// sign in.
+pub fn query_is_active<C: Connection<Backend = DbBackend>>(
+    conn: &C,
+    uuid: String,
+) -> Result<bool, diesel::result::Error> {
+    Ok(user::dsl::user
+        .select(user::dsl::uuid)
+        .filter(user::dsl::uuid.eq(uuid))
+        .filter(user::dsl::disabled_at.is_null())
+        .filter(user::dsl::deleted_at.is_null())
+        .first::<String>(conn)
+        .optional()?
+        .is_some())
+}
+
+/// Disables or enables the user again. Disabling an already disabled user
+/// keeps the time they were disabled at.
+pub fn set_disabled<C: Connection<Backend = DbBackend>>(
+    conn: &C,
+    uuid: String,
+    disabled: bool,
+) -> Result<usize, diesel::result::Error> {
+    let target = user::dsl::user.filter(user::dsl::uuid.eq(uuid));
+    if disabled {
+        diesel::update(target.filter(user::dsl::disabled_at.is_null()))
+            .set(user::dsl::disabled_at.eq(chrono::Utc::now().naive_utc()))
+            .execute(conn)
+    } else {
+        diesel::update(target)
+            .set(user::dsl::disabled_at.eq(None::<chrono::NaiveDateTime>))
+            .execute(conn)
+    }
+}
+
+/// Marks the user as deleted. Like disabled users they cannot sign in nor use
+/// their keys and tokens, and lookups no longer find them, but their rows
+/// stay until [`purge_deleted_users`] removes them or they are restored.
+pub fn delete_user<C: Connection<Backend = DbBackend>>(
+    conn: &C,
+    uuid: String,
+) -> Result<usize, diesel::result::Error> {
+    diesel::update(
+        user::dsl::user
+            .filter(user::dsl::uuid.eq(uuid))
+            .filter(user::dsl::deleted_at.is_null()),
+    )
+    .set(user::dsl::deleted_at.eq(chrono::Utc::now().naive_utc()))
+    .execute(conn)
+}
+
+/// Brings a deleted user back. Usernames are only unique among the users
+/// that are not deleted, so this fails with a unique violation, like an index
+/// would, while somebody else goes by the name.
+pub fn restore_user<C: Connection<Backend = DbBackend>>(
+    conn: &C,
+    uuid: String,
+) -> Result<usize, diesel::result::Error> {
+    conn.transaction(|| {
+        let username = user::dsl::user
+            .select(user::dsl::username)
+            .filter(user::dsl::uuid.eq(&uuid))
+            .filter(user::dsl::deleted_at.is_not_null())
+            .first::<String>(conn)
+            .optional()?;
+        let username = match username {
+            Some(username) => username,
+            None => return Ok(0),
+        };
+        if query_user_id_by_username(conn, username.clone())?.is_some() {
+            return Err(diesel::result::Error::DatabaseError(
+                diesel::result::DatabaseErrorKind::UniqueViolation,
+                Box::new(format!("the username {} is taken", username)),
+            ));
+        }
+        diesel::update(user::dsl::user.filter(user::dsl::uuid.eq(&uuid)))
+            .set(user::dsl::deleted_at.eq(None::<chrono::NaiveDateTime>))
+            .execute(conn)
+    })
+}
+
+/// Deletes the user for good, with their keys and locks. Everything else of
+/// theirs goes with them through the foreign keys.
+pub(crate) fn purge_user<C: Connection<Backend = DbBackend>>(
+    conn: &C,
+    uuid: String,
+) -> Result<usize, diesel::result::Error> {
+    conn.transaction(|| {
+        diesel::delete(public_key::table.filter(public_key::dsl::user.eq(&uuid))).execute(conn)?;
+        diesel::delete(lfs_lock::table.filter(lfs_lock::owner.eq(&uuid))).execute(conn)?;
+        diesel::delete(user::dsl::user.filter(user::dsl::uuid.eq(&uuid))).execute(conn)
+    })
+}
+
+/// Purges the users deleted before `deleted_before`, returning them.
+pub fn purge_deleted_users<C: Connection<Backend = DbBackend>>(
+    conn: &C,
+    deleted_before: chrono::NaiveDateTime,
+) -> Result<Vec<User>, diesel::result::Error> {
+    conn.transaction(|| {
+        let users = user::dsl::user
+            .filter(user::dsl::deleted_at.lt(deleted_before))
+            .load::<User>(conn)?;
+        for user in &users {
+            purge_user(conn, user.uuid.clone())?;
+        }
+        Ok(users)
+    })
+}
+
+pub fn query_users_by_id<C: Connection<Backend = DbBackend>>(
+    conn: &C,
     uuid: String,
 ) -> Result<Vec<User>, diesel::result::Error> {
     user::dsl::user
         .filter(user::dsl::uuid.eq(uuid))
+        .filter(user::dsl::deleted_at.is_null())
         .load::<User>(conn)
 }
 
-pub fn query_users_by_public_key_fingerprint(conn: &MysqlConnection, fingerprint: String) -> Result<Vec<(User, PublicKey)>, diesel::result::Error> {
+/// Matches `fingerprint` against both the SHA-256 (`SHA256:...`) and the
+/// legacy MD5 fingerprints of the keys.
+pub fn query_users_by_public_key_fingerprint<C: Connection<Backend = DbBackend>>(conn: &C, fingerprint: String) -> Result<Vec<(User, PublicKey)>, diesel::result::Error> {
     user::dsl::user
         .inner_join(public_key::table)
-        .filter(public_key::dsl::fingerprint.eq(fingerprint)).load::<(User, PublicKey)>(conn)
+        .filter(
+            public_key::dsl::sha256_fingerprint
+                .eq(&fingerprint)
+                .or(public_key::dsl::fingerprint.eq(&fingerprint)),
+        )
+        .filter(user::dsl::deleted_at.is_null())
+        .load::<(User, PublicKey)>(conn)
+}
+
+/// Matches `fingerprint` against both the SHA-256 (`SHA256:...`) and the
+/// legacy MD5 fingerprints of the keys.
+pub fn query_public_keys_by_fingerprint<C: Connection<Backend = DbBackend>>(
+    conn: &C,
+    fingerprint: String,
+) -> Result<Vec<PublicKey>, diesel::result::Error> {
+    public_key::dsl::public_key
+        .filter(
+            public_key::dsl::sha256_fingerprint
+                .eq(&fingerprint)
+                .or(public_key::dsl::fingerprint.eq(&fingerprint)),
+        )
+        .load::<PublicKey>(conn)
+}
+
+pub fn query_public_keys_by_user<C: Connection<Backend = DbBackend>>(
+    conn: &C,
+    uuid: String,
+) -> Result<Vec<PublicKey>, diesel::result::Error> {
+    public_key::dsl::public_key
+        .filter(public_key::dsl::user.eq(uuid))
+        .order(public_key::dsl::sha256_fingerprint.asc())
+        .load::<PublicKey>(conn)
+}
+
+/// Deletes the key of `uuid` with `fingerprint`, SHA-256 or MD5.
+pub fn delete_public_key<C: Connection<Backend = DbBackend>>(
+    conn: &C,
+    uuid: String,
+    fingerprint: String,
+) -> Result<usize, diesel::result::Error> {
+    diesel::delete(
+        public_key::table
+            .filter(public_key::dsl::user.eq(uuid))
+            .filter(
+                public_key::dsl::sha256_fingerprint
+                    .eq(&fingerprint)
+                    .or(public_key::dsl::fingerprint.eq(&fingerprint)),
+            ),
+    )
+    .execute(conn)
+}
+
+pub fn create_public_key<C: Connection<Backend = DbBackend>>(
+    conn: &C,
+    new_key: NewPublicKey,
+) -> Result<usize, diesel::result::Error> {
+    diesel::insert_into(public_key::table)
+        .values(&new_key)
+        .execute(conn)
+}
+
+/// Records the blob and SHA-256 fingerprint of a key that was registered by
+/// its MD5 fingerprint only.
+pub fn backfill_public_key<C: Connection<Backend = DbBackend>>(
+    conn: &C,
+    fingerprint: String,
+    key_type: String,
+    key_data: String,
+    sha256_fingerprint: String,
+) -> Result<usize, diesel::result::Error> {
+    diesel::update(
+        public_key::table
+            .filter(public_key::dsl::fingerprint.eq(fingerprint))
+            .filter(public_key::dsl::key_data.is_null()),
+    )
+    .set((
+        public_key::dsl::key_type.eq(key_type),
+        public_key::dsl::key_data.eq(key_data),
+        public_key::dsl::sha256_fingerprint.eq(sha256_fingerprint),
+    ))
+    .execute(conn)
+}
+
+pub fn query_username_by_id<C: Connection<Backend = DbBackend>>(
+    conn: &C,
+    uuid: String,
+) -> Result<Option<String>, diesel::result::Error> {
+    user::dsl::user
+        .select(user::dsl::username)
+        .filter(user::dsl::uuid.eq(uuid))
+        .filter(user::dsl::deleted_at.is_null())
+        .first::<String>(conn)
+        .optional()
+}
+
+pub fn query_is_admin<C: Connection<Backend = DbBackend>>(
+    conn: &C,
+    uuid: String,
+) -> Result<bool, diesel::result::Error> {
+    Ok(user::dsl::user
+        .select(user::dsl::is_admin)
+        .filter(user::dsl::uuid.eq(uuid))
+        .filter(user::dsl::deleted_at.is_null())
+        .first::<bool>(conn)
+        .optional()?
+        .unwrap_or(false))
+}
+
+pub fn set_admin<C: Connection<Backend = DbBackend>>(
+    conn: &C,
+    uuid: String,
+    is_admin: bool,
+) -> Result<usize, diesel::result::Error> {
+    diesel::update(user::dsl::user.filter(user::dsl::uuid.eq(uuid)))
+        .set(user::dsl::is_admin.eq(is_admin))
+        .execute(conn)
+}
+
+pub fn query_user_id_by_username<C: Connection<Backend = DbBackend>>(
+    conn: &C,
+    username: String,
+) -> Result<Option<String>, diesel::result::Error> {
+    user::dsl::user
+        .select(user::dsl::uuid)
+        .filter(user::dsl::username.eq(username))
+        .filter(user::dsl::deleted_at.is_null())
+        .first::<String>(conn)
+        .optional()
+}
+
+/// The uuid and password hash of the user signing in as `username`, unless
+/// they are disabled or deleted.
+pub fn query_login<C: Connection<Backend = DbBackend>>(
+    conn: &C,
+    username: String,
+) -> Result<Option<(String, Option<String>)>, diesel::result::Error> {
+    user::dsl::user
+        .select((user::dsl::uuid, user::dsl::password_hash))
+        .filter(user::dsl::username.eq(username))
+        .filter(user::dsl::disabled_at.is_null())
+        .filter(user::dsl::deleted_at.is_null())
+        .first::<(String, Option<String>)>(conn)
+        .optional()
+}
+
+pub fn set_password_hash<C: Connection<Backend = DbBackend>>(
+    conn: &C,
+    uuid: String,
+    password_hash: Option<String>,
+) -> Result<usize, diesel::result::Error> {
+    diesel::update(user::dsl::user.filter(user::dsl::uuid.eq(uuid)))
+        .set(user::dsl::password_hash.eq(password_hash))
+        .execute(conn)
+}
+
+#[cfg(all(test, feature = "sqlite"))]
+mod tests {
+    use super::*;
+    use crate::connection::in_memory;
+
+    fn key(user: &str, md5: &str, sha256: Option<&str>) -> NewPublicKey {
+        NewPublicKey {
+            fingerprint: String::from(md5),
+            user: String::from(user),
+            key_type: sha256.map(|_| String::from("ssh-ed25519")),
+            key_data: sha256.map(|_| String::from("AAAA")),
+            sha256_fingerprint: sha256.map(String::from),
+        }
+    }
+
+    #[test]
+    fn creates_and_finds_users() {
+        let conn = in_memory();
+        let uuid = create_user(&conn, String::from("alice")).unwrap();
+
+        let user = query_user_by_username(&conn, String::from("alice"))
+            .unwrap()
+            .unwrap();
+        assert_eq!(user.uuid, uuid);
+        assert_eq!(
+            query_user_id_by_username(&conn, String::from("alice")).unwrap(),
+            Some(uuid.clone())
+        );
+        assert_eq!(
+            query_username_by_id(&conn, uuid.clone())
+                .unwrap()
+                .as_deref(),
+            Some("alice")
+        );
+        assert!(query_users(&conn)
+            .unwrap()
+            .iter()
+            .any(|user| user.uuid == uuid));
+        assert!(!query_is_admin(&conn, uuid.clone()).unwrap());
+        set_admin(&conn, uuid.clone(), true).unwrap();
+        assert!(query_is_admin(&conn, uuid).unwrap());
+    }
+
+    #[test]
+    fn disabled_users_cannot_sign_in() {
+        let conn = in_memory();
+        let uuid = create_user(&conn, String::from("alice")).unwrap();
+        set_password_hash(&conn, uuid.clone(), Some(String::from("hash"))).unwrap();
+        assert_eq!(
+            query_login(&conn, String::from("alice")).unwrap(),
+            Some((uuid.clone(), Some(String::from("hash"))))
+        );
+
+        assert_eq!(set_disabled(&conn, uuid.clone(), true).unwrap(), 1);
+        assert_eq!(set_disabled(&conn, uuid.clone(), true).unwrap(), 0);
+        assert!(!query_is_active(&conn, uuid.clone()).unwrap());
+        assert_eq!(query_login(&conn, String::from("alice")).unwrap(), None);
+
+        set_disabled(&conn, uuid.clone(), false).unwrap();
+        assert!(query_is_active(&conn, uuid).unwrap());
+    }
+
+    #[test]
+    fn deletes_restores_and_purges_users() {
+        let conn = in_memory();
+        let uuid = create_user(&conn, String::from("alice")).unwrap();
+        create_public_key(&conn, key(&uuid, "aa:bb", Some("SHA256:alice"))).unwrap();
+
+        assert_eq!(delete_user(&conn, uuid.clone()).unwrap(), 1);
+        assert!(query_user_by_username(&conn, String::from("alice"))
+            .unwrap()
+            .is_none());
+        assert!(
+            query_users_by_public_key_fingerprint(&conn, String::from("SHA256:alice"))
+                .unwrap()
+                .is_empty()
+        );
+        let deleted = query_deleted_user_by_username(&conn, String::from("alice"))
+            .unwrap()
+            .unwrap();
+        assert_eq!(deleted.uuid, uuid);
+
+        restore_user(&conn, uuid.clone()).unwrap();
+        assert!(query_is_active(&conn, uuid.clone()).unwrap());
+
+        delete_user(&conn, uuid.clone()).unwrap();
+        let purged = purge_deleted_users(
+            &conn,
+            chrono::Utc::now().naive_utc() + chrono::Duration::seconds(1),
+        )
+        .unwrap();
+        assert_eq!(purged.len(), 1);
+        assert!(query_deleted_users(&conn).unwrap().is_empty());
+        assert!(query_public_keys_by_user(&conn, uuid).unwrap().is_empty());
+    }
+
+    #[test]
+    fn refuses_to_restore_taken_usernames() {
+        let conn = in_memory();
+        let old = create_user(&conn, String::from("alice")).unwrap();
+        delete_user(&conn, old.clone()).unwrap();
+        let new = create_user(&conn, String::from("alice")).unwrap();
+
+        assert!(matches!(
+            restore_user(&conn, old.clone()),
+            Err(diesel::result::Error::DatabaseError(
+                diesel::result::DatabaseErrorKind::UniqueViolation,
+                _
+            ))
+        ));
+        assert!(!query_is_active(&conn, old.clone()).unwrap());
+        assert_eq!(
+            query_user_id_by_username(&conn, String::from("alice")).unwrap(),
+            Some(new.clone())
+        );
+
+        // once the name is free again, the old user may have it back
+        delete_user(&conn, new).unwrap();
+        assert_eq!(restore_user(&conn, old.clone()).unwrap(), 1);
+        assert!(query_is_active(&conn, old.clone()).unwrap());
+        // restoring users who are not deleted changes nothing
+        assert_eq!(restore_user(&conn, old).unwrap(), 0);
+    }
+
+    #[test]
+    fn finds_keys_by_either_fingerprint() {
+        let conn = in_memory();
+        let uuid = create_user(&conn, String::from("alice")).unwrap();
+        create_public_key(&conn, key(&uuid, "aa:bb", Some("SHA256:alice"))).unwrap();
+        create_public_key(&conn, key(&uuid, "cc:dd", None)).unwrap();
+
+        for fingerprint in ["aa:bb", "SHA256:alice"] {
+            let found =
+                query_users_by_public_key_fingerprint(&conn, String::from(fingerprint)).unwrap();
+            assert_eq!(found.len(), 1);
+            assert_eq!(found[0].0.uuid, uuid);
+        }
+        assert_eq!(
+            query_public_keys_by_user(&conn, uuid.clone())
+                .unwrap()
+                .len(),
+            2
+        );
+
+        assert_eq!(
+            delete_public_key(&conn, uuid.clone(), String::from("SHA256:alice")).unwrap(),
+            1
+        );
+        assert!(
+            query_public_keys_by_fingerprint(&conn, String::from("aa:bb"))
+                .unwrap()
+                .is_empty()
+        );
+        assert_eq!(query_public_keys_by_user(&conn, uuid).unwrap().len(), 1);
+    }
+
+    #[test]
+    fn backfills_keys_registered_by_md5_only() {
+        let conn = in_memory();
+        let uuid = create_user(&conn, String::from("alice")).unwrap();
+        create_public_key(&conn, key(&uuid, "cc:dd", None)).unwrap();
+
+        let backfill = || {
+            backfill_public_key(
+                &conn,
+                String::from("cc:dd"),
+                String::from("ssh-ed25519"),
+                String::from("AAAA"),
+                String::from("SHA256:backfilled"),
+            )
+            .unwrap()
+        };
+        assert_eq!(backfill(), 1);
+        assert_eq!(backfill(), 0);
+        let keys =
+            query_public_keys_by_fingerprint(&conn, String::from("SHA256:backfilled")).unwrap();
+        assert_eq!(keys.len(), 1);
+        assert_eq!(keys[0].key_data.as_deref(), Some("AAAA"));
+    }
 }
@@ -0,0 +1,307 @@
+use clap::Parser;
+use common::certificate::{is_certificate_type, CertificateAuthority};
+use common::{Certificate, CertificateAuthorities};
+use database::models::NewSshCertificate;
+
+/// Serves the keys in the `public_key` and `deploy_key` tables to sshd, and
+/// the certificate authorities of `SSH_USER_CA_KEYS` for user certificates.
+/// Configure it with
+///
+/// ```text
+/// AuthorizedKeysCommand /usr/local/bin/git-authorized-keys %u %t %k
+/// AuthorizedKeysCommandUser git
+/// ```
+#[derive(Parser)]
+#[clap(author, version, about)]
+struct Cli {
+    /// Login name of the connecting user (%u)
+    user: String,
+
+    /// Type of the offered key (%t)
+    key_type: String,
+
+    /// Base64 encoded offered key (%k)
+    key: String,
+}
+
+const DEFAULT_GIT_SSH_USER: &str = "git";
+const DEFAULT_FORCED_COMMAND: &str = "rustile-shell";
+const KEY_RESTRICTIONS: &str = "no-port-forwarding,no-X11-forwarding,no-agent-forwarding,no-pty";
+
+/// The command sshd forces on every key, `SSH_FORCED_COMMAND` or
+/// `rustile-shell`.
+fn shell_command() -> String {
+    std::env::var("SSH_FORCED_COMMAND").unwrap_or_else(|_| String::from(DEFAULT_FORCED_COMMAND))
+}
+
+/// The forced command runs through the login shell of the git user, so the
+/// fingerprint is passed as a variable assignment rather than with
+/// `environment=`, which would need `PermitUserEnvironment`.
+fn forced_command(shell: &str, fingerprint: &str) -> String {
+    let command = format!("SSH_KEY_FINGERPRINT={} {}", fingerprint, shell);
+    format!(
+        "command=\"{}\",{}",
+        command.replace('\\', "\\\\").replace('"', "\\\""),
+        KEY_RESTRICTIONS
+    )
+}
+
+fn authorized_key_line(shell: &str, fingerprint: &str, key_type: &str, key: &str) -> String {
+    format!("{} {} {}", forced_command(shell, fingerprint), key_type, key)
+}
+
+/// Whether `principal` can be quoted in a `principals=` option, which has no
+/// way to escape quotes or the commas separating principals.
+fn quotable_principal(principal: &str) -> bool {
+    !principal.contains(['"', ','])
+}
+
+/// Trusts the authority for the one principal the certificate was matched
+/// by. sshd still verifies the signature, the validity window and the
+/// `source-address` option itself. `None` if the principal cannot be quoted.
+fn certificate_authority_line(
+    shell: &str,
+    fingerprint: &str,
+    principal: &str,
+    authority: &CertificateAuthority,
+) -> Option<String> {
+    quotable_principal(principal).then(|| {
+        format!(
+            "cert-authority,principals=\"{}\",{} {} {}",
+            principal,
+            forced_command(shell, fingerprint),
+            authority.key_type,
+            authority.key
+        )
+    })
+}
+
+/// Seconds since the epoch as a datetime, `None` for ones too far in the
+/// future, like the `u64::MAX` of certificates that never expire.
+fn datetime(unix_time: u64) -> Option<chrono::NaiveDateTime> {
+    i64::try_from(unix_time)
+        .ok()
+        .and_then(|secs| chrono::NaiveDateTime::from_timestamp_opt(secs, 0))
+}
+
+/// Checks a user certificate against the trusted authorities and maps its
+/// principals to a user. Returns the authorized_keys line, or why the
+/// certificate is refused.
+fn authorize_certificate(
+    conn: &database::connection::DbConnection,
+    key_type: &str,
+    key: &str,
+) -> Result<Result<String, String>, Box<dyn std::error::Error>> {
+    let authorities = match CertificateAuthorities::from_env() {
+        Some(authorities) => authorities?,
+        None => return Ok(Err(String::from("SSH_USER_CA_KEYS is not set"))),
+    };
+    let certificate = match Certificate::parse(key) {
+        Ok(certificate) if certificate.key_type == key_type => certificate,
+        Ok(certificate) => {
+            return Ok(Err(format!(
+                "certificate of type {} was offered as {}",
+                certificate.key_type, key_type
+            )))
+        }
+        Err(err) => return Ok(Err(err.to_string())),
+    };
+    let authority = match authorities.issuer(&certificate) {
+        Ok(authority) => authority,
+        Err(err) => return Ok(Err(err.to_string())),
+    };
+    let now = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)?
+        .as_secs();
+    if let Err(err) = certificate.check(now) {
+        return Ok(Err(err.to_string()));
+    }
+
+    let mut users = Vec::new();
+    // the principal ends up quoted in the authorized_keys line
+    for principal in certificate
+        .principals
+        .iter()
+        .filter(|principal| quotable_principal(principal))
+    {
+        if let Some(user) = database::user::query_user_id_by_username(conn, principal.clone())? {
+            users.push((principal, user));
+        }
+    }
+    let (principal, user) = match users.len() {
+        1 => users.remove(0),
+        0 => {
+            return Ok(Err(format!(
+                "no principal of certificate {} is a user",
+                certificate.key_id
+            )))
+        }
+        _ => {
+            return Ok(Err(format!(
+                "certificate {} names more than one user",
+                certificate.key_id
+            )))
+        }
+    };
+    if !database::user::query_is_active(conn, user.clone())? {
+        return Ok(Err(format!("the user {} is disabled or deleted", principal)));
+    }
+
+    let fingerprint = common::sha256_fingerprint(key)?;
+    database::certificate::record_certificate(
+        conn,
+        NewSshCertificate {
+            fingerprint: fingerprint.clone(),
+            user,
+            principal: principal.clone(),
+            ca_fingerprint: authority.fingerprint.clone(),
+            key_id: certificate.key_id.clone(),
+            serial: certificate.serial.to_string(),
+            valid_after: datetime(certificate.valid_after).unwrap_or_else(|| {
+                chrono::NaiveDateTime::from_timestamp_opt(now as i64, 0).unwrap()
+            }),
+            valid_before: datetime(certificate.valid_before),
+        },
+    )?;
+    Ok(
+        certificate_authority_line(&shell_command(), &fingerprint, principal, authority)
+            .ok_or_else(|| format!("principal {} cannot be quoted", principal)),
+    )
+}
+
+fn main() -> Result<(), Box<dyn std::error::Error>> {
+    // sshd takes whatever is printed on stdout as authorized_keys lines, so
+    // diagnostics go to stderr only.
+    dotenv::dotenv().ok();
+    dotenv::from_path("/etc/git-server.env").ok();
+
+    let args = Cli::parse();
+
+    let git_user =
+        std::env::var("GIT_SSH_USER").unwrap_or_else(|_| String::from(DEFAULT_GIT_SSH_USER));
+    if args.user != git_user {
+        return Ok(());
+    }
+
+    let conn = database::connection::from_env()?;
+    database::migrations::check_migrations(&conn)?;
+    if is_certificate_type(&args.key_type) {
+        match authorize_certificate(&conn, &args.key_type, &args.key)? {
+            Ok(line) => println!("{}", line),
+            Err(reason) => eprintln!("refusing certificate: {}", reason),
+        }
+        return Ok(());
+    }
+
+    let fingerprint = common::sha256_fingerprint(&args.key)?;
+    let md5_fingerprint = common::md5_fingerprint(&args.key)?;
+
+    let mut keys = database::user::query_public_keys_by_fingerprint(&conn, fingerprint.clone())?;
+    if keys.is_empty() {
+        keys = database::user::query_public_keys_by_fingerprint(&conn, md5_fingerprint.clone())?;
+    }
+    let key = match keys.into_iter().next() {
+        Some(key) => key,
+        None => {
+            // deploy keys are always registered with their blob, by sha256
+            if database::deploy_key::query_deploy_key_by_fingerprint(&conn, fingerprint.clone())?
+                .is_some()
+            {
+                println!(
+                    "{}",
+                    authorized_key_line(&shell_command(), &fingerprint, &args.key_type, &args.key)
+                );
+            } else {
+                eprintln!("no user or deploy key matches {}", fingerprint);
+            }
+            return Ok(());
+        }
+    };
+    if !database::user::query_is_active(&conn, key.user.clone())? {
+        eprintln!("the user of {} is disabled or deleted", fingerprint);
+        return Ok(());
+    }
+
+    // keys registered by their md5 fingerprint learn their blob on first use
+    if key.key_data.is_none() {
+        database::user::backfill_public_key(
+            &conn,
+            key.fingerprint,
+            args.key_type.clone(),
+            args.key.clone(),
+            fingerprint.clone(),
+        )?;
+    }
+
+    println!(
+        "{}",
+        authorized_key_line(&shell_command(), &fingerprint, &args.key_type, &args.key)
+    );
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use common::certificate::CertificateAuthority;
+
+    use super::{authorized_key_line, certificate_authority_line};
+
+    const FINGERPRINT: &str = "SHA256:nThbg6kXUpJWGl7E1IGOCspRomTxdCARLviKw6E5SY8";
+
+    fn authority() -> CertificateAuthority {
+        CertificateAuthority {
+            key_type: String::from("ssh-ed25519"),
+            key: String::from("AAAAC3NzaC1lZDI1NTE5AAAAIA"),
+            fingerprint: String::from("SHA256:ca"),
+        }
+    }
+
+    #[test]
+    fn forces_the_shell_with_the_fingerprint() {
+        assert_eq!(
+            authorized_key_line("rustile-shell", FINGERPRINT, "ssh-ed25519", "AAAAkey"),
+            "command=\"SSH_KEY_FINGERPRINT=SHA256:nThbg6kXUpJWGl7E1IGOCspRomTxdCARLviKw6E5SY8 \
+             rustile-shell\",no-port-forwarding,no-X11-forwarding,no-agent-forwarding,no-pty \
+             ssh-ed25519 AAAAkey"
+        );
+    }
+
+    #[test]
+    fn escapes_the_forced_command() {
+        let line = authorized_key_line(
+            "/opt/rustile/bin/\"rustile shell\" \\x",
+            FINGERPRINT,
+            "ssh-ed25519",
+            "AAAAkey",
+        );
+        assert!(line.starts_with(
+            "command=\"SSH_KEY_FINGERPRINT=SHA256:nThbg6kXUpJWGl7E1IGOCspRomTxdCARLviKw6E5SY8 \
+             /opt/rustile/bin/\\\"rustile shell\\\" \\\\x\",no-port-forwarding,"
+        ));
+    }
+
+    #[test]
+    fn trusts_authorities_for_one_principal() {
+        assert_eq!(
+            certificate_authority_line("rustile-shell", FINGERPRINT, "alice", &authority())
+                .unwrap(),
+            "cert-authority,principals=\"alice\",\
+             command=\"SSH_KEY_FINGERPRINT=SHA256:nThbg6kXUpJWGl7E1IGOCspRomTxdCARLviKw6E5SY8 \
+             rustile-shell\",no-port-forwarding,no-X11-forwarding,no-agent-forwarding,no-pty \
+             ssh-ed25519 AAAAC3NzaC1lZDI1NTE5AAAAIA"
+        );
+    }
+
+    #[test]
+    fn refuses_principals_that_cannot_be_quoted() {
+        for principal in ["alice\",command=\"sh", "alice,root", "\"", ","] {
+            assert!(
+                certificate_authority_line("rustile-shell", FINGERPRINT, principal, &authority())
+                    .is_none(),
+                "{}",
+                principal
+            );
+        }
+    }
+}
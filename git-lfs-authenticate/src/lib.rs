@@ -0,0 +1,96 @@
+use common::{Claims, KeyError, Operation, RepoPath, SigningKey};
+use database::audit::{self, Actor, Event, Outcome};
+use database::authorization::KeyOwner;
+use database::connection::DbConnection;
+use database::models::NewIssuedToken;
+use serde_json::json;
+use std::ops::Add;
+
+/// How long a token handed out by git-lfs-authenticate stays valid.
+pub const TOKEN_LIFETIME_SECONDS: i64 = 1800;
+
+#[derive(Debug)]
+pub enum AuthenticateError {
+    Key(KeyError),
+    Database(diesel::result::Error),
+}
+
+impl std::fmt::Display for AuthenticateError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            AuthenticateError::Key(err) => write!(f, "failed to sign token: {}", err),
+            AuthenticateError::Database(err) => write!(f, "failed to record token: {}", err),
+        }
+    }
+}
+
+impl std::error::Error for AuthenticateError {}
+
+impl From<KeyError> for AuthenticateError {
+    fn from(err: KeyError) -> Self {
+        AuthenticateError::Key(err)
+    }
+}
+
+impl From<diesel::result::Error> for AuthenticateError {
+    fn from(err: diesel::result::Error) -> Self {
+        AuthenticateError::Database(err)
+    }
+}
+
+/// The git-lfs-authenticate response granting `owner`, who signed in with
+/// the ssh key `key_fingerprint`, the `operation` on `repo`. The token is
+/// signed by `TOKEN_SIGNING_KEY` and recorded by its jti, so it can be
+/// revoked before it expires, and its issuance goes to the audit log.
+pub fn authenticate(
+    conn: &DbConnection,
+    owner: &KeyOwner,
+    key_fingerprint: &str,
+    repo: &RepoPath,
+    operation: Operation,
+) -> Result<serde_json::Value, AuthenticateError> {
+    let signing_key = SigningKey::from_env()?;
+    let (sub, user, deploy_key) = match owner {
+        KeyOwner::User(user) => (user.clone(), Some(user.clone()), false),
+        KeyOwner::DeployKey(fingerprint) => (fingerprint.clone(), None, true),
+    };
+    let jti = uuid::Uuid::new_v4().to_hyphenated().to_string();
+    let now = chrono::Utc::now();
+    let expires_at = now.add(chrono::Duration::seconds(TOKEN_LIFETIME_SECONDS));
+    let token = signing_key.sign(&Claims {
+        sub,
+        iat: now.timestamp(),
+        exp: expires_at.timestamp(),
+        jti: jti.clone(),
+
+        repo: repo.to_string(),
+        operation,
+        deploy_key,
+    })?;
+
+    database::issued_token::record_issued_token(
+        conn,
+        NewIssuedToken {
+            jti: jti.clone(),
+            user,
+            key_fingerprint: String::from(key_fingerprint),
+            is_deploy_key: deploy_key,
+            repository: repo.to_string(),
+            operation: operation.to_string(),
+            expires_at: expires_at.naive_utc(),
+        },
+    )?;
+    audit::record(
+        conn,
+        Event::new(audit::TOKEN_ISSUE, Actor::from(owner), Outcome::Success)
+            .repository(repo)
+            .ip(audit::ssh_client_ip())
+            .detail(format!("{} token {}", operation, jti)),
+    )?;
+
+    Ok(json!({
+        "header": {
+            "Authorization": format!("Token {}", token),
+        },
+    }))
+}
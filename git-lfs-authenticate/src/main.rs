@@ -1,13 +1,7 @@
 use clap::{Parser, Subcommand};
-use serde::{Deserialize, Serialize};
-use std::{
-    ops::Add,
-    path::{Path, PathBuf},
-};
-
-use serde_json::json;
-
-use jsonwebtoken::{encode, EncodingKey, Header};
+use common::{Access, Operation, RepoPath};
+use database::audit::{self, Actor, Event, Outcome};
+use std::path::PathBuf;
 
 #[derive(Parser)]
 #[clap(author, version, about)]
@@ -40,23 +34,12 @@ enum Commands {
     Upload,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
-struct Claim {
-    sub: String,
-    iat: i64,
-    exp: i64,
-
-    command: String,
-}
-
 fn main() -> Result<(), Box<dyn std::error::Error>> {
-    match dotenv::dotenv() {
-        Ok(path) => eprintln!("environment loaded from {:?}", path),
-        Err(_) => {}
+    if let Ok(path) = dotenv::dotenv() {
+        eprintln!("environment loaded from {:?}", path);
     }
-    match dotenv::from_path("/etc/git-server.env") {
-        Ok(_) => eprintln!("environment loaded from git-server"),
-        Err(_) => {}
+    if dotenv::from_path("/etc/git-server.env").is_ok() {
+        eprintln!("environment loaded from git-server");
     }
 
     let warning_not_configured = include_bytes!("warnings/ssh_not_configured.txt");
@@ -64,38 +47,42 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
     let args = Cli::parse();
 
     std::env::set_current_dir(std::env::var("HOME")?)?;
-    assert!(args.repo.ends_with(".git"));
-    assert!(Path::new(&args.repo).exists());
-
-    if let Err(_) = std::env::var("SSH_KEY_FINGERPRINT") {
-        println!("{}", String::from_utf8(warning_not_configured.to_vec())?);
-        panic!("no proper environment.");
+    let repo = RepoPath::parse(&args.repo, &std::env::current_dir()?)?;
+
+    let fingerprint = match std::env::var("SSH_KEY_FINGERPRINT") {
+        Ok(fingerprint) => fingerprint,
+        Err(_) => {
+            println!("{}", String::from_utf8(warning_not_configured.to_vec())?);
+            panic!("no proper environment.");
+        }
+    };
+
+    let conn = database::connection::from_env()?;
+    database::migrations::check_migrations(&conn)?;
+    let owner = match database::authorization::query_key_owner(&conn, fingerprint.clone())? {
+        Some(owner) => owner,
+        None => return Err(format!("no user or deploy key matches {}", fingerprint).into()),
+    };
+
+    let operation = match args.command {
+        Commands::Download => Operation::Download,
+        Commands::Upload => Operation::Upload,
+    };
+
+    if !owner.authorize(&conn, repo.as_str(), operation.into())? {
+        audit::record(
+            &conn,
+            Event::new(audit::TOKEN_ISSUE, Actor::from(&owner), Outcome::Denied)
+                .repository(&repo)
+                .ip(audit::ssh_client_ip())
+                .detail(format!("{} token", operation)),
+        )?;
+        return Err(format!("{} access to {} is required", Access::from(operation), repo).into());
     }
 
-    let plain_secret = std::env::var("SECRET").unwrap();
-    let secret = EncodingKey::from_secret(plain_secret.as_bytes());
-    eprintln!("encoding key: {:?}", plain_secret);
-
-    let command = String::from(match args.command {
-        Commands::Download => "download",
-        Commands::Upload => "upload",
-    });
-
-    println!(
-        "{}",
-        json!({
-            "header": {
-                "Authorization": format!("Token {}", encode(&Header::default(), &Claim{
-                    sub: String::from("git.jeffthecoder.xyz"),
-                    iat: chrono::Utc::now().timestamp(),
-                    exp: chrono::Utc::now().add(chrono::Duration::seconds(1800)).timestamp(),
-
-                    command,
-                }, &secret)?),
-            },
-        })
-        .to_string()
-    );
+    let response =
+        git_lfs_authenticate::authenticate(&conn, &owner, &fingerprint, &repo, operation)?;
+    println!("{}", response);
 
     Ok(())
 }
@@ -0,0 +1,354 @@
+use actix_web::{http::header, web, web::Bytes, HttpResponse};
+use askama_actix::TemplateIntoResponse;
+use database::audit::AuditFilter;
+use database::models::AuditEntry;
+use log::error;
+use serde::*;
+
+use super::auth::redirect;
+use crate::session::{Session, SessionUser};
+use crate::templates::*;
+use crate::AppContext;
+
+const AUDIT_PAGE_SIZE: i64 = 100;
+/// Entries loaded at a time while exporting.
+const AUDIT_EXPORT_BATCH: i64 = 1000;
+
+/// The filters of the audit log page, as the form submits them. Empty
+/// fields match everything.
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct AuditQuery {
+    #[serde(default)]
+    pub action: String,
+    #[serde(default)]
+    pub actor: String,
+    #[serde(default)]
+    pub repository: String,
+    #[serde(default)]
+    pub outcome: String,
+    /// A date like `2022-03-01`, inclusive.
+    #[serde(default)]
+    pub since: String,
+    /// A date like `2022-03-31`, inclusive.
+    #[serde(default)]
+    pub until: String,
+    pub before: Option<i64>,
+}
+
+fn non_empty(value: &str) -> Option<String> {
+    Some(value.trim())
+        .filter(|value| !value.is_empty())
+        .map(String::from)
+}
+
+fn date(value: &str, name: &str) -> Result<Option<chrono::NaiveDate>, String> {
+    match non_empty(value) {
+        Some(value) => chrono::NaiveDate::parse_from_str(&value, "%Y-%m-%d")
+            .map(Some)
+            .map_err(|_| format!("{} should be a date like 2022-03-01", name)),
+        None => Ok(None),
+    }
+}
+
+impl AuditQuery {
+    fn filter(&self) -> Result<AuditFilter, String> {
+        Ok(AuditFilter {
+            action: non_empty(&self.action),
+            actor: non_empty(&self.actor),
+            repository: non_empty(&self.repository),
+            outcome: non_empty(&self.outcome),
+            since: date(&self.since, "since")?.map(|day| day.and_hms(0, 0, 0)),
+            until: date(&self.until, "until")?.map(|day| day.succ().and_hms(0, 0, 0)),
+            before: self.before,
+        })
+    }
+
+    fn url(&self, path: &str) -> String {
+        let mut query = url::form_urlencoded::Serializer::new(String::new());
+        query
+            .append_pair("action", &self.action)
+            .append_pair("actor", &self.actor)
+            .append_pair("repository", &self.repository)
+            .append_pair("outcome", &self.outcome)
+            .append_pair("since", &self.since)
+            .append_pair("until", &self.until);
+        if let Some(before) = self.before {
+            query.append_pair("before", &before.to_string());
+        }
+        format!("{}?{}", path, query.finish())
+    }
+}
+
+/// The signed in user, if they are an admin.
+async fn admin(
+    appctx: &AppContext,
+    session: &Session,
+    path: &str,
+) -> Result<Result<SessionUser, HttpResponse>, actix_web::Error> {
+    let user = match session.0.as_ref() {
+        Some(user) => user.clone(),
+        None => return Ok(Err(redirect(&format!("/login?next={}", path)))),
+    };
+    let pool = appctx.pool.clone();
+    let uuid = user.uuid.clone();
+    let is_admin = web::block(move || -> Result<bool, String> {
+        let conn = pool
+            .get()
+            .map_err(|err| format!("failed to get connection: {}", err))?;
+        database::user::query_is_admin(&conn, uuid)
+            .map_err(|err| format!("failed to query user: {}", err))
+    })
+    .await?;
+    match is_admin {
+        true => Ok(Ok(user)),
+        false => Ok(Err(
+            HttpResponse::Forbidden().body("only admins can do that")
+        )),
+    }
+}
+
+/// The audit log, newest first and a page at a time.
+#[actix_web::get("/admin/audit")]
+pub async fn audit_log(
+    query: web::Query<AuditQuery>,
+    appctx: web::Data<AppContext>,
+    session: Session,
+) -> Result<HttpResponse, actix_web::Error> {
+    if let Err(response) = admin(&appctx, &session, "/admin/audit").await? {
+        return Ok(response);
+    }
+
+    let query = query.into_inner();
+    let (entries, error) = match query.filter() {
+        Ok(filter) => {
+            let pool = appctx.pool.clone();
+            let entries = web::block(move || -> Result<Vec<AuditEntry>, String> {
+                let conn = pool
+                    .get()
+                    .map_err(|err| format!("failed to get connection: {}", err))?;
+                database::audit::query_audit_log(&conn, &filter, AUDIT_PAGE_SIZE)
+                    .map_err(|err| format!("failed to query audit log: {}", err))
+            })
+            .await?;
+            (entries, None)
+        }
+        Err(error) => (Vec::new(), Some(error)),
+    };
+
+    let older_url = match entries.last() {
+        Some(last) if entries.len() as i64 == AUDIT_PAGE_SIZE => Some(
+            AuditQuery {
+                before: Some(last.id),
+                ..query.clone()
+            }
+            .url("/admin/audit"),
+        ),
+        _ => None,
+    };
+    let export_url = AuditQuery {
+        before: None,
+        ..query.clone()
+    }
+    .url("/admin/audit/export");
+
+    AuditPage {
+        _parent: BaseTemplate::new()
+            .with_title(String::from("Audit log"))
+            .with_session(&session),
+        query,
+        entries,
+        older_url,
+        export_url,
+        error,
+    }
+    .into_response()
+}
+
+/// Every entry matching the filters as JSON lines, newest first.
+#[actix_web::get("/admin/audit/export")]
+pub async fn audit_log_export(
+    query: web::Query<AuditQuery>,
+    appctx: web::Data<AppContext>,
+    session: Session,
+) -> Result<HttpResponse, actix_web::Error> {
+    if let Err(response) = admin(&appctx, &session, "/admin/audit").await? {
+        return Ok(response);
+    }
+    let filter = match query.filter() {
+        Ok(filter) => filter,
+        Err(error) => return Ok(HttpResponse::BadRequest().body(error)),
+    };
+
+    // the log only grows, so it is sent a batch at a time rather than built
+    // up in memory
+    let pool = appctx.pool.clone();
+    let batches = futures::stream::unfold(Some(filter), move |filter| {
+        let pool = pool.clone();
+        async move {
+            let mut filter = filter?;
+            let query = filter.clone();
+            let batch = web::block(move || -> Result<(String, Option<i64>), String> {
+                let conn = pool
+                    .get()
+                    .map_err(|err| format!("failed to get connection: {}", err))?;
+                let entries = database::audit::query_audit_log(&conn, &query, AUDIT_EXPORT_BATCH)
+                    .map_err(|err| format!("failed to query audit log: {}", err))?;
+                let mut lines = String::new();
+                for entry in entries.iter() {
+                    lines.push_str(
+                        &serde_json::to_string(entry)
+                            .map_err(|err| format!("failed to encode entry: {}", err))?,
+                    );
+                    lines.push('\n');
+                }
+                let next = match entries.last() {
+                    Some(last) if entries.len() as i64 == AUDIT_EXPORT_BATCH => Some(last.id),
+                    _ => None,
+                };
+                Ok((lines, next))
+            })
+            .await;
+            match batch {
+                Ok((lines, next)) => {
+                    filter.before = next;
+                    Some((Ok(Bytes::from(lines)), next.map(|_| filter)))
+                }
+                Err(err) => {
+                    // the response has started, so all that is left is to
+                    // cut it short
+                    error!("failed to export audit log: {}", err);
+                    Some((Err(actix_web::error::ErrorInternalServerError(err)), None))
+                }
+            }
+        }
+    });
+
+    Ok(HttpResponse::Ok()
+        .content_type("application/x-ndjson")
+        .header(
+            header::CONTENT_DISPOSITION,
+            "attachment; filename=\"audit.jsonl\"",
+        )
+        .streaming(Box::pin(batches)))
+}
+
+#[cfg(all(test, feature = "sqlite"))]
+mod tests {
+    use actix_web::{dev::ServiceResponse, http::StatusCode, test, App};
+    use database::audit::{self, Actor, Event, Outcome};
+    use diesel::Connection;
+    use serde_json::Value;
+
+    use super::*;
+    use crate::testing::TestContext;
+
+    /// A context with the admin `root` and alice, returning their uuids. The
+    /// log has a failed login of root, a batch of logins of alice and then
+    /// a login of root, so exporting it takes more than one batch.
+    fn context() -> (TestContext, String, String) {
+        let context = TestContext::new();
+        let root = context.user("root");
+        let alice = context.user("alice");
+        database::user::set_admin(&context.conn(), root.clone(), true).unwrap();
+
+        let conn = context.conn();
+        conn.transaction::<_, diesel::result::Error, _>(|| {
+            audit::record(
+                &*conn,
+                Event::new(audit::LOGIN, Actor::User(root.clone()), Outcome::Failure),
+            )?;
+            for _ in 0..AUDIT_EXPORT_BATCH {
+                audit::record(
+                    &*conn,
+                    Event::new(audit::LOGIN, Actor::User(alice.clone()), Outcome::Success),
+                )?;
+            }
+            audit::record(
+                &*conn,
+                Event::new(audit::LOGIN, Actor::User(root.clone()), Outcome::Success)
+                    .ip(Some(String::from("192.0.2.1"))),
+            )
+        })
+        .unwrap();
+        (context, root, alice)
+    }
+
+    async fn export(context: &TestContext, user: &str, query: &str) -> ServiceResponse {
+        let mut app = test::init_service(
+            App::new()
+                .data(context.appctx.clone())
+                .service(audit_log_export),
+        )
+        .await;
+        test::call_service(
+            &mut app,
+            test::TestRequest::get()
+                .uri(&format!("/admin/audit/export?{}", query))
+                .cookie(context.session(user))
+                .to_request(),
+        )
+        .await
+    }
+
+    async fn lines(response: ServiceResponse) -> Vec<Value> {
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(
+            response.headers().get(header::CONTENT_TYPE).unwrap(),
+            "application/x-ndjson"
+        );
+        let body = test::read_body(response).await;
+        std::str::from_utf8(&body)
+            .unwrap()
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect()
+    }
+
+    #[actix_rt::test]
+    async fn exports_every_entry_newest_first() {
+        let (context, root, _) = context();
+
+        let entries = lines(export(&context, &root, "").await).await;
+        assert_eq!(entries.len(), AUDIT_EXPORT_BATCH as usize + 2);
+        assert!(entries
+            .windows(2)
+            .all(|pair| pair[0]["id"].as_i64() > pair[1]["id"].as_i64()));
+        assert_eq!(entries[0]["action"], "login");
+        assert_eq!(entries[0]["actor"], "root");
+        assert_eq!(entries[0]["actor_user"], root.as_str());
+        assert_eq!(entries[0]["ip"], "192.0.2.1");
+        assert_eq!(entries[0]["outcome"], "success");
+        assert!(entries[0]["created_at"].is_string());
+        assert_eq!(entries[1]["actor"], "alice");
+        assert_eq!(entries.last().unwrap()["outcome"], "failure");
+    }
+
+    #[actix_rt::test]
+    async fn exports_what_matches_the_filters() {
+        let (context, root, _) = context();
+
+        let entries = lines(export(&context, &root, "actor=root").await).await;
+        assert_eq!(entries.len(), 2);
+
+        let entries = lines(export(&context, &root, "actor=root&outcome=failure").await).await;
+        assert_eq!(entries.len(), 1);
+        assert_eq!(entries[0]["outcome"], "failure");
+
+        let entries = lines(export(&context, &root, "action=logout").await).await;
+        assert!(entries.is_empty());
+
+        let entries = lines(export(&context, &root, "until=2000-01-01").await).await;
+        assert!(entries.is_empty());
+    }
+
+    #[actix_rt::test]
+    async fn refuses_bad_filters_and_other_users() {
+        let (context, root, alice) = context();
+
+        let response = export(&context, &root, "since=yesterday").await;
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+
+        let response = export(&context, &alice, "").await;
+        assert_eq!(response.status(), StatusCode::FORBIDDEN);
+    }
+}
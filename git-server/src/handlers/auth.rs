@@ -0,0 +1,328 @@
+use actix_web::{http::header, web, HttpMessage, HttpRequest, HttpResponse};
+use askama_actix::TemplateIntoResponse;
+use common::secret;
+use database::audit::{self, Actor, Event, Outcome};
+use serde::*;
+
+use crate::session::{
+    cookie, create_session, NewSessionId, Session, CSRF_TOKEN_LENGTH, LOGIN_CSRF_COOKIE,
+    SESSION_COOKIE, SESSION_LIFETIME_DAYS,
+};
+use crate::templates::*;
+use crate::AppContext;
+
+#[derive(Debug, Deserialize)]
+pub struct LoginQuery {
+    pub next: Option<String>,
+}
+
+#[derive(Deserialize)]
+pub struct LoginForm {
+    pub username: String,
+    pub password: String,
+    pub csrf_token: String,
+    pub next: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct LogoutForm {
+    pub csrf_token: String,
+}
+
+/// Only paths on this server are followed after signing in, so the login
+/// form cannot be used to send people elsewhere. Browsers drop tabs and
+/// newlines from urls and read backslashes as slashes, so `/\t/host` would
+/// still lead to another host; such values are refused outright.
+pub(crate) fn local_next(next: Option<String>) -> String {
+    match next {
+        Some(next) if is_local_path(&next) => next,
+        _ => String::from("/"),
+    }
+}
+
+fn is_local_path(next: &str) -> bool {
+    const BASE: &str = "http://rustile.invalid/";
+    if !next.starts_with('/')
+        || next.starts_with("//")
+        || next
+            .chars()
+            .any(|c| c == '\\' || c.is_control() || c.is_whitespace())
+    {
+        return false;
+    }
+    // whatever is left must resolve to a path on the same origin
+    let base = url::Url::parse(BASE).unwrap();
+    base.join(next)
+        .is_ok_and(|url| url.origin() == base.origin())
+}
+
+pub(crate) fn redirect(location: &str) -> HttpResponse {
+    HttpResponse::SeeOther()
+        .header(header::LOCATION, location)
+        .finish()
+}
+
+/// The login form, with a fresh CSRF token in both a hidden field and a
+/// cookie. Posting the form only works if the two match.
+pub(crate) fn login_form(
+    appctx: &AppContext,
+    next: String,
+    username: String,
+    error: Option<String>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let csrf_token = secret::random(CSRF_TOKEN_LENGTH);
+    let mut response = LoginPage {
+        _parent: BaseTemplate::new(),
+        csrf_token: csrf_token.clone(),
+        next,
+        username,
+        error,
+        password_login: appctx.password_login,
+        sso: appctx
+            .oidc
+            .as_ref()
+            .map(|provider| provider.config.display_name.clone()),
+    }
+    .into_response()?;
+    response.add_cookie(&cookie(
+        appctx,
+        LOGIN_CSRF_COOKIE,
+        csrf_token,
+        time::Duration::hours(1),
+    ))?;
+    Ok(response)
+}
+
+#[actix_web::get("/login")]
+pub async fn login_page(
+    query: web::Query<LoginQuery>,
+    appctx: web::Data<AppContext>,
+    session: Session,
+) -> Result<HttpResponse, actix_web::Error> {
+    let next = local_next(query.into_inner().next);
+    if session.0.is_some() {
+        return Ok(redirect(&next));
+    }
+    login_form(&appctx, next, String::new(), None)
+}
+
+#[actix_web::post("/login")]
+pub async fn login(
+    request: HttpRequest,
+    form: web::Form<LoginForm>,
+    appctx: web::Data<AppContext>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let form = form.into_inner();
+    let next = local_next(form.next);
+    if !appctx.password_login {
+        return login_form(
+            &appctx,
+            next,
+            form.username,
+            Some(String::from("Signing in with a password is disabled.")),
+        );
+    }
+
+    let csrf_matches = request
+        .cookie(LOGIN_CSRF_COOKIE)
+        .is_some_and(|cookie| secret::constant_time_eq(cookie.value(), &form.csrf_token));
+    if !csrf_matches {
+        return login_form(
+            &appctx,
+            next,
+            form.username,
+            Some(String::from("The sign in form expired, please try again.")),
+        );
+    }
+
+    let pool = appctx.pool.clone();
+    let username = form.username.clone();
+    let session = web::block(move || -> Result<Option<NewSessionId>, String> {
+        let conn = pool
+            .get()
+            .map_err(|err| format!("failed to get connection: {}", err))?;
+        let (user, password_hash) = match database::user::query_login(&conn, username)
+            .map_err(|err| format!("failed to query user: {}", err))?
+        {
+            Some((user, Some(password_hash))) => (user, password_hash),
+            // hash anyway, so the time taken does not tell whether the user
+            // exists or has a password
+            _ => {
+                common::password::verify_dummy_password(&form.password);
+                return Ok(None);
+            }
+        };
+        if !common::password::verify_password(&form.password, &password_hash) {
+            return Ok(None);
+        }
+
+        create_session(&conn, user).map(Some)
+    })
+    .await?;
+
+    let actor = Actor::Username(form.username.clone());
+    let session = match session {
+        Some(session) => {
+            let detail = match session.second_factor_pending {
+                true => "password, second factor pending",
+                false => "password",
+            };
+            appctx
+                .audit(
+                    &request,
+                    Event::new(audit::LOGIN, actor, Outcome::Success).detail(detail),
+                )
+                .await;
+            session
+        }
+        None => {
+            appctx
+                .audit(
+                    &request,
+                    Event::new(audit::LOGIN, actor, Outcome::Failure).detail("password"),
+                )
+                .await;
+            return login_form(
+                &appctx,
+                next,
+                form.username,
+                Some(String::from("Incorrect username or password.")),
+            );
+        }
+    };
+
+    let mut response = signed_in(&appctx, session, &next)?;
+    response.add_cookie(&cookie(
+        &appctx,
+        LOGIN_CSRF_COOKIE,
+        String::new(),
+        time::Duration::zero(),
+    ))?;
+    Ok(response)
+}
+
+/// Sets the session cookie and moves on to `next`, by way of the second
+/// factor if the user has one.
+pub(crate) fn signed_in(
+    appctx: &AppContext,
+    session: NewSessionId,
+    next: &str,
+) -> Result<HttpResponse, actix_web::Error> {
+    let mut response = if session.second_factor_pending {
+        let next: String = url::form_urlencoded::byte_serialize(next.as_bytes()).collect();
+        redirect(&format!("/login/totp?next={}", next))
+    } else {
+        redirect(next)
+    };
+    response.add_cookie(&cookie(
+        appctx,
+        SESSION_COOKIE,
+        session.id,
+        time::Duration::days(SESSION_LIFETIME_DAYS),
+    ))?;
+    Ok(response)
+}
+
+#[actix_web::post("/logout")]
+pub async fn logout(
+    request: HttpRequest,
+    form: web::Form<LogoutForm>,
+    appctx: web::Data<AppContext>,
+    session: Session,
+) -> Result<HttpResponse, actix_web::Error> {
+    let user = match session.0.as_ref() {
+        Some(user) if session.check_csrf(&form.csrf_token) => user,
+        Some(_) => return Ok(HttpResponse::Forbidden().body("invalid csrf token")),
+        None => return Ok(redirect("/")),
+    };
+
+    let pool = appctx.pool.clone();
+    let id_hash = user.id_hash.clone();
+    web::block(move || -> Result<usize, String> {
+        let conn = pool
+            .get()
+            .map_err(|err| format!("failed to get connection: {}", err))?;
+        database::session::delete_session(&conn, id_hash)
+            .map_err(|err| format!("failed to delete session: {}", err))
+    })
+    .await?;
+    appctx
+        .audit(
+            &request,
+            Event::new(
+                audit::LOGOUT,
+                Actor::User(user.uuid.clone()),
+                Outcome::Success,
+            ),
+        )
+        .await;
+
+    let mut response = redirect("/");
+    response.add_cookie(&cookie(
+        &appctx,
+        SESSION_COOKIE,
+        String::new(),
+        time::Duration::zero(),
+    ))?;
+    Ok(response)
+}
+
+#[cfg(test)]
+mod tests {
+    use actix_web::web;
+
+    use super::{local_next, LoginQuery};
+
+    fn next(value: &str) -> String {
+        local_next(Some(String::from(value)))
+    }
+
+    #[test]
+    fn follows_local_paths() {
+        for value in [
+            "/",
+            "/grp/repo.git",
+            "/grp/repo.git/tree/main?path=a%20b#top",
+        ] {
+            assert_eq!(next(value), value);
+        }
+        assert_eq!(local_next(None), "/");
+    }
+
+    #[test]
+    fn refuses_other_hosts() {
+        for value in [
+            "https://evil.example/",
+            "//evil.example/",
+            "/\\evil.example/",
+            "/\t/evil.example",
+            "/\n/evil.example",
+            "/\r\n/evil.example",
+            "\t//evil.example",
+            "/ /evil.example",
+            "/\u{a0}/evil.example",
+            "/\u{0}/evil.example",
+            "/\u{7f}/evil.example",
+            "javascript:alert(1)",
+            "grp/repo.git",
+            "",
+        ] {
+            assert_eq!(next(value), "/", "{:?}", value);
+        }
+    }
+
+    #[test]
+    fn refuses_encoded_whitespace() {
+        for query in [
+            "next=/%09/evil.example",
+            "next=/%0a/evil.example",
+            "next=%2F%5Cevil.example",
+        ] {
+            let next = web::Query::<LoginQuery>::from_query(query)
+                .unwrap()
+                .into_inner()
+                .next;
+            assert_eq!(local_next(next), "/", "{}", query);
+        }
+    }
+}
@@ -0,0 +1,116 @@
+use actix_web::{http::StatusCode, web, HttpResponse};
+use log::error;
+use serde::*;
+
+use crate::AppContext;
+
+#[derive(Debug, Serialize)]
+pub struct HealthResponse {
+    /// `ok`, `migrations_pending` or `database_unavailable`.
+    pub status: &'static str,
+    pub pending_migrations: Vec<&'static str>,
+}
+
+/// For load balancers and monitoring. Answers 503 while the database cannot
+/// be reached or lacks migrations, which only happens when git-server runs
+/// with `--check-migrations`.
+#[actix_web::get("/health")]
+pub async fn health_check(appctx: web::Data<AppContext>) -> HttpResponse {
+    let pool = appctx.pool.clone();
+    let pending = web::block(move || -> Result<Vec<&'static str>, String> {
+        let conn = pool
+            .get()
+            .map_err(|err| format!("failed to get connection: {}", err))?;
+        database::migrations::pending_migrations(&conn)
+            .map_err(|err| format!("failed to read the applied migrations: {}", err))
+    })
+    .await;
+
+    let (status, response) = match pending {
+        Ok(pending) if pending.is_empty() => (
+            StatusCode::OK,
+            HealthResponse {
+                status: "ok",
+                pending_migrations: pending,
+            },
+        ),
+        Ok(pending) => (
+            StatusCode::SERVICE_UNAVAILABLE,
+            HealthResponse {
+                status: "migrations_pending",
+                pending_migrations: pending,
+            },
+        ),
+        Err(err) => {
+            // the error names hosts and users, so it is only logged
+            error!("health check failed: {}", err);
+            (
+                StatusCode::SERVICE_UNAVAILABLE,
+                HealthResponse {
+                    status: "database_unavailable",
+                    pending_migrations: Vec::new(),
+                },
+            )
+        }
+    };
+    HttpResponse::build(status).json(response)
+}
+
+#[cfg(all(test, feature = "sqlite"))]
+mod tests {
+    use actix_web::{dev::ServiceResponse, test, App};
+    use diesel::r2d2::{ConnectionManager, Pool};
+    use serde_json::Value;
+
+    use super::*;
+    use crate::testing::TestContext;
+
+    /// Asks for the health of an in-memory database, with or without the
+    /// migrations applied.
+    async fn health(migrated: bool) -> ServiceResponse {
+        let mut context = TestContext::new();
+        // every connection to :memory: is a database of its own
+        let pool = Pool::builder()
+            .max_size(1)
+            .build(ConnectionManager::new(":memory:"))
+            .unwrap();
+        if migrated {
+            database::migrations::run_pending_migrations(&pool.get().unwrap()).unwrap();
+        }
+        context.appctx.pool = pool;
+
+        let mut app = test::init_service(
+            App::new()
+                .data(context.appctx.clone())
+                .service(health_check),
+        )
+        .await;
+        test::call_service(
+            &mut app,
+            test::TestRequest::get().uri("/health").to_request(),
+        )
+        .await
+    }
+
+    #[actix_rt::test]
+    async fn healthy_once_migrated() {
+        let response = health(true).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        let body: Value = test::read_body_json(response).await;
+        assert_eq!(body["status"], "ok");
+        assert_eq!(body["pending_migrations"].as_array().unwrap().len(), 0);
+    }
+
+    #[actix_rt::test]
+    async fn unavailable_while_migrations_are_pending() {
+        let response = health(false).await;
+        assert_eq!(response.status(), StatusCode::SERVICE_UNAVAILABLE);
+        let body: Value = test::read_body_json(response).await;
+        assert_eq!(body["status"], "migrations_pending");
+        assert_eq!(
+            body["pending_migrations"][0],
+            "2021-12-23-142004_init_database"
+        );
+        assert!(body.get("error").is_none());
+    }
+}
@@ -1,13 +1,24 @@
-use std::{collections::HashMap, fmt::Display, str::FromStr};
+use std::{collections::HashMap, fmt::Display};
 
-use actix_web::{web, HttpResponse};
-use futures::StreamExt;
-use log::debug;
+use actix_web::{
+    error::BlockingError,
+    http::{header, StatusCode},
+    web, HttpRequest, HttpResponse,
+};
+use futures::{channel::mpsc, SinkExt, StreamExt};
+use log::{debug, error};
 use serde::*;
 
-use crate::middleware::token_extractor::Token;
+use crate::middleware::token_extractor::{MaybeToken, Token, AUTHENTICATE_CHALLENGE};
+use crate::storage::stream::{ObjectVerifier, VerifyingReader, VerifyingWriter};
+use crate::storage::{object_key, StorageError};
+use common::{Access, Oid, Operation, RepoPath, RepoPathError};
+use database::audit::{self, Event, Outcome};
 use crate::AppContext;
 
+/// Oids listed in the audit log per batch, the rest are only counted.
+const AUDIT_MAX_OIDS: usize = 100;
+
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct LFSObjectURLAction {
     href: String,
@@ -23,47 +34,27 @@ pub struct LFSObject {
     #[serde(skip_deserializing)]
     pub authenticated: bool,
 
-    #[serde(skip_deserializing)]
+    #[serde(skip_deserializing, skip_serializing_if = "HashMap::is_empty")]
     actions: HashMap<String, LFSObjectURLAction>,
-}
-
-#[derive(Debug, Serialize, Deserialize)]
-pub struct LFSReference {
-    pub name: String,
-}
 
-#[allow(non_camel_case_types)]
-#[derive(Debug, Serialize, Deserialize)]
-pub enum LFSOperation {
-    download,
-    upload,
+    #[serde(skip_deserializing, skip_serializing_if = "Option::is_none")]
+    pub error: Option<LFSObjectError>,
 }
 
-impl Display for LFSOperation {
-    fn fmt(&self, f: &mut __private::Formatter<'_>) -> std::fmt::Result {
-        match self {
-            LFSOperation::download => write!(f, "download"),
-            LFSOperation::upload => write!(f, "upload"),
-        }?;
-        Ok(())
-    }
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct LFSObjectError {
+    pub code: u16,
+    pub message: String,
 }
 
-impl FromStr for LFSOperation {
-    type Err = String;
-
-    fn from_str(s: &str) -> Result<Self, Self::Err> {
-        match s.to_lowercase().as_str() {
-            "download" => Ok(Self::download),
-            "upload" => Ok(Self::upload),
-            _ => Err(format!("unknown operation: {}", s)),
-        }
-    }
+#[derive(Debug, Serialize, Deserialize)]
+pub struct LFSReference {
+    pub name: String,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct LFSBatchRequest {
-    pub operation: LFSOperation,
+    pub operation: Operation,
     pub objects: Vec<LFSObject>,
 
     #[serde(default = "default_transfers")]
@@ -86,98 +77,257 @@ fn default_transfers() -> Vec<String> {
 pub struct LFSBatchResponse {
     pub transfer: String,
     pub objects: Vec<LFSObject>,
+    pub hash_algo: String,
 }
 
-#[actix_web::post("/{repo_path:.*\\.git}/info/lfs/locks/verify")]
-pub async fn lfs_lock_verify(
-    request: actix_web::HttpRequest,
-    mut body: actix_web::web::Payload,
-) -> Result<String, actix_web::error::Error> {
-    println!("{:?}", request.headers());
+#[derive(Debug, Serialize, Deserialize)]
+pub struct LFSVerifyRequest {
+    pub oid: String,
+    pub size: u64,
+}
 
-    let mut bytes = actix_web::web::BytesMut::new();
-    while let Some(item) = body.next().await {
-        let item = item?;
-        println!("Chunk: {:?}", &item);
-        bytes.extend_from_slice(&item);
+#[derive(Debug, Serialize, Deserialize)]
+pub struct LFSErrorResponse {
+    pub message: String,
+}
+
+/// The media type of the bodies of the Git LFS API.
+pub const LFS_CONTENT_TYPE: &str = "application/vnd.git-lfs+json";
+
+pub fn lfs_error(status: StatusCode, message: impl Into<String>) -> HttpResponse {
+    HttpResponse::build(status)
+        .content_type(LFS_CONTENT_TYPE)
+        .json(LFSErrorResponse {
+            message: message.into(),
+        })
+}
+
+/// Validates the repository named in the url, answering 400 for malformed
+/// paths and 404 for paths without a repository.
+pub fn lfs_repo_path(appctx: &AppContext, raw: &str) -> Result<RepoPath, HttpResponse> {
+    RepoPath::parse(raw, &appctx.root).map_err(|err| match err {
+        RepoPathError::Invalid(..) => lfs_error(StatusCode::BAD_REQUEST, err.to_string()),
+        RepoPathError::NotFound(_) => lfs_error(StatusCode::NOT_FOUND, err.to_string()),
+    })
+}
+
+/// Checks what the token and its user may do with the repository right now,
+/// so revoked permissions take effect immediately. The access granted is
+/// capped by the scope of personal access tokens. Tokens of
+/// git-lfs-authenticate are issued for an operation rather than an access,
+/// so their user keeps their own access, like admin access for breaking
+/// locks. Anonymous users are asked to authenticate, and repositories a user
+/// cannot read at all look like they do not exist.
+pub async fn lfs_authorize(
+    appctx: &AppContext,
+    token: Option<&Token>,
+    repo_path: &RepoPath,
+    required: Access,
+) -> Result<Result<Access, HttpResponse>, actix_web::Error> {
+    if let Some(token) = token {
+        if !token.permits(repo_path.as_str(), required) {
+            return Ok(Err(lfs_error(
+                StatusCode::FORBIDDEN,
+                format!("token does not grant {} access to {}", required, repo_path),
+            )));
+        }
     }
 
-    Err(actix_web::error::ErrorInternalServerError(""))
+    Ok(match appctx.token_access(token, repo_path).await? {
+        Some(access) if access >= required => Ok(match token {
+            Some(token @ Token::Personal(_)) => access.min(token.scope()),
+            _ => access,
+        }),
+        _ if token.is_none() => {
+            let mut response = lfs_error(StatusCode::UNAUTHORIZED, "authentication required");
+            response.headers_mut().insert(
+                header::HeaderName::from_static("lfs-authenticate"),
+                header::HeaderValue::from_static(AUTHENTICATE_CHALLENGE),
+            );
+            Err(response)
+        }
+        Some(_) => Err(lfs_error(
+            StatusCode::FORBIDDEN,
+            format!("{} access to {} is required", required, repo_path),
+        )),
+        None => Err(lfs_error(
+            StatusCode::NOT_FOUND,
+            format!("no such repository: {}", repo_path),
+        )),
+    })
+}
+
+fn lfs_oid(raw: &str) -> Result<Oid, HttpResponse> {
+    raw.parse()
+        .map_err(|err: common::OidError| lfs_error(StatusCode::UNPROCESSABLE_ENTITY, err.to_string()))
+}
+
+/// The href of a git-server endpoint below `/info/lfs/objects`, either the
+/// one proxying an object to and from the storage backend, or `verify`.
+fn proxy_href(request: &HttpRequest, repo_path: &RepoPath, name: &str) -> String {
+    let info = request.connection_info();
+    format!(
+        "{}://{}/{}/info/lfs/objects/{}",
+        info.scheme(),
+        info.host(),
+        repo_path,
+        name
+    )
 }
 
 #[actix_web::post("/{repo_path:.*\\.git}/info/lfs/objects/batch")]
 pub async fn lfs_objects_batch(
+    request: HttpRequest,
     web::Path(repo_path): web::Path<String>,
     body: web::Json<LFSBatchRequest>,
     appctx: actix_web::web::Data<AppContext>,
-    token: Token,
+    token: MaybeToken,
 ) -> Result<HttpResponse, actix_web::error::Error> {
     let mut objects = Vec::new();
 
-    assert_eq!(token.command, body.operation.to_string());
+    let repo_path = match lfs_repo_path(&appctx, &repo_path) {
+        Ok(repo_path) => repo_path,
+        Err(response) => return Ok(response),
+    };
+    let action = match body.operation {
+        Operation::Download => audit::LFS_DOWNLOAD,
+        Operation::Upload => audit::LFS_UPLOAD,
+    };
+    if let Err(response) =
+        lfs_authorize(&appctx, token.0.as_ref(), &repo_path, body.operation.into()).await?
+    {
+        // anonymous clients are asked to authenticate first, that is no denial
+        if response.status() != StatusCode::UNAUTHORIZED {
+            appctx
+                .audit(
+                    &request,
+                    Event::new(action, token.actor(), Outcome::Denied)
+                        .repository(&repo_path)
+                        .detail(response.status().to_string()),
+                )
+                .await;
+        }
+        return Ok(response);
+    }
+
+    if body.hash_algo != default_hash_algo() {
+        return Ok(lfs_error(
+            StatusCode::UNPROCESSABLE_ENTITY,
+            format!("unsupported hash algorithm: {}", body.hash_algo),
+        ));
+    }
+
+    // proxied transfers authenticate with the same credentials as the batch
+    let proxy_header: HashMap<String, String> = request
+        .headers()
+        .get(header::AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .map(|value| (String::from("Authorization"), String::from(value)))
+        .into_iter()
+        .collect();
 
     for obj in body.objects.iter() {
         debug!("check object: {}", obj.oid.clone());
-        let _appctx = appctx.clone();
-        let _repo_path = repo_path.clone();
-        let _obj = obj.clone();
-        match web::block(move || {
-            _appctx
-                .bucket
-                .head_object(format!("{}/lfs/objects/{}", _repo_path, _obj.oid.clone()))
-        })
-        .await
-        {
-            Ok(_) => continue,
-            Err(_) => debug!("add object {} to list", obj.oid),
+        let oid = match obj.oid.parse::<Oid>() {
+            Ok(oid) => oid,
+            Err(err) => {
+                objects.push(LFSObject {
+                    oid: obj.oid.clone(),
+                    size: obj.size,
+                    authenticated: true,
+                    actions: HashMap::new(),
+                    error: Some(LFSObjectError {
+                        code: 422,
+                        message: err.to_string(),
+                    }),
+                });
+                continue;
+            }
+        };
+        let storage = appctx.storage.clone();
+        let key = object_key(&repo_path, &oid);
+        let stored_size = web::block(move || storage.size(&key)).await?;
+
+        let error = match (&body.operation, stored_size) {
+            (Operation::Download, None) => Some(LFSObjectError {
+                code: 404,
+                message: String::from("object does not exist"),
+            }),
+            (_, Some(size)) if size != obj.size as u64 => Some(LFSObjectError {
+                code: 422,
+                message: format!("object has {} bytes, not {}", size, obj.size),
+            }),
+            _ => None,
+        };
+        if let Some(error) = error {
+            objects.push(LFSObject {
+                oid: obj.oid.clone(),
+                size: obj.size,
+                authenticated: true,
+                actions: HashMap::new(),
+                error: Some(error),
+            });
+            continue;
+        }
+
+        // the server already has the object, so there is nothing to upload
+        if let (Operation::Upload, Some(_)) = (&body.operation, stored_size) {
+            objects.push(LFSObject {
+                oid: obj.oid.clone(),
+                size: obj.size,
+                authenticated: true,
+                actions: HashMap::new(),
+                error: None,
+            });
+            continue;
         }
+
         debug!("object need operation: {}", obj.oid.clone());
         let expires_in = 3600;
-        let href = match body.operation {
-            LFSOperation::download => appctx
-                .bucket
-                .presign_get(
-                    format!("{}/lfs/objects/{}", repo_path, obj.oid.clone()),
-                    expires_in.try_into().unwrap(),
-                )
-                .unwrap(),
-            LFSOperation::upload => appctx
-                .bucket
-                .presign_put(
-                    format!("{}/lfs/objects/{}", repo_path, obj.oid.clone()),
-                    expires_in.try_into().unwrap(),
-                    None,
-                )
-                .unwrap(),
+        let storage = appctx.storage.clone();
+        let key = object_key(&repo_path, &oid);
+        let operation = body.operation;
+        let proxy_transfers = appctx.proxy_transfers;
+        let presigned = web::block(move || match operation {
+            _ if proxy_transfers => Ok(None),
+            Operation::Download => storage.presign_get(&key, expires_in),
+            Operation::Upload => storage.presign_put(&key, expires_in),
+        })
+        .await?;
+        let (href, transfer_header) = match presigned {
+            Some(href) => (href, HashMap::new()),
+            None => (
+                proxy_href(&request, &repo_path, oid.as_str()),
+                proxy_header.clone(),
+            ),
         };
 
         let actions = match body.operation {
-            LFSOperation::download => HashMap::from_iter(vec![(
+            Operation::Download => HashMap::from_iter(vec![(
                 "download".into(),
                 LFSObjectURLAction {
                     href,
                     expires_in,
-                    header: HashMap::new(),
+                    header: transfer_header,
                 },
             )]),
-            LFSOperation::upload => HashMap::from_iter(vec![
+            Operation::Upload => HashMap::from_iter(vec![
                 (
                     "upload".into(),
                     LFSObjectURLAction {
                         href,
                         expires_in,
-                        header: HashMap::new(),
+                        header: transfer_header,
+                    },
+                ),
+                (
+                    "verify".into(),
+                    LFSObjectURLAction {
+                        href: proxy_href(&request, &repo_path, "verify"),
+                        expires_in,
+                        header: proxy_header.clone(),
                     },
                 ),
-                // (
-                //     "verify".into(),
-                //     LFSObjectURLAction {
-                //         href: format!("/{}/info/lfs/objects", repo_path),
-                //         header: HashMap::from_iter(vec![("Authorization".into(), "Token eyJ0eXAiOiJKV1QiLCJhbGciOiJIUzI1NiJ9.eyJzdWIiOiJnaXQuamVmZnRoZWNvZGVyLnh5eiIsImlhdCI6IjIwMjItMDEtMDJUMTU6MDA6MDMuMTY1ODE3OTM5WiIsImNvb
-                //         W1hbmQiOiJ1cGxvYWQifQ.Mt0mTyFQw-YOaLlJ_ZytfitpZnROus3UorE_NX30V-k".into())]),
-                //         expires_in,
-                //     },
-                // ),
             ]),
         };
         debug!("object processed: {}", obj.oid.clone());
@@ -186,12 +336,575 @@ pub async fn lfs_objects_batch(
             oid: obj.oid.clone(),
             size: obj.size,
             authenticated: true,
-            actions: actions,
+            actions,
+            error: None,
         });
     }
 
+    // presigned transfers bypass git-server, so the batch is where they are seen
+    let transferred: Vec<&str> = objects
+        .iter()
+        .filter(|object| !object.actions.is_empty())
+        .map(|object| object.oid.as_str())
+        .collect();
+    if !transferred.is_empty() {
+        appctx
+            .audit(
+                &request,
+                Event::new(action, token.actor(), Outcome::Success)
+                    .repository(&repo_path)
+                    .detail(format!(
+                        "{} objects: {}{}",
+                        transferred.len(),
+                        transferred[..transferred.len().min(AUDIT_MAX_OIDS)].join(" "),
+                        if transferred.len() > AUDIT_MAX_OIDS {
+                            " ..."
+                        } else {
+                            ""
+                        }
+                    )),
+            )
+            .await;
+    }
+
     Ok(HttpResponse::Ok().json(LFSBatchResponse {
         transfer: "basic".into(),
-        objects: objects,
+        objects,
+        hash_algo: default_hash_algo(),
     }))
 }
+
+enum VerifyOutcome {
+    Verified,
+    Missing,
+    Mismatch(String),
+}
+
+#[actix_web::post("/{repo_path:.*\\.git}/info/lfs/objects/verify")]
+pub async fn lfs_object_verify(
+    web::Path(repo_path): web::Path<String>,
+    body: web::Json<LFSVerifyRequest>,
+    appctx: web::Data<AppContext>,
+    token: Token,
+) -> Result<HttpResponse, actix_web::error::Error> {
+    let repo_path = match lfs_repo_path(&appctx, &repo_path) {
+        Ok(repo_path) => repo_path,
+        Err(response) => return Ok(response),
+    };
+    if let Err(response) =
+        lfs_authorize(&appctx, Some(&token), &repo_path, Access::Write).await?
+    {
+        return Ok(response);
+    }
+    let body = body.into_inner();
+    let oid = match lfs_oid(&body.oid) {
+        Ok(oid) => oid,
+        Err(response) => return Ok(response),
+    };
+    let storage = appctx.storage.clone();
+    let key = object_key(&repo_path, &oid);
+    let verify_hash = appctx.verify_hash;
+    let outcome = web::block(move || -> Result<VerifyOutcome, StorageError> {
+        match storage.size(&key)? {
+            None => return Ok(VerifyOutcome::Missing),
+            Some(size) if size != body.size => {
+                return Ok(VerifyOutcome::Mismatch(format!(
+                    "expected {} bytes, got {} bytes",
+                    body.size, size
+                )))
+            }
+            Some(_) => {}
+        }
+        if verify_hash {
+            let mut hasher = ObjectVerifier::new(oid, Some(body.size));
+            storage.get(&key, &mut hasher)?;
+            if let Err(reason) = hasher.finish() {
+                return Ok(VerifyOutcome::Mismatch(reason));
+            }
+        }
+        Ok(VerifyOutcome::Verified)
+    })
+    .await?;
+
+    Ok(match outcome {
+        VerifyOutcome::Verified => HttpResponse::Ok().finish(),
+        VerifyOutcome::Missing => lfs_error(StatusCode::NOT_FOUND, "object not found"),
+        VerifyOutcome::Mismatch(reason) => lfs_error(StatusCode::UNPROCESSABLE_ENTITY, reason),
+    })
+}
+
+#[actix_web::get("/{repo_path:.*\\.git}/info/lfs/objects/{oid}")]
+pub async fn lfs_object_download(
+    web::Path((repo_path, oid)): web::Path<(String, String)>,
+    appctx: web::Data<AppContext>,
+    token: MaybeToken,
+) -> Result<HttpResponse, actix_web::error::Error> {
+    let repo_path = match lfs_repo_path(&appctx, &repo_path) {
+        Ok(repo_path) => repo_path,
+        Err(response) => return Ok(response),
+    };
+    if let Err(response) =
+        lfs_authorize(&appctx, token.0.as_ref(), &repo_path, Access::Read).await?
+    {
+        return Ok(response);
+    }
+    let oid = match lfs_oid(&oid) {
+        Ok(oid) => oid,
+        Err(response) => return Ok(response),
+    };
+    let storage = appctx.storage.clone();
+    let key = object_key(&repo_path, &oid);
+    let size = match web::block(move || storage.size(&key)).await? {
+        Some(size) => size,
+        None => return Ok(lfs_error(StatusCode::NOT_FOUND, "object not found")),
+    };
+
+    let (tx, rx) = mpsc::channel(16);
+    let storage = appctx.storage.clone();
+    let key = object_key(&repo_path, &oid);
+    actix_web::rt::spawn(async move {
+        let result = web::block(move || {
+            let mut writer = VerifyingWriter::new(tx, ObjectVerifier::new(oid, Some(size)));
+            storage.get(&key, &mut writer)?;
+            writer.finish().map_err(StorageError::from)
+        })
+        .await;
+        if let Err(err) = result {
+            error!("failed to stream object: {}", err);
+        }
+    });
+
+    Ok(HttpResponse::Ok()
+        .content_type("application/octet-stream")
+        .no_chunking(size)
+        .streaming(rx))
+}
+
+#[actix_web::put("/{repo_path:.*\\.git}/info/lfs/objects/{oid}")]
+pub async fn lfs_object_upload(
+    request: HttpRequest,
+    web::Path((repo_path, oid)): web::Path<(String, String)>,
+    mut body: web::Payload,
+    appctx: web::Data<AppContext>,
+    token: Token,
+) -> Result<HttpResponse, actix_web::error::Error> {
+    let repo_path = match lfs_repo_path(&appctx, &repo_path) {
+        Ok(repo_path) => repo_path,
+        Err(response) => return Ok(response),
+    };
+    if let Err(response) =
+        lfs_authorize(&appctx, Some(&token), &repo_path, Access::Write).await?
+    {
+        return Ok(response);
+    }
+    let oid = match lfs_oid(&oid) {
+        Ok(oid) => oid,
+        Err(response) => return Ok(response),
+    };
+    let expected_size = request
+        .headers()
+        .get(header::CONTENT_LENGTH)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.parse::<u64>().ok());
+
+    let (mut tx, rx) = mpsc::channel(16);
+    let storage = appctx.storage.clone();
+    let key = object_key(&repo_path, &oid);
+    let upload = web::block(move || {
+        let mut reader = VerifyingReader::new(rx, ObjectVerifier::new(oid, expected_size));
+        match storage.put(&key, &mut reader) {
+            Ok(()) => Ok(()),
+            Err(err) => Err(match reader.mismatch() {
+                Some(reason) => UploadError::Mismatch(reason.to_string()),
+                None => UploadError::Storage(err),
+            }),
+        }
+    });
+    let feed = async move {
+        while let Some(chunk) = body.next().await {
+            // the backend stops reading once it has failed
+            if tx.send(chunk).await.is_err() {
+                break;
+            }
+        }
+    };
+
+    match futures::join!(upload, feed).0 {
+        Ok(()) => Ok(HttpResponse::Ok().finish()),
+        Err(BlockingError::Error(UploadError::Mismatch(reason))) => {
+            Ok(lfs_error(StatusCode::UNPROCESSABLE_ENTITY, reason))
+        }
+        Err(err) => Err(actix_web::error::ErrorInternalServerError(err)),
+    }
+}
+
+#[derive(Debug)]
+enum UploadError {
+    Mismatch(String),
+    Storage(StorageError),
+}
+
+impl Display for UploadError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            UploadError::Mismatch(reason) => write!(f, "object rejected: {}", reason),
+            UploadError::Storage(err) => write!(f, "{}", err),
+        }
+    }
+}
+
+#[cfg(all(test, feature = "sqlite"))]
+mod tests {
+    use actix_web::{dev::ServiceResponse, test, App};
+    use common::{Operation, RepoPath};
+    use serde_json::{json, Value};
+    use sha2::{Digest, Sha256};
+
+    use super::*;
+    use crate::testing::TestContext;
+
+    const CONTENTS: &[u8] = b"large file contents";
+
+    struct Fixture {
+        context: TestContext,
+        repo: RepoPath,
+        alice: String,
+    }
+
+    /// alice may write to `grp/repo.git`.
+    fn fixture() -> Fixture {
+        let context = TestContext::new();
+        let repo = context.repository("grp/repo.git");
+        let alice = context.user("alice");
+        context.grant(&repo, &alice, Access::Write);
+        Fixture {
+            context,
+            repo,
+            alice,
+        }
+    }
+
+    fn oid_of(data: &[u8]) -> String {
+        hex::encode(Sha256::digest(data))
+    }
+
+    async fn call(
+        context: &TestContext,
+        request: test::TestRequest,
+        authorization: &str,
+    ) -> ServiceResponse {
+        let mut app = test::init_service(
+            App::new()
+                .data(context.appctx.clone())
+                .service(lfs_objects_batch)
+                .service(lfs_object_verify)
+                .service(lfs_object_download)
+                .service(lfs_object_upload),
+        )
+        .await;
+        test::call_service(
+            &mut app,
+            request.header("Authorization", authorization).to_request(),
+        )
+        .await
+    }
+
+    impl Fixture {
+        fn token(&self, operation: Operation) -> String {
+            self.context
+                .authorization(&self.alice, &self.repo, operation)
+        }
+
+        fn key(&self, oid: &str) -> String {
+            object_key(&self.repo, &oid.parse().unwrap())
+        }
+
+        /// Puts `data` into the storage as the object `oid`.
+        fn store(&self, oid: &str, data: &[u8]) {
+            self.context
+                .appctx
+                .storage
+                .put(&self.key(oid), &mut &data[..])
+                .unwrap();
+        }
+
+        fn stored(&self, oid: &str) -> Option<Vec<u8>> {
+            let storage = &self.context.appctx.storage;
+            let key = self.key(oid);
+            storage.exists(&key).unwrap().then(|| {
+                let mut data = Vec::new();
+                storage.get(&key, &mut data).unwrap();
+                data
+            })
+        }
+
+        async fn upload(&self, oid: &str, data: &'static [u8]) -> ServiceResponse {
+            call(
+                &self.context,
+                test::TestRequest::put()
+                    .uri(&format!("/grp/repo.git/info/lfs/objects/{}", oid))
+                    .header("Content-Length", data.len().to_string())
+                    .set_payload(data),
+                &self.token(Operation::Upload),
+            )
+            .await
+        }
+
+        async fn batch(&self, operation: Operation, body: Value) -> ServiceResponse {
+            call(
+                &self.context,
+                test::TestRequest::post()
+                    .uri("/grp/repo.git/info/lfs/objects/batch")
+                    .set_json(&body),
+                &self.token(operation),
+            )
+            .await
+        }
+
+        /// The one object of the answer to a batch request for `oid` and
+        /// `size`.
+        async fn batch_object(&self, operation: Operation, oid: &str, size: usize) -> Value {
+            let response = self
+                .batch(
+                    operation,
+                    json!({
+                        "operation": operation,
+                        "objects": [{ "oid": oid, "size": size }],
+                        "ref": { "name": "refs/heads/main" },
+                    }),
+                )
+                .await;
+            assert_eq!(response.status(), StatusCode::OK);
+            let mut body: Value = test::read_body_json(response).await;
+            assert_eq!(body["objects"].as_array().unwrap().len(), 1);
+            body["objects"][0].take()
+        }
+
+        async fn verify(&self, oid: &str, size: usize) -> ServiceResponse {
+            call(
+                &self.context,
+                test::TestRequest::post()
+                    .uri("/grp/repo.git/info/lfs/objects/verify")
+                    .set_json(&json!({ "oid": oid, "size": size })),
+                &self.token(Operation::Upload),
+            )
+            .await
+        }
+
+        async fn download(&self, oid: &str) -> ServiceResponse {
+            call(
+                &self.context,
+                test::TestRequest::get().uri(&format!("/grp/repo.git/info/lfs/objects/{}", oid)),
+                &self.token(Operation::Download),
+            )
+            .await
+        }
+    }
+
+    async fn assert_lfs_error(response: ServiceResponse, status: StatusCode) -> String {
+        assert_eq!(response.status(), status);
+        assert_eq!(
+            response.headers().get(header::CONTENT_TYPE).unwrap(),
+            LFS_CONTENT_TYPE
+        );
+        let body: Value = test::read_body_json(response).await;
+        String::from(body["message"].as_str().unwrap())
+    }
+
+    #[actix_rt::test]
+    async fn proxied_uploads_are_stored() {
+        let fixture = fixture();
+        let oid = oid_of(CONTENTS);
+
+        let response = fixture.upload(&oid, CONTENTS).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(fixture.stored(&oid).unwrap(), CONTENTS);
+    }
+
+    #[actix_rt::test]
+    async fn proxied_uploads_must_match_their_oid() {
+        let fixture = fixture();
+        let oid = oid_of(CONTENTS);
+
+        let response = fixture.upload(&oid, b"something else").await;
+        let message = assert_lfs_error(response, StatusCode::UNPROCESSABLE_ENTITY).await;
+        assert!(message.contains(&oid), "{}", message);
+        assert!(fixture.stored(&oid).is_none());
+    }
+
+    #[actix_rt::test]
+    async fn proxied_downloads_stream_the_object() {
+        let fixture = fixture();
+        let oid = oid_of(CONTENTS);
+        fixture.store(&oid, CONTENTS);
+
+        let response = fixture.download(&oid).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(test::read_body(response).await, CONTENTS);
+    }
+
+    #[actix_rt::test]
+    async fn downloads_of_missing_objects_are_lfs_errors() {
+        let fixture = fixture();
+
+        let response = fixture.download(&oid_of(CONTENTS)).await;
+        let message = assert_lfs_error(response, StatusCode::NOT_FOUND).await;
+        assert_eq!(message, "object not found");
+    }
+
+    #[actix_rt::test]
+    async fn batch_downloads_point_at_the_proxy() {
+        let fixture = fixture();
+        let oid = oid_of(CONTENTS);
+        fixture.store(&oid, CONTENTS);
+
+        let object = fixture
+            .batch_object(Operation::Download, &oid, CONTENTS.len())
+            .await;
+        assert!(object.get("error").is_none());
+        assert_eq!(
+            object["actions"]["download"]["href"],
+            format!(
+                "http://localhost:8080/grp/repo.git/info/lfs/objects/{}",
+                oid
+            )
+        );
+    }
+
+    #[actix_rt::test]
+    async fn batch_downloads_of_missing_objects_fail_per_object() {
+        let fixture = fixture();
+
+        let object = fixture
+            .batch_object(Operation::Download, &oid_of(CONTENTS), CONTENTS.len())
+            .await;
+        assert_eq!(object["error"]["code"], 404);
+        assert!(object.get("actions").is_none());
+    }
+
+    #[actix_rt::test]
+    async fn batch_objects_must_have_their_stored_size() {
+        let fixture = fixture();
+        let oid = oid_of(CONTENTS);
+        fixture.store(&oid, CONTENTS);
+
+        for operation in [Operation::Download, Operation::Upload] {
+            let object = fixture
+                .batch_object(operation, &oid, CONTENTS.len() + 1)
+                .await;
+            assert_eq!(object["error"]["code"], 422);
+            assert!(object.get("actions").is_none());
+        }
+    }
+
+    #[actix_rt::test]
+    async fn batch_uploads_of_stored_objects_have_nothing_to_do() {
+        let fixture = fixture();
+        let oid = oid_of(CONTENTS);
+        fixture.store(&oid, CONTENTS);
+
+        let object = fixture
+            .batch_object(Operation::Upload, &oid, CONTENTS.len())
+            .await;
+        assert!(object.get("error").is_none());
+        assert!(object.get("actions").is_none());
+    }
+
+    #[actix_rt::test]
+    async fn batches_only_use_sha256() {
+        let fixture = fixture();
+
+        let response = fixture
+            .batch(
+                Operation::Upload,
+                json!({
+                    "operation": "upload",
+                    "objects": [{ "oid": oid_of(CONTENTS), "size": CONTENTS.len() }],
+                    "ref": { "name": "refs/heads/main" },
+                    "hash_algo": "sha512",
+                }),
+            )
+            .await;
+        let message = assert_lfs_error(response, StatusCode::UNPROCESSABLE_ENTITY).await;
+        assert_eq!(message, "unsupported hash algorithm: sha512");
+    }
+
+    #[actix_rt::test]
+    async fn batch_uploads_are_verified() {
+        let fixture = fixture();
+        let oid = oid_of(CONTENTS);
+
+        let object = fixture
+            .batch_object(Operation::Upload, &oid, CONTENTS.len())
+            .await;
+        assert_eq!(
+            object["actions"]["upload"]["href"],
+            format!(
+                "http://localhost:8080/grp/repo.git/info/lfs/objects/{}",
+                oid
+            )
+        );
+        let verify = &object["actions"]["verify"];
+        assert_eq!(
+            verify["href"],
+            "http://localhost:8080/grp/repo.git/info/lfs/objects/verify"
+        );
+        assert!(verify["header"]["Authorization"]
+            .as_str()
+            .unwrap()
+            .starts_with("Token "));
+    }
+
+    #[actix_rt::test]
+    async fn verify_accepts_stored_objects() {
+        let fixture = fixture();
+        let oid = oid_of(CONTENTS);
+        fixture.store(&oid, CONTENTS);
+
+        let response = fixture.verify(&oid, CONTENTS.len()).await;
+        assert_eq!(response.status(), StatusCode::OK);
+    }
+
+    #[actix_rt::test]
+    async fn verify_fails_for_unknown_objects() {
+        let fixture = fixture();
+
+        let response = fixture.verify(&oid_of(CONTENTS), CONTENTS.len()).await;
+        let message = assert_lfs_error(response, StatusCode::NOT_FOUND).await;
+        assert_eq!(message, "object not found");
+    }
+
+    #[actix_rt::test]
+    async fn verify_checks_the_size() {
+        let fixture = fixture();
+        let oid = oid_of(CONTENTS);
+        fixture.store(&oid, CONTENTS);
+
+        let response = fixture.verify(&oid, CONTENTS.len() + 1).await;
+        let message = assert_lfs_error(response, StatusCode::UNPROCESSABLE_ENTITY).await;
+        assert_eq!(
+            message,
+            format!(
+                "expected {} bytes, got {} bytes",
+                CONTENTS.len() + 1,
+                CONTENTS.len()
+            )
+        );
+    }
+
+    #[actix_rt::test]
+    async fn verify_checks_the_hash_if_asked_to() {
+        let mut fixture = fixture();
+        let oid = oid_of(CONTENTS);
+        let corrupted = vec![b'x'; CONTENTS.len()];
+        fixture.store(&oid, &corrupted);
+
+        let response = fixture.verify(&oid, CONTENTS.len()).await;
+        let message = assert_lfs_error(response, StatusCode::UNPROCESSABLE_ENTITY).await;
+        assert!(message.contains(&oid), "{}", message);
+
+        fixture.context.appctx.verify_hash = false;
+        let response = fixture.verify(&oid, CONTENTS.len()).await;
+        assert_eq!(response.status(), StatusCode::OK);
+    }
+}
@@ -0,0 +1,639 @@
+use actix_web::{http::StatusCode, web, HttpRequest, HttpResponse};
+use chrono::{DateTime, Utc};
+use common::Access;
+use database::audit::{self, Event, Outcome};
+use database::lock::{LockFilter, LockWithOwner};
+use diesel::result::{DatabaseErrorKind, Error as DieselError};
+use serde::*;
+
+use crate::handlers::{lfs_authorize, lfs_error, lfs_repo_path, LFSReference};
+use crate::middleware::token_extractor::Token;
+use crate::AppContext;
+
+const DEFAULT_LOCK_PAGE_SIZE: i64 = 100;
+const MAX_LOCK_PAGE_SIZE: i64 = 1000;
+
+/// Locks belong to users, so deploy keys cannot take or release them.
+fn lock_owner(token: &Token) -> Result<String, HttpResponse> {
+    token.user().map(String::from).ok_or_else(|| {
+        lfs_error(
+            StatusCode::FORBIDDEN,
+            "deploy keys cannot lock or unlock files",
+        )
+    })
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct LFSLockOwner {
+    pub name: String,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct LFSLock {
+    pub id: String,
+    pub path: String,
+    pub locked_at: DateTime<Utc>,
+    pub owner: LFSLockOwner,
+}
+
+impl From<LockWithOwner> for LFSLock {
+    fn from((lock, owner): LockWithOwner) -> Self {
+        Self {
+            id: lock.id,
+            path: lock.path,
+            locked_at: DateTime::from_utc(lock.locked_at, Utc),
+            owner: LFSLockOwner { name: owner },
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct LFSCreateLockRequest {
+    pub path: String,
+    pub r#ref: Option<LFSReference>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct LFSLockResponse {
+    pub lock: LFSLock,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct LFSLockConflictResponse {
+    pub lock: LFSLock,
+    pub message: String,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct LFSListLocksQuery {
+    pub path: Option<String>,
+    pub id: Option<String>,
+    pub cursor: Option<String>,
+    pub limit: Option<i64>,
+    pub refspec: Option<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct LFSListLocksResponse {
+    pub locks: Vec<LFSLock>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub next_cursor: Option<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct LFSVerifyLocksRequest {
+    pub cursor: Option<String>,
+    pub limit: Option<i64>,
+    pub r#ref: Option<LFSReference>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct LFSVerifyLocksResponse {
+    pub ours: Vec<LFSLock>,
+    pub theirs: Vec<LFSLock>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub next_cursor: Option<String>,
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct LFSUnlockRequest {
+    #[serde(default)]
+    pub force: bool,
+    pub r#ref: Option<LFSReference>,
+}
+
+fn page_limit(limit: Option<i64>) -> i64 {
+    limit
+        .filter(|limit| *limit > 0)
+        .unwrap_or(DEFAULT_LOCK_PAGE_SIZE)
+        .min(MAX_LOCK_PAGE_SIZE)
+}
+
+/// Queries fetch one row more than the page size; that extra row, if any,
+/// becomes the cursor of the next page.
+fn split_next_cursor(
+    mut locks: Vec<LockWithOwner>,
+    limit: i64,
+) -> (Vec<LockWithOwner>, Option<String>) {
+    if locks.len() as i64 > limit {
+        let next = locks.split_off(limit as usize);
+        let next_cursor = next.into_iter().next().map(|(lock, _)| lock.id);
+        (locks, next_cursor)
+    } else {
+        (locks, None)
+    }
+}
+
+enum CreateLockOutcome {
+    Created(LockWithOwner),
+    Conflict(LockWithOwner),
+}
+
+#[actix_web::post("/{repo_path:.*\\.git}/info/lfs/locks")]
+pub async fn lfs_create_lock(
+    request: HttpRequest,
+    web::Path(repo_path): web::Path<String>,
+    body: web::Json<LFSCreateLockRequest>,
+    appctx: web::Data<AppContext>,
+    token: Token,
+) -> Result<HttpResponse, actix_web::error::Error> {
+    let repo_path = match lfs_repo_path(&appctx, &repo_path) {
+        Ok(repo_path) => repo_path,
+        Err(response) => return Ok(response),
+    };
+    if let Err(response) =
+        lfs_authorize(&appctx, Some(&token), &repo_path, Access::Write).await?
+    {
+        return Ok(response);
+    }
+    let repo_path = repo_path.to_string();
+    let audited_repo = repo_path.clone();
+    let body = body.into_inner();
+    let pool = appctx.pool.clone();
+    let owner = match lock_owner(&token) {
+        Ok(owner) => owner,
+        Err(response) => return Ok(response),
+    };
+    let outcome = web::block(move || -> Result<CreateLockOutcome, String> {
+        let conn = pool
+            .get()
+            .map_err(|err| format!("failed to get connection: {}", err))?;
+
+        let existing =
+            database::lock::query_lock_by_path(&conn, repo_path.clone(), body.path.clone())
+                .map_err(|err| format!("failed to query lock: {}", err))?;
+        if let Some(lock) = existing {
+            return Ok(CreateLockOutcome::Conflict(lock));
+        }
+
+        match database::lock::create_lock(
+            &conn,
+            repo_path.clone(),
+            body.path.clone(),
+            body.r#ref.map(|r| r.name),
+            owner,
+        ) {
+            Ok(id) => database::lock::query_lock_by_id(&conn, repo_path, id)
+                .map_err(|err| format!("failed to query lock: {}", err))?
+                .map(CreateLockOutcome::Created)
+                .ok_or_else(|| String::from("lock vanished after creation")),
+            // someone else took the lock between our lookup and insert
+            Err(DieselError::DatabaseError(DatabaseErrorKind::UniqueViolation, _)) => {
+                database::lock::query_lock_by_path(&conn, repo_path, body.path)
+                    .map_err(|err| format!("failed to query lock: {}", err))?
+                    .map(CreateLockOutcome::Conflict)
+                    .ok_or_else(|| String::from("conflicting lock vanished"))
+            }
+            Err(err) => Err(format!("failed to create lock: {}", err)),
+        }
+    })
+    .await?;
+
+    let event = match &outcome {
+        CreateLockOutcome::Created((lock, _)) => {
+            Event::new(audit::LOCK_CREATE, token.actor(), Outcome::Success)
+                .detail(lock.path.clone())
+        }
+        CreateLockOutcome::Conflict((lock, owner)) => {
+            Event::new(audit::LOCK_CREATE, token.actor(), Outcome::Failure)
+                .detail(format!("{} is locked by {}", lock.path, owner))
+        }
+    };
+    appctx
+        .audit(&request, event.repository(&audited_repo))
+        .await;
+
+    Ok(match outcome {
+        CreateLockOutcome::Created(lock) => {
+            HttpResponse::Created().json(LFSLockResponse { lock: lock.into() })
+        }
+        CreateLockOutcome::Conflict(lock) => {
+            HttpResponse::Conflict().json(LFSLockConflictResponse {
+                lock: lock.into(),
+                message: String::from("already created lock"),
+            })
+        }
+    })
+}
+
+#[actix_web::get("/{repo_path:.*\\.git}/info/lfs/locks")]
+pub async fn lfs_list_locks(
+    web::Path(repo_path): web::Path<String>,
+    query: web::Query<LFSListLocksQuery>,
+    appctx: web::Data<AppContext>,
+    token: Token,
+) -> Result<HttpResponse, actix_web::error::Error> {
+    let repo_path = match lfs_repo_path(&appctx, &repo_path) {
+        Ok(repo_path) => repo_path,
+        Err(response) => return Ok(response),
+    };
+    if let Err(response) =
+        lfs_authorize(&appctx, Some(&token), &repo_path, Access::Read).await?
+    {
+        return Ok(response);
+    }
+    let repo_path = repo_path.to_string();
+    let query = query.into_inner();
+    let limit = page_limit(query.limit);
+    let pool = appctx.pool.clone();
+    let locks = web::block(move || -> Result<Vec<LockWithOwner>, String> {
+        let conn = pool
+            .get()
+            .map_err(|err| format!("failed to get connection: {}", err))?;
+        database::lock::query_locks(
+            &conn,
+            repo_path,
+            LockFilter {
+                id: query.id,
+                path: query.path,
+                cursor: query.cursor,
+                limit: Some(limit + 1),
+                ..Default::default()
+            },
+        )
+        .map_err(|err| format!("failed to query locks: {}", err))
+    })
+    .await?;
+
+    let (locks, next_cursor) = split_next_cursor(locks, limit);
+    Ok(HttpResponse::Ok().json(LFSListLocksResponse {
+        locks: locks.into_iter().map(LFSLock::from).collect(),
+        next_cursor,
+    }))
+}
+
+#[actix_web::post("/{repo_path:.*\\.git}/info/lfs/locks/verify")]
+pub async fn lfs_lock_verify(
+    web::Path(repo_path): web::Path<String>,
+    body: web::Json<LFSVerifyLocksRequest>,
+    appctx: web::Data<AppContext>,
+    token: Token,
+) -> Result<HttpResponse, actix_web::error::Error> {
+    let repo_path = match lfs_repo_path(&appctx, &repo_path) {
+        Ok(repo_path) => repo_path,
+        Err(response) => return Ok(response),
+    };
+    if let Err(response) =
+        lfs_authorize(&appctx, Some(&token), &repo_path, Access::Write).await?
+    {
+        return Ok(response);
+    }
+    let repo_path = repo_path.to_string();
+    let body = body.into_inner();
+    let limit = page_limit(body.limit);
+    let pool = appctx.pool.clone();
+    let locks = web::block(move || -> Result<Vec<LockWithOwner>, String> {
+        let conn = pool
+            .get()
+            .map_err(|err| format!("failed to get connection: {}", err))?;
+        database::lock::query_locks(
+            &conn,
+            repo_path,
+            LockFilter {
+                cursor: body.cursor,
+                limit: Some(limit + 1),
+                ..Default::default()
+            },
+        )
+        .map_err(|err| format!("failed to query locks: {}", err))
+    })
+    .await?;
+
+    let (locks, next_cursor) = split_next_cursor(locks, limit);
+    let (ours, theirs): (Vec<_>, Vec<_>) = locks
+        .into_iter()
+        .partition(|(lock, _)| Some(lock.owner.as_str()) == token.user());
+    Ok(HttpResponse::Ok().json(LFSVerifyLocksResponse {
+        ours: ours.into_iter().map(LFSLock::from).collect(),
+        theirs: theirs.into_iter().map(LFSLock::from).collect(),
+        next_cursor,
+    }))
+}
+
+enum UnlockOutcome {
+    Unlocked(LockWithOwner),
+    NotFound,
+    NotOwner,
+    NotAdmin,
+}
+
+#[actix_web::post("/{repo_path:.*\\.git}/info/lfs/locks/{id}/unlock")]
+pub async fn lfs_unlock(
+    request: HttpRequest,
+    web::Path((repo_path, id)): web::Path<(String, String)>,
+    body: Option<web::Json<LFSUnlockRequest>>,
+    appctx: web::Data<AppContext>,
+    token: Token,
+) -> Result<HttpResponse, actix_web::error::Error> {
+    let repo_path = match lfs_repo_path(&appctx, &repo_path) {
+        Ok(repo_path) => repo_path,
+        Err(response) => return Ok(response),
+    };
+    let access =
+        match lfs_authorize(&appctx, Some(&token), &repo_path, Access::Write).await? {
+            Ok(access) => access,
+            Err(response) => return Ok(response),
+        };
+    let repo_path = repo_path.to_string();
+    let audited_repo = repo_path.clone();
+    let lock_id = id.clone();
+    let force = body.map(|body| body.force).unwrap_or_default();
+    let pool = appctx.pool.clone();
+    let user = match lock_owner(&token) {
+        Ok(user) => user,
+        Err(response) => return Ok(response),
+    };
+    let outcome = web::block(move || -> Result<UnlockOutcome, String> {
+        let conn = pool
+            .get()
+            .map_err(|err| format!("failed to get connection: {}", err))?;
+        let lock = match database::lock::query_lock_by_id(&conn, repo_path, id)
+            .map_err(|err| format!("failed to query lock: {}", err))?
+        {
+            Some(lock) => lock,
+            None => return Ok(UnlockOutcome::NotFound),
+        };
+        if lock.0.owner != user {
+            if !force {
+                return Ok(UnlockOutcome::NotOwner);
+            }
+            if access < Access::Admin {
+                return Ok(UnlockOutcome::NotAdmin);
+            }
+        }
+        database::lock::delete_lock(&conn, lock.0.id.clone())
+            .map_err(|err| format!("failed to delete lock: {}", err))?;
+        Ok(UnlockOutcome::Unlocked(lock))
+    })
+    .await?;
+
+    let event = match &outcome {
+        UnlockOutcome::Unlocked((lock, owner)) if Some(lock.owner.as_str()) != token.user() => {
+            Event::new(audit::LOCK_DELETE, token.actor(), Outcome::Success)
+                .detail(format!("{}, forced, locked by {}", lock.path, owner))
+        }
+        UnlockOutcome::Unlocked((lock, _)) => {
+            Event::new(audit::LOCK_DELETE, token.actor(), Outcome::Success)
+                .detail(lock.path.clone())
+        }
+        UnlockOutcome::NotFound => {
+            Event::new(audit::LOCK_DELETE, token.actor(), Outcome::Failure)
+                .detail(format!("no lock {}", lock_id))
+        }
+        UnlockOutcome::NotOwner | UnlockOutcome::NotAdmin => {
+            Event::new(audit::LOCK_DELETE, token.actor(), Outcome::Denied)
+                .detail(format!("lock {} of another user", lock_id))
+        }
+    };
+    appctx
+        .audit(&request, event.repository(&audited_repo))
+        .await;
+
+    Ok(match outcome {
+        UnlockOutcome::Unlocked(lock) => {
+            HttpResponse::Ok().json(LFSLockResponse { lock: lock.into() })
+        }
+        UnlockOutcome::NotFound => lfs_error(StatusCode::NOT_FOUND, "no such lock"),
+        UnlockOutcome::NotOwner => lfs_error(
+            StatusCode::FORBIDDEN,
+            "lock is owned by another user, use force to unlock",
+        ),
+        UnlockOutcome::NotAdmin => lfs_error(
+            StatusCode::FORBIDDEN,
+            "breaking the locks of other users requires admin access",
+        ),
+    })
+}
+
+#[cfg(all(test, feature = "sqlite"))]
+mod tests {
+    use actix_web::{dev::ServiceResponse, test, App};
+    use common::{Operation, RepoPath};
+    use serde_json::{json, Value};
+
+    use super::*;
+    use crate::testing::TestContext;
+
+    struct Fixture {
+        context: TestContext,
+        repo: RepoPath,
+        alice: String,
+        bob: String,
+    }
+
+    /// alice and bob may both write to `grp/repo.git`.
+    fn fixture() -> Fixture {
+        let context = TestContext::new();
+        let repo = context.repository("grp/repo.git");
+        let alice = context.user("alice");
+        let bob = context.user("bob");
+        context.grant(&repo, &alice, Access::Write);
+        context.grant(&repo, &bob, Access::Write);
+        Fixture {
+            context,
+            repo,
+            alice,
+            bob,
+        }
+    }
+
+    async fn call(
+        context: &TestContext,
+        request: test::TestRequest,
+        authorization: &str,
+    ) -> ServiceResponse {
+        let mut app = test::init_service(
+            App::new()
+                .data(context.appctx.clone())
+                .service(lfs_lock_verify)
+                .service(lfs_unlock)
+                .service(lfs_create_lock)
+                .service(lfs_list_locks),
+        )
+        .await;
+        test::call_service(
+            &mut app,
+            request.header("Authorization", authorization).to_request(),
+        )
+        .await
+    }
+
+    impl Fixture {
+        fn upload_token(&self, user: &str) -> String {
+            self.context
+                .authorization(user, &self.repo, Operation::Upload)
+        }
+
+        async fn lock(&self, user: &str, path: &str) -> ServiceResponse {
+            call(
+                &self.context,
+                test::TestRequest::post()
+                    .uri("/grp/repo.git/info/lfs/locks")
+                    .set_json(&json!({ "path": path })),
+                &self.upload_token(user),
+            )
+            .await
+        }
+
+        async fn lock_id(&self, user: &str, path: &str) -> String {
+            let response = self.lock(user, path).await;
+            assert_eq!(response.status(), StatusCode::CREATED);
+            let body: Value = test::read_body_json(response).await;
+            String::from(body["lock"]["id"].as_str().unwrap())
+        }
+
+        async fn unlock(&self, id: &str, force: bool, authorization: &str) -> ServiceResponse {
+            call(
+                &self.context,
+                test::TestRequest::post()
+                    .uri(&format!("/grp/repo.git/info/lfs/locks/{}/unlock", id))
+                    .set_json(&json!({ "force": force })),
+                authorization,
+            )
+            .await
+        }
+    }
+
+    fn paths(locks: &Value) -> Vec<&str> {
+        locks
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|lock| lock["path"].as_str().unwrap())
+            .collect()
+    }
+
+    #[actix_rt::test]
+    async fn creates_and_lists_locks() {
+        let fixture = fixture();
+        fixture.lock_id(&fixture.alice, "a.psd").await;
+        fixture.lock_id(&fixture.bob, "b.psd").await;
+
+        let response = call(
+            &fixture.context,
+            test::TestRequest::get().uri("/grp/repo.git/info/lfs/locks?path=a.psd"),
+            &fixture
+                .context
+                .authorization(&fixture.bob, &fixture.repo, Operation::Download),
+        )
+        .await;
+        assert_eq!(response.status(), StatusCode::OK);
+        let body: Value = test::read_body_json(response).await;
+        assert_eq!(paths(&body["locks"]), ["a.psd"]);
+        assert_eq!(body["locks"][0]["owner"]["name"], "alice");
+    }
+
+    #[actix_rt::test]
+    async fn conflicting_locks_are_refused() {
+        let fixture = fixture();
+        fixture.lock_id(&fixture.alice, "a.psd").await;
+
+        let response = fixture.lock(&fixture.bob, "a.psd").await;
+        assert_eq!(response.status(), StatusCode::CONFLICT);
+        let body: Value = test::read_body_json(response).await;
+        assert_eq!(body["lock"]["owner"]["name"], "alice");
+    }
+
+    #[actix_rt::test]
+    async fn verify_splits_ours_and_theirs() {
+        let fixture = fixture();
+        fixture.lock_id(&fixture.alice, "a.psd").await;
+        fixture.lock_id(&fixture.bob, "b.psd").await;
+
+        let response = call(
+            &fixture.context,
+            test::TestRequest::post()
+                .uri("/grp/repo.git/info/lfs/locks/verify")
+                .set_json(&json!({})),
+            &fixture.upload_token(&fixture.alice),
+        )
+        .await;
+        assert_eq!(response.status(), StatusCode::OK);
+        let body: Value = test::read_body_json(response).await;
+        assert_eq!(paths(&body["ours"]), ["a.psd"]);
+        assert_eq!(paths(&body["theirs"]), ["b.psd"]);
+    }
+
+    #[actix_rt::test]
+    async fn owners_unlock_their_locks() {
+        let fixture = fixture();
+        let id = fixture.lock_id(&fixture.alice, "a.psd").await;
+
+        let token = fixture.upload_token(&fixture.alice);
+        assert_eq!(
+            fixture.unlock(&id, false, &token).await.status(),
+            StatusCode::OK
+        );
+        assert_eq!(
+            fixture.unlock(&id, false, &token).await.status(),
+            StatusCode::NOT_FOUND
+        );
+    }
+
+    #[actix_rt::test]
+    async fn unlocking_the_locks_of_others_needs_force_and_admin_access() {
+        let fixture = fixture();
+        let id = fixture.lock_id(&fixture.alice, "a.psd").await;
+
+        let bob = fixture.upload_token(&fixture.bob);
+        assert_eq!(
+            fixture.unlock(&id, false, &bob).await.status(),
+            StatusCode::FORBIDDEN
+        );
+        assert_eq!(
+            fixture.unlock(&id, true, &bob).await.status(),
+            StatusCode::FORBIDDEN
+        );
+
+        fixture
+            .context
+            .grant(&fixture.repo, &fixture.bob, Access::Admin);
+        let bob = fixture
+            .context
+            .personal_authorization(&fixture.bob, Access::Admin);
+        assert_eq!(
+            fixture.unlock(&id, false, &bob).await.status(),
+            StatusCode::FORBIDDEN
+        );
+        assert_eq!(
+            fixture.unlock(&id, true, &bob).await.status(),
+            StatusCode::OK
+        );
+    }
+
+    #[actix_rt::test]
+    async fn admins_force_unlock_with_an_upload_token() {
+        let fixture = fixture();
+        let id = fixture.lock_id(&fixture.alice, "a.psd").await;
+        fixture
+            .context
+            .grant(&fixture.repo, &fixture.bob, Access::Admin);
+
+        let bob = fixture.upload_token(&fixture.bob);
+        assert_eq!(
+            fixture.unlock(&id, false, &bob).await.status(),
+            StatusCode::FORBIDDEN
+        );
+        assert_eq!(
+            fixture.unlock(&id, true, &bob).await.status(),
+            StatusCode::OK
+        );
+    }
+
+    #[actix_rt::test]
+    async fn personal_tokens_cap_force_unlocking() {
+        let fixture = fixture();
+        let id = fixture.lock_id(&fixture.alice, "a.psd").await;
+        fixture
+            .context
+            .grant(&fixture.repo, &fixture.bob, Access::Admin);
+
+        let bob = fixture
+            .context
+            .personal_authorization(&fixture.bob, Access::Write);
+        assert_eq!(
+            fixture.unlock(&id, true, &bob).await.status(),
+            StatusCode::FORBIDDEN
+        );
+    }
+}
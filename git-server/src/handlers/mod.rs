@@ -1,5 +1,19 @@
+pub use admin::*;
+pub use auth::*;
+pub use health::*;
 pub use lfs::*;
+pub use locks::*;
+pub use oidc::*;
+pub use tokens::*;
+pub use totp::*;
 pub use views::*;
 
+mod admin;
+mod auth;
+mod health;
 mod lfs;
+mod locks;
+mod oidc;
+mod tokens;
+mod totp;
 mod views;
@@ -0,0 +1,264 @@
+use actix_web::{web, HttpMessage, HttpRequest, HttpResponse};
+use common::secret;
+use database::audit::{self, Actor, Event, Outcome};
+use database::group::GroupChanges;
+use log::*;
+use serde::*;
+
+use super::auth::{local_next, login_form, redirect, signed_in};
+use crate::session::{cookie, create_session, NewSessionId, Session};
+use crate::AppContext;
+
+/// Holds the state, nonce and PKCE verifier of a sign in at the identity
+/// provider, until it redirects back.
+pub const OIDC_FLOW_COOKIE: &str = "rustile_oidc";
+
+const FLOW_SECRET_LENGTH: usize = 43;
+
+#[derive(Debug, Serialize, Deserialize)]
+struct Flow {
+    state: String,
+    nonce: String,
+    code_verifier: String,
+    next: String,
+}
+
+impl Flow {
+    fn encode(&self) -> String {
+        base64::encode_config(serde_json::to_vec(self).unwrap(), base64::URL_SAFE_NO_PAD)
+    }
+
+    fn decode(value: &str) -> Option<Self> {
+        let json = base64::decode_config(value, base64::URL_SAFE_NO_PAD).ok()?;
+        serde_json::from_slice(&json).ok()
+    }
+
+    /// The flow in the `cookie`, if the provider sent its state back.
+    fn resume(cookie: Option<&str>, state: Option<&str>) -> Option<Self> {
+        let flow = Self::decode(cookie?)?;
+        match secret::constant_time_eq(state?, &flow.state) {
+            true => Some(flow),
+            false => None,
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct OidcLoginQuery {
+    pub next: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct OidcCallbackQuery {
+    pub code: Option<String>,
+    pub state: Option<String>,
+    pub error: Option<String>,
+    pub error_description: Option<String>,
+}
+
+#[actix_web::get("/login/oidc")]
+pub async fn oidc_login(
+    query: web::Query<OidcLoginQuery>,
+    appctx: web::Data<AppContext>,
+    session: Session,
+) -> Result<HttpResponse, actix_web::Error> {
+    let provider = match appctx.oidc.as_ref() {
+        Some(provider) => provider,
+        None => return Ok(HttpResponse::NotFound().finish()),
+    };
+    let next = local_next(query.into_inner().next);
+    if session.0.is_some() {
+        return Ok(redirect(&next));
+    }
+
+    let flow = Flow {
+        state: secret::random(FLOW_SECRET_LENGTH),
+        nonce: secret::random(FLOW_SECRET_LENGTH),
+        code_verifier: secret::random(FLOW_SECRET_LENGTH),
+        next,
+    };
+    let mut response =
+        redirect(&provider.authorization_url(&flow.state, &flow.nonce, &flow.code_verifier));
+    response.add_cookie(&cookie(
+        &appctx,
+        OIDC_FLOW_COOKIE,
+        flow.encode(),
+        time::Duration::minutes(10),
+    ))?;
+    Ok(response)
+}
+
+#[actix_web::get("/login/oidc/callback")]
+pub async fn oidc_callback(
+    request: HttpRequest,
+    query: web::Query<OidcCallbackQuery>,
+    appctx: web::Data<AppContext>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let provider = match appctx.oidc.clone() {
+        Some(provider) => provider,
+        None => return Ok(HttpResponse::NotFound().finish()),
+    };
+    let query = query.into_inner();
+    let failed = |next: String, message: &str| {
+        login_form(&appctx, next, String::new(), Some(String::from(message)))
+    };
+
+    let flow_cookie = request.cookie(OIDC_FLOW_COOKIE);
+    let flow = match Flow::resume(
+        flow_cookie.as_ref().map(|cookie| cookie.value()),
+        query.state.as_deref(),
+    ) {
+        Some(flow) => flow,
+        None => return failed(String::from("/"), "The sign in expired, please try again."),
+    };
+    let code = match (query.code, query.error) {
+        (_, Some(error)) => {
+            info!(
+                "identity provider refused the sign in: {} {}",
+                error,
+                query.error_description.unwrap_or_default()
+            );
+            return failed(flow.next, "The identity provider refused the sign in.");
+        }
+        (Some(code), None) => code,
+        (None, None) => return failed(flow.next, "The identity provider sent no code."),
+    };
+
+    let identity = match provider
+        .sign_in(&code, &flow.code_verifier, &flow.nonce)
+        .await
+    {
+        Ok(identity) => identity,
+        Err(err) => {
+            error!("{}", err);
+            appctx
+                .audit(
+                    &request,
+                    Event::new(audit::LOGIN, Actor::Anonymous, Outcome::Failure)
+                        .detail(format!("single sign-on: {}", err)),
+                )
+                .await;
+            return failed(flow.next, "Signing in with the identity provider failed.");
+        }
+    };
+
+    let pool = appctx.pool.clone();
+    let managed_groups = provider.managed_groups();
+    let (user, signed_in_as) = web::block(
+        move || -> Result<(String, Option<(GroupChanges, NewSessionId)>), String> {
+            let conn = pool
+                .get()
+                .map_err(|err| format!("failed to get connection: {}", err))?;
+            let user = database::identity::resolve_identity(
+                &conn,
+                identity.issuer,
+                identity.subject,
+                identity.username,
+            )
+            .map_err(|err| format!("failed to resolve identity: {}", err))?;
+            if !database::user::query_is_active(&conn, user.clone())
+                .map_err(|err| format!("failed to query user: {}", err))?
+            {
+                return Ok((user, None));
+            }
+            let changes = database::group::sync_group_members(
+                &conn,
+                &user,
+                &managed_groups,
+                &identity.groups,
+            )
+            .map_err(|err| format!("failed to sync groups: {}", err))?;
+            let session = create_session(&conn, user.clone())?;
+            Ok((user, Some((changes, session))))
+        },
+    )
+    .await?;
+    let (changes, session) = match signed_in_as {
+        Some(signed_in_as) => signed_in_as,
+        None => {
+            appctx
+                .audit(
+                    &request,
+                    Event::new(audit::LOGIN, Actor::User(user), Outcome::Denied)
+                        .detail("single sign-on, user disabled or deleted"),
+                )
+                .await;
+            return failed(flow.next, "This account is disabled.");
+        }
+    };
+
+    if !changes.is_empty() {
+        let detail = format!(
+            "joined [{}], left [{}]",
+            changes.joined.join(", "),
+            changes.left.join(", ")
+        );
+        appctx
+            .audit(
+                &request,
+                Event::new(
+                    audit::GROUP_SYNC,
+                    Actor::User(user.clone()),
+                    Outcome::Success,
+                )
+                .detail(detail),
+            )
+            .await;
+    }
+    let detail = match session.second_factor_pending {
+        true => "single sign-on, second factor pending",
+        false => "single sign-on",
+    };
+    appctx
+        .audit(
+            &request,
+            Event::new(audit::LOGIN, Actor::User(user), Outcome::Success).detail(detail),
+        )
+        .await;
+
+    let mut response = signed_in(&appctx, session, &flow.next)?;
+    response.add_cookie(&cookie(
+        &appctx,
+        OIDC_FLOW_COOKIE,
+        String::new(),
+        time::Duration::zero(),
+    ))?;
+    Ok(response)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Flow;
+
+    fn flow() -> Flow {
+        Flow {
+            state: String::from("the-state"),
+            nonce: String::from("the-nonce"),
+            code_verifier: String::from("the-code-verifier"),
+            next: String::from("/group/project.git"),
+        }
+    }
+
+    #[test]
+    fn resumes_the_flow_with_its_state() {
+        let cookie = flow().encode();
+        let resumed = Flow::resume(Some(&cookie), Some("the-state")).unwrap();
+        assert_eq!(resumed.nonce, "the-nonce");
+        assert_eq!(resumed.code_verifier, "the-code-verifier");
+        assert_eq!(resumed.next, "/group/project.git");
+    }
+
+    #[test]
+    fn refuses_a_state_mismatch() {
+        let cookie = flow().encode();
+        assert!(Flow::resume(Some(&cookie), Some("another-state")).is_none());
+        assert!(Flow::resume(Some(&cookie), Some("")).is_none());
+        assert!(Flow::resume(Some(&cookie), None).is_none());
+    }
+
+    #[test]
+    fn refuses_missing_or_garbled_cookies() {
+        assert!(Flow::resume(None, Some("the-state")).is_none());
+        assert!(Flow::resume(Some("not base64 json"), Some("the-state")).is_none());
+    }
+}
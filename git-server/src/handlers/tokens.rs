@@ -0,0 +1,161 @@
+use actix_web::{web, HttpRequest, HttpResponse};
+use askama_actix::TemplateIntoResponse;
+use database::audit::{self, Actor, Event, Outcome};
+use database::models::{AccessToken, IssuedToken};
+use serde::*;
+
+use super::auth::redirect;
+use crate::session::Session;
+use crate::templates::*;
+use crate::AppContext;
+
+#[derive(Debug, Clone, Copy, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum RevokeKind {
+    /// A personal access token, by name.
+    Access,
+    /// A git-lfs-authenticate token, by jti.
+    Lfs,
+    /// A web session, by the hash of its id.
+    Session,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct RevokeForm {
+    pub csrf_token: String,
+    pub kind: RevokeKind,
+    pub id: String,
+}
+
+struct Tokens {
+    access_tokens: Vec<AccessToken>,
+    issued_tokens: Vec<IssuedToken>,
+    sessions: Vec<database::models::Session>,
+}
+
+fn tokens(conn: &database::connection::DbConnection, user: String) -> Result<Tokens, String> {
+    let now = chrono::Utc::now().naive_utc();
+    let access_tokens = database::access_token::query_access_tokens_by_user(conn, user.clone())
+        .map_err(|err| format!("failed to query access tokens: {}", err))?
+        .into_iter()
+        .filter(|token| token.expires_at > now)
+        .collect();
+    let issued_tokens = database::issued_token::query_active_tokens_by_user(conn, user.clone())
+        .map_err(|err| format!("failed to query issued tokens: {}", err))?;
+    let sessions = database::session::query_sessions_by_user(conn, user)
+        .map_err(|err| format!("failed to query sessions: {}", err))?;
+    Ok(Tokens {
+        access_tokens,
+        issued_tokens,
+        sessions,
+    })
+}
+
+fn tokens_page(
+    session: &Session,
+    tokens: Tokens,
+    message: Option<String>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let user = session.0.as_ref().unwrap();
+    TokensPage {
+        _parent: BaseTemplate::new()
+            .with_title(String::from("Tokens"))
+            .with_session(session),
+        csrf_token: user.csrf_token.clone(),
+        access_tokens: tokens.access_tokens,
+        issued_tokens: tokens.issued_tokens,
+        sessions: tokens
+            .sessions
+            .into_iter()
+            .map(|other| SessionEntry {
+                current: other.id_hash == user.id_hash,
+                id_hash: other.id_hash,
+                created_at: other.created_at,
+                expires_at: other.expires_at,
+            })
+            .collect(),
+        message,
+    }
+    .into_response()
+}
+
+/// Lists the personal access tokens, git-lfs-authenticate tokens and web
+/// sessions of the signed in user, each with a button revoking it.
+#[actix_web::get("/settings/tokens")]
+pub async fn token_settings(
+    appctx: web::Data<AppContext>,
+    session: Session,
+) -> Result<HttpResponse, actix_web::Error> {
+    let user = match session.user() {
+        Some(user) => user,
+        None => return Ok(redirect("/login?next=/settings/tokens")),
+    };
+
+    let pool = appctx.pool.clone();
+    let tokens = web::block(move || -> Result<Tokens, String> {
+        let conn = pool
+            .get()
+            .map_err(|err| format!("failed to get connection: {}", err))?;
+        tokens(&conn, user)
+    })
+    .await?;
+    tokens_page(&session, tokens, None)
+}
+
+#[actix_web::post("/settings/tokens/revoke")]
+pub async fn revoke_token(
+    request: HttpRequest,
+    form: web::Form<RevokeForm>,
+    appctx: web::Data<AppContext>,
+    session: Session,
+) -> Result<HttpResponse, actix_web::Error> {
+    let user = match session.0.as_ref() {
+        Some(user) if session.check_csrf(&form.csrf_token) => user.clone(),
+        Some(_) => return Ok(HttpResponse::Forbidden().body("invalid csrf token")),
+        None => return Ok(redirect("/login?next=/settings/tokens")),
+    };
+
+    let form = form.into_inner();
+    let kind = form.kind;
+    let actor = Actor::User(user.uuid.clone());
+    let (action, detail) = match kind {
+        RevokeKind::Access => (audit::TOKEN_REVOKE, format!("access token {}", form.id)),
+        RevokeKind::Lfs => (audit::TOKEN_REVOKE, format!("git-lfs token {}", form.id)),
+        RevokeKind::Session => (audit::SESSION_REVOKE, String::from("web session")),
+    };
+    let pool = appctx.pool.clone();
+    let (revoked, tokens) = web::block(move || -> Result<(usize, Tokens), String> {
+        let conn = pool
+            .get()
+            .map_err(|err| format!("failed to get connection: {}", err))?;
+        let uuid = user.uuid.clone();
+        let revoked = match kind {
+            RevokeKind::Access => database::access_token::delete_access_token(&conn, uuid, form.id),
+            RevokeKind::Lfs => database::issued_token::revoke_issued_token(&conn, uuid, form.id),
+            // signing out of the current session is what the sign out button is for
+            RevokeKind::Session if form.id == user.id_hash => Ok(0),
+            RevokeKind::Session => database::session::delete_session_of_user(&conn, uuid, form.id),
+        }
+        .map_err(|err| format!("failed to revoke: {}", err))?;
+        Ok((revoked, tokens(&conn, user.uuid)?))
+    })
+    .await?;
+
+    if revoked > 0 {
+        if let RevokeKind::Lfs = kind {
+            appctx.revocations.invalidate();
+        }
+        appctx
+            .audit(
+                &request,
+                Event::new(action, actor, Outcome::Success).detail(detail),
+            )
+            .await;
+    }
+    let message = match (kind, revoked) {
+        (_, 0) => "Nothing was revoked, it may have expired already.",
+        (RevokeKind::Session, _) => "The session was signed out.",
+        _ => "The token was revoked.",
+    };
+    tokens_page(&session, tokens, Some(String::from(message)))
+}
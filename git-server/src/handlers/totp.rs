@@ -0,0 +1,396 @@
+use actix_web::{web, HttpRequest, HttpResponse};
+use askama_actix::TemplateIntoResponse;
+use common::secret;
+use database::audit::{self, Actor, Event, Outcome};
+use database::models::UserTotp;
+use serde::*;
+
+use super::auth::{local_next, login_form, redirect, LoginQuery};
+use crate::session::{
+    cookie, PendingSession, Session, SessionUser, MAX_SECOND_FACTOR_FAILURES, SESSION_COOKIE,
+    SESSION_LIFETIME_DAYS,
+};
+use crate::templates::*;
+use crate::AppContext;
+
+#[derive(Debug, Deserialize)]
+pub struct TotpLoginForm {
+    pub csrf_token: String,
+    pub code: String,
+    pub next: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct TotpEnrollForm {
+    pub csrf_token: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct TotpCodeForm {
+    pub csrf_token: String,
+    pub code: String,
+}
+
+enum SecondFactor {
+    Verified,
+    Wrong,
+    /// Too many wrong codes, the pending session is gone.
+    TooManyFailures,
+}
+
+#[actix_web::get("/login/totp")]
+pub async fn login_totp_page(
+    query: web::Query<LoginQuery>,
+    pending: PendingSession,
+) -> Result<HttpResponse, actix_web::Error> {
+    let next = local_next(query.into_inner().next);
+    match pending.0 {
+        Some(user) => TotpLoginPage {
+            _parent: BaseTemplate::new(),
+            csrf_token: user.csrf_token,
+            next,
+            error: None,
+        }
+        .into_response(),
+        None => Ok(redirect("/login")),
+    }
+}
+
+#[actix_web::post("/login/totp")]
+pub async fn login_totp(
+    request: HttpRequest,
+    form: web::Form<TotpLoginForm>,
+    appctx: web::Data<AppContext>,
+    pending: PendingSession,
+) -> Result<HttpResponse, actix_web::Error> {
+    let form = form.into_inner();
+    let next = local_next(form.next);
+    let user = match pending.0 {
+        Some(user) if secret::constant_time_eq(&user.csrf_token, &form.csrf_token) => user,
+        Some(_) => return Ok(HttpResponse::Forbidden().body("invalid csrf token")),
+        None => return Ok(redirect("/login")),
+    };
+
+    let pool = appctx.pool.clone();
+    let (uuid, id_hash) = (user.uuid.clone(), user.id_hash.clone());
+    let outcome = web::block(move || -> Result<SecondFactor, String> {
+        let conn = pool
+            .get()
+            .map_err(|err| format!("failed to get connection: {}", err))?;
+        if database::totp::check_second_factor(&conn, uuid, &form.code)
+            .map_err(|err| format!("failed to check second factor: {}", err))?
+        {
+            let expires_at = chrono::Utc::now() + chrono::Duration::days(SESSION_LIFETIME_DAYS);
+            database::session::complete_second_factor(&conn, id_hash, expires_at.naive_utc())
+                .map_err(|err| format!("failed to update session: {}", err))?;
+            return Ok(SecondFactor::Verified);
+        }
+
+        let failures = database::session::record_second_factor_failure(&conn, id_hash.clone())
+            .map_err(|err| format!("failed to update session: {}", err))?;
+        if failures < MAX_SECOND_FACTOR_FAILURES {
+            return Ok(SecondFactor::Wrong);
+        }
+        database::session::delete_session(&conn, id_hash)
+            .map_err(|err| format!("failed to delete session: {}", err))?;
+        Ok(SecondFactor::TooManyFailures)
+    })
+    .await?;
+
+    let (outcome_of, detail) = match outcome {
+        SecondFactor::Verified => (Outcome::Success, None),
+        SecondFactor::Wrong => (Outcome::Failure, None),
+        SecondFactor::TooManyFailures => (Outcome::Failure, Some("too many failures")),
+    };
+    let mut event = Event::new(
+        audit::SECOND_FACTOR,
+        Actor::User(user.uuid.clone()),
+        outcome_of,
+    );
+    if let Some(detail) = detail {
+        event = event.detail(detail);
+    }
+    appctx.audit(&request, event).await;
+
+    match outcome {
+        SecondFactor::Verified => Ok(redirect(&next)),
+        SecondFactor::Wrong => TotpLoginPage {
+            _parent: BaseTemplate::new(),
+            csrf_token: user.csrf_token,
+            next,
+            error: Some(String::from("That code is not valid.")),
+        }
+        .into_response(),
+        SecondFactor::TooManyFailures => {
+            let mut response = login_form(
+                &appctx,
+                next,
+                user.username,
+                Some(String::from(
+                    "Too many invalid codes, please sign in again.",
+                )),
+            )?;
+            response.add_cookie(&cookie(
+                &appctx,
+                SESSION_COOKIE,
+                String::new(),
+                time::Duration::zero(),
+            ))?;
+            Ok(response)
+        }
+    }
+}
+
+/// The signed in user, if the form carries their CSRF token.
+fn signed_in_user(session: &Session, csrf_token: &str) -> Result<SessionUser, HttpResponse> {
+    match session.0.as_ref() {
+        Some(user) if session.check_csrf(csrf_token) => Ok(user.clone()),
+        Some(_) => Err(HttpResponse::Forbidden().body("invalid csrf token")),
+        None => Err(redirect("/login?next=/settings/security")),
+    }
+}
+
+fn qr_svg(data: &str) -> Result<String, actix_web::Error> {
+    let code =
+        qrcode::QrCode::new(data.as_bytes()).map_err(actix_web::error::ErrorInternalServerError)?;
+    Ok(code
+        .render::<qrcode::render::svg::Color>()
+        .min_dimensions(200, 200)
+        .build())
+}
+
+struct SecurityState {
+    enrolled: bool,
+    recovery_codes_left: i64,
+    /// The secret being enrolled, if enrolling.
+    pending_secret: Option<String>,
+}
+
+fn security_state(
+    conn: &database::connection::DbConnection,
+    user: String,
+) -> Result<SecurityState, String> {
+    let totp = database::totp::query_totp(conn, user.clone())
+        .map_err(|err| format!("failed to query second factor: {}", err))?;
+    let recovery_codes_left = database::totp::count_recovery_codes(conn, user)
+        .map_err(|err| format!("failed to count recovery codes: {}", err))?;
+    Ok(match totp {
+        Some(UserTotp {
+            confirmed_at: Some(_),
+            ..
+        }) => SecurityState {
+            enrolled: true,
+            recovery_codes_left,
+            pending_secret: None,
+        },
+        Some(totp) => SecurityState {
+            enrolled: false,
+            recovery_codes_left,
+            pending_secret: Some(totp.secret),
+        },
+        None => SecurityState {
+            enrolled: false,
+            recovery_codes_left,
+            pending_secret: None,
+        },
+    })
+}
+
+fn security_page(
+    appctx: &AppContext,
+    session: &Session,
+    state: SecurityState,
+    show_enrollment: bool,
+    recovery_codes: Vec<String>,
+    error: Option<String>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let user = session.0.as_ref().unwrap();
+    let enrollment = match state.pending_secret.filter(|_| show_enrollment) {
+        Some(secret) => Some(TotpEnrollment {
+            qr_svg: qr_svg(&common::totp::otpauth_url(
+                &appctx.totp_issuer,
+                &user.username,
+                &secret,
+            ))?,
+            secret,
+        }),
+        None => None,
+    };
+    SecurityPage {
+        _parent: BaseTemplate::new()
+            .with_title(String::from("Security"))
+            .with_session(session),
+        csrf_token: user.csrf_token.clone(),
+        enrolled: state.enrolled,
+        recovery_codes_left: state.recovery_codes_left,
+        enrollment,
+        recovery_codes,
+        error,
+    }
+    .into_response()
+}
+
+#[actix_web::get("/settings/security")]
+pub async fn security_settings(
+    appctx: web::Data<AppContext>,
+    session: Session,
+) -> Result<HttpResponse, actix_web::Error> {
+    let user = match session.user() {
+        Some(user) => user,
+        None => return Ok(redirect("/login?next=/settings/security")),
+    };
+
+    let pool = appctx.pool.clone();
+    let state = web::block(move || -> Result<SecurityState, String> {
+        let conn = pool
+            .get()
+            .map_err(|err| format!("failed to get connection: {}", err))?;
+        security_state(&conn, user)
+    })
+    .await?;
+    security_page(&appctx, &session, state, false, Vec::new(), None)
+}
+
+#[actix_web::post("/settings/totp/enroll")]
+pub async fn totp_enroll(
+    form: web::Form<TotpEnrollForm>,
+    appctx: web::Data<AppContext>,
+    session: Session,
+) -> Result<HttpResponse, actix_web::Error> {
+    let user = match signed_in_user(&session, &form.csrf_token) {
+        Ok(user) => user,
+        Err(response) => return Ok(response),
+    };
+
+    let pool = appctx.pool.clone();
+    let state = web::block(move || -> Result<SecurityState, String> {
+        let conn = pool
+            .get()
+            .map_err(|err| format!("failed to get connection: {}", err))?;
+        if !security_state(&conn, user.uuid.clone())?.enrolled {
+            database::totp::start_enrollment(
+                &conn,
+                user.uuid.clone(),
+                common::totp::generate_secret(),
+            )
+            .map_err(|err| format!("failed to start enrollment: {}", err))?;
+        }
+        security_state(&conn, user.uuid)
+    })
+    .await?;
+    security_page(&appctx, &session, state, true, Vec::new(), None)
+}
+
+#[actix_web::post("/settings/totp/confirm")]
+pub async fn totp_confirm(
+    request: HttpRequest,
+    form: web::Form<TotpCodeForm>,
+    appctx: web::Data<AppContext>,
+    session: Session,
+) -> Result<HttpResponse, actix_web::Error> {
+    let user = match signed_in_user(&session, &form.csrf_token) {
+        Ok(user) => user,
+        Err(response) => return Ok(response),
+    };
+
+    let pool = appctx.pool.clone();
+    let code = form.into_inner().code;
+    let actor = Actor::User(user.uuid.clone());
+    let (state, recovery_codes) =
+        web::block(move || -> Result<(SecurityState, Vec<String>), String> {
+            let conn = pool
+                .get()
+                .map_err(|err| format!("failed to get connection: {}", err))?;
+            let state = security_state(&conn, user.uuid.clone())?;
+            let now = chrono::Utc::now().timestamp() as u64;
+            let step = match state
+                .pending_secret
+                .as_deref()
+                .and_then(|secret| common::totp::verify(secret, &code, now))
+            {
+                Some(step) => step,
+                None => return Ok((state, Vec::new())),
+            };
+
+            let recovery_codes: Vec<String> = (0..common::totp::RECOVERY_CODE_COUNT)
+                .map(|_| common::totp::generate_recovery_code())
+                .collect();
+            database::totp::confirm_enrollment(
+                &conn,
+                user.uuid.clone(),
+                step as i64,
+                recovery_codes
+                    .iter()
+                    .map(|code| common::totp::hash_recovery_code(code))
+                    .collect(),
+            )
+            .map_err(|err| format!("failed to confirm enrollment: {}", err))?;
+            Ok((security_state(&conn, user.uuid)?, recovery_codes))
+        })
+        .await?;
+
+    if recovery_codes.is_empty() {
+        let error = if state.pending_secret.is_some() {
+            "That code is not valid, check the time on your device and try again."
+        } else {
+            "There is nothing to confirm, please start over."
+        };
+        return security_page(
+            &appctx,
+            &session,
+            state,
+            true,
+            Vec::new(),
+            Some(String::from(error)),
+        );
+    }
+    appctx
+        .audit(
+            &request,
+            Event::new(audit::TOTP_ENABLE, actor, Outcome::Success),
+        )
+        .await;
+    security_page(&appctx, &session, state, false, recovery_codes, None)
+}
+
+#[actix_web::post("/settings/totp/disable")]
+pub async fn totp_disable(
+    request: HttpRequest,
+    form: web::Form<TotpCodeForm>,
+    appctx: web::Data<AppContext>,
+    session: Session,
+) -> Result<HttpResponse, actix_web::Error> {
+    let user = match signed_in_user(&session, &form.csrf_token) {
+        Ok(user) => user,
+        Err(response) => return Ok(response),
+    };
+
+    let pool = appctx.pool.clone();
+    let code = form.into_inner().code;
+    let actor = Actor::User(user.uuid.clone());
+    let (state, disabled) = web::block(move || -> Result<(SecurityState, bool), String> {
+        let conn = pool
+            .get()
+            .map_err(|err| format!("failed to get connection: {}", err))?;
+        let disabled = database::totp::check_second_factor(&conn, user.uuid.clone(), &code)
+            .map_err(|err| format!("failed to check second factor: {}", err))?;
+        if disabled {
+            database::totp::delete_totp(&conn, user.uuid.clone())
+                .map_err(|err| format!("failed to disable second factor: {}", err))?;
+        }
+        Ok((security_state(&conn, user.uuid)?, disabled))
+    })
+    .await?;
+
+    let (outcome, error) = match disabled {
+        true => (Outcome::Success, None),
+        false => (
+            Outcome::Failure,
+            Some(String::from("That code is not valid.")),
+        ),
+    };
+    appctx
+        .audit(&request, Event::new(audit::TOTP_DISABLE, actor, outcome))
+        .await;
+    security_page(&appctx, &session, state, false, Vec::new(), error)
+}
@@ -1,11 +1,16 @@
 use actix_web::{web, HttpResponse};
 use askama_actix::TemplateIntoResponse;
 
+use std::collections::BTreeSet;
 use std::path::Path;
 
 use git2::{BranchType, ObjectType, Oid};
 
+use crate::middleware::token_extractor::MaybeToken;
+use crate::session::Session;
 use crate::templates::*;
+use crate::AppContext;
+use common::{Access, RepoPath, RepoPathError};
 
 lazy_static::lazy_static! {
     static ref TAG_CAPTURE: regex::Regex = regex::Regex::new("refs/tags/(?P<tag_name>.*)").unwrap();
@@ -22,13 +27,9 @@ fn extract_repo_info(repo: &git2::Repository) -> (Vec<String>, Vec<String>) {
     let mut branches = Vec::new();
     let mut tags = Vec::new();
     if let Ok(repo_branches) = repo.branches(Some(BranchType::Local)) {
-        for branch in repo_branches {
-            if let Ok((branch, _)) = branch {
-                if let Ok(name) = branch.name() {
-                    if let Some(name) = name {
-                        branches.push(String::from(name));
-                    }
-                }
+        for (branch, _) in repo_branches.flatten() {
+            if let Ok(Some(name)) = branch.name() {
+                branches.push(String::from(name));
             }
         }
     }
@@ -49,12 +50,10 @@ fn extract_oid_from_name(
     name: String,
 ) -> Result<Option<Oid>, git2::Error> {
     match repo.find_branch(&name, BranchType::Local) {
-        Ok(branch) => match branch.get().peel_to_tree() {
-            Ok(tree) => {
-                return Ok(Some(tree.id()));
-            }
-            Err(err) => return Err(err),
-        },
+        Ok(branch) => {
+            let tree = branch.get().peel_to_tree()?;
+            return Ok(Some(tree.id()));
+        }
         Err(_err) => {
             // println!("failed to get branch: {}", _err);
         }
@@ -68,9 +67,9 @@ fn extract_oid_from_name(
                 }
             }
         }
-        return true;
+        true
     })?;
-    if maybe_oid != None {
+    if maybe_oid.is_some() {
         return Ok(maybe_oid);
     }
     match Oid::from_str(name.as_str()) {
@@ -79,12 +78,41 @@ fn extract_oid_from_name(
     }
 }
 
+/// Validates the repository named in the url, answering 400 for malformed
+/// paths and 404 for paths without a repository.
+fn view_repo_path(appctx: &AppContext, raw: &str) -> Result<RepoPath, actix_web::Error> {
+    RepoPath::parse(raw, &appctx.root).map_err(|err| match err {
+        RepoPathError::Invalid(..) => actix_web::error::ErrorBadRequest(err.to_string()),
+        RepoPathError::NotFound(_) => actix_web::error::ErrorNotFound(err.to_string()),
+    })
+}
+
+/// Repositories the visitor cannot read answer 404, just like missing ones.
+async fn view_authorize(
+    appctx: &AppContext,
+    session: &Session,
+    token: &MaybeToken,
+    repo_path: &RepoPath,
+) -> Result<(), actix_web::Error> {
+    let user = session
+        .user()
+        .or_else(|| token.user_for(repo_path.as_str(), Access::Read));
+    match appctx.repository_access(user, repo_path).await? {
+        Some(_) => Ok(()),
+        None => Err(actix_web::error::ErrorNotFound(
+            RepoPathError::NotFound(repo_path.to_string()).to_string(),
+        )),
+    }
+}
+
 async fn git_repo_page(
-    repo_path: String,
+    session: Session,
+    repo_path: RepoPath,
     object_type: String,
     ref_name: String,
     object_path: Option<String>,
 ) -> Result<impl actix_web::Responder, actix_web::Error> {
+    let repo_path = repo_path.to_string();
     let _repo_path = repo_path.clone();
     let _ref_name = ref_name.clone();
     let _object_path = object_path.clone();
@@ -148,14 +176,14 @@ async fn git_repo_page(
             }
         }
 
-        return Ok((
+        Ok((
             branches,
             tags,
             spec_oid,
             spec_object.kind().unwrap(),
             path_oid,
             path_type,
-        ));
+        ))
     })
     .await?;
 
@@ -194,10 +222,7 @@ async fn git_repo_page(
                         if let Some(kind) = entry.kind() {
                             if kind == ObjectType::Blob {
                                 if let Ok(blob_obj) = entry.to_object(&repo) {
-                                    readme_blob = match blob_obj.as_blob() {
-                                        Some(blob) => Some(blob.clone()),
-                                        None => None,
-                                    }
+                                    readme_blob = blob_obj.as_blob().cloned()
                                 }
                             }
                         }
@@ -210,11 +235,7 @@ async fn git_repo_page(
                     if blob.is_binary() {
                         None
                     } else {
-                        if let Ok(s) = String::from_utf8(blob.content().to_vec()) {
-                            Some(s)
-                        } else {
-                            None
-                        }
+                        String::from_utf8(blob.content().to_vec()).ok()
                     }
                 }
             };
@@ -225,7 +246,9 @@ async fn git_repo_page(
 
         let page = GitTreePage {
             _parent: GitBaseTemplate {
-                _parent: BaseTemplate::new().with_title(repo_path.clone()),
+                _parent: BaseTemplate::new()
+                    .with_title(repo_path.clone())
+                    .with_session(&session),
                 repo_path: repo_path.clone(),
                 branches,
                 ref_name: ref_name.clone(),
@@ -254,16 +277,16 @@ async fn git_repo_page(
                 .iter()
                 .map(|(name, kind)| Entry {
                     name: name.clone(),
-                    kind: kind.clone(),
+                    kind: *kind,
                 })
                 .collect(),
             readme,
         };
 
-        return match page.into_response() {
+        match page.into_response() {
             Ok(response) => Ok(response),
             Err(err) => Err(actix_web::error::ErrorInternalServerError(err)),
-        };
+        }
     } else if path_type.unwrap() == ObjectType::Blob && object_type == "blob" {
         let _repo_path = repo_path.clone();
         let _ref_name = ref_name.clone();
@@ -304,7 +327,9 @@ async fn git_repo_page(
         .await?;
         let page = GitBlobPage {
             _parent: GitBaseTemplate {
-                _parent: BaseTemplate::new().with_title(repo_path.clone()),
+                _parent: BaseTemplate::new()
+                    .with_title(repo_path.clone())
+                    .with_session(&session),
                 repo_path: repo_path.clone(),
                 branches,
                 tags,
@@ -356,19 +381,30 @@ pub async fn git_repo_detail(
         String,
         String,
     )>,
+    appctx: web::Data<AppContext>,
+    session: Session,
+    token: MaybeToken,
 ) -> Result<impl actix_web::Responder, actix_web::Error> {
-    if object_path != "" {
-        git_repo_page(repo_path, object_type, ref_name, Some(object_path)).await
+    let repo_path = view_repo_path(&appctx, &repo_path)?;
+    view_authorize(&appctx, &session, &token, &repo_path).await?;
+    if !object_path.is_empty() {
+        git_repo_page(session, repo_path, object_type, ref_name, Some(object_path)).await
     } else {
-        git_repo_page(repo_path, object_type, ref_name, None).await
+        git_repo_page(session, repo_path, object_type, ref_name, None).await
     }
 }
 
 #[actix_web::get("/{path:.*\\.git}")]
 pub async fn git_repo(
     web::Path(repo_path): web::Path<String>,
+    appctx: web::Data<AppContext>,
+    session: Session,
+    token: MaybeToken,
 ) -> Result<impl actix_web::Responder, actix_web::Error> {
+    let repo_path = view_repo_path(&appctx, &repo_path)?;
+    view_authorize(&appctx, &session, &token, &repo_path).await?;
     git_repo_page(
+        session,
         repo_path,
         String::from("tree"),
         String::from("master"),
@@ -377,72 +413,75 @@ pub async fn git_repo(
     .await
 }
 
+/// Browses the registered repositories the visitor can read like a directory
+/// tree: `path` lists the next segment of every such repository below it.
+/// Nothing else under the served root is shown, and paths no readable
+/// repository lies below answer 404.
 #[actix_web::get("/{path:.*}")]
-pub async fn index(web::Path(path): web::Path<String>) -> actix_web::Result<HttpResponse> {
-    let mut _path = path.clone();
-    if _path == "" {
-        _path = String::from(".");
+pub async fn index(
+    web::Path(path): web::Path<String>,
+    appctx: web::Data<AppContext>,
+    session: Session,
+    token: MaybeToken,
+) -> actix_web::Result<HttpResponse> {
+    let path = String::from(path.trim_matches('/'));
+    if path.split('/').any(|segment| segment.starts_with('.')) {
+        return Err(actix_web::error::ErrorNotFound("no such directory"));
     }
 
-    let (metadata, content, entries) = web::block(move || {
-        match std::fs::symlink_metadata(_path.clone()) {
-            Ok(metadata) => {
-                if metadata.is_dir() {
-                    return match std::fs::read_dir(_path) {
-                        Ok(read_dir) => {
-                            let mut v = Vec::new();
-                            for entry in read_dir {
-                                if let Ok(entry) = entry {
-                                    if let Some(s) = entry.file_name().to_str() {
-                                        if String::from(s).starts_with(".") {
-                                            continue;
-                                        }
-                                    }
-                                    let path = entry.file_name();
-                                    if let Ok(s) = path.into_string() {
-                                        v.push(s);
-                                    }
-                                }
-                            }
-                            Ok((metadata, None, v))
-                        }
-                        Err(err) => Err(format!("failed to read dir: {}", err)),
-                    };
-                } else if metadata.is_file() {
-                    match std::fs::read(_path.clone()) {
-                        Ok(binary) => match String::from_utf8(binary) {
-                            Ok(content) => return Ok((metadata, Some(content), vec![])),
-                            Err(_) => return Ok((metadata, None, vec![])),
-                        },
-                        Err(err) => return Err(format!("failed to read file: {}", err)),
-                    };
-                } else {
-                    return Ok((metadata, None, vec![]));
-                }
+    let pool = appctx.pool.clone();
+    let root = appctx.root.clone();
+    // a token bound to one repository only lets its user see that one, next
+    // to the public ones
+    let (user, token) = match (session.user(), token.0) {
+        (Some(user), _) => (Some(user), None),
+        (None, Some(token)) => (token.user().map(String::from), Some(token)),
+        (None, None) => (None, None),
+    };
+    let prefix = path.clone();
+    let entries = web::block(move || -> Result<Vec<String>, String> {
+        let conn = pool
+            .get()
+            .map_err(|err| format!("failed to get connection: {}", err))?;
+        let repositories =
+            database::authorization::query_readable_repositories(&conn, user.as_deref())
+                .map_err(|err| format!("failed to query repositories: {}", err))?;
+        let mut entries = BTreeSet::new();
+        for repository in repositories {
+            let below = match prefix.is_empty() {
+                true => Some(repository.path.as_str()),
+                false => repository
+                    .path
+                    .strip_prefix(prefix.as_str())
+                    .and_then(|rest| rest.strip_prefix('/')),
+            };
+            let below = match below {
+                Some(below) => below,
+                None => continue,
+            };
+            let permitted = token
+                .as_ref()
+                .is_none_or(|token| token.permits(&repository.path, Access::Read));
+            if !repository.is_public && !permitted {
+                continue;
             }
-            Err(err) => return Err(format!("failed to stat file: {}", err)),
-        };
+            // registered repositories may have gone from disk since
+            if RepoPath::parse(&repository.path, &root).is_err() {
+                continue;
+            }
+            entries.insert(String::from(below.split('/').next().unwrap_or(below)));
+        }
+        Ok(entries.into_iter().collect())
     })
     .await?;
-
-    let mut path = path.clone();
-    if path == "" {
-        path = ".".into()
+    if entries.is_empty() && !path.is_empty() {
+        return Err(actix_web::error::ErrorNotFound("no such directory"));
     }
 
-    let rootfs = std::env::current_dir()?.to_str().unwrap().into();
-    let realpath = std::fs::canonicalize(path.clone())?
-        .to_str()
-        .unwrap()
-        .into();
-
-    Ok(FileBrowserPage {
+    FileBrowserPage {
+        _parent: BaseTemplate::new().with_session(&session),
         entries,
-        rootfs,
         path,
-        realpath,
-        content,
-        metadata,
     }
-    .into_response()?)
+    .into_response()
 }
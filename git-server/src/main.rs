@@ -1,46 +1,355 @@
-#![feature(is_symlink)]
+use std::{
+    path::{Path, PathBuf},
+    sync::Arc,
+};
 
-use actix_web::{middleware::Logger, App, HttpServer};
-use middleware::token_extractor::JWTSecret;
-use s3::{creds::Credentials, Bucket, Region};
+use actix_web::{middleware::Logger, web, App, HttpRequest, HttpServer};
+use clap::Parser;
+use database::{audit, connection::ConnectionPool};
+use common::{Access, PublicKey, RepoPath, VerifyingKeys};
+use oidc::{OidcConfig, Provider};
+use proxy::TrustedProxies;
+use revocation::RevocationList;
+use storage::Storage;
 
 use handlers::*;
+use middleware::token_extractor::Token;
+use log::{error, info, warn};
 
 pub mod handlers;
 pub mod middleware;
+pub mod oidc;
+pub mod proxy;
+pub mod revocation;
+pub mod session;
+pub mod storage;
 pub mod templates;
 
-#[derive(Debug, Clone)]
+#[cfg(all(test, feature = "sqlite"))]
+mod testing;
+
+#[derive(Clone)]
 pub struct AppContext {
-    pub bucket: Bucket,
+    /// The directory repositories are served from.
+    pub root: PathBuf,
+    pub storage: Arc<dyn Storage>,
+    /// Hand out git-server urls instead of presigned ones, so clients never
+    /// talk to the storage backend directly.
+    pub proxy_transfers: bool,
+    /// Hash objects on verify, on top of checking their size. This reads the
+    /// whole object back from the storage backend.
+    pub verify_hash: bool,
+    pub pool: ConnectionPool,
+    /// Public keys of git-lfs-authenticate, for verifying its tokens.
+    pub keys: Arc<VerifyingKeys>,
+    /// Send session cookies over plain http too, for local development.
+    pub insecure_cookies: bool,
+    /// Whether users may sign in with a password, next to single sign-on.
+    pub password_login: bool,
+    pub oidc: Option<Arc<Provider>>,
+    /// Names this server in authenticator apps.
+    pub totp_issuer: String,
+    pub revocations: Arc<RevocationList>,
+    /// Whose forwarding headers name the client in the audit log.
+    pub trusted_proxies: Arc<TrustedProxies>,
+}
+
+impl AppContext {
+    /// The access `user`, or an anonymous visitor for `None`, has on `repo`.
+    pub async fn repository_access(
+        &self,
+        user: Option<String>,
+        repo: &RepoPath,
+    ) -> Result<Option<Access>, actix_web::Error> {
+        let pool = self.pool.clone();
+        let repo = repo.to_string();
+        let access = web::block(move || -> Result<Option<Access>, String> {
+            let conn = pool
+                .get()
+                .map_err(|err| format!("failed to get connection: {}", err))?;
+            database::authorization::repository_access(&conn, user.as_deref(), &repo)
+                .map_err(|err| format!("failed to query access: {}", err))
+        })
+        .await?;
+        Ok(access)
+    }
+
+    /// The access the user or deploy key a token was issued to has on
+    /// `repo`, not capped by the token's scope. Anonymous for `None`.
+    pub async fn token_access(
+        &self,
+        token: Option<&Token>,
+        repo: &RepoPath,
+    ) -> Result<Option<Access>, actix_web::Error> {
+        let fingerprint = match token.and_then(Token::deploy_key) {
+            Some(fingerprint) => String::from(fingerprint),
+            None => {
+                let user = token.and_then(Token::user).map(String::from);
+                return self.repository_access(user, repo).await;
+            }
+        };
+
+        let pool = self.pool.clone();
+        let repo = repo.to_string();
+        let access = web::block(move || -> Result<Option<Access>, String> {
+            let conn = pool
+                .get()
+                .map_err(|err| format!("failed to get connection: {}", err))?;
+            database::authorization::deploy_key_access(&conn, &fingerprint, &repo)
+                .map_err(|err| format!("failed to query access: {}", err))
+        })
+        .await?;
+        Ok(access)
+    }
+}
+
+impl AppContext {
+    /// Appends `event` to the audit log, with the address of the client of
+    /// `request` as far as [`TrustedProxies`] tells. Failing to record is
+    /// logged, it does not fail the request.
+    pub async fn audit(&self, request: &HttpRequest, event: audit::Event) {
+        let event = event.ip(self.trusted_proxies.client_ip(request).map(|ip| ip.to_string()));
+        let pool = self.pool.clone();
+        let recorded = web::block(move || -> Result<usize, String> {
+            let conn = pool
+                .get()
+                .map_err(|err| format!("failed to get connection: {}", err))?;
+            audit::record(&conn, event).map_err(|err| format!("failed to record audit event: {}", err))
+        })
+        .await;
+        if let Err(err) = recorded {
+            error!("{}", err);
+        }
+    }
+}
+
+/// Serves the web interface and the git LFS api. Configured through the
+/// environment, see the README.
+#[derive(Parser)]
+#[clap(author, version, about)]
+struct Cli {
+    /// Only check for pending migrations instead of applying them, for when
+    /// the database user may not change the schema. /health reports them
+    /// until they are applied
+    #[clap(long)]
+    check_migrations: bool,
+
+    #[clap(subcommand)]
+    command: Option<Command>,
+}
+
+#[derive(clap::Subcommand)]
+enum Command {
+    /// Creates the first admin on a fresh installation and exits
+    Bootstrap {
+        /// The username of the admin
+        #[clap(long)]
+        username: String,
+
+        /// A .pub file with the admin's ssh public key
+        #[clap(long)]
+        public_key: PathBuf,
+    },
+}
+
+/// Reads the single key of a `.pub` file.
+fn read_public_key(path: &Path) -> Result<PublicKey, String> {
+    let contents = std::fs::read_to_string(path)
+        .map_err(|err| format!("failed to read {}: {}", path.display(), err))?;
+    let mut keys =
+        PublicKey::parse_file(&contents).map_err(|err| format!("{}: {}", path.display(), err))?;
+    match keys.len() {
+        1 => Ok(keys.remove(0)),
+        0 => Err(format!("{} holds no public key", path.display())),
+        _ => Err(format!("{} holds more than one public key", path.display())),
+    }
+}
+
+/// Creates the first admin with `key`, once. The username and the key must
+/// not be in use, as they may be if users signed in before bootstrapping.
+fn bootstrap(pool: &ConnectionPool, username: String, key: PublicKey) -> Result<(), String> {
+    let conn = pool
+        .get()
+        .map_err(|err| format!("failed to get connection: {}", err))?;
+    let query = |err| format!("failed to query the database: {}", err);
+    if database::instance::query_initialized(&conn).map_err(query)? {
+        return Err(String::from("this instance has been bootstrapped already"));
+    }
+    if database::user::query_user_id_by_username(&conn, username.clone())
+        .map_err(query)?
+        .is_some()
+    {
+        return Err(format!("the username {} is taken", username));
+    }
+    if database::authorization::query_key_registered(&conn, &key).map_err(query)? {
+        return Err(format!("the key {} is in use", key.sha256_fingerprint));
+    }
+
+    let uuid = database::instance::bootstrap(&conn, username.clone(), &key)
+        .map_err(|err| format!("failed to bootstrap: {}", err))?;
+    let event = audit::Event::new(
+        audit::BOOTSTRAP,
+        audit::Actor::User(uuid),
+        audit::Outcome::Success,
+    )
+    .detail(format!("key {}", key.sha256_fingerprint));
+    if let Err(err) = audit::record(&conn, event) {
+        error!("failed to record audit event: {}", err);
+    }
+    info!(
+        "created the admin {} with the key {}",
+        username, key.sha256_fingerprint
+    );
+    Ok(())
+}
+
+/// Brings the schema up to date, or only checks that it is.
+fn migrate(pool: &ConnectionPool, check_only: bool) -> Result<(), String> {
+    let conn = pool
+        .get()
+        .map_err(|err| format!("failed to get connection: {}", err))?;
+    if check_only {
+        if let Err(err) = database::migrations::check_migrations(&conn) {
+            warn!("{}", err);
+        }
+        return Ok(());
+    }
+    let applied = database::migrations::run_pending_migrations(&conn)
+        .map_err(|err| format!("failed to apply migrations: {}", err))?;
+    for name in applied {
+        info!("applied migration {}", name);
+    }
+    Ok(())
+}
+
+/// Points out how to create the first admin while there is none.
+fn warn_uninitialized(pool: &ConnectionPool) {
+    let initialized = pool.get().map_err(|err| err.to_string()).and_then(|conn| {
+        database::instance::query_initialized(&conn).map_err(|err| err.to_string())
+    });
+    match initialized {
+        Ok(true) => {}
+        Ok(false) => warn!(
+            "there is no admin yet, create one with `git-server bootstrap --username <name> --public-key <file>`"
+        ),
+        Err(err) => warn!("failed to check whether this instance is bootstrapped: {}", err),
+    }
+}
+
+/// Points out the user and keys an early version seeded into every
+/// installation, while they are there. Removing them is up to the operator,
+/// as they may have been taken into use.
+fn warn_legacy_seed(pool: &ConnectionPool) {
+    let seeded = pool.get().map_err(|err| err.to_string()).and_then(|conn| {
+        database::instance::query_legacy_seed(&conn).map_err(|err| err.to_string())
+    });
+    match seeded {
+        Ok(false) => {}
+        Ok(true) => warn!(
+            "the database holds the user guochao ({}) or their keys, seeded by an early version; unless they are in use, remove them with `rustile-admin user remove-legacy-seed`",
+            database::instance::LEGACY_SEED_USER
+        ),
+        Err(err) => warn!("failed to check for the seeded user: {}", err),
+    }
+}
+
+fn env_flag(name: &str) -> bool {
+    std::env::var(name)
+        .map(|value| value == "1" || value.eq_ignore_ascii_case("true"))
+        .unwrap_or(false)
 }
 
 #[actix_web::main]
 async fn main() -> std::io::Result<()> {
+    let args = Cli::parse();
+    // before changing into HOME, the path may be relative to where we started
+    let bootstrap_with = match args.command {
+        Some(Command::Bootstrap {
+            username,
+            public_key,
+        }) => Some((
+            username,
+            read_public_key(&public_key).map_err(std::io::Error::other)?,
+        )),
+        None => None,
+    };
     std::env::set_current_dir(std::env::var("HOME").unwrap_or(String::from("/")))?;
 
     env_logger::init();
 
-    HttpServer::new(|| {
-        let mut bucket = Bucket::new_with_path_style(
-            std::env::var("AWS_BUCKET_NAME").unwrap().as_str(),
-            Region::Custom {
-                region: std::env::var("AWS_ENDPOINT_REGION").unwrap(),
-                endpoint: std::env::var("AWS_ENDPOINT_PREFIX").unwrap(),
-            },
-            Credentials::from_env().unwrap(),
-        )
-        .unwrap();
-        bucket.set_subdomain_style();
+    let pool = database::connection::pool_from_env().map_err(std::io::Error::other)?;
+    migrate(&pool, args.check_migrations).map_err(std::io::Error::other)?;
+    if let Some((username, key)) = bootstrap_with {
+        return bootstrap(&pool, username, key).map_err(std::io::Error::other);
+    }
+    warn_uninitialized(&pool);
+    warn_legacy_seed(&pool);
+
+    let root = std::env::current_dir()?;
+    let keys = Arc::new(VerifyingKeys::from_env().map_err(std::io::Error::other)?);
+    info!("accepting tokens signed by {:?}", keys.kids().collect::<Vec<_>>());
+    let storage = storage::from_env().map_err(std::io::Error::other)?;
+    let proxy_transfers = env_flag("LFS_PROXY_TRANSFERS");
+    let verify_hash = env_flag("LFS_VERIFY_HASH");
+    let insecure_cookies = env_flag("SESSION_COOKIE_INSECURE");
+    let password_login = !env_flag("DISABLE_PASSWORD_LOGIN");
+    let totp_issuer = std::env::var("TOTP_ISSUER").unwrap_or_else(|_| String::from("Rustile"));
+    let revocations = Arc::new(RevocationList::from_env().map_err(std::io::Error::other)?);
+    let trusted_proxies = Arc::new(TrustedProxies::from_env().map_err(std::io::Error::other)?);
+    let oidc = match OidcConfig::from_env() {
+        Some(config) => {
+            let config = config.map_err(std::io::Error::other)?;
+            let provider = Provider::discover(config)
+                .await
+                .map_err(std::io::Error::other)?;
+            Some(Arc::new(provider))
+        }
+        None => None,
+    };
 
+    HttpServer::new(move || {
         App::new()
-            .app_data(JWTSecret(std::env::var("SECRET").unwrap()))
-            .data(AppContext { bucket })
+            .data(AppContext {
+                root: root.clone(),
+                storage: storage.clone(),
+                proxy_transfers,
+                verify_hash,
+                pool: pool.clone(),
+                keys: keys.clone(),
+                insecure_cookies,
+                password_login,
+                oidc: oidc.clone(),
+                totp_issuer: totp_issuer.clone(),
+                revocations: revocations.clone(),
+                trusted_proxies: trusted_proxies.clone(),
+            })
             .wrap(Logger::new("%a %{User-Agent}i"))
+            .service(login_page)
+            .service(login)
+            .service(logout)
+            .service(oidc_login)
+            .service(oidc_callback)
+            .service(login_totp_page)
+            .service(login_totp)
+            .service(security_settings)
+            .service(totp_enroll)
+            .service(totp_confirm)
+            .service(totp_disable)
+            .service(token_settings)
+            .service(revoke_token)
+            .service(audit_log)
+            .service(audit_log_export)
             .service(lfs_lock_verify)
+            .service(lfs_unlock)
+            .service(lfs_create_lock)
+            .service(lfs_list_locks)
             .service(lfs_objects_batch)
+            .service(lfs_object_verify)
+            .service(lfs_object_download)
+            .service(lfs_object_upload)
             .service(git_repo_detail)
             .service(git_repo)
+            .service(health_check)
             .service(index)
     })
     .bind("127.0.0.1:8080")?
@@ -1,37 +1,153 @@
 pub mod token_extractor {
-    use actix_web::{Error, FromRequest};
-    use jsonwebtoken::{Algorithm, DecodingKey, Validation};
+    use actix_web::{
+        error::InternalError, http::header, web, Error, FromRequest, HttpRequest, HttpResponse,
+    };
+    use common::{access_token, Access, Claims, RepoPath};
+    use database::audit::Actor;
+    use database::models::AccessToken;
+    use futures::future::{ready, FutureExt, LocalBoxFuture};
     use log::*;
-    use serde::{Deserialize, Serialize};
 
+    use crate::AppContext;
+
+    lazy_static::lazy_static! {
+        static ref AUTH_CAPTURE: regex::Regex = regex::Regex::new("(?P<kind>[[:alpha:]]*) (?P<cred>.*)").unwrap();
+    }
+
+    /// Asks git and git-lfs to come back with a username and a personal
+    /// access token.
+    pub const AUTHENTICATE_CHALLENGE: &str = "Basic realm=\"git-server\"";
+
+    fn forbidden(message: &'static str) -> Error {
+        InternalError::from_response(
+            message,
+            HttpResponse::Forbidden().json(serde_json::json!({ "message": message })),
+        )
+        .into()
+    }
+
+    pub fn unauthorized(message: &'static str) -> Error {
+        InternalError::from_response(
+            message,
+            HttpResponse::Unauthorized()
+                .header(header::WWW_AUTHENTICATE, AUTHENTICATE_CHALLENGE)
+                .header("LFS-Authenticate", AUTHENTICATE_CHALLENGE)
+                .json(serde_json::json!({ "message": message })),
+        )
+        .into()
+    }
+
+    /// The credentials of a request, checked to be valid and not revoked.
+    /// Extraction fails with 403 if the token is bound to another repository
+    /// than the one in the url.
     #[derive(Debug)]
-    pub struct JWTSecret(pub String);
+    pub enum Token {
+        /// Minted by git-lfs-authenticate, `Authorization: Token <jwt>`.
+        Lfs(Claims),
+        /// A personal access token, sent as `Authorization: Bearer <token>`
+        /// or as the password of `Authorization: Basic`.
+        Personal(AccessToken),
+    }
+
+    impl Token {
+        /// The uuid of the user the token was issued to, `None` for tokens
+        /// issued to deploy keys.
+        pub fn user(&self) -> Option<&str> {
+            match self {
+                Token::Lfs(claims) if claims.deploy_key => None,
+                Token::Lfs(claims) => Some(&claims.sub),
+                Token::Personal(token) => Some(&token.user),
+            }
+        }
+
+        /// The SHA-256 fingerprint of the deploy key the token was issued to.
+        pub fn deploy_key(&self) -> Option<&str> {
+            match self {
+                Token::Lfs(claims) if claims.deploy_key => Some(&claims.sub),
+                _ => None,
+            }
+        }
+
+        /// Who is acting with the token, for the audit log.
+        pub fn actor(&self) -> Actor {
+            match (self.user(), self.deploy_key()) {
+                (Some(user), _) => Actor::User(String::from(user)),
+                (None, Some(fingerprint)) => Actor::DeployKey(String::from(fingerprint)),
+                (None, None) => Actor::Anonymous,
+            }
+        }
+
+        /// The highest access the token itself grants.
+        pub fn scope(&self) -> Access {
+            match self {
+                Token::Lfs(claims) => Access::from(claims.operation),
+                Token::Personal(token) => token.scope.parse().unwrap_or(Access::Read),
+            }
+        }
 
-    impl Default for JWTSecret {
-        fn default() -> Self {
-            Self(Default::default())
+        /// Whether the token itself allows `access` on `repo`. The user it
+        /// was issued to needs the permission as well.
+        pub fn permits(&self, repo: &str, access: Access) -> bool {
+            self.repo().is_none_or(|only| only == repo) && self.scope() >= access
+        }
+
+        fn repo(&self) -> Option<&str> {
+            match self {
+                Token::Lfs(claims) => Some(&claims.repo),
+                Token::Personal(token) => token.repository.as_deref(),
+            }
         }
     }
 
-    lazy_static::lazy_static! {
-        static ref AUTH_CAPTURE: regex::Regex = regex::Regex::new("(?P<kind>[[:alpha:]]*) (?P<cred>.*)").unwrap();
+    enum Presented {
+        Lfs(String),
+        Personal(String),
     }
 
-    #[derive(Debug, Serialize, Deserialize)]
-    pub struct Token {
-        sub: String,
-        iat: i64,
-        exp: i64,
+    fn presented(req: &HttpRequest) -> Option<Presented> {
+        let header = req.headers().get(header::AUTHORIZATION)?.to_str().ok()?;
+        let captures = AUTH_CAPTURE.captures(header)?;
+        let cred = captures.name("cred")?.as_str().trim();
+        match captures.name("kind")?.as_str().to_lowercase().as_str() {
+            "token" => Some(Presented::Lfs(String::from(cred))),
+            "bearer" if access_token::is_access_token(cred) => {
+                Some(Presented::Personal(String::from(cred)))
+            }
+            "bearer" => Some(Presented::Lfs(String::from(cred))),
+            "basic" => {
+                // the username is up to the client, the password is the token
+                let decoded = String::from_utf8(base64::decode(cred).ok()?).ok()?;
+                let (_, password) = decoded.split_once(':')?;
+                if access_token::is_access_token(password) {
+                    Some(Presented::Personal(String::from(password)))
+                } else {
+                    None
+                }
+            }
+            kind => {
+                debug!("unsupported auth scheme: {}", kind);
+                None
+            }
+        }
+    }
 
-        pub command: String,
+    /// Tokens bound to a repository only work on that repository's urls.
+    fn check_repo(req: &HttpRequest, token: Token) -> Result<Token, Error> {
+        if let (Some(repo_path), Some(repo)) = (req.match_info().get("repo_path"), token.repo()) {
+            if RepoPath::normalize(repo_path).ok().as_deref() != Some(repo) {
+                debug!("token for {} used on {}", repo, repo_path);
+                return Err(forbidden("token was issued for another repository"));
+            }
+        }
+        Ok(token)
     }
 
     impl FromRequest for Token {
         type Error = actix_web::error::Error;
 
-        type Future = futures::future::Ready<Result<Self, Error>>;
+        type Future = LocalBoxFuture<'static, Result<Self, Error>>;
 
-        type Config = JWTSecret;
+        type Config = ();
 
         fn from_request(
             req: &actix_web::HttpRequest,
@@ -39,35 +155,102 @@ pub mod token_extractor {
         ) -> Self::Future {
             debug!("extracting token info");
 
-            let jwtsecret = req.app_data::<JWTSecret>().unwrap();
-            debug!("jwt secret ok: {:?}", jwtsecret);
-
-            if let Some(header) = req.headers().get("Authorization") {
-                match AUTH_CAPTURE.captures(
-                    String::from_utf8(header.as_bytes().to_vec())
-                        .unwrap()
-                        .as_str(),
-                ) {
-                    Some(captures) => {
-                        if captures.name("kind").unwrap().as_str().to_lowercase() == "token" {
-                            match jsonwebtoken::decode::<Token>(
-                                captures.name("cred").unwrap().as_str(),
-                                &DecodingKey::from_secret(jwtsecret.0.as_bytes()),
-                                &Validation::new(Algorithm::HS256),
-                            ) {
-                                Ok(token) => return futures::future::ready(Ok(token.claims)),
-                                Err(err) => debug!("failed to decode token: {}", err),
+            let appctx = req.app_data::<web::Data<AppContext>>().unwrap().clone();
+
+            match presented(req) {
+                Some(Presented::Lfs(jwt)) => match appctx.keys.verify(&jwt) {
+                    Ok(claims) => {
+                        let req = req.clone();
+                        async move {
+                            if appctx
+                                .revocations
+                                .is_revoked(&appctx.pool, &claims.jti)
+                                .await?
+                            {
+                                debug!("revoked token {}", claims.jti);
+                                return Err(unauthorized("token was revoked"));
                             }
-                        } else {
-                            debug!("is not token auth");
+                            check_repo(&req, Token::Lfs(claims))
                         }
+                        .boxed_local()
+                    }
+                    Err(err) => {
+                        debug!("failed to decode token: {}", err);
+                        ready(Err(unauthorized("auth needed"))).boxed_local()
                     }
-                    None => debug!("not valid auth"),
+                },
+                Some(Presented::Personal(secret)) => {
+                    let req = req.clone();
+                    async move {
+                        let pool = appctx.pool.clone();
+                        let token = web::block(move || -> Result<Option<AccessToken>, String> {
+                            let conn = pool
+                                .get()
+                                .map_err(|err| format!("failed to get connection: {}", err))?;
+                            database::access_token::query_access_token_by_hash(
+                                &conn,
+                                access_token::hash(&secret),
+                            )
+                            .map_err(|err| format!("failed to query access token: {}", err))
+                        })
+                        .await?;
+                        match token {
+                            Some(token) => check_repo(&req, Token::Personal(token)),
+                            None => {
+                                debug!("unknown or expired access token");
+                                Err(unauthorized("auth needed"))
+                            }
+                        }
+                    }
+                    .boxed_local()
+                }
+                None => {
+                    debug!("auth needed");
+                    ready(Err(unauthorized("auth needed"))).boxed_local()
                 }
             }
+        }
+    }
 
-            debug!("auth needed");
-            futures::future::ready(Err(actix_web::error::ErrorUnauthorized("auth needed")))
+    /// A token if the request carries credentials, `None` for anonymous
+    /// requests. Credentials that are present but invalid still fail like
+    /// [`Token`] does.
+    #[derive(Debug)]
+    pub struct MaybeToken(pub Option<Token>);
+
+    impl MaybeToken {
+        pub fn actor(&self) -> Actor {
+            self.0.as_ref().map_or(Actor::Anonymous, Token::actor)
+        }
+
+        /// The user to check permissions for on `repo`: the token's user, if
+        /// the token itself permits `access` there.
+        pub fn user_for(&self, repo: &str, access: Access) -> Option<String> {
+            self.0
+                .as_ref()
+                .filter(|token| token.permits(repo, access))
+                .and_then(|token| token.user())
+                .map(String::from)
+        }
+    }
+
+    impl FromRequest for MaybeToken {
+        type Error = actix_web::error::Error;
+
+        type Future = LocalBoxFuture<'static, Result<Self, Error>>;
+
+        type Config = ();
+
+        fn from_request(
+            req: &actix_web::HttpRequest,
+            payload: &mut actix_web::dev::Payload,
+        ) -> Self::Future {
+            if req.headers().get(header::AUTHORIZATION).is_none() {
+                return ready(Ok(MaybeToken(None))).boxed_local();
+            }
+            Token::from_request(req, payload)
+                .map(|token| token.map(|token| MaybeToken(Some(token))))
+                .boxed_local()
         }
     }
 }
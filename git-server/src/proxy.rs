@@ -0,0 +1,146 @@
+use std::net::{IpAddr, SocketAddr};
+
+use actix_web::HttpRequest;
+
+/// The reverse proxies in front of git-server, from `TRUSTED_PROXIES`. Only
+/// requests coming from one of them have their `X-Forwarded-For` header
+/// believed, anyone else could put any address there.
+#[derive(Debug, Default)]
+pub struct TrustedProxies(Vec<IpAddr>);
+
+impl TrustedProxies {
+    pub fn new(proxies: Vec<IpAddr>) -> Self {
+        Self(proxies)
+    }
+
+    /// Reads the comma separated addresses of `TRUSTED_PROXIES`, trusting
+    /// none if it is not set.
+    pub fn from_env() -> Result<Self, String> {
+        let proxies = match std::env::var("TRUSTED_PROXIES") {
+            Ok(proxies) => proxies,
+            Err(_) => return Ok(Self::default()),
+        };
+        proxies
+            .split(',')
+            .map(str::trim)
+            .filter(|proxy| !proxy.is_empty())
+            .map(|proxy| {
+                proxy
+                    .parse()
+                    .map_err(|err| format!("invalid TRUSTED_PROXIES entry '{}': {}", proxy, err))
+            })
+            .collect::<Result<_, _>>()
+            .map(Self)
+    }
+
+    fn trusts(&self, addr: &IpAddr) -> bool {
+        self.0.contains(addr)
+    }
+
+    /// The address of the client of `request`: the peer, or behind trusted
+    /// proxies the last address they forwarded for that is not one of them.
+    pub fn client_ip(&self, request: &HttpRequest) -> Option<IpAddr> {
+        let peer = request.peer_addr()?.ip();
+        let hops: Vec<&str> = request
+            .headers()
+            .get_all("x-forwarded-for")
+            .filter_map(|value| value.to_str().ok())
+            .flat_map(|value| value.split(','))
+            .collect();
+        Some(self.resolve(peer, &hops))
+    }
+
+    /// Walks the proxy chain back from `peer`, the hops being listed
+    /// closest to the client first.
+    fn resolve(&self, peer: IpAddr, hops: &[&str]) -> IpAddr {
+        let mut client = peer;
+        for hop in hops.iter().rev() {
+            if !self.trusts(&client) {
+                break;
+            }
+            match parse_hop(hop) {
+                Some(addr) => client = addr,
+                // whatever a proxy forwards past garbage is made up
+                None => break,
+            }
+        }
+        client
+    }
+}
+
+/// An `X-Forwarded-For` entry, an address with or without a port.
+fn parse_hop(hop: &str) -> Option<IpAddr> {
+    let hop = hop.trim();
+    hop.parse::<IpAddr>()
+        .ok()
+        .or_else(|| hop.parse::<SocketAddr>().ok().map(|addr| addr.ip()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::test::TestRequest;
+
+    fn ip(addr: &str) -> IpAddr {
+        addr.parse().unwrap()
+    }
+
+    fn request(peer: &str, forwarded_for: Option<&str>) -> HttpRequest {
+        let request = TestRequest::default().peer_addr(format!("{}:40000", peer).parse().unwrap());
+        match forwarded_for {
+            Some(value) => request.header("X-Forwarded-For", value),
+            None => request,
+        }
+        .to_http_request()
+    }
+
+    #[test]
+    fn ignores_forwarding_headers_of_untrusted_peers() {
+        let proxies = TrustedProxies::default();
+        assert_eq!(
+            proxies.client_ip(&request("203.0.113.7", Some("10.0.0.1"))),
+            Some(ip("203.0.113.7"))
+        );
+
+        let proxies = TrustedProxies::new(vec![ip("127.0.0.1")]);
+        assert_eq!(
+            proxies.client_ip(&request("203.0.113.7", Some("10.0.0.1"))),
+            Some(ip("203.0.113.7"))
+        );
+    }
+
+    #[test]
+    fn follows_trusted_proxies() {
+        let proxies = TrustedProxies::new(vec![ip("127.0.0.1"), ip("10.0.0.2")]);
+        assert_eq!(
+            proxies.client_ip(&request("127.0.0.1", None)),
+            Some(ip("127.0.0.1"))
+        );
+        assert_eq!(
+            proxies.client_ip(&request("127.0.0.1", Some("198.51.100.4"))),
+            Some(ip("198.51.100.4"))
+        );
+        assert_eq!(
+            proxies.client_ip(&request("127.0.0.1", Some("198.51.100.4, 10.0.0.2"))),
+            Some(ip("198.51.100.4"))
+        );
+        assert_eq!(
+            proxies.client_ip(&request("127.0.0.1", Some("[2001:db8::1]:1234"))),
+            Some(ip("2001:db8::1"))
+        );
+    }
+
+    #[test]
+    fn stops_at_the_first_untrusted_hop() {
+        let proxies = TrustedProxies::new(vec![ip("127.0.0.1")]);
+        // the client made up the first entry, the proxy appended the second
+        assert_eq!(
+            proxies.client_ip(&request("127.0.0.1", Some("192.0.2.1, 198.51.100.4"))),
+            Some(ip("198.51.100.4"))
+        );
+        assert_eq!(
+            proxies.client_ip(&request("127.0.0.1", Some("192.0.2.1, garbage"))),
+            Some(ip("127.0.0.1"))
+        );
+    }
+}
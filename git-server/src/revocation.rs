@@ -0,0 +1,81 @@
+use std::{
+    collections::HashSet,
+    sync::{Arc, RwLock},
+    time::{Duration, Instant},
+};
+
+use actix_web::web;
+use database::connection::ConnectionPool;
+use log::debug;
+
+/// How long the revoked jtis are trusted before they are loaded again, unless
+/// `REVOCATION_CACHE_SECONDS` says otherwise. Tokens revoked through another
+/// git-server process, or over ssh, are refused at most this much later.
+pub const DEFAULT_REFRESH_SECONDS: u64 = 30;
+
+/// The jtis of revoked git-lfs-authenticate tokens that have not expired yet,
+/// shared by the workers and loaded from the database every so often.
+pub struct RevocationList {
+    refresh_every: Duration,
+    cache: RwLock<Option<(Instant, Arc<HashSet<String>>)>>,
+}
+
+impl RevocationList {
+    pub fn new(refresh_every: Duration) -> Self {
+        Self {
+            refresh_every,
+            cache: RwLock::new(None),
+        }
+    }
+
+    pub fn from_env() -> Result<Self, String> {
+        let seconds = match std::env::var("REVOCATION_CACHE_SECONDS") {
+            Ok(seconds) => seconds
+                .parse()
+                .map_err(|err| format!("invalid REVOCATION_CACHE_SECONDS: {}", err))?,
+            Err(_) => DEFAULT_REFRESH_SECONDS,
+        };
+        Ok(Self::new(Duration::from_secs(seconds)))
+    }
+
+    fn cached(&self) -> Option<Arc<HashSet<String>>> {
+        match &*self.cache.read().unwrap() {
+            Some((loaded_at, jtis)) if loaded_at.elapsed() < self.refresh_every => {
+                Some(jtis.clone())
+            }
+            _ => None,
+        }
+    }
+
+    /// Whether the token `jti` was revoked.
+    pub async fn is_revoked(
+        &self,
+        pool: &ConnectionPool,
+        jti: &str,
+    ) -> Result<bool, actix_web::Error> {
+        if let Some(jtis) = self.cached() {
+            return Ok(jtis.contains(jti));
+        }
+
+        let pool = pool.clone();
+        let jtis = web::block(move || -> Result<Vec<String>, String> {
+            let conn = pool
+                .get()
+                .map_err(|err| format!("failed to get connection: {}", err))?;
+            database::issued_token::query_revoked_jtis(&conn)
+                .map_err(|err| format!("failed to query revoked tokens: {}", err))
+        })
+        .await?;
+        debug!("loaded {} revoked tokens", jtis.len());
+
+        let jtis: Arc<HashSet<String>> = Arc::new(jtis.into_iter().collect());
+        let revoked = jtis.contains(jti);
+        *self.cache.write().unwrap() = Some((Instant::now(), jtis));
+        Ok(revoked)
+    }
+
+    /// Drops the cache, for when a token was just revoked by this process.
+    pub fn invalidate(&self) {
+        *self.cache.write().unwrap() = None;
+    }
+}
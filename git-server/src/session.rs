@@ -0,0 +1,188 @@
+use actix_web::{
+    cookie::{Cookie, SameSite},
+    web, Error, FromRequest, HttpMessage, HttpRequest,
+};
+use common::secret;
+use database::connection::DbConnection;
+use database::models::NewSession;
+use futures::future::{ready, FutureExt, LocalBoxFuture};
+use log::error;
+
+use crate::AppContext;
+
+/// Holds the session id. Only its sha256 is stored in the database.
+pub const SESSION_COOKIE: &str = "rustile_session";
+/// Holds the CSRF token of the login form, before there is a session.
+pub const LOGIN_CSRF_COOKIE: &str = "rustile_login_csrf";
+
+pub const SESSION_LIFETIME_DAYS: i64 = 14;
+/// How long a session waits for the second factor.
+pub const PENDING_SESSION_LIFETIME_MINUTES: i64 = 10;
+/// Wrong codes after which a pending session is dropped, and signing in has
+/// to start over.
+pub const MAX_SECOND_FACTOR_FAILURES: i32 = 5;
+pub const SESSION_ID_LENGTH: usize = 48;
+pub const CSRF_TOKEN_LENGTH: usize = 32;
+
+/// The user signed in with the session cookie of a request.
+#[derive(Debug, Clone)]
+pub struct SessionUser {
+    pub uuid: String,
+    pub username: String,
+    /// Forms posted within the session have to carry this.
+    pub csrf_token: String,
+    pub id_hash: String,
+    /// The second factor is still missing, see [`PendingSession`].
+    pub second_factor_pending: bool,
+}
+
+/// The session of a request, `None` for visitors who are not signed in.
+/// Unknown and expired session cookies count as not signed in, and so do
+/// sessions still waiting for the second factor.
+#[derive(Debug, Clone, Default)]
+pub struct Session(pub Option<SessionUser>);
+
+impl Session {
+    /// The uuid of the signed in user.
+    pub fn user(&self) -> Option<String> {
+        self.0.as_ref().map(|user| user.uuid.clone())
+    }
+
+    /// Whether a posted form carries the CSRF token of this session.
+    pub fn check_csrf(&self, csrf_token: &str) -> bool {
+        self.0
+            .as_ref()
+            .is_some_and(|user| secret::constant_time_eq(&user.csrf_token, csrf_token))
+    }
+}
+
+/// A session that still waits for the second factor, for the page asking
+/// for it.
+#[derive(Debug, Clone, Default)]
+pub struct PendingSession(pub Option<SessionUser>);
+
+impl FromRequest for Session {
+    type Error = Error;
+
+    type Future = LocalBoxFuture<'static, Result<Self, Error>>;
+
+    type Config = ();
+
+    fn from_request(req: &HttpRequest, _: &mut actix_web::dev::Payload) -> Self::Future {
+        load(req)
+            .map(|user| Ok(Session(user.filter(|user| !user.second_factor_pending))))
+            .boxed_local()
+    }
+}
+
+impl FromRequest for PendingSession {
+    type Error = Error;
+
+    type Future = LocalBoxFuture<'static, Result<Self, Error>>;
+
+    type Config = ();
+
+    fn from_request(req: &HttpRequest, _: &mut actix_web::dev::Payload) -> Self::Future {
+        load(req)
+            .map(|user| {
+                Ok(PendingSession(
+                    user.filter(|user| user.second_factor_pending),
+                ))
+            })
+            .boxed_local()
+    }
+}
+
+fn load(req: &HttpRequest) -> LocalBoxFuture<'static, Option<SessionUser>> {
+    let id = match req.cookie(SESSION_COOKIE) {
+        Some(cookie) => String::from(cookie.value()),
+        None => return ready(None).boxed_local(),
+    };
+    let pool = req
+        .app_data::<web::Data<AppContext>>()
+        .unwrap()
+        .pool
+        .clone();
+    async move {
+        let id_hash = secret::hash(&id);
+        let session = web::block(move || -> Result<_, String> {
+            let conn = pool
+                .get()
+                .map_err(|err| format!("failed to get connection: {}", err))?;
+            database::session::query_session(&conn, id_hash)
+                .map_err(|err| format!("failed to query session: {}", err))
+        })
+        .await;
+        match session {
+            Ok(Some((session, username))) => Some(SessionUser {
+                uuid: session.user,
+                username,
+                csrf_token: session.csrf_token,
+                id_hash: session.id_hash,
+                second_factor_pending: session.second_factor_pending,
+            }),
+            Ok(None) => None,
+            Err(err) => {
+                error!("{}", err);
+                None
+            }
+        }
+    }
+    .boxed_local()
+}
+
+/// A cookie only sent back to git-server, over https unless
+/// `SESSION_COOKIE_INSECURE` is set.
+pub fn cookie(
+    appctx: &AppContext,
+    name: &'static str,
+    value: String,
+    max_age: time::Duration,
+) -> Cookie<'static> {
+    Cookie::build(name, value)
+        .path("/")
+        .http_only(true)
+        .secure(!appctx.insecure_cookies)
+        .same_site(SameSite::Lax)
+        .max_age(max_age)
+        .finish()
+}
+
+/// A session just started, see [`create_session`].
+pub struct NewSessionId {
+    pub id: String,
+    /// The user has a second factor to enter before they are signed in.
+    pub second_factor_pending: bool,
+}
+
+/// Starts a session for `user`, returning the id for the session cookie.
+/// Sessions of users with a second factor start out pending. Expired
+/// sessions are cleaned up on the way.
+pub fn create_session(conn: &DbConnection, user: String) -> Result<NewSessionId, String> {
+    database::session::delete_expired_sessions(conn)
+        .map_err(|err| format!("failed to delete expired sessions: {}", err))?;
+    let second_factor_pending = database::totp::has_second_factor(conn, user.clone())
+        .map_err(|err| format!("failed to query second factor: {}", err))?;
+
+    let session_id = secret::random(SESSION_ID_LENGTH);
+    let expires_at = if second_factor_pending {
+        chrono::Utc::now() + chrono::Duration::minutes(PENDING_SESSION_LIFETIME_MINUTES)
+    } else {
+        chrono::Utc::now() + chrono::Duration::days(SESSION_LIFETIME_DAYS)
+    };
+    database::session::create_session(
+        conn,
+        NewSession {
+            id_hash: secret::hash(&session_id),
+            user,
+            csrf_token: secret::random(CSRF_TOKEN_LENGTH),
+            expires_at: expires_at.naive_utc(),
+            second_factor_pending,
+        },
+    )
+    .map_err(|err| format!("failed to create session: {}", err))?;
+    Ok(NewSessionId {
+        id: session_id,
+        second_factor_pending,
+    })
+}
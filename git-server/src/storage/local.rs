@@ -0,0 +1,178 @@
+use std::{
+    fs::File,
+    io::{Read, Write},
+    path::PathBuf,
+};
+
+use super::{Storage, StorageError};
+
+/// Stores objects as plain files below `root`. There is nothing to presign,
+/// so every transfer goes through git-server.
+#[derive(Debug, Clone)]
+pub struct LocalStorage {
+    root: PathBuf,
+}
+
+impl LocalStorage {
+    pub fn new(root: impl Into<PathBuf>) -> Self {
+        Self { root: root.into() }
+    }
+
+    /// Reads the root directory from `LFS_STORAGE_PATH`, `.lfs` below the
+    /// working directory by default.
+    pub fn from_env() -> Result<Self, StorageError> {
+        let root = std::env::var("LFS_STORAGE_PATH").unwrap_or_else(|_| String::from(".lfs"));
+        std::fs::create_dir_all(&root)?;
+        Ok(Self::new(root))
+    }
+
+    fn path(&self, key: &str) -> PathBuf {
+        self.root.join(key)
+    }
+}
+
+impl Storage for LocalStorage {
+    fn size(&self, key: &str) -> Result<Option<u64>, StorageError> {
+        match std::fs::metadata(self.path(key)) {
+            Ok(metadata) if metadata.is_file() => Ok(Some(metadata.len())),
+            Ok(_) => Ok(None),
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => Ok(None),
+            Err(err) => Err(err.into()),
+        }
+    }
+
+    fn presign_get(&self, _key: &str, _expires_in: u32) -> Result<Option<String>, StorageError> {
+        Ok(None)
+    }
+
+    fn presign_put(&self, _key: &str, _expires_in: u32) -> Result<Option<String>, StorageError> {
+        Ok(None)
+    }
+
+    fn get(&self, key: &str, writer: &mut dyn Write) -> Result<(), StorageError> {
+        let mut file = File::open(self.path(key))?;
+        std::io::copy(&mut file, writer)?;
+        Ok(())
+    }
+
+    fn put(&self, key: &str, reader: &mut dyn Read) -> Result<(), StorageError> {
+        let path = self.path(key);
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+
+        // write next to the destination and rename, so readers never see a
+        // partially written object
+        let partial = path.with_extension(format!("partial-{}", uuid::Uuid::new_v4()));
+        let result = File::create(&partial).and_then(|mut file| {
+            std::io::copy(reader, &mut file)?;
+            file.sync_all()
+        });
+        if let Err(err) = result.and_then(|_| std::fs::rename(&partial, &path)) {
+            let _ = std::fs::remove_file(&partial);
+            return Err(err.into());
+        }
+        Ok(())
+    }
+
+    fn delete(&self, key: &str) -> Result<(), StorageError> {
+        std::fs::remove_file(self.path(key))?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{Cursor, Read};
+
+    use super::{LocalStorage, Storage, StorageError};
+
+    const KEY: &str = "grp/repo.git/lfs/objects/oid";
+
+    /// Yields `data` and then fails, like a client hanging up mid upload.
+    struct FailingReader(Cursor<&'static [u8]>);
+
+    impl Read for FailingReader {
+        fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+            match self.0.read(buf)? {
+                0 => Err(std::io::Error::other("connection reset")),
+                read => Ok(read),
+            }
+        }
+    }
+
+    fn files_below(dir: &std::path::Path) -> Vec<std::path::PathBuf> {
+        let mut files = Vec::new();
+        for entry in std::fs::read_dir(dir).unwrap() {
+            let path = entry.unwrap().path();
+            if path.is_dir() {
+                files.extend(files_below(&path));
+            } else {
+                files.push(path);
+            }
+        }
+        files
+    }
+
+    #[test]
+    fn stores_and_reads_objects() {
+        let dir = tempfile::tempdir().unwrap();
+        let storage = LocalStorage::new(dir.path());
+        assert_eq!(storage.size(KEY).unwrap(), None);
+        assert!(!storage.exists(KEY).unwrap());
+
+        storage.put(KEY, &mut Cursor::new(b"contents")).unwrap();
+        assert_eq!(storage.size(KEY).unwrap(), Some(8));
+        assert!(storage.exists(KEY).unwrap());
+        let mut read = Vec::new();
+        storage.get(KEY, &mut read).unwrap();
+        assert_eq!(read, b"contents");
+
+        storage.delete(KEY).unwrap();
+        assert!(!storage.exists(KEY).unwrap());
+        assert!(matches!(
+            storage.get(KEY, &mut Vec::new()),
+            Err(StorageError::NotFound)
+        ));
+    }
+
+    #[test]
+    fn directories_are_no_objects() {
+        let dir = tempfile::tempdir().unwrap();
+        let storage = LocalStorage::new(dir.path());
+        storage.put(KEY, &mut Cursor::new(b"contents")).unwrap();
+        assert_eq!(storage.size("grp/repo.git").unwrap(), None);
+    }
+
+    #[test]
+    fn failed_puts_leave_nothing_behind() {
+        let dir = tempfile::tempdir().unwrap();
+        let storage = LocalStorage::new(dir.path());
+        let result = storage.put(KEY, &mut FailingReader(Cursor::new(b"partial")));
+        assert!(matches!(result, Err(StorageError::Backend(_))));
+        assert!(!storage.exists(KEY).unwrap());
+        assert!(files_below(dir.path()).is_empty());
+    }
+
+    #[test]
+    fn failed_puts_keep_the_previous_object() {
+        let dir = tempfile::tempdir().unwrap();
+        let storage = LocalStorage::new(dir.path());
+        storage.put(KEY, &mut Cursor::new(b"contents")).unwrap();
+        assert!(storage
+            .put(KEY, &mut FailingReader(Cursor::new(b"other")))
+            .is_err());
+
+        let mut read = Vec::new();
+        storage.get(KEY, &mut read).unwrap();
+        assert_eq!(read, b"contents");
+        assert_eq!(files_below(dir.path()).len(), 1);
+    }
+
+    #[test]
+    fn never_presigns() {
+        let storage = LocalStorage::new("unused");
+        assert_eq!(storage.presign_get(KEY, 60).unwrap(), None);
+        assert_eq!(storage.presign_put(KEY, 60).unwrap(), None);
+    }
+}
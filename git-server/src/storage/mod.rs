@@ -0,0 +1,87 @@
+use std::{
+    fmt::Display,
+    io::{Read, Write},
+    sync::Arc,
+};
+
+use common::{Oid, RepoPath};
+
+pub use local::LocalStorage;
+pub use s3::S3Storage;
+
+pub mod stream;
+
+mod local;
+mod s3;
+
+#[derive(Debug)]
+pub enum StorageError {
+    NotFound,
+    Config(String),
+    Backend(String),
+}
+
+impl Display for StorageError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            StorageError::NotFound => write!(f, "object not found"),
+            StorageError::Config(msg) => write!(f, "storage misconfigured: {}", msg),
+            StorageError::Backend(msg) => write!(f, "storage backend failed: {}", msg),
+        }
+    }
+}
+
+impl std::error::Error for StorageError {}
+
+impl From<std::io::Error> for StorageError {
+    fn from(err: std::io::Error) -> Self {
+        match err.kind() {
+            std::io::ErrorKind::NotFound => StorageError::NotFound,
+            _ => StorageError::Backend(err.to_string()),
+        }
+    }
+}
+
+/// Where LFS objects live. All methods block, so call them from `web::block`.
+pub trait Storage: Send + Sync {
+    /// Size of the object in bytes, or `None` if there is no such object.
+    fn size(&self, key: &str) -> Result<Option<u64>, StorageError>;
+
+    fn exists(&self, key: &str) -> Result<bool, StorageError> {
+        Ok(self.size(key)?.is_some())
+    }
+
+    /// A url clients can download the object from directly, or `None` if the
+    /// download has to be proxied through git-server.
+    fn presign_get(&self, key: &str, expires_in: u32) -> Result<Option<String>, StorageError>;
+
+    /// A url clients can upload the object to directly, or `None` if the
+    /// upload has to be proxied through git-server.
+    fn presign_put(&self, key: &str, expires_in: u32) -> Result<Option<String>, StorageError>;
+
+    fn get(&self, key: &str, writer: &mut dyn Write) -> Result<(), StorageError>;
+
+    fn put(&self, key: &str, reader: &mut dyn Read) -> Result<(), StorageError>;
+
+    fn delete(&self, key: &str) -> Result<(), StorageError>;
+}
+
+pub fn object_key(repo_path: &RepoPath, oid: &Oid) -> String {
+    format!("{}/lfs/objects/{}", repo_path, oid)
+}
+
+/// Picks the backend named by `LFS_STORAGE`, `s3` unless told otherwise.
+pub fn from_env() -> Result<Arc<dyn Storage>, StorageError> {
+    match std::env::var("LFS_STORAGE")
+        .unwrap_or_else(|_| String::from("s3"))
+        .to_lowercase()
+        .as_str()
+    {
+        "s3" => Ok(Arc::new(S3Storage::from_env()?)),
+        "local" => Ok(Arc::new(LocalStorage::from_env()?)),
+        other => Err(StorageError::Config(format!(
+            "unknown storage backend: {}",
+            other
+        ))),
+    }
+}
@@ -0,0 +1,369 @@
+use std::io::{Read, Write};
+
+use s3::{
+    blocking::AttoRequest,
+    bucket::CHUNK_SIZE,
+    command::{Command, Multipart},
+    creds::Credentials,
+    request_trait::Request,
+    serde_types::{CompleteMultipartUploadData, InitiateMultipartUploadResponse, Part},
+    Bucket, Region,
+};
+
+use super::{Storage, StorageError};
+
+fn backend_error(err: impl std::fmt::Display) -> StorageError {
+    StorageError::Backend(err.to_string())
+}
+
+fn env(name: &str) -> Result<String, StorageError> {
+    std::env::var(name).map_err(|_| StorageError::Config(format!("{} is not set", name)))
+}
+
+fn check_status(status: u16) -> Result<(), StorageError> {
+    match status {
+        200..=299 => Ok(()),
+        404 => Err(StorageError::NotFound),
+        _ => Err(StorageError::Backend(format!(
+            "unexpected status code {}",
+            status
+        ))),
+    }
+}
+
+/// How long the url `get` downloads from is valid, it only has to outlive
+/// the start of the transfer.
+const DOWNLOAD_EXPIRES_IN: u32 = 300;
+
+/// Reads up to one part of a multipart upload.
+fn read_part(reader: &mut dyn Read) -> Result<Vec<u8>, StorageError> {
+    let mut part = Vec::with_capacity(CHUNK_SIZE);
+    reader.take(CHUNK_SIZE as u64).read_to_end(&mut part)?;
+    Ok(part)
+}
+
+#[derive(Debug, Clone)]
+pub struct S3Storage {
+    bucket: Bucket,
+}
+
+impl S3Storage {
+    pub fn new(bucket: Bucket) -> Self {
+        Self { bucket }
+    }
+
+    pub fn from_env() -> Result<Self, StorageError> {
+        let credentials =
+            Credentials::from_env().map_err(|err| StorageError::Config(err.to_string()))?;
+        let mut bucket = Bucket::new_with_path_style(
+            env("AWS_BUCKET_NAME")?.as_str(),
+            Region::Custom {
+                region: env("AWS_ENDPOINT_REGION")?,
+                endpoint: env("AWS_ENDPOINT_PREFIX")?,
+            },
+            credentials,
+        )
+        .map_err(|err| StorageError::Config(err.to_string()))?;
+        bucket.set_subdomain_style();
+
+        Ok(Self::new(bucket))
+    }
+
+    fn initiate_upload(&self, key: &str) -> Result<String, StorageError> {
+        let request = AttoRequest::new(&self.bucket, key, Command::InitiateMultipartUpload);
+        let (body, status) = request.response_data(false).map_err(backend_error)?;
+        check_status(status)?;
+        let response: InitiateMultipartUploadResponse =
+            serde_xml_rs::from_reader(body.as_slice()).map_err(backend_error)?;
+        Ok(response.upload_id)
+    }
+
+    fn upload_parts(
+        &self,
+        key: &str,
+        upload_id: &str,
+        first: Vec<u8>,
+        reader: &mut dyn Read,
+    ) -> Result<(), StorageError> {
+        let mut parts = Vec::new();
+        let mut part = first;
+        loop {
+            let command = Command::PutObject {
+                content: &part,
+                content_type: "application/octet-stream",
+                multipart: Some(Multipart::new(parts.len() as u32 + 1, upload_id)),
+            };
+            let (etag, status) = AttoRequest::new(&self.bucket, key, command)
+                .response_data(true)
+                .map_err(backend_error)?;
+            check_status(status)?;
+            parts.push(Part {
+                part_number: parts.len() as u32 + 1,
+                etag: String::from_utf8_lossy(&etag).into_owned(),
+            });
+
+            if part.len() < CHUNK_SIZE {
+                break;
+            }
+            part = read_part(reader)?;
+            if part.is_empty() {
+                break;
+            }
+        }
+
+        let command = Command::CompleteMultipartUpload {
+            upload_id,
+            data: CompleteMultipartUploadData { parts },
+        };
+        let (_, status) = AttoRequest::new(&self.bucket, key, command)
+            .response_data(false)
+            .map_err(backend_error)?;
+        check_status(status)
+    }
+}
+
+impl Storage for S3Storage {
+    fn size(&self, key: &str) -> Result<Option<u64>, StorageError> {
+        let (head, status) = self.bucket.head_object(key).map_err(backend_error)?;
+        match check_status(status) {
+            Ok(()) => Ok(Some(head.content_length.unwrap_or_default() as u64)),
+            Err(StorageError::NotFound) => Ok(None),
+            Err(err) => Err(err),
+        }
+    }
+
+    fn presign_get(&self, key: &str, expires_in: u32) -> Result<Option<String>, StorageError> {
+        self.bucket
+            .presign_get(key, expires_in)
+            .map(Some)
+            .map_err(backend_error)
+    }
+
+    fn presign_put(&self, key: &str, expires_in: u32) -> Result<Option<String>, StorageError> {
+        self.bucket
+            .presign_put(key, expires_in, None)
+            .map(Some)
+            .map_err(backend_error)
+    }
+
+    fn get(&self, key: &str, writer: &mut dyn Write) -> Result<(), StorageError> {
+        // the blocking get_object_stream of rust-s3 reads the whole body into
+        // memory before writing it, so fetch a presigned url instead and copy
+        // the body as it arrives, once the status says it is the object
+        let url = self
+            .bucket
+            .presign_get(key, DOWNLOAD_EXPIRES_IN)
+            .map_err(backend_error)?;
+        let response = attohttpc::get(url).send().map_err(backend_error)?;
+        let (status, _, body) = response.split();
+        check_status(status.as_u16())?;
+        body.write_to(writer).map_err(backend_error)?;
+        Ok(())
+    }
+
+    fn put(&self, key: &str, reader: &mut dyn Read) -> Result<(), StorageError> {
+        // put_object_stream of rust-s3 leaves the multipart upload behind when
+        // the reader fails, which it does for every rejected object, so small
+        // objects are uploaded in one go and large ones part by part here
+        let first = read_part(reader)?;
+        if first.len() < CHUNK_SIZE {
+            let (_, status) = self.bucket.put_object(key, &first).map_err(backend_error)?;
+            return check_status(status);
+        }
+
+        let upload_id = self.initiate_upload(key)?;
+        let result = self.upload_parts(key, &upload_id, first, reader);
+        if result.is_err() {
+            if let Err(err) = self.bucket.abort_upload(key, &upload_id) {
+                log::warn!("failed to abort the upload of {}: {}", key, err);
+            }
+        }
+        result
+    }
+
+    fn delete(&self, key: &str) -> Result<(), StorageError> {
+        let (_, status) = self.bucket.delete_object(key).map_err(backend_error)?;
+        check_status(status)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{BufRead, BufReader, Cursor, Read, Write};
+    use std::net::TcpListener;
+    use std::sync::mpsc;
+
+    use actix_web::web::Bytes;
+    use common::Oid;
+    use s3::{bucket::CHUNK_SIZE, creds::Credentials, Bucket, Region};
+    use sha2::{Digest, Sha256};
+
+    use super::{S3Storage, Storage, StorageError};
+    use crate::storage::stream::{ObjectVerifier, VerifyingReader};
+
+    const KEY: &str = "repo.git/lfs/objects/oid";
+
+    const INITIATED: &[u8] = br#"<?xml version="1.0" encoding="UTF-8"?>
+<InitiateMultipartUploadResult><Bucket>lfs</Bucket><Key>repo.git/lfs/objects/oid</Key><UploadId>upload-id</UploadId></InitiateMultipartUploadResult>"#;
+
+    /// A bucket whose endpoint answers one request after the other with
+    /// `responses`, status, extra headers and body, and reports the request
+    /// line of each.
+    fn storage_serving(
+        responses: Vec<(&'static str, &'static str, &'static [u8])>,
+    ) -> (S3Storage, mpsc::Receiver<String>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let endpoint = format!("http://{}", listener.local_addr().unwrap());
+        let (requests, received) = mpsc::channel();
+        std::thread::spawn(move || {
+            for (status, headers, body) in responses {
+                let (stream, _) = listener.accept().unwrap();
+                let mut reader = BufReader::new(stream.try_clone().unwrap());
+                let mut request_line = String::new();
+                reader.read_line(&mut request_line).unwrap();
+                let mut content_length = 0;
+                let mut line = String::new();
+                while reader.read_line(&mut line).unwrap() > 2 {
+                    if let Some((name, value)) = line.split_once(':') {
+                        if name.eq_ignore_ascii_case("content-length") {
+                            content_length = value.trim().parse().unwrap();
+                        }
+                    }
+                    line.clear();
+                }
+                std::io::copy(&mut reader.take(content_length), &mut std::io::sink()).unwrap();
+                let _ = requests.send(request_line.trim_end().to_string());
+
+                let mut stream = stream;
+                write!(
+                    stream,
+                    "HTTP/1.1 {}\r\n{}Content-Length: {}\r\nConnection: close\r\n\r\n",
+                    status,
+                    headers,
+                    body.len()
+                )
+                .unwrap();
+                stream.write_all(body).unwrap();
+            }
+        });
+
+        let credentials = Credentials {
+            access_key: Some(String::from("key")),
+            secret_key: Some(String::from("secret")),
+            security_token: None,
+            session_token: None,
+        };
+        let region = Region::Custom {
+            region: String::from("us-east-1"),
+            endpoint,
+        };
+        let bucket = Bucket::new_with_path_style("lfs", region, credentials).unwrap();
+        (S3Storage::new(bucket), received)
+    }
+
+    /// A bucket whose endpoint answers the next request with `status` and
+    /// `body`.
+    fn storage_answering(status: &'static str, body: &'static [u8]) -> S3Storage {
+        storage_serving(vec![(status, "", body)]).0
+    }
+
+    /// A request body carrying `data` in two chunks, checked against `oid`.
+    fn request_body(data: &[u8], oid: &str) -> VerifyingReader<std::io::Error> {
+        let (mut tx, rx) = futures::channel::mpsc::channel(2);
+        let (head, tail) = data.split_at(data.len() / 2);
+        tx.try_send(Ok(Bytes::copy_from_slice(head))).unwrap();
+        tx.try_send(Ok(Bytes::copy_from_slice(tail))).unwrap();
+        VerifyingReader::new(
+            rx,
+            ObjectVerifier::new(oid.parse::<Oid>().unwrap(), Some(data.len() as u64)),
+        )
+    }
+
+    fn oid_of(data: &[u8]) -> String {
+        hex::encode(Sha256::digest(data))
+    }
+
+    #[test]
+    fn get_copies_the_object() {
+        let storage = storage_answering("200 OK", b"object contents");
+        let mut written = Vec::new();
+        storage.get(KEY, &mut written).unwrap();
+        assert_eq!(written, b"object contents");
+    }
+
+    #[test]
+    fn get_maps_missing_objects_without_writing() {
+        let storage = storage_answering("404 Not Found", b"<Error>NoSuchKey</Error>");
+        let mut written = Vec::new();
+        let result = storage.get(KEY, &mut written);
+        assert!(matches!(result, Err(StorageError::NotFound)));
+        assert!(written.is_empty());
+    }
+
+    #[test]
+    fn get_maps_other_failures() {
+        let storage = storage_answering("403 Forbidden", b"<Error>AccessDenied</Error>");
+        let mut written = Vec::new();
+        let result = storage.get(KEY, &mut written);
+        assert!(matches!(result, Err(StorageError::Backend(_))));
+        assert!(written.is_empty());
+    }
+
+    #[test]
+    fn put_uploads_small_objects_at_once() {
+        let (storage, requests) = storage_serving(vec![("200 OK", "", b"")]);
+        storage
+            .put(KEY, &mut Cursor::new(b"object contents"))
+            .unwrap();
+        let requests = requests.try_iter().collect::<Vec<_>>();
+        assert_eq!(requests.len(), 1);
+        assert!(requests[0].starts_with("PUT "));
+        assert!(!requests[0].contains("uploadId"));
+    }
+
+    #[test]
+    fn put_uploads_large_objects_in_parts() {
+        let data = vec![7; CHUNK_SIZE + 1];
+        let (storage, requests) = storage_serving(vec![
+            ("200 OK", "", INITIATED),
+            ("200 OK", "ETag: \"part-1\"\r\n", b""),
+            ("200 OK", "ETag: \"part-2\"\r\n", b""),
+            ("200 OK", "", b""),
+        ]);
+        storage
+            .put(KEY, &mut request_body(&data, &oid_of(&data)))
+            .unwrap();
+        let requests = requests.try_iter().collect::<Vec<_>>();
+        assert_eq!(requests.len(), 4);
+        assert!(requests[0].starts_with("POST ") && requests[0].contains("?uploads "));
+        assert!(requests[1].contains("?partNumber=1&uploadId=upload-id "));
+        assert!(requests[2].contains("?partNumber=2&uploadId=upload-id "));
+        assert!(requests[3].starts_with("POST ") && requests[3].contains("?uploadId=upload-id "));
+    }
+
+    #[test]
+    fn put_aborts_rejected_uploads() {
+        let data = vec![7; CHUNK_SIZE + 1];
+        let (storage, requests) = storage_serving(vec![
+            ("200 OK", "", INITIATED),
+            ("200 OK", "ETag: \"part-1\"\r\n", b""),
+            ("204 No Content", "", b""),
+        ]);
+        let mut body = request_body(&data, &oid_of(b"something else"));
+        let result = storage.put(KEY, &mut body);
+        assert!(matches!(result, Err(StorageError::Backend(_))));
+        assert!(body.mismatch().is_some());
+        let requests = requests.try_iter().collect::<Vec<_>>();
+        assert_eq!(requests.len(), 3);
+        assert!(requests[1].contains("?partNumber=1&uploadId=upload-id "));
+        assert!(requests[2].starts_with("DELETE ") && requests[2].contains("?uploadId=upload-id "));
+    }
+
+    #[test]
+    fn put_sends_nothing_for_rejected_small_objects() {
+        let (storage, requests) = storage_serving(vec![]);
+        let mut body = request_body(b"object contents", &oid_of(b"something else"));
+        assert!(storage.put(KEY, &mut body).is_err());
+        assert!(requests.try_iter().next().is_none());
+    }
+}
@@ -0,0 +1,379 @@
+use std::io::{Read, Write};
+
+use actix_web::web::Bytes;
+use futures::{channel::mpsc, executor::block_on, SinkExt, StreamExt};
+use common::Oid;
+use sha2::{Digest, Sha256};
+
+/// Hashes and counts the bytes of an object as they pass by.
+pub struct ObjectVerifier {
+    hasher: Sha256,
+    size: u64,
+    expected_oid: Oid,
+    expected_size: Option<u64>,
+}
+
+impl ObjectVerifier {
+    pub fn new(expected_oid: Oid, expected_size: Option<u64>) -> Self {
+        Self {
+            hasher: Sha256::new(),
+            size: 0,
+            expected_oid,
+            expected_size,
+        }
+    }
+
+    pub fn update(&mut self, data: &[u8]) -> Result<(), String> {
+        self.hasher.update(data);
+        self.size += data.len() as u64;
+        match self.expected_size {
+            Some(expected) if self.size > expected => Err(format!(
+                "object is larger than the expected {} bytes",
+                expected
+            )),
+            _ => Ok(()),
+        }
+    }
+
+    pub fn finish(&self) -> Result<(), String> {
+        if let Some(expected) = self.expected_size {
+            if self.size != expected {
+                return Err(format!(
+                    "expected {} bytes, got {} bytes",
+                    expected, self.size
+                ));
+            }
+        }
+        let oid = hex::encode(self.hasher.clone().finalize());
+        if oid != self.expected_oid.as_str() {
+            return Err(format!("expected oid {}, got {}", self.expected_oid, oid));
+        }
+        Ok(())
+    }
+}
+
+impl Write for ObjectVerifier {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.update(buf)
+            .map_err(|reason| std::io::Error::new(std::io::ErrorKind::InvalidData, reason))?;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Turns a request body that is fed through a channel into a blocking
+/// reader for [`Storage::put`](super::Storage::put). The reader fails
+/// instead of reporting the end of the body if the object does not match its
+/// oid or size, so backends never commit a bad object.
+pub struct VerifyingReader<E> {
+    chunks: mpsc::Receiver<Result<Bytes, E>>,
+    current: Bytes,
+    verifier: ObjectVerifier,
+    mismatch: Option<String>,
+}
+
+impl<E: std::fmt::Display> VerifyingReader<E> {
+    pub fn new(chunks: mpsc::Receiver<Result<Bytes, E>>, verifier: ObjectVerifier) -> Self {
+        Self {
+            chunks,
+            current: Bytes::new(),
+            verifier,
+            mismatch: None,
+        }
+    }
+
+    /// Why the object was rejected, if it was.
+    pub fn mismatch(&self) -> Option<&str> {
+        self.mismatch.as_deref()
+    }
+
+    fn reject(&mut self, reason: String) -> std::io::Error {
+        let err = std::io::Error::new(std::io::ErrorKind::InvalidData, reason.clone());
+        self.mismatch = Some(reason);
+        err
+    }
+}
+
+impl<E: std::fmt::Display> Read for VerifyingReader<E> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        while self.current.is_empty() {
+            match block_on(self.chunks.next()) {
+                Some(Ok(chunk)) => {
+                    if let Err(reason) = self.verifier.update(&chunk) {
+                        return Err(self.reject(reason));
+                    }
+                    self.current = chunk;
+                }
+                Some(Err(err)) => {
+                    return Err(std::io::Error::other(format!(
+                        "failed to read request body: {}",
+                        err
+                    )))
+                }
+                None => {
+                    return match self.verifier.finish() {
+                        Ok(()) => Ok(0),
+                        Err(reason) => Err(self.reject(reason)),
+                    }
+                }
+            }
+        }
+
+        let len = buf.len().min(self.current.len());
+        buf[..len].copy_from_slice(&self.current.split_to(len));
+        Ok(len)
+    }
+}
+
+/// A blocking writer for [`Storage::get`](super::Storage::get) that sends
+/// what it is given to a response body stream. The stream ends with an error
+/// if the stored object does not match its oid or size.
+pub struct VerifyingWriter {
+    chunks: mpsc::Sender<Result<Bytes, std::io::Error>>,
+    verifier: ObjectVerifier,
+}
+
+impl VerifyingWriter {
+    pub fn new(
+        chunks: mpsc::Sender<Result<Bytes, std::io::Error>>,
+        verifier: ObjectVerifier,
+    ) -> Self {
+        Self { chunks, verifier }
+    }
+
+    fn send(&mut self, item: Result<Bytes, std::io::Error>) -> std::io::Result<()> {
+        block_on(self.chunks.send(item))
+            .map_err(|_| std::io::Error::new(std::io::ErrorKind::BrokenPipe, "client went away"))
+    }
+
+    /// Checks the object once the backend is done writing it.
+    pub fn finish(mut self) -> std::io::Result<()> {
+        match self.verifier.finish() {
+            Ok(()) => Ok(()),
+            Err(reason) => {
+                let err = std::io::Error::new(std::io::ErrorKind::InvalidData, reason.clone());
+                self.send(Err(std::io::Error::new(
+                    std::io::ErrorKind::InvalidData,
+                    reason,
+                )))?;
+                Err(err)
+            }
+        }
+    }
+}
+
+impl Write for VerifyingWriter {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        if let Err(reason) = self.verifier.update(buf) {
+            self.send(Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                reason.clone(),
+            )))?;
+            return Err(std::io::Error::new(std::io::ErrorKind::InvalidData, reason));
+        }
+        self.send(Ok(Bytes::copy_from_slice(buf)))?;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{Cursor, Read, Write};
+
+    use actix_web::web::Bytes;
+    use common::Oid;
+    use futures::{channel::mpsc, executor::block_on, StreamExt};
+    use sha2::{Digest, Sha256};
+
+    use super::{ObjectVerifier, VerifyingReader, VerifyingWriter};
+    use crate::storage::{LocalStorage, Storage};
+
+    const CONTENTS: &[u8] = b"the contents of an lfs object";
+
+    fn oid_of(data: &[u8]) -> Oid {
+        hex::encode(Sha256::digest(data)).parse().unwrap()
+    }
+
+    /// A reader over `chunks`, like the upload handler feeds it the body.
+    fn reader(chunks: &[&'static [u8]], expected_size: u64) -> VerifyingReader<String> {
+        let (mut sender, receiver) = mpsc::channel(chunks.len());
+        for chunk in chunks {
+            sender.try_send(Ok(Bytes::from_static(chunk))).unwrap();
+        }
+        VerifyingReader::new(
+            receiver,
+            ObjectVerifier::new(oid_of(CONTENTS), Some(expected_size)),
+        )
+    }
+
+    /// Writes `data` like a backend serving a download, returning what the
+    /// response body stream got and whether the writer accepted it.
+    fn write(data: &[u8], expected_size: u64) -> (Vec<Result<Bytes, String>>, bool) {
+        let (sender, receiver) = mpsc::channel(16);
+        let mut writer = VerifyingWriter::new(
+            sender,
+            ObjectVerifier::new(oid_of(CONTENTS), Some(expected_size)),
+        );
+        let accepted = writer.write_all(data).and_then(|_| writer.finish()).is_ok();
+        let sent = block_on(
+            receiver
+                .map(|item| item.map_err(|err| err.to_string()))
+                .collect(),
+        );
+        (sent, accepted)
+    }
+
+    #[test]
+    fn verifier_accepts_matching_objects() {
+        let mut verifier = ObjectVerifier::new(oid_of(CONTENTS), Some(CONTENTS.len() as u64));
+        verifier.update(&CONTENTS[..10]).unwrap();
+        verifier.update(&CONTENTS[10..]).unwrap();
+        verifier.finish().unwrap();
+
+        let mut unsized_verifier = ObjectVerifier::new(oid_of(CONTENTS), None);
+        unsized_verifier.update(CONTENTS).unwrap();
+        unsized_verifier.finish().unwrap();
+    }
+
+    #[test]
+    fn verifier_rejects_size_mismatches() {
+        let mut verifier = ObjectVerifier::new(oid_of(CONTENTS), Some(4));
+        assert!(verifier.update(CONTENTS).is_err());
+
+        let mut verifier = ObjectVerifier::new(oid_of(CONTENTS), Some(CONTENTS.len() as u64 + 1));
+        verifier.update(CONTENTS).unwrap();
+        assert!(verifier.finish().is_err());
+    }
+
+    #[test]
+    fn verifier_rejects_hash_mismatches() {
+        let mut verifier = ObjectVerifier::new(oid_of(b"other"), Some(CONTENTS.len() as u64));
+        verifier.update(CONTENTS).unwrap();
+        assert!(verifier.finish().unwrap_err().contains("expected oid"));
+    }
+
+    #[test]
+    fn reader_passes_matching_bodies() {
+        let mut reader = reader(&[&CONTENTS[..7], &CONTENTS[7..]], CONTENTS.len() as u64);
+        let mut read = Vec::new();
+        reader.read_to_end(&mut read).unwrap();
+        assert_eq!(read, CONTENTS);
+        assert_eq!(reader.mismatch(), None);
+    }
+
+    #[test]
+    fn reader_rejects_short_bodies() {
+        let mut reader = reader(&[&CONTENTS[..7]], CONTENTS.len() as u64);
+        let err = reader.read_to_end(&mut Vec::new()).unwrap_err();
+        assert_eq!(err.kind(), std::io::ErrorKind::InvalidData);
+        assert!(reader.mismatch().unwrap().contains("bytes"));
+    }
+
+    #[test]
+    fn reader_rejects_long_bodies() {
+        let mut reader = reader(&[CONTENTS, b"trailing"], CONTENTS.len() as u64);
+        assert!(reader.read_to_end(&mut Vec::new()).is_err());
+        assert!(reader.mismatch().unwrap().contains("larger"));
+    }
+
+    #[test]
+    fn reader_rejects_hash_mismatches() {
+        let mut reader = reader(&[b"the contents of an lfs objecT"], CONTENTS.len() as u64);
+        assert!(reader.read_to_end(&mut Vec::new()).is_err());
+        assert!(reader.mismatch().unwrap().contains("expected oid"));
+    }
+
+    #[test]
+    fn reader_fails_on_body_errors() {
+        let (mut sender, receiver) = mpsc::channel(2);
+        sender.try_send(Ok(Bytes::from_static(CONTENTS))).unwrap();
+        sender
+            .try_send(Err(String::from("client went away")))
+            .unwrap();
+        let mut reader =
+            VerifyingReader::new(receiver, ObjectVerifier::new(oid_of(CONTENTS), None));
+        assert!(reader.read_to_end(&mut Vec::new()).is_err());
+        assert_eq!(reader.mismatch(), None);
+    }
+
+    #[test]
+    fn rejected_uploads_are_not_stored() {
+        let dir = tempfile::tempdir().unwrap();
+        let storage = LocalStorage::new(dir.path());
+        let mut rejected = reader(&[b"the contents of an lfs objecT"], CONTENTS.len() as u64);
+        assert!(storage.put("object", &mut rejected).is_err());
+        assert!(!storage.exists("object").unwrap());
+
+        let mut accepted = reader(&[CONTENTS], CONTENTS.len() as u64);
+        storage.put("object", &mut accepted).unwrap();
+        assert_eq!(storage.size("object").unwrap(), Some(CONTENTS.len() as u64));
+    }
+
+    #[test]
+    fn writer_streams_matching_objects() {
+        let (sent, accepted) = write(CONTENTS, CONTENTS.len() as u64);
+        assert!(accepted);
+        let body: Vec<u8> = sent
+            .into_iter()
+            .flat_map(|chunk| chunk.unwrap().to_vec())
+            .collect();
+        assert_eq!(body, CONTENTS);
+    }
+
+    #[test]
+    fn writer_ends_short_objects_with_an_error() {
+        let (sent, accepted) = write(&CONTENTS[..7], CONTENTS.len() as u64);
+        assert!(!accepted);
+        assert!(sent.last().unwrap().is_err());
+    }
+
+    #[test]
+    fn writer_stops_long_objects() {
+        let (sent, accepted) = write(CONTENTS, 7);
+        assert!(!accepted);
+        assert_eq!(sent.len(), 1);
+        assert!(sent[0].as_ref().unwrap_err().contains("larger"));
+    }
+
+    #[test]
+    fn writer_ends_hash_mismatches_with_an_error() {
+        let (sent, accepted) = write(b"the contents of an lfs objecT", CONTENTS.len() as u64);
+        assert!(!accepted);
+        assert!(sent[0].is_ok());
+        let last = sent.last().unwrap().as_ref();
+        assert!(last.unwrap_err().contains("expected oid"));
+    }
+
+    #[test]
+    fn writer_reports_clients_going_away() {
+        let (sender, receiver) = mpsc::channel(0);
+        drop(receiver);
+        let mut writer = VerifyingWriter::new(sender, ObjectVerifier::new(oid_of(CONTENTS), None));
+        let err = writer.write(CONTENTS).unwrap_err();
+        assert_eq!(err.kind(), std::io::ErrorKind::BrokenPipe);
+    }
+
+    #[test]
+    fn storage_get_feeds_the_writer() {
+        let dir = tempfile::tempdir().unwrap();
+        let storage = LocalStorage::new(dir.path());
+        storage.put("object", &mut Cursor::new(CONTENTS)).unwrap();
+
+        let (sender, receiver) = mpsc::channel(16);
+        let mut writer = VerifyingWriter::new(
+            sender,
+            ObjectVerifier::new(oid_of(CONTENTS), Some(CONTENTS.len() as u64)),
+        );
+        storage.get("object", &mut writer).unwrap();
+        writer.finish().unwrap();
+        let sent: Vec<_> = block_on(receiver.collect());
+        assert!(sent.iter().all(Result::is_ok));
+    }
+}
@@ -1,21 +1,43 @@
-use std::fs::Metadata;
 
 use askama::Template;
 use git2::ObjectType;
 
+use crate::session::{Session, SessionUser};
+
 #[derive(Template)]
 #[template(path = "_base.html")]
 pub struct BaseTemplate {
     pub title: Option<String>,
+    /// Shown in the navbar, with a sign out button.
+    pub viewer: Option<SessionUser>,
+}
+
+impl Default for BaseTemplate {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl BaseTemplate {
     pub fn new() -> Self {
-        Self { title: None }
+        Self {
+            title: None,
+            viewer: None,
+        }
     }
 
     pub fn with_title(self, title: String) -> Self {
-        Self { title: Some(title) }
+        Self {
+            title: Some(title),
+            ..self
+        }
+    }
+
+    pub fn with_session(self, session: &Session) -> Self {
+        Self {
+            viewer: session.0.clone(),
+            ..self
+        }
     }
 }
 
@@ -60,10 +82,89 @@ pub struct GitBlobPage {
 #[derive(Template)]
 #[template(path = "file_browser.html")]
 pub struct FileBrowserPage {
-    pub metadata: Metadata,
-    pub rootfs: String,
+    pub _parent: BaseTemplate,
+    /// The directory being browsed, empty for the root.
     pub path: String,
-    pub realpath: String,
-    pub content: Option<String>,
+    /// Groups and repositories right below `path`.
     pub entries: Vec<String>,
 }
+
+#[derive(Template)]
+#[template(path = "login.html")]
+pub struct LoginPage {
+    pub _parent: BaseTemplate,
+
+    pub csrf_token: String,
+    /// Where to go after signing in.
+    pub next: String,
+    pub username: String,
+    pub error: Option<String>,
+    pub password_login: bool,
+    /// The name of the single sign-on button, if OpenID Connect is set up.
+    pub sso: Option<String>,
+}
+
+#[derive(Template)]
+#[template(path = "login_totp.html")]
+pub struct TotpLoginPage {
+    pub _parent: BaseTemplate,
+
+    pub csrf_token: String,
+    pub next: String,
+    pub error: Option<String>,
+}
+
+/// A secret being enrolled, shown as a QR code and as text.
+pub struct TotpEnrollment {
+    pub qr_svg: String,
+    pub secret: String,
+}
+
+#[derive(Template)]
+#[template(path = "security.html")]
+pub struct SecurityPage {
+    pub _parent: BaseTemplate,
+
+    pub csrf_token: String,
+    pub enrolled: bool,
+    pub recovery_codes_left: i64,
+    pub enrollment: Option<TotpEnrollment>,
+    /// Recovery codes just generated, shown this once.
+    pub recovery_codes: Vec<String>,
+    pub error: Option<String>,
+}
+
+/// A web session of the signed in user.
+pub struct SessionEntry {
+    pub id_hash: String,
+    pub created_at: chrono::NaiveDateTime,
+    pub expires_at: chrono::NaiveDateTime,
+    /// The session the page is viewed with.
+    pub current: bool,
+}
+
+#[derive(Template)]
+#[template(path = "tokens.html")]
+pub struct TokensPage {
+    pub _parent: BaseTemplate,
+
+    pub csrf_token: String,
+    pub access_tokens: Vec<database::models::AccessToken>,
+    /// Active tokens minted by git-lfs-authenticate.
+    pub issued_tokens: Vec<database::models::IssuedToken>,
+    pub sessions: Vec<SessionEntry>,
+    pub message: Option<String>,
+}
+
+#[derive(Template)]
+#[template(path = "audit.html")]
+pub struct AuditPage {
+    pub _parent: BaseTemplate,
+
+    pub query: crate::handlers::AuditQuery,
+    pub entries: Vec<database::models::AuditEntry>,
+    /// The next page, if there are more entries.
+    pub older_url: Option<String>,
+    pub export_url: String,
+    pub error: Option<String>,
+}
@@ -0,0 +1,322 @@
+use clap::Subcommand;
+use common::PublicKey;
+use database::audit;
+use database::connection::DbConnection;
+use database::models::NewPublicKey;
+use diesel::Connection;
+use serde::Serialize;
+use std::path::{Path, PathBuf};
+
+use crate::output::{table, Output};
+use crate::user::find_user;
+use crate::{internal, record_event, AdminError};
+
+#[derive(Debug, Subcommand)]
+pub enum KeyCommand {
+    /// Registers every key of a .pub or authorized_keys file to a user
+    Add { username: String, file: PathBuf },
+    /// Removes a key of a user, by its SHA-256 or MD5 fingerprint
+    Remove {
+        username: String,
+        fingerprint: String,
+    },
+    /// Lists the keys of a user
+    List { username: String },
+    /// Records the blob and SHA-256 fingerprint of keys registered by their
+    /// MD5 fingerprint only, from a .pub or authorized_keys file
+    Backfill { file: PathBuf },
+}
+
+#[derive(Debug, Serialize)]
+pub struct KeyView {
+    /// `None` for keys registered by their MD5 fingerprint that were never
+    /// used since.
+    pub sha256_fingerprint: Option<String>,
+    pub md5_fingerprint: String,
+    pub key_type: Option<String>,
+}
+
+impl From<database::models::PublicKey> for KeyView {
+    fn from(key: database::models::PublicKey) -> Self {
+        Self {
+            sha256_fingerprint: key.sha256_fingerprint,
+            md5_fingerprint: key.fingerprint,
+            key_type: key.key_type,
+        }
+    }
+}
+
+fn key_table(keys: &[KeyView]) -> String {
+    let rows: Vec<Vec<String>> = keys
+        .iter()
+        .map(|key| {
+            vec![
+                key.sha256_fingerprint.clone().unwrap_or_default(),
+                key.md5_fingerprint.clone(),
+                key.key_type.clone().unwrap_or_default(),
+            ]
+        })
+        .collect();
+    table(&["SHA256", "MD5", "TYPE"], &rows)
+}
+
+fn read_keys(file: &Path) -> Result<Vec<PublicKey>, AdminError> {
+    let contents = std::fs::read_to_string(file).map_err(|err| {
+        AdminError::Invalid(format!("failed to read {}: {}", file.display(), err))
+    })?;
+    let keys = PublicKey::parse_file(&contents)
+        .map_err(|err| AdminError::Invalid(format!("{}: {}", file.display(), err)))?;
+    if keys.is_empty() {
+        return Err(AdminError::Invalid(format!(
+            "{} holds no public key",
+            file.display()
+        )));
+    }
+    Ok(keys)
+}
+
+pub fn run(conn: &DbConnection, command: KeyCommand) -> Result<Output, AdminError> {
+    match command {
+        KeyCommand::Add { username, file } => {
+            let user = find_user(conn, &username)?;
+            let keys = read_keys(&file)?;
+            for key in &keys {
+                if database::authorization::query_key_registered(conn, key).map_err(internal)? {
+                    return Err(AdminError::Invalid(format!(
+                        "the key {} is registered already",
+                        key.sha256_fingerprint
+                    )));
+                }
+            }
+            conn.transaction(|| {
+                for key in &keys {
+                    database::user::create_public_key(
+                        conn,
+                        NewPublicKey {
+                            fingerprint: key.md5_fingerprint.clone(),
+                            user: user.uuid.clone(),
+                            key_type: Some(key.key_type.clone()),
+                            key_data: Some(key.key_data.clone()),
+                            sha256_fingerprint: Some(key.sha256_fingerprint.clone()),
+                        },
+                    )?;
+                }
+                Ok::<_, diesel::result::Error>(())
+            })
+            .map_err(internal)?;
+            for key in &keys {
+                record_event(conn, audit::KEY_ADD, |event| {
+                    event.detail(format!("{} for {}", key.sha256_fingerprint, username))
+                })?;
+            }
+
+            let added: Vec<KeyView> = keys
+                .into_iter()
+                .map(|key| KeyView {
+                    sha256_fingerprint: Some(key.sha256_fingerprint),
+                    md5_fingerprint: key.md5_fingerprint,
+                    key_type: Some(key.key_type),
+                })
+                .collect();
+            let fingerprints: Vec<&str> = added
+                .iter()
+                .filter_map(|key| key.sha256_fingerprint.as_deref())
+                .collect();
+            Ok(Output::new(
+                format!("registered {} to {}", fingerprints.join(", "), username),
+                &added,
+            ))
+        }
+        KeyCommand::Remove {
+            username,
+            fingerprint,
+        } => {
+            let user = find_user(conn, &username)?;
+            let key = database::user::query_public_keys_by_user(conn, user.uuid.clone())
+                .map_err(internal)?
+                .into_iter()
+                .find(|key| {
+                    key.fingerprint == fingerprint
+                        || key.sha256_fingerprint.as_deref() == Some(fingerprint.as_str())
+                })
+                .ok_or_else(|| {
+                    AdminError::Invalid(format!("{} has no key {}", username, fingerprint))
+                })?;
+            // tokens handed to the key stay good until they expire otherwise
+            conn.transaction(|| {
+                database::user::delete_public_key(conn, user.uuid, fingerprint.clone())?;
+                database::issued_token::revoke_tokens_by_key(conn, key.fingerprint.clone())?;
+                if let Some(sha256_fingerprint) = &key.sha256_fingerprint {
+                    database::issued_token::revoke_tokens_by_key(conn, sha256_fingerprint.clone())?;
+                }
+                Ok::<_, diesel::result::Error>(())
+            })
+            .map_err(internal)?;
+            record_event(conn, audit::KEY_REMOVE, |event| {
+                event.detail(format!("{} of {}", fingerprint, username))
+            })?;
+            Ok(Output::new(
+                format!("removed {} of {}", fingerprint, username),
+                &KeyView::from(key),
+            ))
+        }
+        KeyCommand::List { username } => {
+            let user = find_user(conn, &username)?;
+            let keys: Vec<KeyView> = database::user::query_public_keys_by_user(conn, user.uuid)
+                .map_err(internal)?
+                .into_iter()
+                .map(KeyView::from)
+                .collect();
+            Ok(Output::new(key_table(&keys), &keys))
+        }
+        KeyCommand::Backfill { file } => {
+            let mut filled = Vec::new();
+            for key in read_keys(&file)? {
+                let updated = database::user::backfill_public_key(
+                    conn,
+                    key.md5_fingerprint.clone(),
+                    key.key_type.clone(),
+                    key.key_data.clone(),
+                    key.sha256_fingerprint.clone(),
+                )
+                .map_err(internal)?;
+                if updated == 0 {
+                    continue;
+                }
+                record_event(conn, audit::KEY_BACKFILL, |event| {
+                    event.detail(format!(
+                        "{} as {}",
+                        key.md5_fingerprint, key.sha256_fingerprint
+                    ))
+                })?;
+                filled.push(KeyView {
+                    sha256_fingerprint: Some(key.sha256_fingerprint),
+                    md5_fingerprint: key.md5_fingerprint,
+                    key_type: Some(key.key_type),
+                });
+            }
+            let text = match filled.is_empty() {
+                true => String::from("no key registered by its MD5 fingerprint only matches"),
+                false => key_table(&filled),
+            };
+            Ok(Output::new(text, &filled))
+        }
+    }
+}
+
+#[cfg(all(test, feature = "sqlite"))]
+mod tests {
+    use super::*;
+    use crate::testing::TestDatabase;
+
+    const ED25519: &str =
+        "ssh-ed25519 AAAAC3NzaC1lZDI1NTE5AAAAIE/hcVWfNPmcwACIWWLf8C0PEZaSYYeVI7dWj8xIJIwX alice@laptop\n";
+    const SHA256: &str = "SHA256:FMAC7eebBGD/Gw3gL1+joP0s2+TbMR7onM0CmHVADwA";
+    const MD5: &str = "ee:16:f5:c2:f6:30:7f:cb:19:55:11:a4:ce:b3:0e:fb";
+
+    fn add(db: &TestDatabase, username: &str) -> Result<Output, AdminError> {
+        run(
+            &db.conn(),
+            KeyCommand::Add {
+                username: String::from(username),
+                file: db.file("alice.pub", ED25519),
+            },
+        )
+    }
+
+    fn remove(db: &TestDatabase, fingerprint: &str) -> Result<Output, AdminError> {
+        run(
+            &db.conn(),
+            KeyCommand::Remove {
+                username: String::from("alice"),
+                fingerprint: String::from(fingerprint),
+            },
+        )
+    }
+
+    #[test]
+    fn adds_keys() {
+        let db = TestDatabase::new();
+        let alice = db.user("alice");
+
+        let added = add(&db, "alice").unwrap();
+        assert_eq!(added.text(), format!("registered {} to alice", SHA256));
+        assert_eq!(added.json()[0]["sha256_fingerprint"], SHA256);
+        assert_eq!(added.json()[0]["md5_fingerprint"], MD5);
+        assert_eq!(added.json()[0]["key_type"], "ssh-ed25519");
+        let keys = database::user::query_public_keys_by_user(&*db.conn(), alice).unwrap();
+        assert_eq!(keys.len(), 1);
+        assert_eq!(keys[0].fingerprint, MD5);
+
+        let listed = run(
+            &db.conn(),
+            KeyCommand::List {
+                username: String::from("alice"),
+            },
+        )
+        .unwrap();
+        assert!(listed.text().contains(SHA256));
+        assert_eq!(listed.json().as_array().unwrap().len(), 1);
+    }
+
+    #[test]
+    fn refuses_registered_keys() {
+        let db = TestDatabase::new();
+        let alice = db.user("alice");
+        let bob = db.user("bob");
+        add(&db, "alice").unwrap();
+
+        for username in ["alice", "bob"] {
+            match add(&db, username) {
+                Err(AdminError::Invalid(message)) => {
+                    assert_eq!(message, format!("the key {} is registered already", SHA256))
+                }
+                Err(err) => panic!("failed to add the key to {}: {}", username, err),
+                Ok(_) => panic!("added the key again to {}", username),
+            }
+        }
+        let keys =
+            |user: String| database::user::query_public_keys_by_user(&*db.conn(), user).unwrap();
+        assert_eq!(keys(alice).len(), 1);
+        assert!(keys(bob).is_empty());
+    }
+
+    #[test]
+    fn removes_keys() {
+        for fingerprint in [SHA256, MD5] {
+            let db = TestDatabase::new();
+            let alice = db.user("alice");
+            add(&db, "alice").unwrap();
+
+            let removed = remove(&db, fingerprint).unwrap();
+            assert_eq!(removed.text(), format!("removed {} of alice", fingerprint));
+            assert_eq!(removed.json()["sha256_fingerprint"], SHA256);
+            assert!(
+                database::user::query_public_keys_by_user(&*db.conn(), alice)
+                    .unwrap()
+                    .is_empty()
+            );
+
+            assert!(matches!(
+                remove(&db, fingerprint),
+                Err(AdminError::Invalid(_))
+            ));
+        }
+    }
+
+    #[test]
+    fn removing_keys_revokes_their_tokens() {
+        for fingerprint in [SHA256, MD5] {
+            let db = TestDatabase::new();
+            let alice = db.user("alice");
+            add(&db, "alice").unwrap();
+            // sshd hands over either fingerprint, depending on its version
+            db.issue_token(&alice, SHA256);
+            db.issue_token(&alice, MD5);
+            db.issue_token(&alice, "SHA256:another-key");
+
+            remove(&db, fingerprint).unwrap();
+            assert_eq!(db.active_tokens(&alice), 1, "removed by {}", fingerprint);
+        }
+    }
+}
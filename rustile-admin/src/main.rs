@@ -0,0 +1,103 @@
+use clap::{Parser, Subcommand};
+use database::audit::{Actor, Event};
+use database::connection::DbConnection;
+use std::fmt::Display;
+
+use output::Output;
+
+mod key;
+mod output;
+mod repo;
+#[cfg(all(test, feature = "sqlite"))]
+mod testing;
+mod user;
+
+/// Manages users, their ssh keys and repositories in the database named by
+/// `DATABASE_URL`, reading `/etc/git-server.env` like the ssh helpers.
+#[derive(Parser)]
+#[clap(author, version, about)]
+struct Cli {
+    /// Prints JSON instead of text
+    #[clap(long, global = true)]
+    json: bool,
+
+    #[clap(subcommand)]
+    command: Command,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Adds, lists, disables and deletes users
+    #[clap(subcommand)]
+    User(user::UserCommand),
+    /// Registers and removes the ssh keys of users
+    #[clap(subcommand)]
+    Key(key::KeyCommand),
+    /// Registers repositories and grants access to them
+    #[clap(subcommand)]
+    Repo(repo::RepoCommand),
+}
+
+#[derive(Debug)]
+pub enum AdminError {
+    /// Arguments naming nothing that exists, or clashing with what does.
+    Invalid(String),
+    Internal(String),
+}
+
+impl Display for AdminError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            AdminError::Invalid(message) => f.write_str(message),
+            AdminError::Internal(message) => write!(f, "internal error: {}", message),
+        }
+    }
+}
+
+pub fn internal(err: impl Display) -> AdminError {
+    AdminError::Internal(err.to_string())
+}
+
+/// Whoever runs rustile-admin, by the login they came from when they
+/// switched to the git user with sudo.
+fn operator() -> Actor {
+    let login = std::env::var("SUDO_USER")
+        .or_else(|_| std::env::var("USER"))
+        .unwrap_or_else(|_| String::from("unknown"));
+    Actor::Operator(login)
+}
+
+/// Appends a change made by the operator to the audit log.
+pub fn record_event(
+    conn: &DbConnection,
+    action: &'static str,
+    detail: impl FnOnce(Event) -> Event,
+) -> Result<(), AdminError> {
+    let event = Event::new(action, operator(), database::audit::Outcome::Success);
+    database::audit::record(conn, detail(event)).map_err(internal)?;
+    Ok(())
+}
+
+fn run(command: Command) -> Result<Output, AdminError> {
+    let conn = database::connection::from_env().map_err(internal)?;
+    database::migrations::check_migrations(&conn).map_err(internal)?;
+    match command {
+        Command::User(command) => user::run(&conn, command),
+        Command::Key(command) => key::run(&conn, command),
+        Command::Repo(command) => repo::run(&conn, command),
+    }
+}
+
+fn main() {
+    dotenv::dotenv().ok();
+    dotenv::from_path("/etc/git-server.env").ok();
+
+    let args = Cli::parse();
+    match run(args.command) {
+        Ok(output) => output.print(args.json),
+        Err(err) => {
+            eprintln!("error: {}", err);
+            std::process::exit(1);
+        }
+    }
+}
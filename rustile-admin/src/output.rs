@@ -0,0 +1,62 @@
+use serde::Serialize;
+
+/// What a command prints, as text for people or as JSON with `--json`.
+pub struct Output {
+    text: String,
+    json: serde_json::Value,
+}
+
+impl Output {
+    pub fn new(text: impl Into<String>, json: &impl Serialize) -> Self {
+        Self {
+            text: text.into(),
+            json: serde_json::to_value(json).unwrap_or(serde_json::Value::Null),
+        }
+    }
+
+    #[cfg(all(test, feature = "sqlite"))]
+    pub fn text(&self) -> &str {
+        &self.text
+    }
+
+    #[cfg(all(test, feature = "sqlite"))]
+    pub fn json(&self) -> &serde_json::Value {
+        &self.json
+    }
+
+    pub fn print(&self, json: bool) {
+        if json {
+            println!("{}", self.json);
+        } else if !self.text.is_empty() {
+            println!("{}", self.text.trim_end());
+        }
+    }
+}
+
+/// Lines `rows` up below `header`, in columns two spaces apart.
+pub fn table(header: &[&str], rows: &[Vec<String>]) -> String {
+    let mut widths: Vec<usize> = header.iter().map(|title| title.len()).collect();
+    for row in rows {
+        for (width, cell) in widths.iter_mut().zip(row) {
+            *width = (*width).max(cell.chars().count());
+        }
+    }
+
+    let line = |cells: Vec<&str>| {
+        let padded: Vec<String> = cells
+            .iter()
+            .zip(&widths)
+            .map(|(cell, width)| format!("{:width$}", cell, width = width))
+            .collect();
+        format!("{}\n", padded.join("  ").trim_end())
+    };
+    let mut text = line(header.to_vec());
+    for row in rows {
+        text.push_str(&line(row.iter().map(String::as_str).collect()));
+    }
+    text
+}
+
+pub fn datetime(datetime: &chrono::NaiveDateTime) -> String {
+    datetime.format("%Y-%m-%d %H:%M:%S").to_string()
+}
@@ -0,0 +1,331 @@
+use clap::Subcommand;
+use common::{Access, RepoPath};
+use database::audit;
+use database::connection::DbConnection;
+use database::models::Repository;
+use database::repository::Grantee;
+use serde::Serialize;
+use std::path::PathBuf;
+
+use crate::output::{datetime, table, Output};
+use crate::user::find_user;
+use crate::{internal, record_event, AdminError};
+
+#[derive(Debug, Subcommand)]
+pub enum RepoCommand {
+    /// Registers a repository below $HOME of the git user, so it is served
+    Create {
+        /// Like group/project.git, the .git may be left out
+        path: String,
+
+        /// Lets anyone read the repository, even anonymously
+        #[clap(long)]
+        public: bool,
+
+        /// Also creates the bare repository with git init --bare
+        #[clap(long)]
+        init: bool,
+    },
+    /// Lists the registered repositories and who has access to them
+    List,
+    /// Grants a user or a group read, write or admin access, replacing
+    /// what they had
+    Grant {
+        path: String,
+
+        access: Access,
+
+        #[clap(long, conflicts_with = "group", required_unless_present = "group")]
+        user: Option<String>,
+
+        #[clap(long)]
+        group: Option<String>,
+    },
+}
+
+#[derive(Debug, Serialize)]
+pub struct PermissionView {
+    /// The username, for permissions granted to a user.
+    pub user: Option<String>,
+    /// The group name, for permissions granted to a group.
+    pub group: Option<String>,
+    pub access: String,
+}
+
+impl PermissionView {
+    /// Groups are told apart from users by an `@`.
+    fn grantee(&self) -> String {
+        match (&self.user, &self.group) {
+            (Some(user), _) => user.clone(),
+            (_, Some(group)) => format!("@{}", group),
+            (None, None) => String::from("?"),
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct RepositoryView {
+    pub path: String,
+    pub is_public: bool,
+    pub created_at: chrono::NaiveDateTime,
+    pub permissions: Vec<PermissionView>,
+}
+
+/// Normalizes `raw` like rustile-shell does, without requiring the
+/// repository to exist on disk.
+fn normalize(raw: &str) -> Result<String, AdminError> {
+    let raw = raw.strip_prefix("~/").unwrap_or(raw).trim_end_matches('/');
+    let raw = match raw.ends_with(".git") {
+        true => String::from(raw),
+        false => format!("{}.git", raw),
+    };
+    RepoPath::normalize(&raw).map_err(|err| AdminError::Invalid(err.to_string()))
+}
+
+fn find_repository(conn: &DbConnection, path: &str) -> Result<Repository, AdminError> {
+    database::repository::query_repository(conn, normalize(path)?)
+        .map_err(internal)?
+        .ok_or_else(|| AdminError::Invalid(format!("{} is not registered", path)))
+}
+
+fn repository_view(
+    conn: &DbConnection,
+    repository: Repository,
+) -> Result<RepositoryView, AdminError> {
+    let mut permissions = Vec::new();
+    for permission in
+        database::repository::query_permissions(conn, repository.path.clone()).map_err(internal)?
+    {
+        let user = match permission.user {
+            Some(user) => database::user::query_username_by_id(conn, user).map_err(internal)?,
+            None => None,
+        };
+        let group = match permission.group {
+            Some(group) => database::group::query_group_by_id(conn, group)
+                .map_err(internal)?
+                .map(|group| group.name),
+            None => None,
+        };
+        permissions.push(PermissionView {
+            user,
+            group,
+            access: permission.access,
+        });
+    }
+    Ok(RepositoryView {
+        path: repository.path,
+        is_public: repository.is_public,
+        created_at: repository.created_at,
+        permissions,
+    })
+}
+
+/// Creates the bare repository at `path` below $HOME, unless there is one.
+fn init_bare(path: &str) -> Result<(), AdminError> {
+    let home = std::env::var("HOME").map_err(|_| internal("HOME is not set"))?;
+    let directory = PathBuf::from(home).join(path);
+    if directory.join("HEAD").is_file() {
+        return Ok(());
+    }
+    let status = std::process::Command::new("git")
+        .args(["init", "--quiet", "--bare"])
+        .arg(&directory)
+        .status()
+        .map_err(|err| internal(format!("failed to run git: {}", err)))?;
+    if !status.success() {
+        return Err(internal(format!(
+            "git init --bare {} failed",
+            directory.display()
+        )));
+    }
+    Ok(())
+}
+
+pub fn run(conn: &DbConnection, command: RepoCommand) -> Result<Output, AdminError> {
+    match command {
+        RepoCommand::Create { path, public, init } => {
+            let path = normalize(&path)?;
+            if database::repository::query_repository(conn, path.clone())
+                .map_err(internal)?
+                .is_some()
+            {
+                return Err(AdminError::Invalid(format!(
+                    "{} is registered already",
+                    path
+                )));
+            }
+            if init {
+                init_bare(&path)?;
+            }
+            database::repository::create_repository(conn, path.clone(), public)
+                .map_err(internal)?;
+            record_event(conn, audit::REPOSITORY_CREATE, |event| {
+                let event = event.repository(&path);
+                match public {
+                    true => event.detail("public"),
+                    false => event,
+                }
+            })?;
+            let repository = repository_view(conn, find_repository(conn, &path)?)?;
+            Ok(Output::new(format!("registered {}", path), &repository))
+        }
+        RepoCommand::List => {
+            let mut repositories = Vec::new();
+            for repository in database::repository::query_repositories(conn).map_err(internal)? {
+                repositories.push(repository_view(conn, repository)?);
+            }
+            let rows: Vec<Vec<String>> = repositories
+                .iter()
+                .map(|repository| {
+                    let access: Vec<String> = repository
+                        .permissions
+                        .iter()
+                        .map(|permission| format!("{}:{}", permission.grantee(), permission.access))
+                        .collect();
+                    vec![
+                        repository.path.clone(),
+                        String::from(if repository.is_public {
+                            "public"
+                        } else {
+                            "private"
+                        }),
+                        datetime(&repository.created_at),
+                        access.join(", "),
+                    ]
+                })
+                .collect();
+            Ok(Output::new(
+                table(&["PATH", "VISIBILITY", "CREATED", "ACCESS"], &rows),
+                &repositories,
+            ))
+        }
+        RepoCommand::Grant {
+            path,
+            access,
+            user,
+            group,
+        } => {
+            let repository = find_repository(conn, &path)?;
+            let (grantee, name) = match (user, group) {
+                (Some(username), _) => (Grantee::User(find_user(conn, &username)?.uuid), username),
+                (_, Some(name)) => {
+                    let group = database::group::query_group_by_name(conn, name.clone())
+                        .map_err(internal)?
+                        .ok_or_else(|| {
+                            AdminError::Invalid(format!("there is no group {}", name))
+                        })?;
+                    (Grantee::Group(group.uuid), format!("@{}", name))
+                }
+                (None, None) => {
+                    return Err(AdminError::Invalid(String::from(
+                        "name a --user or a --group",
+                    )))
+                }
+            };
+            database::repository::grant_permission(conn, repository.path.clone(), grantee, access)
+                .map_err(internal)?;
+            record_event(conn, audit::PERMISSION_GRANT, |event| {
+                event
+                    .repository(&repository.path)
+                    .detail(format!("{} to {}", access, name))
+            })?;
+            let text = format!(
+                "granted {} access on {} to {}",
+                access, repository.path, name
+            );
+            Ok(Output::new(text, &repository_view(conn, repository)?))
+        }
+    }
+}
+
+#[cfg(all(test, feature = "sqlite"))]
+mod tests {
+    use super::*;
+    use crate::testing::TestDatabase;
+
+    fn grant(
+        db: &TestDatabase,
+        access: Access,
+        user: Option<&str>,
+        group: Option<&str>,
+    ) -> Result<Output, AdminError> {
+        run(
+            &db.conn(),
+            RepoCommand::Grant {
+                path: String::from("grp/repo"),
+                access,
+                user: user.map(String::from),
+                group: group.map(String::from),
+            },
+        )
+    }
+
+    #[test]
+    fn grants_access() {
+        let db = TestDatabase::new();
+        db.user("alice");
+        database::group::create_group(&*db.conn(), String::from("developers")).unwrap();
+        let created = run(
+            &db.conn(),
+            RepoCommand::Create {
+                path: String::from("grp/repo"),
+                public: false,
+                init: false,
+            },
+        )
+        .unwrap();
+        assert_eq!(created.text(), "registered grp/repo.git");
+        assert_eq!(created.json()["path"], "grp/repo.git");
+
+        let granted = grant(&db, Access::Write, Some("alice"), None).unwrap();
+        assert_eq!(
+            granted.text(),
+            "granted write access on grp/repo.git to alice"
+        );
+        assert_eq!(
+            granted.json()["permissions"],
+            serde_json::json!([{"user": "alice", "group": null, "access": "write"}])
+        );
+
+        // grants replace what the grantee had
+        grant(&db, Access::Read, Some("alice"), None).unwrap();
+        let granted = grant(&db, Access::Admin, None, Some("developers")).unwrap();
+        assert_eq!(
+            granted.text(),
+            "granted admin access on grp/repo.git to @developers"
+        );
+        let mut permissions = granted.json()["permissions"].as_array().unwrap().clone();
+        permissions.sort_by_key(|permission| permission["user"].is_null());
+        assert_eq!(
+            permissions,
+            [
+                serde_json::json!({"user": "alice", "group": null, "access": "read"}),
+                serde_json::json!({"user": null, "group": "developers", "access": "admin"}),
+            ]
+        );
+
+        let listed = run(&db.conn(), RepoCommand::List).unwrap();
+        assert!(listed.text().contains("alice:read"));
+        assert!(listed.text().contains("@developers:admin"));
+        assert_eq!(listed.json()[0]["permissions"].as_array().unwrap().len(), 2);
+    }
+
+    #[test]
+    fn refuses_unknown_grantees() {
+        let db = TestDatabase::new();
+        assert!(matches!(
+            grant(&db, Access::Read, Some("alice"), None),
+            Err(AdminError::Invalid(_))
+        ));
+        database::repository::create_repository(&*db.conn(), String::from("grp/repo.git"), false)
+            .unwrap();
+        assert!(matches!(
+            grant(&db, Access::Read, Some("alice"), None),
+            Err(AdminError::Invalid(_))
+        ));
+        assert!(matches!(
+            grant(&db, Access::Read, None, Some("developers")),
+            Err(AdminError::Invalid(_))
+        ));
+    }
+}
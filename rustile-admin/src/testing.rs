@@ -0,0 +1,65 @@
+//! A fresh SQLite database in a temporary directory, for the tests of the
+//! commands.
+
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use database::connection::{ConnectionPool, PooledConnection};
+use database::models::NewIssuedToken;
+use tempfile::TempDir;
+
+static NEXT_JTI: AtomicUsize = AtomicUsize::new(0);
+
+pub struct TestDatabase {
+    pool: ConnectionPool,
+    dir: TempDir,
+}
+
+impl TestDatabase {
+    pub fn new() -> Self {
+        let dir = tempfile::tempdir().unwrap();
+        let url = dir.path().join("rustile.sqlite");
+        let pool = database::connection::pool(url.to_str().unwrap()).unwrap();
+        database::migrations::run_pending_migrations(&pool.get().unwrap()).unwrap();
+        Self { pool, dir }
+    }
+
+    pub fn conn(&self) -> PooledConnection {
+        self.pool.get().unwrap()
+    }
+
+    /// Creates the user `username`, returning their uuid.
+    pub fn user(&self, username: &str) -> String {
+        database::user::create_user(&*self.conn(), String::from(username)).unwrap()
+    }
+
+    /// Writes `contents` to the file `name` of the temporary directory.
+    pub fn file(&self, name: &str, contents: &str) -> std::path::PathBuf {
+        let path = self.dir.path().join(name);
+        std::fs::write(&path, contents).unwrap();
+        path
+    }
+
+    /// Records an LFS token handed to `user` through the key `key_fingerprint`.
+    pub fn issue_token(&self, user: &str, key_fingerprint: &str) {
+        database::issued_token::record_issued_token(
+            &*self.conn(),
+            NewIssuedToken {
+                jti: format!("jti-{}", NEXT_JTI.fetch_add(1, Ordering::Relaxed)),
+                user: Some(String::from(user)),
+                key_fingerprint: String::from(key_fingerprint),
+                is_deploy_key: false,
+                repository: String::from("grp/repo.git"),
+                operation: String::from("upload"),
+                expires_at: (chrono::Utc::now() + chrono::Duration::minutes(5)).naive_utc(),
+            },
+        )
+        .unwrap();
+    }
+
+    /// The number of unrevoked tokens of `user`.
+    pub fn active_tokens(&self, user: &str) -> usize {
+        database::issued_token::query_active_tokens_by_user(&*self.conn(), String::from(user))
+            .unwrap()
+            .len()
+    }
+}
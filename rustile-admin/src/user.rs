@@ -0,0 +1,440 @@
+use clap::Subcommand;
+use database::audit;
+use database::connection::DbConnection;
+use database::models::User;
+use diesel::result::{DatabaseErrorKind, Error as DieselError};
+use diesel::Connection;
+use serde::Serialize;
+
+use crate::output::{datetime, table, Output};
+use crate::{internal, record_event, AdminError};
+
+#[derive(Debug, Subcommand)]
+pub enum UserCommand {
+    /// Creates a user, without keys or a password
+    Add {
+        username: String,
+
+        /// Gives the user admin access to every repository
+        #[clap(long)]
+        admin: bool,
+    },
+    /// Lists all users
+    List {
+        /// Lists the deleted users waiting to be purged instead
+        #[clap(long)]
+        deleted: bool,
+    },
+    /// Keeps a user from signing in and from using their keys and tokens
+    Disable { username: String },
+    /// Lets a disabled user in again
+    Enable { username: String },
+    /// Deletes a user, who loses all access until they are restored
+    Delete { username: String },
+    /// Brings back the most recently deleted user with this username
+    Restore { username: String },
+    /// Removes users deleted longer ago than the retention window for good,
+    /// with their keys, tokens, sessions and locks
+    Purge {
+        #[clap(long, default_value = "30")]
+        retention_days: u32,
+    },
+    /// Removes the user guochao and their two keys, which early versions
+    /// seeded into every installation, with whatever hangs off them
+    RemoveLegacySeed,
+}
+
+#[derive(Debug, Serialize)]
+pub struct UserView {
+    pub uuid: String,
+    pub username: String,
+    pub is_admin: bool,
+    pub disabled_at: Option<chrono::NaiveDateTime>,
+    pub deleted_at: Option<chrono::NaiveDateTime>,
+    pub created_at: chrono::NaiveDateTime,
+}
+
+impl From<User> for UserView {
+    fn from(user: User) -> Self {
+        Self {
+            uuid: user.uuid,
+            username: user.username,
+            is_admin: user.is_admin,
+            disabled_at: user.disabled_at,
+            deleted_at: user.deleted_at,
+            created_at: user.created_at,
+        }
+    }
+}
+
+pub fn find_user(conn: &DbConnection, username: &str) -> Result<User, AdminError> {
+    database::user::query_user_by_username(conn, String::from(username))
+        .map_err(internal)?
+        .ok_or_else(|| AdminError::Invalid(format!("there is no user {}", username)))
+}
+
+/// Refuses the usernames [`common::is_valid_username`] does not accept.
+fn check_username(username: &str) -> Result<(), AdminError> {
+    if !common::is_valid_username(username) {
+        return Err(AdminError::Invalid(format!(
+            "'{}' is no valid username, it may not be empty nor contain whitespace, quotes, commas or slashes",
+            username
+        )));
+    }
+    Ok(())
+}
+
+/// Shows when users were deleted rather than disabled, for `deleted` users.
+fn user_table(users: &[UserView], deleted: bool) -> String {
+    let rows: Vec<Vec<String>> = users
+        .iter()
+        .map(|user| {
+            let removed_at = match deleted {
+                true => &user.deleted_at,
+                false => &user.disabled_at,
+            };
+            vec![
+                user.username.clone(),
+                user.uuid.clone(),
+                String::from(if user.is_admin { "yes" } else { "" }),
+                removed_at.as_ref().map(datetime).unwrap_or_default(),
+                datetime(&user.created_at),
+            ]
+        })
+        .collect();
+    let header = match deleted {
+        true => ["USERNAME", "UUID", "ADMIN", "DELETED", "CREATED"],
+        false => ["USERNAME", "UUID", "ADMIN", "DISABLED", "CREATED"],
+    };
+    table(&header, &rows)
+}
+
+fn set_disabled(
+    conn: &DbConnection,
+    username: String,
+    disabled: bool,
+) -> Result<Output, AdminError> {
+    let user = find_user(conn, &username)?;
+    conn.transaction(|| {
+        database::user::set_disabled(conn, user.uuid.clone(), disabled)?;
+        if disabled {
+            database::issued_token::revoke_tokens_by_user(conn, user.uuid)?;
+        }
+        Ok::<_, diesel::result::Error>(())
+    })
+    .map_err(internal)?;
+    let action = match disabled {
+        true => audit::USER_DISABLE,
+        false => audit::USER_ENABLE,
+    };
+    record_event(conn, action, |event| event.detail(username.clone()))?;
+    let user = UserView::from(find_user(conn, &username)?);
+    let text = match disabled {
+        true => format!("disabled the user {}", user.username),
+        false => format!("enabled the user {}", user.username),
+    };
+    Ok(Output::new(text, &user))
+}
+
+pub fn run(conn: &DbConnection, command: UserCommand) -> Result<Output, AdminError> {
+    match command {
+        UserCommand::Add { username, admin } => {
+            check_username(&username)?;
+            if database::user::query_user_id_by_username(conn, username.clone())
+                .map_err(internal)?
+                .is_some()
+            {
+                return Err(AdminError::Invalid(format!(
+                    "the username {} is taken",
+                    username
+                )));
+            }
+            let uuid = database::user::create_user(conn, username.clone()).map_err(internal)?;
+            if admin {
+                database::user::set_admin(conn, uuid.clone(), true).map_err(internal)?;
+            }
+            record_event(conn, audit::USER_CREATE, |event| {
+                event.detail(match admin {
+                    true => format!("{}, admin", username),
+                    false => username.clone(),
+                })
+            })?;
+            let user = UserView::from(find_user(conn, &username)?);
+            Ok(Output::new(
+                format!("created the user {} ({})", user.username, user.uuid),
+                &user,
+            ))
+        }
+        UserCommand::List { deleted } => {
+            let users = match deleted {
+                true => database::user::query_deleted_users(conn),
+                false => database::user::query_users(conn),
+            };
+            let users: Vec<UserView> = users
+                .map_err(internal)?
+                .into_iter()
+                .map(UserView::from)
+                .collect();
+            Ok(Output::new(user_table(&users, deleted), &users))
+        }
+        UserCommand::Disable { username } => set_disabled(conn, username, true),
+        UserCommand::Enable { username } => set_disabled(conn, username, false),
+        UserCommand::Delete { username } => {
+            let user = find_user(conn, &username)?;
+            conn.transaction(|| {
+                database::user::delete_user(conn, user.uuid.clone())?;
+                database::issued_token::revoke_tokens_by_user(conn, user.uuid.clone())
+            })
+            .map_err(internal)?;
+            record_event(conn, audit::USER_DELETE, |event| {
+                event.detail(format!("{} ({})", username, user.uuid))
+            })?;
+            let user = UserView::from(user);
+            Ok(Output::new(
+                format!("deleted the user {}", user.username),
+                &user,
+            ))
+        }
+        UserCommand::Restore { username } => {
+            let user = database::user::query_deleted_user_by_username(conn, username.clone())
+                .map_err(internal)?
+                .ok_or_else(|| {
+                    AdminError::Invalid(format!("there is no deleted user {}", username))
+                })?;
+            match database::user::restore_user(conn, user.uuid.clone()) {
+                Ok(_) => {}
+                Err(DieselError::DatabaseError(DatabaseErrorKind::UniqueViolation, _)) => {
+                    return Err(AdminError::Invalid(format!(
+                        "the username {} is taken",
+                        username
+                    )))
+                }
+                Err(err) => return Err(internal(err)),
+            }
+            record_event(conn, audit::USER_RESTORE, |event| {
+                event.detail(format!("{} ({})", username, user.uuid))
+            })?;
+            let user = UserView::from(find_user(conn, &username)?);
+            Ok(Output::new(
+                format!("restored the user {}", user.username),
+                &user,
+            ))
+        }
+        UserCommand::Purge { retention_days } => {
+            let before =
+                chrono::Utc::now().naive_utc() - chrono::Duration::days(retention_days.into());
+            let users: Vec<UserView> = database::user::purge_deleted_users(conn, before)
+                .map_err(internal)?
+                .into_iter()
+                .map(UserView::from)
+                .collect();
+            for user in &users {
+                record_event(conn, audit::USER_PURGE, |event| {
+                    event.detail(format!("{} ({})", user.username, user.uuid))
+                })?;
+            }
+            let text = match users.is_empty() {
+                true => String::from("no user to purge"),
+                false => format!("purged {} users\n{}", users.len(), user_table(&users, true)),
+            };
+            Ok(Output::new(text, &users))
+        }
+        UserCommand::RemoveLegacySeed => {
+            let removed = database::instance::remove_legacy_seed(conn).map_err(internal)?;
+            if removed {
+                record_event(conn, audit::USER_PURGE, |event| {
+                    event.detail(format!(
+                        "legacy seed guochao ({})",
+                        database::instance::LEGACY_SEED_USER
+                    ))
+                })?;
+            }
+            let text = match removed {
+                true => "removed the seeded user guochao and their keys",
+                false => "the seeded user and keys are gone already",
+            };
+            Ok(Output::new(
+                text,
+                &serde_json::json!({ "removed": removed }),
+            ))
+        }
+    }
+}
+
+#[cfg(all(test, feature = "sqlite"))]
+mod tests {
+    use super::*;
+    use crate::testing::TestDatabase;
+
+    #[test]
+    fn adds_users() {
+        let db = TestDatabase::new();
+        let added = run(
+            &db.conn(),
+            UserCommand::Add {
+                username: String::from("alice"),
+                admin: true,
+            },
+        )
+        .unwrap();
+        let uuid = added.json()["uuid"].as_str().unwrap();
+        assert_eq!(added.text(), format!("created the user alice ({})", uuid));
+        assert_eq!(added.json()["username"], "alice");
+        assert_eq!(added.json()["is_admin"], true);
+        assert!(database::user::query_is_admin(&*db.conn(), String::from(uuid)).unwrap());
+
+        for username in ["alice", "bad name", ""] {
+            let refused = run(
+                &db.conn(),
+                UserCommand::Add {
+                    username: String::from(username),
+                    admin: false,
+                },
+            );
+            assert!(
+                matches!(refused, Err(AdminError::Invalid(_))),
+                "added '{}'",
+                username
+            );
+        }
+
+        let listed = run(&db.conn(), UserCommand::List { deleted: false }).unwrap();
+        assert!(listed.text().starts_with("USERNAME"));
+        assert!(listed.text().contains(uuid));
+        assert_eq!(listed.json().as_array().unwrap().len(), 1);
+    }
+
+    #[test]
+    fn disables_and_enables_users() {
+        let db = TestDatabase::new();
+        db.user("alice");
+
+        let disabled = run(
+            &db.conn(),
+            UserCommand::Disable {
+                username: String::from("alice"),
+            },
+        )
+        .unwrap();
+        assert_eq!(disabled.text(), "disabled the user alice");
+        assert!(disabled.json()["disabled_at"].is_string());
+
+        let enabled = run(
+            &db.conn(),
+            UserCommand::Enable {
+                username: String::from("alice"),
+            },
+        )
+        .unwrap();
+        assert_eq!(enabled.text(), "enabled the user alice");
+        assert!(enabled.json()["disabled_at"].is_null());
+
+        assert!(matches!(
+            run(
+                &db.conn(),
+                UserCommand::Disable {
+                    username: String::from("bob")
+                }
+            ),
+            Err(AdminError::Invalid(_))
+        ));
+    }
+
+    #[test]
+    fn deletes_and_restores_users() {
+        let db = TestDatabase::new();
+        let alice = db.user("alice");
+
+        let deleted = run(
+            &db.conn(),
+            UserCommand::Delete {
+                username: String::from("alice"),
+            },
+        )
+        .unwrap();
+        assert_eq!(deleted.text(), "deleted the user alice");
+        assert_eq!(deleted.json()["uuid"], alice.as_str());
+        assert!(find_user(&db.conn(), "alice").is_err());
+
+        let listed = run(&db.conn(), UserCommand::List { deleted: true }).unwrap();
+        assert!(listed.text().contains(&alice));
+        assert_eq!(listed.json()[0]["username"], "alice");
+        assert!(listed.json()[0]["deleted_at"].is_string());
+
+        let restored = run(
+            &db.conn(),
+            UserCommand::Restore {
+                username: String::from("alice"),
+            },
+        )
+        .unwrap();
+        assert_eq!(restored.text(), "restored the user alice");
+        assert!(restored.json()["deleted_at"].is_null());
+        assert_eq!(find_user(&db.conn(), "alice").unwrap().uuid, alice);
+    }
+
+    #[test]
+    fn refuses_to_restore_taken_usernames() {
+        let db = TestDatabase::new();
+        let old = db.user("alice");
+        run(
+            &db.conn(),
+            UserCommand::Delete {
+                username: String::from("alice"),
+            },
+        )
+        .unwrap();
+        let new = db.user("alice");
+
+        match run(
+            &db.conn(),
+            UserCommand::Restore {
+                username: String::from("alice"),
+            },
+        ) {
+            Err(AdminError::Invalid(message)) => assert_eq!(message, "the username alice is taken"),
+            Err(err) => panic!("failed to restore alice: {}", err),
+            Ok(_) => panic!("restored a second alice"),
+        }
+        assert_eq!(find_user(&db.conn(), "alice").unwrap().uuid, new);
+        assert_ne!(old, new);
+    }
+
+    #[test]
+    fn disabling_users_revokes_their_tokens() {
+        let db = TestDatabase::new();
+        let alice = db.user("alice");
+        let bob = db.user("bob");
+        db.issue_token(&alice, "SHA256:alice");
+        db.issue_token(&bob, "SHA256:bob");
+
+        run(
+            &db.conn(),
+            UserCommand::Disable {
+                username: String::from("alice"),
+            },
+        )
+        .unwrap();
+        assert_eq!(db.active_tokens(&alice), 0);
+        assert_eq!(db.active_tokens(&bob), 1);
+    }
+
+    #[test]
+    fn deleting_users_revokes_their_tokens() {
+        let db = TestDatabase::new();
+        let alice = db.user("alice");
+        let bob = db.user("bob");
+        db.issue_token(&alice, "SHA256:alice");
+        db.issue_token(&bob, "SHA256:bob");
+
+        run(
+            &db.conn(),
+            UserCommand::Delete {
+                username: String::from("alice"),
+            },
+        )
+        .unwrap();
+        assert_eq!(db.active_tokens(&alice), 0);
+        assert_eq!(db.active_tokens(&bob), 1);
+    }
+}
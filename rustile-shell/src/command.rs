@@ -0,0 +1,365 @@
+use clap::Parser;
+use common::{Access, Operation};
+
+use crate::ShellError;
+
+/// A command the shell knows how to run, parsed from `SSH_ORIGINAL_COMMAND`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Command {
+    Git(GitCommand),
+    Token(TokenCommand),
+    DeployKey(DeployKeyCommand),
+    /// Sets the password for signing in on the web, read from stdin.
+    Password,
+    /// Removes the second factor of a user who lost it, admins only.
+    TotpReset(String),
+}
+
+/// The commands git and git-lfs run over ssh, all working on a repository.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum GitCommand {
+    UploadPack(String),
+    ReceivePack(String),
+    LfsAuthenticate(String, Operation),
+}
+
+/// Manages the personal access tokens of the connecting user, like
+/// `ssh git@host token create ci --scope write`.
+#[derive(Debug, Clone, PartialEq, Eq, Parser)]
+#[clap(name = "token")]
+pub enum TokenCommand {
+    /// Creates a token, printing it once
+    Create {
+        /// A name to tell the token apart from the others
+        name: String,
+
+        /// The highest access the token grants: read, write or admin
+        #[clap(long, default_value = "read")]
+        scope: Access,
+
+        /// Restricts the token to a single repository
+        #[clap(long)]
+        repo: Option<String>,
+
+        /// Days until the token expires, at most 365
+        #[clap(long, default_value = "30")]
+        expires_in: i64,
+
+        /// A code from your authenticator app, or a recovery code, once
+        /// two-factor authentication is set up
+        #[clap(long)]
+        otp: Option<String>,
+    },
+    /// Lists your tokens, and the git-lfs tokens handed out to you
+    List {
+        /// Lists the tokens of another user, admins only
+        #[clap(long)]
+        user: Option<String>,
+    },
+    /// Revokes a token by its name, or a git-lfs token by its id
+    Revoke {
+        name: String,
+
+        /// Revokes a token of another user, admins only
+        #[clap(long)]
+        user: Option<String>,
+    },
+}
+
+/// Manages the deploy keys of a repository, which takes admin access, like
+/// `ssh git@host deploy-key add group/project.git ci < ci.pub`.
+#[derive(Debug, Clone, PartialEq, Eq, Parser)]
+#[clap(name = "deploy-key")]
+pub enum DeployKeyCommand {
+    /// Adds the public key read from stdin, in `ssh-ed25519 AAAA... comment` form
+    Add {
+        repo: String,
+
+        /// A name to tell the key apart from the others of the repository
+        name: String,
+
+        /// Allows pushing, not only fetching
+        #[clap(long)]
+        write: bool,
+    },
+    /// Lists the deploy keys of a repository
+    List { repo: String },
+    /// Removes a deploy key by its name
+    Remove { repo: String, name: String },
+}
+
+impl Command {
+    /// Parses the command line git sends over ssh, like
+    /// `git-upload-pack 'group/project.git'`. The `git upload-pack` spelling
+    /// is accepted as well.
+    pub fn parse(original: &str) -> Result<Self, ShellError> {
+        let words = shell_words::split(original)
+            .map_err(|_| ShellError::Unsupported(String::from(original)))?;
+        let mut words = words.iter().map(String::as_str);
+
+        let program = match (words.next(), words.clone().next()) {
+            (Some("git"), Some(subcommand)) => {
+                words.next();
+                format!("git-{}", subcommand)
+            }
+            (Some(program), _) => String::from(program),
+            (None, _) => return Err(ShellError::Unsupported(String::from(original))),
+        };
+        let args: Vec<&str> = words.collect();
+
+        match (program.as_str(), args.as_slice()) {
+            ("git-upload-pack", [repo]) => {
+                Ok(Command::Git(GitCommand::UploadPack(String::from(*repo))))
+            }
+            ("git-receive-pack", [repo]) => {
+                Ok(Command::Git(GitCommand::ReceivePack(String::from(*repo))))
+            }
+            ("git-lfs-authenticate", [repo, operation]) => {
+                let operation = operation.parse().map_err(|_| {
+                    ShellError::Usage("git-lfs-authenticate <repo> <download|upload>")
+                })?;
+                Ok(Command::Git(GitCommand::LfsAuthenticate(
+                    String::from(*repo),
+                    operation,
+                )))
+            }
+            ("git-upload-pack", _) => Err(ShellError::Usage("git-upload-pack <repo>")),
+            ("git-receive-pack", _) => Err(ShellError::Usage("git-receive-pack <repo>")),
+            ("git-lfs-authenticate", _) => Err(ShellError::Usage(
+                "git-lfs-authenticate <repo> <download|upload>",
+            )),
+            ("token", _) => TokenCommand::try_parse_from(std::iter::once("token").chain(args))
+                .map(Command::Token)
+                .map_err(|err| ShellError::Invalid(err.to_string())),
+            ("deploy-key", _) => {
+                DeployKeyCommand::try_parse_from(std::iter::once("deploy-key").chain(args))
+                    .map(Command::DeployKey)
+                    .map_err(|err| ShellError::Invalid(err.to_string()))
+            }
+            ("password", []) => Ok(Command::Password),
+            ("password", _) => Err(ShellError::Usage("password < file-with-new-password")),
+            ("totp-reset", [username]) => Ok(Command::TotpReset(String::from(*username))),
+            ("totp-reset", _) => Err(ShellError::Usage("totp-reset <username>")),
+            _ => Err(ShellError::Unsupported(program)),
+        }
+    }
+}
+
+impl GitCommand {
+    pub fn repo(&self) -> &str {
+        match self {
+            GitCommand::UploadPack(repo)
+            | GitCommand::ReceivePack(repo)
+            | GitCommand::LfsAuthenticate(repo, _) => repo,
+        }
+    }
+
+    /// The access the command needs: fetching reads, pushing writes.
+    pub fn operation(&self) -> Operation {
+        match self {
+            GitCommand::UploadPack(_) => Operation::Download,
+            GitCommand::ReceivePack(_) => Operation::Upload,
+            GitCommand::LfsAuthenticate(_, operation) => *operation,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use common::RepoPathError;
+
+    use super::*;
+    use crate::repo_path;
+
+    fn git(original: &str) -> GitCommand {
+        match Command::parse(original) {
+            Ok(Command::Git(command)) => command,
+            other => panic!("{} parsed as {:?}", original, other),
+        }
+    }
+
+    #[test]
+    fn parses_git_commands_as_git_sends_them() {
+        assert_eq!(
+            git("git-upload-pack 'grp/repo.git'"),
+            GitCommand::UploadPack(String::from("grp/repo.git"))
+        );
+        assert_eq!(
+            git("git-receive-pack '/grp/repo.git'"),
+            GitCommand::ReceivePack(String::from("/grp/repo.git"))
+        );
+        assert_eq!(
+            git("git upload-pack \"grp/repo.git\""),
+            GitCommand::UploadPack(String::from("grp/repo.git"))
+        );
+        assert_eq!(
+            git("git-upload-pack 'grp/my repo.git'"),
+            GitCommand::UploadPack(String::from("grp/my repo.git"))
+        );
+    }
+
+    #[test]
+    fn parses_lfs_operations() {
+        assert_eq!(
+            git("git-lfs-authenticate 'grp/repo.git' download"),
+            GitCommand::LfsAuthenticate(String::from("grp/repo.git"), Operation::Download)
+        );
+        let upload = git("git-lfs-authenticate grp/repo.git upload");
+        assert_eq!(upload.repo(), "grp/repo.git");
+        assert_eq!(upload.operation(), Operation::Upload);
+
+        for original in [
+            "git-lfs-authenticate grp/repo.git delete",
+            "git-lfs-authenticate grp/repo.git",
+            "git-lfs-authenticate grp/repo.git upload extra",
+        ] {
+            assert!(
+                matches!(Command::parse(original), Err(ShellError::Usage(_))),
+                "{}",
+                original
+            );
+        }
+    }
+
+    #[test]
+    fn rejects_extra_and_missing_arguments() {
+        for original in [
+            "git-upload-pack",
+            "git-upload-pack grp/repo.git other.git",
+            "git-receive-pack grp/repo.git --upload-pack=touch",
+            "git upload-pack",
+            "password now",
+            "totp-reset",
+        ] {
+            assert!(
+                matches!(Command::parse(original), Err(ShellError::Usage(_))),
+                "{}",
+                original
+            );
+        }
+    }
+
+    #[test]
+    fn rejects_unsupported_commands() {
+        for original in [
+            "",
+            "sh",
+            "git-upload-archive grp/repo.git",
+            "git log",
+            "rm -rf /",
+        ] {
+            assert!(
+                matches!(Command::parse(original), Err(ShellError::Unsupported(_))),
+                "{}",
+                original
+            );
+        }
+    }
+
+    #[test]
+    fn shell_metacharacters_stay_literal() {
+        // without a shell, a trailing command only adds arguments
+        for original in [
+            "git-upload-pack 'grp/repo.git'; touch pwned",
+            "git-upload-pack grp/repo.git && touch pwned",
+            "git-upload-pack grp/repo.git | sh",
+        ] {
+            assert!(
+                matches!(Command::parse(original), Err(ShellError::Usage(_))),
+                "{}",
+                original
+            );
+        }
+        // and substitutions are never expanded
+        assert_eq!(
+            git("git-upload-pack '$(touch pwned).git'"),
+            GitCommand::UploadPack(String::from("$(touch pwned).git"))
+        );
+        assert_eq!(git("git-upload-pack `id`.git").repo(), "`id`.git");
+        // unbalanced quotes cannot be split at all
+        assert!(matches!(
+            Command::parse("git-upload-pack 'grp/repo.git"),
+            Err(ShellError::Unsupported(_))
+        ));
+    }
+
+    #[test]
+    fn repositories_stay_below_the_root() {
+        let root = tempfile::tempdir().unwrap();
+        let repo = root.path().join("grp/repo.git");
+        std::fs::create_dir_all(repo.join("objects")).unwrap();
+        std::fs::create_dir_all(repo.join("refs")).unwrap();
+        std::fs::write(repo.join("HEAD"), "ref: refs/heads/main\n").unwrap();
+
+        for raw in [
+            "grp/repo.git",
+            "/grp/repo.git",
+            "~/grp/repo",
+            "grp/repo.git/",
+        ] {
+            let command = git(&format!("git-upload-pack '{}'", raw));
+            assert_eq!(
+                repo_path(command.repo(), root.path()).unwrap().as_str(),
+                "grp/repo.git"
+            );
+        }
+
+        for raw in ["../grp/repo.git", "grp/../../etc.git", "grp/.hidden.git"] {
+            let command = git(&format!("git-upload-pack '{}'", raw));
+            assert!(
+                matches!(
+                    repo_path(command.repo(), root.path()),
+                    Err(ShellError::Repo(RepoPathError::Invalid(..)))
+                ),
+                "{}",
+                raw
+            );
+        }
+        for raw in ["/etc/passwd", "/etc", "$(touch pwned).git"] {
+            let command = git(&format!("git-upload-pack '{}'", raw));
+            assert!(
+                matches!(
+                    repo_path(command.repo(), root.path()),
+                    Err(ShellError::Repo(RepoPathError::NotFound(_)))
+                ),
+                "{}",
+                raw
+            );
+        }
+    }
+
+    #[test]
+    fn parses_subcommands() {
+        assert_eq!(Command::parse("password").unwrap(), Command::Password);
+        assert_eq!(
+            Command::parse("totp-reset alice").unwrap(),
+            Command::TotpReset(String::from("alice"))
+        );
+        assert_eq!(
+            Command::parse("token create ci --scope write --repo 'grp/repo.git'").unwrap(),
+            Command::Token(TokenCommand::Create {
+                name: String::from("ci"),
+                scope: Access::Write,
+                repo: Some(String::from("grp/repo.git")),
+                expires_in: 30,
+                otp: None,
+            })
+        );
+        assert_eq!(
+            Command::parse("deploy-key add grp/repo.git ci --write").unwrap(),
+            Command::DeployKey(DeployKeyCommand::Add {
+                repo: String::from("grp/repo.git"),
+                name: String::from("ci"),
+                write: true,
+            })
+        );
+        assert!(matches!(
+            Command::parse("token create ci --scope root"),
+            Err(ShellError::Invalid(_))
+        ));
+        assert!(matches!(
+            Command::parse("deploy-key drop grp/repo.git ci"),
+            Err(ShellError::Invalid(_))
+        ));
+    }
+}
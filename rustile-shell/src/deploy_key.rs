@@ -0,0 +1,285 @@
+use common::{Access, PublicKey, RepoPath};
+use database::audit::{self, Actor, Event, Outcome};
+use database::connection::DbConnection;
+use database::models::NewDeployKey;
+use diesel::result::{DatabaseErrorKind, Error as DieselError};
+use std::path::Path;
+
+use crate::command::DeployKeyCommand;
+use crate::{internal, record_event, repo_path, ShellError};
+
+/// Managing deploy keys takes admin access on the repository. Refusals go
+/// to the audit log as `action`.
+fn check_admin(
+    conn: &DbConnection,
+    user: &str,
+    repo: &RepoPath,
+    action: &'static str,
+) -> Result<(), ShellError> {
+    if !database::authorization::authorize(conn, Some(user), repo.as_str(), Access::Admin)
+        .map_err(internal)?
+    {
+        record_event(
+            conn,
+            Event::new(action, Actor::User(String::from(user)), Outcome::Denied).repository(repo),
+        )?;
+        return Err(ShellError::Invalid(format!(
+            "managing the deploy keys of {} takes admin access",
+            repo
+        )));
+    }
+    Ok(())
+}
+
+/// Reads a public key in `authorized_keys` form, `<type> <base64> [comment]`.
+fn read_public_key() -> Result<PublicKey, ShellError> {
+    let mut line = String::new();
+    std::io::stdin().read_line(&mut line).map_err(internal)?;
+    PublicKey::parse(&line).map_err(|err| ShellError::Invalid(err.to_string()))
+}
+
+/// Registers `key` as the deploy key `name` of `repo`, unless it is in use
+/// as a user or deploy key already.
+fn add(
+    conn: &DbConnection,
+    user: &str,
+    repo: &RepoPath,
+    name: &str,
+    access: Access,
+    key: PublicKey,
+) -> Result<(), ShellError> {
+    // user keys win over deploy keys, so such a deploy key would never be used
+    if database::authorization::query_key_registered(conn, &key).map_err(internal)? {
+        return Err(ShellError::Invalid(format!(
+            "the key {} is registered already",
+            key.sha256_fingerprint
+        )));
+    }
+    match database::deploy_key::create_deploy_key(
+        conn,
+        NewDeployKey {
+            sha256_fingerprint: key.sha256_fingerprint.clone(),
+            repository: repo.to_string(),
+            name: String::from(name),
+            key_type: key.key_type,
+            key_data: key.key_data,
+            access: access.to_string(),
+            created_by: Some(String::from(user)),
+        },
+    ) {
+        Ok(_) => Ok(()),
+        Err(DieselError::DatabaseError(DatabaseErrorKind::UniqueViolation, _)) => {
+            Err(ShellError::Invalid(format!(
+                "either {} already has a deploy key named '{}', or the key {} is in use",
+                repo, name, key.sha256_fingerprint
+            )))
+        }
+        Err(err) => Err(internal(err)),
+    }
+}
+
+pub fn run(
+    conn: &DbConnection,
+    user: &str,
+    root: &Path,
+    command: DeployKeyCommand,
+) -> Result<(), ShellError> {
+    match command {
+        DeployKeyCommand::Add { repo, name, write } => {
+            let repo = repo_path(&repo, root)?;
+            check_admin(conn, user, &repo, audit::DEPLOY_KEY_ADD)?;
+            if database::repository::query_repository(conn, repo.to_string())
+                .map_err(internal)?
+                .is_none()
+            {
+                return Err(ShellError::Invalid(format!("{} is not registered", repo)));
+            }
+
+            let key = read_public_key()?;
+            let fingerprint = key.sha256_fingerprint.clone();
+            let access = if write { Access::Write } else { Access::Read };
+            add(conn, user, &repo, &name, access, key)?;
+            record_event(
+                conn,
+                Event::new(
+                    audit::DEPLOY_KEY_ADD,
+                    Actor::User(String::from(user)),
+                    Outcome::Success,
+                )
+                .repository(&repo)
+                .detail(format!("'{}' {} with {} access", name, fingerprint, access)),
+            )?;
+            println!(
+                "added deploy key '{}' {} with {} access to {}",
+                name, fingerprint, access, repo
+            );
+        }
+        DeployKeyCommand::List { repo } => {
+            let repo = repo_path(&repo, root)?;
+            check_admin(conn, user, &repo, audit::DEPLOY_KEY_LIST)?;
+            let keys =
+                database::deploy_key::query_deploy_keys_by_repository(conn, repo.to_string())
+                    .map_err(internal)?;
+            for key in keys {
+                println!(
+                    "{:<24} {:<6} {} {}",
+                    key.name,
+                    key.access,
+                    key.sha256_fingerprint,
+                    key.created_at.format("%Y-%m-%d %H:%M UTC"),
+                );
+            }
+        }
+        DeployKeyCommand::Remove { repo, name } => {
+            let repo = repo_path(&repo, root)?;
+            check_admin(conn, user, &repo, audit::DEPLOY_KEY_REMOVE)?;
+            let deleted =
+                database::deploy_key::delete_deploy_key(conn, repo.to_string(), name.clone())
+                    .map_err(internal)?;
+            if deleted == 0 {
+                return Err(ShellError::Invalid(format!(
+                    "{} has no deploy key named '{}'",
+                    repo, name
+                )));
+            }
+            record_event(
+                conn,
+                Event::new(
+                    audit::DEPLOY_KEY_REMOVE,
+                    Actor::User(String::from(user)),
+                    Outcome::Success,
+                )
+                .repository(&repo)
+                .detail(format!("'{}'", name)),
+            )?;
+            println!("removed deploy key '{}' from {}", name, repo);
+        }
+    }
+    Ok(())
+}
+
+#[cfg(all(test, feature = "sqlite"))]
+mod tests {
+    use database::models::NewPublicKey;
+
+    use super::*;
+
+    const ED25519: &str =
+        "ssh-ed25519 AAAAC3NzaC1lZDI1NTE5AAAAIE/hcVWfNPmcwACIWWLf8C0PEZaSYYeVI7dWj8xIJIwX";
+    const ECDSA: &str = "ecdsa-sha2-nistp256 AAAAE2VjZHNhLXNoYTItbmlzdHAyNTYAAAAIbmlzdHAyNTYAAABBBMisfePqT4spjEM1gVZvD9I2858FWRK456VKl9dLsupq07c3gxRRd7R1WlZlwRqH1cCafYDac3fEolAvibaMCyg=";
+
+    struct Fixture {
+        pool: database::connection::ConnectionPool,
+        alice: String,
+        repo: RepoPath,
+        other: RepoPath,
+        _dir: tempfile::TempDir,
+    }
+
+    fn fixture() -> Fixture {
+        let dir = tempfile::tempdir().unwrap();
+        let url = dir.path().join("rustile.sqlite");
+        let pool = database::connection::pool(url.to_str().unwrap()).unwrap();
+        let conn = pool.get().unwrap();
+        database::migrations::run_pending_migrations(&conn).unwrap();
+        let alice = database::user::create_user(&conn, String::from("alice")).unwrap();
+        let mut repos = Vec::new();
+        for path in ["grp/repo.git", "grp/other.git"] {
+            let git_dir = dir.path().join(path);
+            std::fs::create_dir_all(git_dir.join("objects")).unwrap();
+            std::fs::create_dir_all(git_dir.join("refs")).unwrap();
+            std::fs::write(git_dir.join("HEAD"), "ref: refs/heads/main\n").unwrap();
+            database::repository::create_repository(&conn, String::from(path), false).unwrap();
+            repos.push(crate::repo_path(path, dir.path()).unwrap());
+        }
+        let other = repos.pop().unwrap();
+        let repo = repos.pop().unwrap();
+        drop(conn);
+        Fixture {
+            pool,
+            alice,
+            repo,
+            other,
+            _dir: dir,
+        }
+    }
+
+    #[test]
+    fn adds_deploy_keys() {
+        let fixture = fixture();
+        let conn = fixture.pool.get().unwrap();
+        let key = PublicKey::parse(ED25519).unwrap();
+        add(
+            &conn,
+            &fixture.alice,
+            &fixture.repo,
+            "ci",
+            Access::Read,
+            key.clone(),
+        )
+        .unwrap();
+
+        let stored =
+            database::deploy_key::query_deploy_key_by_fingerprint(&*conn, key.sha256_fingerprint)
+                .unwrap()
+                .unwrap();
+        assert_eq!(stored.repository, "grp/repo.git");
+        assert_eq!(stored.key_type, "ssh-ed25519");
+        assert_eq!(stored.access, "read");
+    }
+
+    #[test]
+    fn refuses_keys_in_use() {
+        let fixture = fixture();
+        let conn = fixture.pool.get().unwrap();
+        let deploy_key = PublicKey::parse(ED25519).unwrap();
+        add(
+            &conn,
+            &fixture.alice,
+            &fixture.repo,
+            "ci",
+            Access::Read,
+            deploy_key.clone(),
+        )
+        .unwrap();
+        // neither on the same nor on another repository
+        for repo in [&fixture.repo, &fixture.other] {
+            assert!(matches!(
+                add(
+                    &conn,
+                    &fixture.alice,
+                    repo,
+                    "again",
+                    Access::Read,
+                    deploy_key.clone()
+                ),
+                Err(ShellError::Invalid(_))
+            ));
+        }
+
+        // registered under its MD5 fingerprint only, as older installations did
+        let user_key = PublicKey::parse(ECDSA).unwrap();
+        database::user::create_public_key(
+            &*conn,
+            NewPublicKey {
+                fingerprint: user_key.md5_fingerprint.clone(),
+                user: fixture.alice.clone(),
+                key_type: None,
+                key_data: None,
+                sha256_fingerprint: None,
+            },
+        )
+        .unwrap();
+        assert!(matches!(
+            add(
+                &conn,
+                &fixture.alice,
+                &fixture.repo,
+                "laptop",
+                Access::Write,
+                user_key
+            ),
+            Err(ShellError::Invalid(_))
+        ));
+    }
+}
@@ -0,0 +1,262 @@
+use common::{Operation, RepoPath, RepoPathError};
+use database::audit::{self, Actor, Event, Outcome};
+use database::authorization::KeyOwner;
+use database::connection::DbConnection;
+use std::{fmt::Display, os::unix::process::CommandExt, path::Path};
+
+use command::{Command, GitCommand};
+
+mod command;
+mod deploy_key;
+mod token;
+
+/// Why a session was refused. The message is what the person on the other
+/// end of the ssh connection gets to see.
+#[derive(Debug)]
+pub enum ShellError {
+    /// `SSH_KEY_FINGERPRINT` is missing, sshd is not set up for us.
+    NotConfigured,
+    UnknownKey(String),
+    Unsupported(String),
+    /// Deploy keys can only fetch and push.
+    DeployKey,
+    Usage(&'static str),
+    /// Arguments rejected by a subcommand, with its own explanation.
+    Invalid(String),
+    Repo(RepoPathError),
+    Forbidden(String, Operation),
+    Internal(String),
+}
+
+impl Display for ShellError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ShellError::NotConfigured => write!(
+                f,
+                "this server is not set up for ssh access, SSH_KEY_FINGERPRINT is missing"
+            ),
+            ShellError::UnknownKey(fingerprint) => write!(
+                f,
+                "the key {} is neither registered to a user nor a deploy key",
+                fingerprint
+            ),
+            ShellError::Unsupported(command) => write!(
+                f,
+                "'{}' is not supported, only git-upload-pack, git-receive-pack, git-lfs-authenticate, token, password, deploy-key and totp-reset are",
+                command
+            ),
+            ShellError::DeployKey => {
+                write!(f, "deploy keys can only run git and git-lfs commands")
+            }
+            ShellError::Usage(usage) => write!(f, "usage: {}", usage),
+            ShellError::Invalid(message) => f.write_str(message.trim_end()),
+            ShellError::Repo(err) => err.fmt(f),
+            ShellError::Forbidden(repo, Operation::Download) => {
+                write!(f, "you are not allowed to read {}", repo)
+            }
+            ShellError::Forbidden(repo, Operation::Upload) => {
+                write!(f, "you are not allowed to write to {}", repo)
+            }
+            ShellError::Internal(message) => write!(f, "internal error: {}", message),
+        }
+    }
+}
+
+impl From<RepoPathError> for ShellError {
+    fn from(err: RepoPathError) -> Self {
+        ShellError::Repo(err)
+    }
+}
+
+pub fn internal<E: Display>(err: E) -> ShellError {
+    ShellError::Internal(err.to_string())
+}
+
+/// Appends `event` to the audit log, with the address of the ssh client.
+pub fn record_event(conn: &DbConnection, event: Event) -> Result<(), ShellError> {
+    database::audit::record(conn, event.ip(audit::ssh_client_ip())).map_err(internal)?;
+    Ok(())
+}
+
+/// Git clients send paths as `group/project.git`, `/group/project.git` or
+/// `~/group/project.git`, and the `.git` suffix is optional.
+pub fn repo_path(raw: &str, root: &Path) -> Result<RepoPath, ShellError> {
+    let raw = raw.strip_prefix("~/").unwrap_or(raw);
+    let raw = raw.trim_end_matches('/');
+    if raw.ends_with(".git") {
+        Ok(RepoPath::parse(raw, root)?)
+    } else {
+        Ok(RepoPath::parse(&format!("{}.git", raw), root)?)
+    }
+}
+
+fn run() -> Result<(), ShellError> {
+    let fingerprint =
+        std::env::var("SSH_KEY_FINGERPRINT").map_err(|_| ShellError::NotConfigured)?;
+
+    let conn = database::connection::from_env().map_err(internal)?;
+    database::migrations::check_migrations(&conn).map_err(internal)?;
+    let owner = database::authorization::query_key_owner(&conn, fingerprint.clone())
+        .map_err(internal)?
+        .ok_or_else(|| ShellError::UnknownKey(fingerprint.clone()))?;
+
+    let original = match std::env::var("SSH_ORIGINAL_COMMAND") {
+        Ok(original) if !original.trim().is_empty() => original,
+        _ => {
+            let username = match &owner {
+                KeyOwner::User(user) => database::user::query_username_by_id(&conn, user.clone())
+                    .map_err(internal)?
+                    .unwrap_or_default(),
+                KeyOwner::DeployKey(_) => String::from("deploy key"),
+            };
+            eprintln!(
+                "Hi {}! You've successfully authenticated, but interactive shell access is not provided.",
+                username
+            );
+            return Ok(());
+        }
+    };
+
+    std::env::set_current_dir(std::env::var("HOME").map_err(internal)?).map_err(internal)?;
+    let root = std::env::current_dir().map_err(internal)?;
+
+    match (owner, Command::parse(&original)?) {
+        (owner, Command::Git(command)) => serve(&conn, owner, &fingerprint, &root, command),
+        (KeyOwner::DeployKey(_), _) => Err(ShellError::DeployKey),
+        (KeyOwner::User(user), Command::Token(command)) => {
+            token::run(&conn, &user, &root, command)
+        }
+        (KeyOwner::User(user), Command::DeployKey(command)) => {
+            deploy_key::run(&conn, &user, &root, command)
+        }
+        (KeyOwner::User(user), Command::Password) => set_password(&conn, user),
+        (KeyOwner::User(user), Command::TotpReset(username)) => {
+            reset_totp(&conn, user, username)
+        }
+    }
+}
+
+/// Lets an admin remove the second factor of someone who lost both their
+/// authenticator and their recovery codes.
+fn reset_totp(conn: &DbConnection, admin: String, username: String) -> Result<(), ShellError> {
+    if !database::user::query_is_admin(conn, admin.clone()).map_err(internal)? {
+        record_event(
+            conn,
+            Event::new(audit::TOTP_RESET, Actor::User(admin), Outcome::Denied)
+                .detail(format!("of {}", username)),
+        )?;
+        return Err(ShellError::Invalid(String::from(
+            "only admins can reset two-factor authentication",
+        )));
+    }
+    let user = database::user::query_user_id_by_username(conn, username.clone())
+        .map_err(internal)?
+        .ok_or_else(|| ShellError::Invalid(format!("there is no user named '{}'", username)))?;
+
+    if database::totp::delete_totp(conn, user).map_err(internal)? == 0 {
+        println!("{} has no two-factor authentication", username);
+    } else {
+        record_event(
+            conn,
+            Event::new(audit::TOTP_RESET, Actor::User(admin), Outcome::Success)
+                .detail(format!("of {}", username)),
+        )?;
+        println!("two-factor authentication reset for {}", username);
+    }
+    Ok(())
+}
+
+/// There is no tty behind the forced command, so the new password comes in
+/// as the first line of stdin: `ssh git@host password < file`.
+fn set_password(conn: &DbConnection, user: String) -> Result<(), ShellError> {
+    let mut password = String::new();
+    std::io::stdin()
+        .read_line(&mut password)
+        .map_err(internal)?;
+    let password = password.trim_end_matches(&['\r', '\n'][..]);
+    if password.chars().count() < common::password::MIN_PASSWORD_LENGTH {
+        return Err(ShellError::Invalid(format!(
+            "passwords need at least {} characters",
+            common::password::MIN_PASSWORD_LENGTH
+        )));
+    }
+
+    let password_hash = common::password::hash_password(password).map_err(internal)?;
+    database::user::set_password_hash(conn, user.clone(), Some(password_hash)).map_err(internal)?;
+    record_event(
+        conn,
+        Event::new(audit::PASSWORD_SET, Actor::User(user), Outcome::Success),
+    )?;
+    println!("password updated");
+    Ok(())
+}
+
+fn serve(
+    conn: &DbConnection,
+    owner: KeyOwner,
+    fingerprint: &str,
+    root: &Path,
+    command: GitCommand,
+) -> Result<(), ShellError> {
+    let repo = repo_path(command.repo(), root)?;
+
+    let action = match command {
+        GitCommand::UploadPack(_) => audit::GIT_FETCH,
+        GitCommand::ReceivePack(_) => audit::GIT_PUSH,
+        GitCommand::LfsAuthenticate(..) => audit::TOKEN_ISSUE,
+    };
+    if !owner
+        .authorize(conn, repo.as_str(), command.operation().into())
+        .map_err(internal)?
+    {
+        record_event(
+            conn,
+            Event::new(action, Actor::from(&owner), Outcome::Denied).repository(&repo),
+        )?;
+        return Err(ShellError::Forbidden(repo.to_string(), command.operation()));
+    }
+
+    let program = match command {
+        GitCommand::UploadPack(_) => "upload-pack",
+        GitCommand::ReceivePack(_) => "receive-pack",
+        GitCommand::LfsAuthenticate(_, operation) => {
+            let response =
+                git_lfs_authenticate::authenticate(conn, &owner, fingerprint, &repo, operation)
+                    .map_err(internal)?;
+            println!("{}", response);
+            return Ok(());
+        }
+    };
+
+    // git takes over the process, so this is when the push starts rather
+    // than whether it went through
+    if let GitCommand::ReceivePack(_) = command {
+        record_event(
+            conn,
+            Event::new(action, Actor::from(&owner), Outcome::Success).repository(&repo),
+        )?;
+    }
+
+    // hooks tell users and deploy keys apart by the variable that is set
+    let (variable, value) = match owner {
+        KeyOwner::User(user) => ("GIT_SERVER_USER", user),
+        KeyOwner::DeployKey(fingerprint) => ("GIT_SERVER_DEPLOY_KEY", fingerprint),
+    };
+    // only returns if git could not be started
+    let err = std::process::Command::new("git")
+        .arg(program)
+        .arg(repo.to_path(root))
+        .env(variable, value)
+        .exec();
+    Err(internal(err))
+}
+
+fn main() {
+    dotenv::dotenv().ok();
+    dotenv::from_path("/etc/git-server.env").ok();
+
+    if let Err(err) = run() {
+        eprintln!("fatal: {}", err);
+        std::process::exit(128);
+    }
+}
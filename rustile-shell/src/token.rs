@@ -0,0 +1,294 @@
+use common::access_token;
+use database::audit::{self, Actor, Event, Outcome};
+use database::connection::DbConnection;
+use diesel::result::{DatabaseErrorKind, Error as DieselError};
+use std::path::Path;
+
+use crate::command::TokenCommand;
+use crate::{internal, record_event, repo_path, ShellError};
+
+const MAX_EXPIRES_IN_DAYS: i64 = 365;
+
+/// The uuid of the user whose tokens to manage: the connecting user, or
+/// `username` if an admin asks for it.
+fn token_owner(
+    conn: &DbConnection,
+    user: &str,
+    username: Option<String>,
+) -> Result<String, ShellError> {
+    let username = match username {
+        Some(username) => username,
+        None => return Ok(String::from(user)),
+    };
+    if !database::user::query_is_admin(conn, String::from(user)).map_err(internal)? {
+        return Err(ShellError::Invalid(String::from(
+            "only admins can manage the tokens of other users",
+        )));
+    }
+    database::user::query_user_id_by_username(conn, username.clone())
+        .map_err(internal)?
+        .ok_or_else(|| ShellError::Invalid(format!("there is no user named '{}'", username)))
+}
+
+pub fn run(
+    conn: &DbConnection,
+    user: &str,
+    root: &Path,
+    command: TokenCommand,
+) -> Result<(), ShellError> {
+    match command {
+        TokenCommand::Create {
+            name,
+            scope,
+            repo,
+            expires_in,
+            otp,
+        } => {
+            if !(1..=MAX_EXPIRES_IN_DAYS).contains(&expires_in) {
+                return Err(ShellError::Invalid(format!(
+                    "tokens expire in 1 to {} days",
+                    MAX_EXPIRES_IN_DAYS
+                )));
+            }
+            let repo = match repo {
+                Some(raw) => Some(repo_path(&raw, root)?.to_string()),
+                None => None,
+            };
+
+            // once there is a second factor, a leaked ssh key alone must not
+            // be enough to mint tokens, like it is not enough to sign in
+            if database::totp::has_second_factor(conn, String::from(user)).map_err(internal)? {
+                let verified = match otp {
+                    Some(code) => {
+                        database::totp::check_second_factor(conn, String::from(user), &code)
+                            .map_err(internal)?
+                    }
+                    None => false,
+                };
+                if !verified {
+                    record_event(
+                        conn,
+                        Event::new(
+                            audit::TOKEN_CREATE,
+                            Actor::User(String::from(user)),
+                            Outcome::Failure,
+                        )
+                        .detail(format!("access token '{}', no valid one-time code", name)),
+                    )?;
+                    return Err(ShellError::Invalid(String::from(
+                        "a valid --otp code from your authenticator app is required",
+                    )));
+                }
+            }
+
+            let expires_at = chrono::Utc::now() + chrono::Duration::days(expires_in);
+
+            let secret = access_token::generate();
+            match database::access_token::create_access_token(
+                conn,
+                String::from(user),
+                name.clone(),
+                access_token::hash(&secret),
+                scope,
+                repo.clone(),
+                expires_at.naive_utc(),
+            ) {
+                Ok(_) => {}
+                Err(DieselError::DatabaseError(DatabaseErrorKind::UniqueViolation, _)) => {
+                    return Err(ShellError::Invalid(format!(
+                        "you already have a token named '{}'",
+                        name
+                    )))
+                }
+                Err(err) => return Err(internal(err)),
+            }
+            let mut event = Event::new(
+                audit::TOKEN_CREATE,
+                Actor::User(String::from(user)),
+                Outcome::Success,
+            )
+            .detail(format!("access token '{}' with {} scope", name, scope));
+            if let Some(repo) = &repo {
+                event = event.repository(repo);
+            }
+            record_event(conn, event)?;
+
+            println!(
+                "created token '{}' with {} access to {}, expiring {}:\n\n    {}\n\nit is shown only this once.",
+                name,
+                scope,
+                repo.as_deref().unwrap_or("all your repositories"),
+                expires_at.format("%Y-%m-%d %H:%M UTC"),
+                secret
+            );
+        }
+        TokenCommand::List { user: username } => {
+            let owner = token_owner(conn, user, username)?;
+            let tokens = database::access_token::query_access_tokens_by_user(conn, owner.clone())
+                .map_err(internal)?;
+            let now = chrono::Utc::now().naive_utc();
+            for token in tokens {
+                println!(
+                    "{:<24} {:<6} {:<32} {} {}",
+                    token.name,
+                    token.scope,
+                    token.repository.as_deref().unwrap_or("*"),
+                    if token.expires_at > now {
+                        "expires"
+                    } else {
+                        "expired"
+                    },
+                    token.expires_at.format("%Y-%m-%d %H:%M UTC"),
+                );
+            }
+
+            let issued = database::issued_token::query_active_tokens_by_user(conn, owner)
+                .map_err(internal)?;
+            if !issued.is_empty() {
+                println!("\nactive git-lfs tokens:");
+            }
+            for token in issued {
+                println!(
+                    "{} {:<8} {:<32} expires {}",
+                    token.jti,
+                    token.operation,
+                    token.repository,
+                    token.expires_at.format("%Y-%m-%d %H:%M UTC"),
+                );
+            }
+        }
+        TokenCommand::Revoke {
+            name,
+            user: username,
+        } => {
+            let of = match &username {
+                Some(username) => format!(" of {}", username),
+                None => String::new(),
+            };
+            let owner = token_owner(conn, user, username)?;
+            let deleted =
+                database::access_token::delete_access_token(conn, owner.clone(), name.clone())
+                    .map_err(internal)?;
+            if deleted > 0 {
+                record_event(
+                    conn,
+                    Event::new(
+                        audit::TOKEN_REVOKE,
+                        Actor::User(String::from(user)),
+                        Outcome::Success,
+                    )
+                    .detail(format!("access token '{}'{}", name, of)),
+                )?;
+                println!("revoked token '{}'", name);
+                return Ok(());
+            }
+            // git-server notices within REVOCATION_CACHE_SECONDS
+            let revoked = database::issued_token::revoke_issued_token(conn, owner, name.clone())
+                .map_err(internal)?;
+            if revoked == 0 {
+                return Err(ShellError::Invalid(format!(
+                    "no token named '{}' and no active git-lfs token with that id",
+                    name
+                )));
+            }
+            record_event(
+                conn,
+                Event::new(
+                    audit::TOKEN_REVOKE,
+                    Actor::User(String::from(user)),
+                    Outcome::Success,
+                )
+                .detail(format!("git-lfs token {}{}", name, of)),
+            )?;
+            println!("revoked git-lfs token {}", name);
+        }
+    }
+    Ok(())
+}
+
+#[cfg(all(test, feature = "sqlite"))]
+mod tests {
+    use common::Access;
+
+    use super::*;
+
+    struct Fixture {
+        pool: database::connection::ConnectionPool,
+        alice: String,
+        dir: tempfile::TempDir,
+    }
+
+    fn fixture() -> Fixture {
+        let dir = tempfile::tempdir().unwrap();
+        let url = dir.path().join("rustile.sqlite");
+        let pool = database::connection::pool(url.to_str().unwrap()).unwrap();
+        let conn = pool.get().unwrap();
+        database::migrations::run_pending_migrations(&conn).unwrap();
+        let alice = database::user::create_user(&conn, String::from("alice")).unwrap();
+        drop(conn);
+        Fixture { pool, alice, dir }
+    }
+
+    impl Fixture {
+        fn create(&self, name: &str, otp: Option<String>) -> Result<(), ShellError> {
+            run(
+                &self.pool.get().unwrap(),
+                &self.alice,
+                self.dir.path(),
+                TokenCommand::Create {
+                    name: String::from(name),
+                    scope: Access::Read,
+                    repo: None,
+                    expires_in: 30,
+                    otp,
+                },
+            )
+        }
+
+        fn tokens(&self) -> Vec<String> {
+            database::access_token::query_access_tokens_by_user(
+                &self.pool.get().unwrap(),
+                self.alice.clone(),
+            )
+            .unwrap()
+            .into_iter()
+            .map(|token| token.name)
+            .collect()
+        }
+
+        /// Enrolls alice in TOTP, returning the secret.
+        fn enroll(&self) -> String {
+            let conn = self.pool.get().unwrap();
+            let secret = common::totp::generate_secret();
+            database::totp::start_enrollment(&conn, self.alice.clone(), secret.clone()).unwrap();
+            database::totp::confirm_enrollment(&conn, self.alice.clone(), 0, Vec::new()).unwrap();
+            secret
+        }
+    }
+
+    #[test]
+    fn creates_tokens_without_a_second_factor() {
+        let fixture = fixture();
+        fixture.create("ci", None).unwrap();
+        assert_eq!(fixture.tokens(), ["ci"]);
+    }
+
+    #[test]
+    fn asks_enrolled_users_for_a_code() {
+        let fixture = fixture();
+        let secret = fixture.enroll();
+
+        for otp in [None, Some(String::from("000000"))] {
+            assert!(matches!(
+                fixture.create("ci", otp),
+                Err(ShellError::Invalid(_))
+            ));
+        }
+        assert!(fixture.tokens().is_empty());
+
+        let now = chrono::Utc::now().timestamp() as u64;
+        let code = common::totp::generate_code(&secret, now).unwrap();
+        fixture.create("ci", Some(code)).unwrap();
+        assert_eq!(fixture.tokens(), ["ci"]);
+    }
+}